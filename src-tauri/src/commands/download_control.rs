@@ -1,6 +1,6 @@
 use crate::core::persistence::{delete_state, save_state};
 use crate::core::state::DownloadMetadata;
-use crate::core::strategy::http::HttpStrategy;
+use crate::core::strategy;
 use std::sync::Arc;
 /// Download control commands module
 ///
@@ -253,10 +253,18 @@ pub async fn resume_download(
         .await
         .ok_or_else(|| "Control signals not found".to_string())?;
 
-    // Reset signal
+
+    // Increment generation to invalidate old workers
+    control
+        .generation
+        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let generation = control.generation.load(std::sync::atomic::Ordering::SeqCst);
+
+    // Reset signal (only after the generation moved on, so a task that has not seen the pause
+    // yet still stops instead of carrying on next to the new one)
     control
         .signal
-        .store(0, std::sync::atomic::Ordering::Relaxed);
+        .store(0, std::sync::atomic::Ordering::SeqCst);
 
     // Initialize control state from metadata (CRITICAL for resume)
     control.downloaded_bytes.store(
@@ -268,12 +276,6 @@ pub async fn resume_download(
         *completed_lock = metadata.completed_chunks.clone();
     }
 
-    // Increment generation to invalidate old workers
-    control
-        .generation
-        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-    let generation = control.generation.load(std::sync::atomic::Ordering::SeqCst);
-
     info!(download_id = %download_id, generation = generation, "Download resumed, spawning background task");
     let _ = app.emit("download-state", "active");
 
@@ -286,7 +288,8 @@ pub async fn resume_download(
     let control_cloned = control.clone();
 
     tokio::spawn(async move {
-        let strategy = Box::new(HttpStrategy);
+        // Streaming downloads re-resolve the manifest and append from their `.progress` sidecar
+        let strategy = strategy::for_url(&meta_cloned.url);
         match crate::core::engine::DownloadEngine::start(
             app_handle,
             id_cloned.clone(),
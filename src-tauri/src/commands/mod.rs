@@ -2,6 +2,7 @@
 ///
 /// This module organizes all Tauri commands into logical groups
 pub mod download_control;
+pub mod streaming;
 
 // Re-export DownloadManager and DownloadControl for use in lib.rs
 pub use download_control::{DownloadControl, DownloadManager};
@@ -0,0 +1,59 @@
+/// Streaming inspection commands
+///
+/// Lets the frontend ask which stream resolver a URL would be handed to before it is queued,
+/// and list the media found on a web page so the user can pick one.
+use crate::core::error::DownloadError;
+use crate::core::strategy::stream::registry::ResolverRegistry;
+use crate::core::strategy::stream::webpage::{self, MediaCandidate};
+use crate::core::strategy::stream::StreamingConfig;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Resolver chosen for a URL
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamResolverInfo {
+    pub name: String,
+    pub priority: i32,
+    /// Whether the resolver scrapes a website rather than reading a manifest
+    pub platform: bool,
+}
+
+#[tauri::command]
+pub async fn get_stream_resolver(url: String) -> Result<StreamResolverInfo, DownloadError> {
+    let client = crate::network::client::create_client()?;
+    let registry = ResolverRegistry::from_config(&StreamingConfig::default());
+    let resolver = registry
+        .resolver_for(&url, &client, &reqwest::header::HeaderMap::new())
+        .await?;
+    Ok(StreamResolverInfo {
+        name: resolver.name().to_string(),
+        priority: resolver.priority(),
+        platform: resolver.is_platform(),
+    })
+}
+
+/// Lists the media referenced by a web page. `headers` carries the browser's cookies when the
+/// request came from the extension.
+#[tauri::command]
+pub async fn find_page_media(
+    url: String,
+    headers: Option<HashMap<String, String>>,
+    referrer: Option<String>,
+) -> Result<Vec<MediaCandidate>, DownloadError> {
+    let mut header_map = reqwest::header::HeaderMap::new();
+    for (k, v) in headers.unwrap_or_default() {
+        if let (Ok(name), Ok(val)) = (
+            reqwest::header::HeaderName::from_bytes(k.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&v),
+        ) {
+            header_map.insert(name, val);
+        }
+    }
+    if let Some(val) = referrer.and_then(|r| reqwest::header::HeaderValue::from_str(&r).ok()) {
+        header_map.insert(reqwest::header::REFERER, val);
+    }
+
+    let client = crate::network::client::create_client()?;
+    webpage::fetch_candidates(&url, &client, &header_map).await
+}
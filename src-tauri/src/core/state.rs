@@ -47,6 +47,140 @@ impl DownloadState {
     }
 }
 
+/// What to do at discontinuities (ad breaks, spliced content) in a stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiscontinuityMode {
+    /// Concatenate every section into one file
+    #[default]
+    Keep,
+    /// Leave out short sections that look like inserted ads
+    DropAds,
+    /// Write each continuous section to its own file
+    Split,
+}
+
+/// Output format of audio-only stream downloads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AudioContainer {
+    /// AAC in an MP4 container (`.m4a`); MP3 audio is always saved as `.mp3`
+    #[default]
+    M4a,
+    /// The elementary stream as-is (`.aac` / `.mp3`)
+    Raw,
+}
+
+/// Container of live RTSP recordings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordingContainer {
+    /// Matroska (`.mkv`); a resumed recording continues in a new `_partNN` file
+    #[default]
+    Matroska,
+    /// MPEG transport stream (`.ts`); a resumed recording is appended to the same file
+    Ts,
+}
+
+impl RecordingContainer {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingContainer::Matroska => "mkv",
+            RecordingContainer::Ts => "ts",
+        }
+    }
+}
+
+/// Per-download options for streaming (HLS/DASH) downloads
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StreamOptions {
+    /// Remux the finished capture into MP4 (instead of keeping TS / MKV)
+    pub remux_to_mp4: bool,
+
+    /// Subtitle languages to download (BCP 47 tags, `*` for all)
+    pub subtitle_languages: Vec<String>,
+
+    /// Convert downloaded WebVTT subtitles to SRT
+    pub subtitles_as_srt: bool,
+
+    /// Only download the part of the stream starting at this many seconds
+    pub clip_start: Option<f64>,
+
+    /// Only download the part of the stream before this many seconds
+    pub clip_end: Option<f64>,
+
+    /// Handling of discontinuity-bounded sections
+    pub discontinuity_mode: DiscontinuityMode,
+
+    /// Tallest video to pick when a platform offers several qualities (`None` for the best)
+    pub max_height: Option<u32>,
+
+    /// Save HLS streams as served (playlists, keys and segments in a directory) instead of one file
+    pub mirror: bool,
+
+    /// Only download the audio, preferring an audio-only rendition over demuxing the full stream
+    pub audio_only: bool,
+
+    /// File format for `audio_only` downloads
+    pub audio_container: AudioContainer,
+
+    /// File format for RTSP recordings
+    pub recording_container: RecordingContainer,
+
+    /// Stop an RTSP recording after this many seconds of media (`None` records until stopped)
+    pub max_duration: Option<f64>,
+}
+
+impl StreamOptions {
+    /// Requested time window as `(start, end)` in seconds, if any bound was given
+    pub fn clip_range(&self) -> Option<(f64, Option<f64>)> {
+        if self.clip_start.is_none() && self.clip_end.is_none() {
+            return None;
+        }
+        Some((self.clip_start.unwrap_or(0.0).max(0.0), self.clip_end))
+    }
+}
+
+/// A streaming segment that still failed validation after every re-fetch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DamagedSegment {
+    /// Track the segment belongs to (`video`, `audio`, ...)
+    pub track: String,
+
+    /// Position of the segment in its track
+    pub index: usize,
+
+    pub url: String,
+
+    /// Human-readable problems found in the last copy fetched
+    pub issues: Vec<String>,
+}
+
+/// A streaming segment fetched from another variant after its own URL kept failing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubstitutedSegment {
+    /// Track the segment belongs to (`video`, `audio`, ...)
+    pub track: String,
+
+    /// Position of the segment in its track
+    pub index: usize,
+
+    /// URL that could not be fetched
+    pub url: String,
+
+    /// URL the segment was fetched from instead
+    pub substitute_url: String,
+
+    /// Media playlist of the variant that supplied it
+    pub variant: String,
+
+    /// Whether that variant is a redundant copy of the same stream (otherwise another bitrate)
+    pub redundant: bool,
+}
+
 /// Download metadata - all information needed to resume a download
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadMetadata {
@@ -97,6 +231,18 @@ pub struct DownloadMetadata {
 
     /// Error message if download failed
     pub error_message: Option<String>,
+
+    /// Streaming options chosen when the download was added
+    #[serde(default)]
+    pub stream_options: StreamOptions,
+
+    /// Streaming segments that were written despite failing validation
+    #[serde(default)]
+    pub damaged_segments: Vec<DamagedSegment>,
+
+    /// Streaming segments fetched from a redundant or alternate variant
+    #[serde(default)]
+    pub substituted_segments: Vec<SubstitutedSegment>,
 }
 
 impl DownloadMetadata {
@@ -119,6 +265,9 @@ impl DownloadMetadata {
             stopped_at: None,
             completed_at: None,
             error_message: None,
+            stream_options: StreamOptions::default(),
+            damaged_segments: Vec::new(),
+            substituted_segments: Vec::new(),
         }
     }
 
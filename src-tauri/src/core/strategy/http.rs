@@ -2,7 +2,7 @@ use super::{DownloadContext, DownloadStrategy};
 use crate::commands::DownloadCommandResult;
 use crate::core::error::DownloadError;
 use crate::core::{integrity, types};
-use crate::network::client;
+use crate::network::{client, headers};
 use crate::utils;
 use reqwest::header::RANGE;
 use std::io::SeekFrom;
@@ -23,6 +23,8 @@ impl DownloadStrategy for HttpStrategy {
         context: &DownloadContext,
     ) -> Result<DownloadCommandResult, DownloadError> {
         let url = context.metadata.url.clone();
+        // Cookies and tokens the link needs (e.g. from a resolved share link) go on every chunk
+        let request_headers = headers::request_headers(&context.metadata.headers, context.metadata.referrer.as_deref());
         let filepath = context.metadata.filepath.clone();
         let total_size = context.metadata.total_size;
         let actual_threads = context.metadata.thread_count as usize;
@@ -70,6 +72,7 @@ impl DownloadStrategy for HttpStrategy {
 
         for _ in 0..actual_threads {
             let url = url.clone();
+            let request_headers = request_headers.clone();
             let path = filepath.clone();
             let app_handle = context.app.clone();
             let queue = chunk_queue.clone();
@@ -163,6 +166,7 @@ impl DownloadStrategy for HttpStrategy {
 
                         if let Ok(mut response) = client
                             .get(&url)
+                            .headers(request_headers.clone())
                             .header(RANGE, range_header.clone())
                             .send()
                             .await
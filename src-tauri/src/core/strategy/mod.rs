@@ -1,11 +1,16 @@
 use crate::core::error::DownloadError;
 use crate::commands::DownloadCommandResult;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 use std::sync::Arc;
+use tauri::Emitter;
+use tracing::warn;
 use crate::commands;
-use crate::core::state;
+use crate::core::{persistence, state};
+use crate::utils::format;
 
 pub mod http;
-pub mod hls;
+pub mod rtmp;
+pub mod rtsp;
 pub mod stream;
 
 /// The context required for a strategy to execute a download.
@@ -18,9 +23,109 @@ pub struct DownloadContext {
     pub generation: u32,
 }
 
+impl DownloadContext {
+    /// The control signal as this task sees it.
+    pub fn signal(&self) -> TaskSignal {
+        TaskSignal::new(&self.control, self.generation)
+    }
+
+    /// Publishes how many bytes a live recording has written so far.
+    pub async fn report_progress(&self, bytes: u64) {
+        self.control.downloaded_bytes.store(bytes, Ordering::Relaxed);
+        if let Some(mut meta) = self.manager.get_download(&self.download_id).await {
+            meta.downloaded_bytes = bytes;
+            self.manager.update_download(&self.download_id, meta).await;
+        }
+        let _ = self.app.emit("download-progress", bytes);
+    }
+
+    /// Marks a stream download or recording completed with `bytes` written: final progress to
+    /// the frontend, `completed` state in the manager, then the download leaves the active list.
+    ///
+    /// A record carrying a segment report is saved next to the output, as that is its only copy.
+    pub async fn complete(&self, bytes: u64) {
+        let _ = self.app.emit("download-progress", bytes);
+        if let Some(mut meta) = self.manager.get_download(&self.download_id).await {
+            meta.complete();
+            meta.downloaded_bytes = bytes;
+            meta.total_size = bytes;
+            if !meta.damaged_segments.is_empty() || !meta.substituted_segments.is_empty() {
+                if let Err(e) = persistence::save_state(&meta) {
+                    warn!(download_id = %self.download_id, error = %e, "Failed to save the segment report");
+                }
+            }
+            let _ = self.app.emit("download-state", "completed");
+            self.manager.update_download(&self.download_id, meta).await;
+        }
+        self.manager.remove_download(&self.download_id).await;
+    }
+}
+
+/// The control signal of one download task (0=run, 1=pause, 2=stop, 3=cancel).
+///
+/// Resuming resets the signal and starts a new task under the next generation. A task that
+/// has not noticed the pause yet reads that as a pause, so it never keeps writing the output
+/// next to its replacement.
+#[derive(Clone)]
+pub struct TaskSignal {
+    signal: Arc<AtomicU8>,
+    generation: Arc<AtomicU32>,
+    task_generation: u32,
+}
+
+impl TaskSignal {
+    /// The signal of `control` for the task started under `task_generation`.
+    pub fn new(control: &commands::DownloadControl, task_generation: u32) -> Self {
+        Self {
+            signal: control.signal.clone(),
+            generation: control.generation.clone(),
+            task_generation,
+        }
+    }
+
+    pub fn load(&self) -> u8 {
+        if self.generation.load(Ordering::SeqCst) != self.task_generation {
+            return 1;
+        }
+        self.signal.load(Ordering::Relaxed)
+    }
+}
+
 /// A trait that defines a method for downloading a file.
 /// This allows for different strategies (e.g., HTTP, HLS).
 #[async_trait::async_trait]
 pub trait DownloadStrategy: Send + Sync {
     async fn execute(&self, context: &DownloadContext) -> Result<DownloadCommandResult, DownloadError>;
 }
+
+/// Picks the strategy for a URL; used both when a download starts and when it resumes.
+pub fn for_url(url: &str) -> Box<dyn DownloadStrategy> {
+    if format::is_rtmp(url) {
+        Box::new(rtmp::RtmpStrategy)
+    } else if format::is_rtsp(url) {
+        Box::new(rtsp::RtspStrategy)
+    } else if format::uses_streaming_engine(url) {
+        Box::new(stream::UniversalStreamingStrategy::new(None))
+    } else {
+        Box::new(http::HttpStrategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_signal_sees_a_resume_as_a_pause() {
+        let control = commands::DownloadControl::new();
+        let signal = TaskSignal::new(&control, 0);
+        assert_eq!(signal.load(), 0);
+        control.signal.store(2, Ordering::SeqCst);
+        assert_eq!(signal.load(), 2);
+
+        // Paused and resumed before the task looked: the signal is back to 0 but it belongs to the next task.
+        control.generation.fetch_add(1, Ordering::SeqCst);
+        control.signal.store(0, Ordering::SeqCst);
+        assert_eq!(signal.load(), 1);
+    }
+}
@@ -0,0 +1,244 @@
+//! AMF0, the encoding of RTMP command and data messages.
+
+use crate::core::error::DownloadError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Amf0Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Amf0Value)>),
+    Null,
+    Undefined,
+    /// Associative array; encoded like an object with a count hint in front.
+    EcmaArray(Vec<(String, Amf0Value)>),
+    StrictArray(Vec<Amf0Value>),
+    /// Milliseconds since the epoch.
+    Date(f64),
+}
+
+impl Amf0Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Amf0Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Amf0Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Property `key` of an object or ECMA array.
+    pub fn get(&self, key: &str) -> Option<&Amf0Value> {
+        match self {
+            Amf0Value::Object(properties) | Amf0Value::EcmaArray(properties) => {
+                properties.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// An object from `(key, value)` pairs.
+    pub fn object<'a>(properties: impl IntoIterator<Item = (&'a str, Amf0Value)>) -> Self {
+        Amf0Value::Object(properties.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+}
+
+/// Encodes `values` one after another, as a command or data message body.
+pub fn encode(values: &[Amf0Value]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for value in values {
+        write_value(&mut out, value);
+    }
+    out
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Amf0Value) {
+    match value {
+        Amf0Value::Number(n) => {
+            out.push(0x00);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+        Amf0Value::Boolean(b) => out.extend_from_slice(&[0x01, *b as u8]),
+        Amf0Value::String(s) if s.len() > u16::MAX as usize => {
+            out.push(0x0C);
+            out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Amf0Value::String(s) => {
+            out.push(0x02);
+            write_short_string(out, s);
+        }
+        Amf0Value::Object(properties) => {
+            out.push(0x03);
+            write_properties(out, properties);
+        }
+        Amf0Value::Null => out.push(0x05),
+        Amf0Value::Undefined => out.push(0x06),
+        Amf0Value::EcmaArray(properties) => {
+            out.push(0x08);
+            out.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+            write_properties(out, properties);
+        }
+        Amf0Value::StrictArray(items) => {
+            out.push(0x0A);
+            out.extend_from_slice(&(items.len() as u32).to_be_bytes());
+            for item in items {
+                write_value(out, item);
+            }
+        }
+        Amf0Value::Date(ms) => {
+            out.push(0x0B);
+            out.extend_from_slice(&ms.to_be_bytes());
+            out.extend_from_slice(&[0, 0]);
+        }
+    }
+}
+
+fn write_short_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_properties(out: &mut Vec<u8>, properties: &[(String, Amf0Value)]) {
+    for (key, value) in properties {
+        write_short_string(out, key);
+        write_value(out, value);
+    }
+    out.extend_from_slice(&[0, 0, 0x09]);
+}
+
+/// Decodes every value in a command or data message body.
+pub fn decode(data: &[u8]) -> Result<Vec<Amf0Value>, DownloadError> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut values = Vec::new();
+    while reader.pos < data.len() {
+        values.push(reader.value()?);
+    }
+    Ok(values)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], DownloadError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| DownloadError::Parse("Truncated AMF0 value".to_string()))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DownloadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DownloadError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, DownloadError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, DownloadError> {
+        Ok(f64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, DownloadError> {
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn value(&mut self) -> Result<Amf0Value, DownloadError> {
+        let marker = self.u8()?;
+        Ok(match marker {
+            0x00 => Amf0Value::Number(self.f64()?),
+            0x01 => Amf0Value::Boolean(self.u8()? != 0),
+            0x02 => {
+                let len = self.u16()? as usize;
+                Amf0Value::String(self.string(len)?)
+            }
+            0x03 => Amf0Value::Object(self.properties()?),
+            0x05 => Amf0Value::Null,
+            0x06 | 0x0D => Amf0Value::Undefined,
+            0x08 => {
+                self.u32()?; // Count hint; the end marker is what counts.
+                Amf0Value::EcmaArray(self.properties()?)
+            }
+            0x0A => {
+                let count = self.u32()? as usize;
+                let mut items = Vec::with_capacity(count.min(1024));
+                for _ in 0..count {
+                    items.push(self.value()?);
+                }
+                Amf0Value::StrictArray(items)
+            }
+            0x0B => {
+                let ms = self.f64()?;
+                self.u16()?; // Time zone, unused.
+                Amf0Value::Date(ms)
+            }
+            0x0C | 0x0F => {
+                let len = self.u32()? as usize;
+                Amf0Value::String(self.string(len)?)
+            }
+            0x10 => {
+                let len = self.u16()? as usize;
+                self.take(len)?; // Class name.
+                Amf0Value::Object(self.properties()?)
+            }
+            marker => return Err(DownloadError::Parse(format!("Unsupported AMF0 marker 0x{:02X}", marker))),
+        })
+    }
+
+    fn properties(&mut self) -> Result<Vec<(String, Amf0Value)>, DownloadError> {
+        let mut properties = Vec::new();
+        loop {
+            let len = self.u16()? as usize;
+            if len == 0 && self.data.get(self.pos) == Some(&0x09) {
+                self.pos += 1;
+                return Ok(properties);
+            }
+            let key = self.string(len)?;
+            properties.push((key, self.value()?));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amf0_round_trip() {
+        let values = vec![
+            Amf0Value::String("connect".to_string()),
+            Amf0Value::Number(1.0),
+            Amf0Value::object([
+                ("app", Amf0Value::String("live".to_string())),
+                ("fpad", Amf0Value::Boolean(false)),
+                ("nested", Amf0Value::EcmaArray(vec![("width".to_string(), Amf0Value::Number(1280.0))])),
+            ]),
+            Amf0Value::Null,
+            Amf0Value::StrictArray(vec![Amf0Value::Undefined, Amf0Value::Date(1.5e12)]),
+        ];
+        let encoded = encode(&values);
+        // "connect" is a short string: marker, 16-bit length, bytes.
+        assert_eq!(&encoded[..10], b"\x02\x00\x07connect");
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded, values);
+        assert_eq!(decoded[2].get("app").and_then(Amf0Value::as_str), Some("live"));
+        assert_eq!(decoded[2].get("nested").and_then(|n| n.get("width")).and_then(Amf0Value::as_number), Some(1280.0));
+
+        assert!(decode(&[0x02, 0x00, 0x05, b'a']).is_err());
+        assert!(decode(&[0x11]).is_err());
+    }
+}
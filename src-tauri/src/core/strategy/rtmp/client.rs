@@ -0,0 +1,471 @@
+//! RTMP playback session: handshake, `connect`, `createStream` and `play`, then the media
+//! messages the server sends, with protocol control (chunk size, acknowledgements, pings)
+//! handled along the way.
+
+use super::amf::{self, Amf0Value};
+use super::chunk::{self, ChunkReader, ChunkWriter, RtmpMessage};
+use super::handshake;
+use crate::core::error::DownloadError;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info};
+use url::Url;
+
+/// Chunk streams used for what we send.
+const CONTROL_CHUNK_STREAM: u32 = 2;
+const COMMAND_CHUNK_STREAM: u32 = 3;
+const PLAY_CHUNK_STREAM: u32 = 8;
+
+/// User control events.
+const STREAM_EOF: u16 = 1;
+const SET_BUFFER_LENGTH: u16 = 3;
+const PING_REQUEST: u16 = 6;
+const PING_RESPONSE: u16 = 7;
+
+/// Chunk size we send with; commands fit in one chunk.
+const OUTGOING_CHUNK_SIZE: u32 = 4096;
+
+/// How long the server may take to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long a playing stream may stay silent before the connection is considered lost.
+const MEDIA_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where an `rtmp://` or `rtmps://` URL points: `rtmp://host[:port]/app/stream`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtmpUrl {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub app: String,
+    /// Stream name passed to `play`, including any query string.
+    pub stream: String,
+    pub tc_url: String,
+}
+
+impl RtmpUrl {
+    /// Splits the path after its first segment, which names the application (`live`, `vod`).
+    /// Everything after it is the stream name, e.g. `mp4:folder/clip.mp4` or `key?token=1`.
+    pub fn parse(url: &str) -> Result<Self, DownloadError> {
+        let parsed = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid RTMP URL: {}", e)))?;
+        let tls = match parsed.scheme() {
+            "rtmp" => false,
+            "rtmps" => true,
+            scheme => return Err(DownloadError::Config(format!("Unsupported RTMP scheme {}://", scheme))),
+        };
+        let host = parsed.host_str().filter(|h| !h.is_empty()).ok_or_else(|| DownloadError::Config("RTMP URL has no host".to_string()))?;
+        let port = parsed.port().unwrap_or(if tls { 443 } else { 1935 });
+
+        let path = parsed.path().trim_start_matches('/');
+        let (app, stream) = path.split_once('/').unwrap_or((path, ""));
+        if app.is_empty() || stream.is_empty() {
+            return Err(DownloadError::Config(format!("RTMP URL needs an application and a stream name: {}", url)));
+        }
+        let stream = match parsed.query() {
+            Some(query) => format!("{}?{}", stream, query),
+            None => stream.to_string(),
+        };
+        let port_part = parsed.port().map(|p| format!(":{}", p)).unwrap_or_default();
+        Ok(Self {
+            tc_url: format!("{}://{}{}/{}", parsed.scheme(), host, port_part, app),
+            host: host.to_string(),
+            port,
+            tls,
+            app: app.to_string(),
+            stream,
+        })
+    }
+}
+
+/// A command message's name, transaction id and remaining arguments.
+struct Command {
+    name: String,
+    transaction: f64,
+    args: Vec<Amf0Value>,
+}
+
+impl Command {
+    fn parse(message: &RtmpMessage) -> Option<Self> {
+        let mut values = amf::decode(&message.payload).ok()?.into_iter();
+        let name = values.next()?.as_str()?.to_string();
+        let transaction = values.next().and_then(|v| v.as_number()).unwrap_or(0.0);
+        Some(Self { name, transaction, args: values.collect() })
+    }
+
+    /// `code` of the info object of an `onStatus` or `_error` reply.
+    fn status_code(&self) -> Option<&str> {
+        self.args.iter().find_map(|a| a.get("code")).and_then(Amf0Value::as_str)
+    }
+
+    fn description(&self) -> String {
+        let info = self.args.iter().find(|a| a.get("code").is_some());
+        let description = info.and_then(|i| i.get("description")).and_then(Amf0Value::as_str);
+        match (self.status_code(), description) {
+            (Some(code), Some(description)) => format!("{} ({})", code, description),
+            (Some(code), None) => code.to_string(),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// A playing RTMP stream.
+pub struct RtmpSession<S> {
+    stream: S,
+    reader: ChunkReader,
+    writer: ChunkWriter,
+    window_ack_size: u32,
+    acknowledged: u64,
+    stream_id: u32,
+    /// Media that arrived while waiting for `play` to be confirmed.
+    pending: VecDeque<RtmpMessage>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RtmpSession<S> {
+    /// Runs the handshake and the `connect`/`createStream`/`play` exchange on `stream`.
+    pub async fn start(stream: S, url: &RtmpUrl) -> Result<Self, DownloadError> {
+        let mut session = Self {
+            stream,
+            reader: ChunkReader::new(),
+            writer: ChunkWriter::new(),
+            window_ack_size: 2_500_000,
+            acknowledged: 0,
+            stream_id: 0,
+            pending: VecDeque::new(),
+        };
+        handshake::handshake(&mut session.stream).await?;
+        debug!("RTMP: Handshake done with {}:{}", url.host, url.port);
+        let chunk_size = RtmpMessage::new(chunk::SET_CHUNK_SIZE, 0, 0, OUTGOING_CHUNK_SIZE.to_be_bytes().to_vec());
+        session.send(CONTROL_CHUNK_STREAM, &chunk_size).await?;
+        session.writer.set_chunk_size(OUTGOING_CHUNK_SIZE as usize);
+
+        let connect = Amf0Value::object([
+            ("app", Amf0Value::String(url.app.clone())),
+            ("flashVer", Amf0Value::String("LNX 9,0,124,2".to_string())),
+            ("tcUrl", Amf0Value::String(url.tc_url.clone())),
+            ("fpad", Amf0Value::Boolean(false)),
+            ("capabilities", Amf0Value::Number(15.0)),
+            ("audioCodecs", Amf0Value::Number(4071.0)),
+            ("videoCodecs", Amf0Value::Number(252.0)),
+            ("videoFunction", Amf0Value::Number(1.0)),
+        ]);
+        session.send_command(0, &[Amf0Value::String("connect".to_string()), Amf0Value::Number(1.0), connect]).await?;
+        session.expect_result(1.0).await?;
+
+        session
+            .send_command(0, &[Amf0Value::String("createStream".to_string()), Amf0Value::Number(2.0), Amf0Value::Null])
+            .await?;
+        let reply = session.expect_result(2.0).await?;
+        session.stream_id = reply
+            .args
+            .iter()
+            .find_map(Amf0Value::as_number)
+            .ok_or_else(|| DownloadError::Parse("createStream reply has no stream id".to_string()))? as u32;
+
+        let play = [
+            Amf0Value::String("play".to_string()),
+            Amf0Value::Number(0.0),
+            Amf0Value::Null,
+            Amf0Value::String(url.stream.clone()),
+            Amf0Value::Number(-2.0),
+        ];
+        let message = RtmpMessage::new(chunk::COMMAND_AMF0, session.stream_id, 0, amf::encode(&play));
+        session.send(PLAY_CHUNK_STREAM, &message).await?;
+        let mut buffer = Vec::with_capacity(10);
+        buffer.extend_from_slice(&SET_BUFFER_LENGTH.to_be_bytes());
+        buffer.extend_from_slice(&session.stream_id.to_be_bytes());
+        buffer.extend_from_slice(&3000u32.to_be_bytes());
+        session.send(CONTROL_CHUNK_STREAM, &RtmpMessage::new(chunk::USER_CONTROL, 0, 0, buffer)).await?;
+
+        session.wait_for_play_start().await?;
+        info!("RTMP: Playing {} from {}", url.stream, url.tc_url);
+        Ok(session)
+    }
+
+    /// The next audio, video, metadata or aggregate message; `None` once the stream has ended.
+    pub async fn next_media(&mut self) -> Result<Option<RtmpMessage>, DownloadError> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            let Some(message) = self.read(MEDIA_TIMEOUT).await? else {
+                info!("RTMP: Server closed the connection");
+                return Ok(None);
+            };
+            match message.type_id {
+                chunk::AUDIO | chunk::VIDEO | chunk::DATA_AMF0 | chunk::AGGREGATE => {
+                    if is_play_complete(&message) {
+                        return Ok(None);
+                    }
+                    return Ok(Some(message));
+                }
+                chunk::COMMAND_AMF0 => {
+                    let Some(command) = Command::parse(&message) else { continue };
+                    if command.name == "onStatus" && is_end_status(command.status_code()) {
+                        info!("RTMP: Stream ended ({})", command.description());
+                        return Ok(None);
+                    }
+                    debug!("RTMP: {} {}", command.name, command.description());
+                }
+                chunk::USER_CONTROL if user_control_event(&message) == Some(STREAM_EOF) => {
+                    info!("RTMP: Stream EOF");
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Asks the server to stop sending and closes the connection.
+    pub async fn close(&mut self) {
+        let delete = [
+            Amf0Value::String("deleteStream".to_string()),
+            Amf0Value::Number(0.0),
+            Amf0Value::Null,
+            Amf0Value::Number(self.stream_id as f64),
+        ];
+        let message = RtmpMessage::new(chunk::COMMAND_AMF0, 0, 0, amf::encode(&delete));
+        if let Err(e) = self.send(COMMAND_CHUNK_STREAM, &message).await {
+            debug!("RTMP: deleteStream failed: {}", e);
+        }
+        let _ = self.stream.shutdown().await;
+    }
+
+    async fn send(&mut self, chunk_stream_id: u32, message: &RtmpMessage) -> Result<(), DownloadError> {
+        let bytes = self.writer.encode(chunk_stream_id, message);
+        self.stream.write_all(&bytes).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn send_command(&mut self, stream_id: u32, values: &[Amf0Value]) -> Result<(), DownloadError> {
+        let message = RtmpMessage::new(chunk::COMMAND_AMF0, stream_id, 0, amf::encode(values));
+        self.send(COMMAND_CHUNK_STREAM, &message).await
+    }
+
+    /// Reads the next message, answering protocol control messages on the way.
+    async fn read(&mut self, timeout: Duration) -> Result<Option<RtmpMessage>, DownloadError> {
+        loop {
+            let message = tokio::time::timeout(timeout, self.reader.read_message(&mut self.stream))
+                .await
+                .map_err(|_| DownloadError::Network(format!("RTMP server sent nothing for {}s", timeout.as_secs())))??;
+            let Some(message) = message else { return Ok(None) };
+
+            if self.reader.bytes_read() - self.acknowledged >= self.window_ack_size as u64 {
+                self.acknowledged = self.reader.bytes_read();
+                let ack = RtmpMessage::new(chunk::ACKNOWLEDGEMENT, 0, 0, (self.acknowledged as u32).to_be_bytes().to_vec());
+                self.send(CONTROL_CHUNK_STREAM, &ack).await?;
+            }
+
+            let value = message.payload.get(..4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+            match (message.type_id, value) {
+                (chunk::SET_CHUNK_SIZE, Some(size)) => {
+                    debug!("RTMP: Server chunk size {}", size & 0x7FFF_FFFF);
+                    self.reader.set_chunk_size((size & 0x7FFF_FFFF) as usize);
+                }
+                (chunk::ABORT, Some(chunk_stream_id)) => self.reader.abort(chunk_stream_id),
+                (chunk::WINDOW_ACK_SIZE, Some(size)) => self.window_ack_size = size.max(1),
+                (chunk::SET_PEER_BANDWIDTH, Some(size)) => {
+                    let reply = RtmpMessage::new(chunk::WINDOW_ACK_SIZE, 0, 0, size.to_be_bytes().to_vec());
+                    self.send(CONTROL_CHUNK_STREAM, &reply).await?;
+                }
+                (chunk::USER_CONTROL, _) if user_control_event(&message) == Some(PING_REQUEST) => {
+                    let mut pong = PING_RESPONSE.to_be_bytes().to_vec();
+                    pong.extend_from_slice(message.payload.get(2..6).unwrap_or(&[0; 4]));
+                    self.send(CONTROL_CHUNK_STREAM, &RtmpMessage::new(chunk::USER_CONTROL, 0, 0, pong)).await?;
+                }
+                _ => return Ok(Some(message)),
+            }
+        }
+    }
+
+    /// Waits for the `_result` of the command sent with `transaction`.
+    async fn expect_result(&mut self, transaction: f64) -> Result<Command, DownloadError> {
+        loop {
+            let message = self
+                .read(COMMAND_TIMEOUT)
+                .await?
+                .ok_or_else(|| DownloadError::Network("RTMP server closed the connection".to_string()))?;
+            if message.type_id != chunk::COMMAND_AMF0 {
+                continue;
+            }
+            let Some(command) = Command::parse(&message) else { continue };
+            match command.name.as_str() {
+                "_result" if command.transaction == transaction => return Ok(command),
+                "_error" if command.transaction == transaction => {
+                    return Err(DownloadError::Network(format!("RTMP server refused the request: {}", command.description())));
+                }
+                _ => debug!("RTMP: Ignoring {} while waiting for a reply", command.name),
+            }
+        }
+    }
+
+    /// Waits for `NetStream.Play.Start`, keeping any media that comes first.
+    async fn wait_for_play_start(&mut self) -> Result<(), DownloadError> {
+        loop {
+            let message = self
+                .read(COMMAND_TIMEOUT)
+                .await?
+                .ok_or_else(|| DownloadError::Network("RTMP server closed the connection before playing".to_string()))?;
+            if matches!(message.type_id, chunk::AUDIO | chunk::VIDEO | chunk::DATA_AMF0 | chunk::AGGREGATE) {
+                self.pending.push_back(message);
+                continue;
+            }
+            if message.type_id != chunk::COMMAND_AMF0 {
+                continue;
+            }
+            let Some(command) = Command::parse(&message) else { continue };
+            let code = command.status_code().unwrap_or_default();
+            let failed = command.name == "_error"
+                || command.args.iter().any(|a| a.get("level").and_then(Amf0Value::as_str) == Some("error"));
+            if failed {
+                return Err(DownloadError::Network(format!("RTMP server refused to play the stream: {}", command.description())));
+            }
+            match code {
+                "NetStream.Play.Start" => return Ok(()),
+                "" => {}
+                _ => debug!("RTMP: {}", command.description()),
+            }
+        }
+    }
+}
+
+fn user_control_event(message: &RtmpMessage) -> Option<u16> {
+    message.payload.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn is_end_status(code: Option<&str>) -> bool {
+    matches!(
+        code,
+        Some("NetStream.Play.Stop" | "NetStream.Play.Complete" | "NetStream.Play.UnpublishNotify")
+    )
+}
+
+/// `onPlayStatus` data message announcing the end of a recorded stream.
+fn is_play_complete(message: &RtmpMessage) -> bool {
+    message.type_id == chunk::DATA_AMF0
+        && amf::decode(&message.payload).is_ok_and(|values| {
+            values.first().and_then(Amf0Value::as_str) == Some("onPlayStatus")
+                && values.iter().any(|v| v.get("code").and_then(Amf0Value::as_str) == Some("NetStream.Play.Complete"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flv::{tests::read_tags, FlvWriter, TAG_AUDIO, TAG_SCRIPT, TAG_VIDEO};
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_parse_rtmp_url() {
+        let url = RtmpUrl::parse("rtmp://live.example.com/live/stream_key?token=abc").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.tls), ("live.example.com", 1935, false));
+        assert_eq!((url.app.as_str(), url.stream.as_str()), ("live", "stream_key?token=abc"));
+        assert_eq!(url.tc_url, "rtmp://live.example.com/live");
+
+        let url = RtmpUrl::parse("rtmps://vod.example.com:8443/vod/mp4:folder/clip.mp4").unwrap();
+        assert_eq!((url.port, url.tls), (8443, true));
+        assert_eq!((url.app.as_str(), url.stream.as_str()), ("vod", "mp4:folder/clip.mp4"));
+        assert_eq!(url.tc_url, "rtmps://vod.example.com:8443/vod");
+
+        assert!(RtmpUrl::parse("rtmp://live.example.com/live").is_err());
+        assert!(RtmpUrl::parse("http://live.example.com/live/key").is_err());
+    }
+
+    async fn send(socket: &mut TcpStream, writer: &ChunkWriter, chunk_stream_id: u32, message: RtmpMessage) {
+        socket.write_all(&writer.encode(chunk_stream_id, &message)).await.unwrap();
+    }
+
+    /// Minimal RTMP server: accepts one client, checks its commands and plays a few messages.
+    async fn serve_one(listener: TcpListener) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut c0c1 = vec![0u8; 1 + handshake::HANDSHAKE_SIZE];
+        socket.read_exact(&mut c0c1).await.unwrap();
+        assert_eq!(c0c1[0], handshake::RTMP_VERSION);
+        let mut s0s1s2 = vec![handshake::RTMP_VERSION];
+        s0s1s2.extend(std::iter::repeat_n(7u8, handshake::HANDSHAKE_SIZE));
+        s0s1s2.extend_from_slice(&c0c1[1..]);
+        socket.write_all(&s0s1s2).await.unwrap();
+        let mut c2 = vec![0u8; handshake::HANDSHAKE_SIZE];
+        socket.read_exact(&mut c2).await.unwrap();
+        assert!(c2.iter().all(|b| *b == 7));
+
+        let mut reader = ChunkReader::new();
+        let mut writer = ChunkWriter::new();
+        let command = |values: Vec<Amf0Value>| RtmpMessage::new(chunk::COMMAND_AMF0, 0, 0, amf::encode(&values));
+
+        let chunk_size = reader.read_message(&mut socket).await.unwrap().unwrap();
+        assert_eq!(chunk_size.type_id, chunk::SET_CHUNK_SIZE);
+        reader.set_chunk_size(u32::from_be_bytes(chunk_size.payload[..4].try_into().unwrap()) as usize);
+        let connect = Command::parse(&reader.read_message(&mut socket).await.unwrap().unwrap()).unwrap();
+        assert_eq!(connect.name, "connect");
+        assert!(connect.args[0].get("tcUrl").and_then(Amf0Value::as_str).is_some_and(|url| url.ends_with("/live")));
+        send(&mut socket, &writer, 2, RtmpMessage::new(chunk::WINDOW_ACK_SIZE, 0, 0, 5_000_000u32.to_be_bytes().to_vec())).await;
+        send(&mut socket, &writer, 2, RtmpMessage::new(chunk::SET_CHUNK_SIZE, 0, 0, 4096u32.to_be_bytes().to_vec())).await;
+        writer.set_chunk_size(4096);
+        send(&mut socket, &writer, 3, command(vec![Amf0Value::String("_result".to_string()), Amf0Value::Number(1.0), Amf0Value::Null, Amf0Value::object([("code", Amf0Value::String("NetConnection.Connect.Success".to_string()))])])).await;
+
+        let create = Command::parse(&reader.read_message(&mut socket).await.unwrap().unwrap()).unwrap();
+        assert_eq!(create.name, "createStream");
+        send(&mut socket, &writer, 3, command(vec![Amf0Value::String("_result".to_string()), Amf0Value::Number(create.transaction), Amf0Value::Null, Amf0Value::Number(1.0)])).await;
+
+        let play = loop {
+            let message = reader.read_message(&mut socket).await.unwrap().unwrap();
+            if let Some(command) = Command::parse(&message).filter(|c| c.name == "play") {
+                assert_eq!(message.stream_id, 1);
+                break command;
+            }
+        };
+        assert_eq!(play.args[1].as_str(), Some("camera1"));
+
+        let status = |code: &str| {
+            let mut message = command(vec![Amf0Value::String("onStatus".to_string()), Amf0Value::Number(0.0), Amf0Value::Null, Amf0Value::object([("level", Amf0Value::String("status".to_string())), ("code", Amf0Value::String(code.to_string()))])]);
+            message.stream_id = 1;
+            message
+        };
+        send(&mut socket, &writer, 5, status("NetStream.Play.Start")).await;
+        let metadata = amf::encode(&[Amf0Value::String("onMetaData".to_string()), Amf0Value::EcmaArray(vec![])]);
+        send(&mut socket, &writer, 5, RtmpMessage::new(chunk::DATA_AMF0, 1, 0, metadata)).await;
+        send(&mut socket, &writer, 6, RtmpMessage::new(chunk::VIDEO, 1, 1000, vec![0x17; 10_000])).await;
+        send(&mut socket, &writer, 4, RtmpMessage::new(chunk::AUDIO, 1, 1020, vec![0xAF, 1, 2])).await;
+        // Two tags in one aggregate message, 40ms apart on their own clock.
+        let mut aggregate = Vec::new();
+        for (tag_type, ts, body) in [(TAG_VIDEO, 90_000u32, vec![0x27, 1]), (TAG_AUDIO, 90_040, vec![0xAF, 3])] {
+            aggregate.extend_from_slice(&[tag_type, 0, 0, body.len() as u8, (ts >> 16) as u8, (ts >> 8) as u8, ts as u8, 0, 0, 0, 0]);
+            aggregate.extend_from_slice(&body);
+            aggregate.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+        }
+        send(&mut socket, &writer, 6, RtmpMessage::new(chunk::AGGREGATE, 1, 1040, aggregate)).await;
+        send(&mut socket, &writer, 5, status("NetStream.Play.Stop")).await;
+        let mut rest = Vec::new();
+        let _ = socket.read_to_end(&mut rest).await;
+    }
+
+    #[tokio::test]
+    async fn test_records_from_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve_one(listener));
+
+        let url = RtmpUrl::parse(&format!("rtmp://127.0.0.1:{}/live/camera1", port)).unwrap();
+        let socket = TcpStream::connect((url.host.as_str(), url.port)).await.unwrap();
+        let mut session = RtmpSession::start(socket, &url).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("rtmp-test-{}.flv", uuid::Uuid::new_v4()));
+        let mut flv = FlvWriter::open(&path).await.unwrap();
+        while let Some(message) = session.next_media().await.unwrap() {
+            flv.write_message(&message).await.unwrap();
+        }
+        flv.flush().await.unwrap();
+        session.close().await;
+        server.await.unwrap();
+
+        let tags = read_tags(&std::fs::read(&path).unwrap());
+        let summary: Vec<(u8, u32, usize)> = tags.iter().map(|(t, ts, body)| (*t, *ts, body.len())).collect();
+        assert_eq!(
+            summary,
+            vec![(TAG_SCRIPT, 0, 21), (TAG_VIDEO, 1000, 10_000), (TAG_AUDIO, 1020, 3), (TAG_VIDEO, 1040, 2), (TAG_AUDIO, 1080, 2)]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
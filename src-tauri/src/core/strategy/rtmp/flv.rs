@@ -0,0 +1,257 @@
+//! FLV output for RTMP recordings.
+//!
+//! RTMP audio, video and data messages carry exactly the bodies of FLV tags, so recording them
+//! is a matter of framing. Timestamps are rebased so a recording starts at 0; a resumed
+//! recording is appended after the last complete tag in the file and continues its timeline.
+
+use super::amf::{self, Amf0Value};
+use super::chunk::{RtmpMessage, AGGREGATE, AUDIO, DATA_AMF0, VIDEO};
+use crate::core::error::DownloadError;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tracing::{debug, info};
+
+pub const TAG_AUDIO: u8 = 8;
+pub const TAG_VIDEO: u8 = 9;
+pub const TAG_SCRIPT: u8 = 18;
+
+/// File header (audio and video present) followed by the first, zero PreviousTagSize.
+const HEADER: [u8; 13] = [b'F', b'L', b'V', 1, 0x05, 0, 0, 0, 9, 0, 0, 0, 0];
+const TAG_HEADER_SIZE: u64 = 11;
+
+pub struct FlvWriter {
+    writer: BufWriter<File>,
+    /// Where on the file's timeline this session's first message goes.
+    start: u32,
+    /// Timestamp of this session's first message.
+    base: Option<u32>,
+    resumed: bool,
+    bytes_written: u64,
+}
+
+impl FlvWriter {
+    /// Opens `path` for recording. A new or unrecognised file starts with a fresh FLV header;
+    /// an FLV recording from an earlier session is continued after its last complete tag.
+    pub async fn open(path: &Path) -> Result<Self, DownloadError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(path)
+            .await
+            .map_err(|e| DownloadError::Config(format!("Failed to create output file: {}", e)))?;
+
+        let resume = match last_complete_tag(&mut file).await? {
+            Some((end, timestamp)) => {
+                file.set_len(end).await?;
+                info!(path = ?path, timestamp, "Continuing FLV recording after its last tag");
+                Some(timestamp.saturating_add(1))
+            }
+            None => {
+                file.set_len(0).await?;
+                None
+            }
+        };
+        file.seek(SeekFrom::End(0)).await?;
+
+        let mut writer = BufWriter::new(file);
+        if resume.is_none() {
+            writer.write_all(&HEADER).await?;
+        }
+        Ok(Self { writer, start: resume.unwrap_or(0), base: None, resumed: resume.is_some(), bytes_written: 0 })
+    }
+
+    /// Bytes of tags written by this session.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Writes an audio, video, metadata or aggregate message; other messages are ignored.
+    pub async fn write_message(&mut self, message: &RtmpMessage) -> Result<(), DownloadError> {
+        match message.type_id {
+            AUDIO | VIDEO if !message.payload.is_empty() => self.write_tag(message.type_id, message.timestamp, &message.payload).await,
+            DATA_AMF0 => match script_data(&message.payload) {
+                Some(body) if !self.resumed => self.write_tag(TAG_SCRIPT, message.timestamp, &body).await,
+                _ => Ok(()),
+            },
+            AGGREGATE => {
+                for (tag_type, timestamp, body) in aggregate_tags(message)? {
+                    self.write_tag(tag_type, timestamp, body).await?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn write_tag(&mut self, tag_type: u8, timestamp: u32, body: &[u8]) -> Result<(), DownloadError> {
+        let base = *self.base.get_or_insert(timestamp);
+        let timestamp = self.start.wrapping_add((timestamp as i64 - base as i64).max(0) as u32);
+        let size = body.len() as u32;
+        let header = [
+            tag_type,
+            (size >> 16) as u8,
+            (size >> 8) as u8,
+            size as u8,
+            (timestamp >> 16) as u8,
+            (timestamp >> 8) as u8,
+            timestamp as u8,
+            (timestamp >> 24) as u8,
+            0,
+            0,
+            0,
+        ];
+        self.writer.write_all(&header).await?;
+        self.writer.write_all(body).await?;
+        self.writer.write_all(&(size + TAG_HEADER_SIZE as u32).to_be_bytes()).await?;
+        self.bytes_written += TAG_HEADER_SIZE + body.len() as u64 + 4;
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), DownloadError> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// The `onMetaData` script body of a data message, without the `@setDataFrame` wrapper that
+/// publishers add. Other data messages are not kept.
+fn script_data(payload: &[u8]) -> Option<Vec<u8>> {
+    let mut values = amf::decode(payload).ok()?;
+    if values.first().and_then(Amf0Value::as_str) == Some("@setDataFrame") {
+        values.remove(0);
+    }
+    (values.first().and_then(Amf0Value::as_str) == Some("onMetaData")).then(|| amf::encode(&values))
+}
+
+/// `(type, timestamp, body)` of one FLV tag.
+type Tag<'a> = (u8, u32, &'a [u8]);
+
+/// Splits an aggregate message into its FLV tags, shifting their timestamps so the first one
+/// lands on the message's own timestamp.
+fn aggregate_tags(message: &RtmpMessage) -> Result<Vec<Tag<'_>>, DownloadError> {
+    let data = &message.payload;
+    let mut tags = Vec::new();
+    let mut first_timestamp = None;
+    let mut pos = 0;
+    while pos + TAG_HEADER_SIZE as usize <= data.len() {
+        let header = &data[pos..pos + TAG_HEADER_SIZE as usize];
+        let size = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+        let body_start = pos + TAG_HEADER_SIZE as usize;
+        let body = data
+            .get(body_start..body_start + size)
+            .ok_or_else(|| DownloadError::Parse("Truncated tag in RTMP aggregate message".to_string()))?;
+        let first = *first_timestamp.get_or_insert(timestamp);
+        if matches!(header[0], TAG_AUDIO | TAG_VIDEO) && !body.is_empty() {
+            tags.push((header[0], message.timestamp.wrapping_add(timestamp.wrapping_sub(first)), body));
+        }
+        pos = body_start + size + 4;
+    }
+    Ok(tags)
+}
+
+/// End offset and timestamp of the last complete tag of an FLV file, or `None` if the file is
+/// not an FLV file with at least one tag.
+async fn last_complete_tag(file: &mut File) -> Result<Option<(u64, u32)>, DownloadError> {
+    let len = file.metadata().await?.len();
+    let mut signature = [0u8; 3];
+    if len <= HEADER.len() as u64 || file.read_exact(&mut signature).await.is_err() || &signature != b"FLV" {
+        return Ok(None);
+    }
+
+    // The trailing PreviousTagSize points back at the last tag when the file ends cleanly.
+    file.seek(SeekFrom::Start(len - 4)).await?;
+    let previous_size = file.read_u32().await? as u64;
+    if previous_size >= TAG_HEADER_SIZE && len >= HEADER.len() as u64 + previous_size + 4 {
+        let start = len - 4 - previous_size;
+        if let Some((tag_type, size, timestamp)) = read_tag_header(file, start).await? {
+            if matches!(tag_type, TAG_AUDIO | TAG_VIDEO | TAG_SCRIPT) && size + TAG_HEADER_SIZE == previous_size {
+                return Ok(Some((len, timestamp)));
+            }
+        }
+    }
+
+    // Cut short mid-tag: walk the tags from the front.
+    debug!("FLV file does not end on a tag boundary, scanning its tags");
+    let mut pos = HEADER.len() as u64;
+    let mut last = None;
+    while let Some((_, size, timestamp)) = read_tag_header(file, pos).await? {
+        let end = pos + TAG_HEADER_SIZE + size + 4;
+        if end > len {
+            break;
+        }
+        last = Some((end, timestamp));
+        pos = end;
+    }
+    Ok(last)
+}
+
+async fn read_tag_header(file: &mut File, offset: u64) -> Result<Option<(u8, u64, u32)>, DownloadError> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut header = [0u8; TAG_HEADER_SIZE as usize];
+    if file.read_exact(&mut header).await.is_err() {
+        return Ok(None);
+    }
+    let size = (header[1] as u64) << 16 | (header[2] as u64) << 8 | header[3] as u64;
+    let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+    Ok(Some((header[0], size, timestamp)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `(type, timestamp, body)` of every tag in an FLV file.
+    pub(crate) fn read_tags(data: &[u8]) -> Vec<(u8, u32, Vec<u8>)> {
+        assert_eq!(&data[..HEADER.len()], &HEADER);
+        let mut tags = Vec::new();
+        let mut pos = HEADER.len();
+        while pos < data.len() {
+            let size = (data[pos + 1] as usize) << 16 | (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+            let timestamp = u32::from_be_bytes([data[pos + 7], data[pos + 4], data[pos + 5], data[pos + 6]]);
+            let body = data[pos + 11..pos + 11 + size].to_vec();
+            let previous = u32::from_be_bytes(data[pos + 11 + size..pos + 15 + size].try_into().unwrap());
+            assert_eq!(previous as usize, size + 11);
+            tags.push((data[pos], timestamp, body));
+            pos += 15 + size;
+        }
+        tags
+    }
+
+    #[tokio::test]
+    async fn test_resume_appends_after_last_complete_tag() {
+        let path = std::env::temp_dir().join(format!("flv-test-{}.flv", uuid::Uuid::new_v4()));
+        let mut flv = FlvWriter::open(&path).await.unwrap();
+        let metadata = amf::encode(&[
+            Amf0Value::String("@setDataFrame".to_string()),
+            Amf0Value::String("onMetaData".to_string()),
+            Amf0Value::EcmaArray(vec![("width".to_string(), Amf0Value::Number(640.0))]),
+        ]);
+        flv.write_message(&RtmpMessage::new(DATA_AMF0, 1, 5000, metadata)).await.unwrap();
+        flv.write_message(&RtmpMessage::new(VIDEO, 1, 5000, vec![0x17, 0, 1])).await.unwrap();
+        flv.write_message(&RtmpMessage::new(AUDIO, 1, 5040, vec![0xAF, 1])).await.unwrap();
+        flv.flush().await.unwrap();
+        drop(flv);
+
+        // A tag cut off by a crash is dropped on resume.
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&[TAG_VIDEO, 0, 0, 50, 0, 0]);
+        std::fs::write(&path, &data).unwrap();
+
+        let mut flv = FlvWriter::open(&path).await.unwrap();
+        flv.write_message(&RtmpMessage::new(DATA_AMF0, 1, 100, amf::encode(&[Amf0Value::String("onMetaData".to_string())]))).await.unwrap();
+        flv.write_message(&RtmpMessage::new(VIDEO, 1, 100, vec![0x17, 0, 2])).await.unwrap();
+        flv.flush().await.unwrap();
+        drop(flv);
+
+        let tags = read_tags(&std::fs::read(&path).unwrap());
+        let summary: Vec<(u8, u32)> = tags.iter().map(|(t, ts, _)| (*t, *ts)).collect();
+        assert_eq!(summary, vec![(TAG_SCRIPT, 0), (TAG_VIDEO, 0), (TAG_AUDIO, 40), (TAG_VIDEO, 41)]);
+        assert_eq!(amf::decode(&tags[0].2).unwrap()[0], Amf0Value::String("onMetaData".to_string()));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,115 @@
+//! RTMP recording strategy.
+//!
+//! Connects to an `rtmp://` or `rtmps://` URL, plays the stream and writes its audio, video and
+//! metadata messages to an FLV file as they arrive. Pausing or stopping closes the connection;
+//! resuming plays the stream again and appends to the recording.
+
+pub mod amf;
+pub mod chunk;
+pub mod client;
+pub mod flv;
+pub mod handshake;
+
+use super::stream::downloader::wait_for_signal;
+use super::{DownloadContext, DownloadStrategy};
+use crate::commands::DownloadCommandResult;
+use crate::core::error::DownloadError;
+use client::{RtmpSession, RtmpUrl};
+use flv::FlvWriter;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tracing::info;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct RtmpStrategy;
+
+/// How a recording session ended.
+enum Recording {
+    Ended,
+    Interrupted(u8),
+}
+
+#[async_trait::async_trait]
+impl DownloadStrategy for RtmpStrategy {
+    async fn execute(&self, context: &DownloadContext) -> Result<DownloadCommandResult, DownloadError> {
+        let url = RtmpUrl::parse(&context.metadata.url)?;
+        info!(download_id = %context.download_id, "Recording RTMP stream {} from {}", url.stream, url.tc_url);
+
+        let socket = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((url.host.as_str(), url.port)))
+            .await
+            .map_err(|_| DownloadError::Network(format!("Timed out connecting to {}:{}", url.host, url.port)))??;
+        socket.set_nodelay(true)?;
+
+        let outcome = if url.tls {
+            let connector = tokio_native_tls::native_tls::TlsConnector::new()
+                .map_err(|e| DownloadError::Network(format!("TLS setup failed: {}", e)))?;
+            let stream = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&url.host, socket)
+                .await
+                .map_err(|e| DownloadError::Network(format!("TLS handshake with {} failed: {}", url.host, e)))?;
+            record(context, stream, &url).await?
+        } else {
+            record(context, socket, &url).await?
+        };
+
+        let status = match outcome {
+            Recording::Ended => {
+                let bytes = context.control.downloaded_bytes.load(Ordering::Relaxed);
+                info!(download_id = %context.download_id, bytes, "RTMP recording finished");
+                context.complete(bytes).await;
+                "completed"
+            }
+            Recording::Interrupted(1) => "paused",
+            Recording::Interrupted(2) => "stopped",
+            Recording::Interrupted(_) => "cancelled",
+        };
+        Ok(DownloadCommandResult {
+            id: context.download_id.clone(),
+            status: status.to_string(),
+        })
+    }
+}
+
+/// Plays the stream and appends it to the output until it ends or the user interrupts it.
+async fn record<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    context: &DownloadContext,
+    stream: S,
+    url: &RtmpUrl,
+) -> Result<Recording, DownloadError> {
+    let mut session = RtmpSession::start(stream, url).await?;
+    let mut flv = FlvWriter::open(Path::new(&context.metadata.filepath)).await?;
+    // A resumed recording counts on from what earlier sessions wrote.
+    let previous = context.metadata.downloaded_bytes;
+    let mut last_report = Instant::now();
+
+    let signal = context.signal();
+    let outcome = loop {
+        let message = tokio::select! {
+            message = session.next_media() => message,
+            signal = wait_for_signal(&signal) => break Recording::Interrupted(signal),
+        };
+        match message {
+            Ok(Some(message)) => flv.write_message(&message).await?,
+            Ok(None) => break Recording::Ended,
+            Err(e) => {
+                flv.flush().await?;
+                return Err(e);
+            }
+        }
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            context.report_progress(previous + flv.bytes_written()).await;
+        }
+    };
+
+    session.close().await;
+    flv.flush().await?;
+    context.report_progress(previous + flv.bytes_written()).await;
+    Ok(outcome)
+}
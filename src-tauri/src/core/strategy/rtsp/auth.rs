@@ -0,0 +1,165 @@
+//! RTSP authentication: Basic, and Digest (RFC 2617) as most IP cameras require.
+
+use base64::Engine;
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Challenge {
+    Basic,
+    Digest {
+        realm: String,
+        nonce: String,
+        opaque: Option<String>,
+        /// Whether the server offered `qop=auth`.
+        qop_auth: bool,
+    },
+}
+
+/// Answers the server's `WWW-Authenticate` challenge on every request.
+#[derive(Debug)]
+pub struct Authenticator {
+    credentials: Credentials,
+    challenge: Option<Challenge>,
+    nonce_count: u32,
+    cnonce: String,
+}
+
+impl Authenticator {
+    pub fn new(credentials: Credentials) -> Self {
+        let cnonce = format!("{:016x}", uuid::Uuid::new_v4().as_u128() as u64);
+        Self { credentials, challenge: None, nonce_count: 0, cnonce }
+    }
+
+    /// Takes the challenge from the `WWW-Authenticate` headers of a 401 response, preferring
+    /// Digest. Returns `false` if none is supported or the same challenge already failed.
+    pub fn set_challenge<'a>(&mut self, headers: impl IntoIterator<Item = &'a str>) -> bool {
+        let mut basic = None;
+        let mut digest = None;
+        for header in headers {
+            let (scheme, params) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+            if scheme.eq_ignore_ascii_case("basic") {
+                basic = Some(Challenge::Basic);
+            } else if scheme.eq_ignore_ascii_case("digest") {
+                let params = parse_params(params);
+                let algorithm = params.get("algorithm").map(|a| a.to_ascii_uppercase());
+                if algorithm.is_some_and(|a| a != "MD5") {
+                    continue;
+                }
+                let (Some(realm), Some(nonce)) = (params.get("realm"), params.get("nonce")) else { continue };
+                digest = Some(Challenge::Digest {
+                    realm: realm.clone(),
+                    nonce: nonce.clone(),
+                    opaque: params.get("opaque").cloned(),
+                    qop_auth: params.get("qop").is_some_and(|q| q.split(',').any(|v| v.trim() == "auth")),
+                });
+            }
+        }
+
+        let Some(challenge) = digest.or(basic) else { return false };
+        // A repeated challenge means the credentials were wrong; only a new (stale) nonce is retried.
+        if self.challenge.as_ref() == Some(&challenge) {
+            return false;
+        }
+        self.challenge = Some(challenge);
+        self.nonce_count = 0;
+        true
+    }
+
+    /// `Authorization` header value for a request, once a challenge was received.
+    pub fn header(&mut self, method: &str, uri: &str) -> Option<String> {
+        let Credentials { username, password } = &self.credentials;
+        match self.challenge.as_ref()? {
+            Challenge::Basic => {
+                let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+                Some(format!("Basic {}", token))
+            }
+            Challenge::Digest { realm, nonce, opaque, qop_auth } => {
+                let ha1 = md5_hex(&format!("{}:{}:{}", username, realm, password));
+                let ha2 = md5_hex(&format!("{}:{}", method, uri));
+                let mut header = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\"", username, realm, nonce, uri);
+                let response = if *qop_auth {
+                    self.nonce_count += 1;
+                    let nc = format!("{:08x}", self.nonce_count);
+                    header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, self.cnonce));
+                    md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, self.cnonce, ha2))
+                } else {
+                    md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2))
+                };
+                header.push_str(&format!(", response=\"{}\"", response));
+                if let Some(opaque) = opaque {
+                    header.push_str(&format!(", opaque=\"{}\"", opaque));
+                }
+                Some(header)
+            }
+        }
+    }
+}
+
+fn md5_hex(value: &str) -> String {
+    format!("{:x}", Md5::digest(value.as_bytes()))
+}
+
+/// `key=value` pairs of an authentication header; values may be quoted and contain commas.
+fn parse_params(params: &str) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else { break };
+        let key = key.trim().trim_start_matches(',').trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match after.find(',') {
+                Some(end) => (after[..end].trim(), &after[end..]),
+                None => (after.trim(), ""),
+            },
+        };
+        out.insert(key, value.to_string());
+        rest = remaining.trim_start().trim_start_matches(',').trim_start();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(Credentials { username: "Mufasa".to_string(), password: "Circle Of Life".to_string() })
+    }
+
+    #[test]
+    fn test_digest_and_basic_authorization() {
+        // RFC 2617, section 3.5.
+        let mut auth = authenticator();
+        auth.cnonce = "0a4f113b".to_string();
+        assert!(auth.set_challenge([
+            "Basic realm=\"testrealm@host.com\"",
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        ]));
+        let header = auth.header("GET", "/dir/index.html").unwrap();
+        assert!(header.starts_with("Digest username=\"Mufasa\", realm=\"testrealm@host.com\""));
+        assert!(header.contains("nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(header.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(header.ends_with("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+        assert!(auth.header("GET", "/dir/index.html").unwrap().contains("nc=00000002"));
+
+        // The same challenge again means the password was rejected.
+        assert!(!auth.set_challenge(["Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""]));
+
+        let mut auth = authenticator();
+        assert!(auth.set_challenge(["Basic realm=\"camera\""]));
+        assert_eq!(auth.header("DESCRIBE", "rtsp://cam/stream").unwrap(), "Basic TXVmYXNhOkNpcmNsZSBPZiBMaWZl");
+        assert!(!authenticator().set_challenge(["Bearer realm=\"x\""]));
+    }
+}
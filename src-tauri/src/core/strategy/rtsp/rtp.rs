@@ -0,0 +1,328 @@
+//! RTP packets and depacketizers for H.264 (RFC 6184), H.265 (RFC 7798) and AAC (RFC 3640).
+//!
+//! Video comes out as access units of 4-byte length-prefixed NAL units, audio as raw AAC
+//! frames, each with the RTP timestamp it belongs to.
+
+use crate::core::strategy::stream::mux::codec;
+use tracing::debug;
+
+/// Samples per AAC frame, the RTP timestamp step between consecutive frames.
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtpPacket<'a> {
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub payload: &'a [u8],
+}
+
+impl<'a> RtpPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < 12 || data[0] >> 6 != 2 {
+            return None;
+        }
+        let csrc_count = (data[0] & 0x0F) as usize;
+        let mut start = 12 + 4 * csrc_count;
+        if data[0] & 0x10 != 0 {
+            let words = u16::from_be_bytes([*data.get(start + 2)?, *data.get(start + 3)?]) as usize;
+            start += 4 + 4 * words;
+        }
+        let mut end = data.len();
+        if data[0] & 0x20 != 0 {
+            end = end.checked_sub(*data.last()? as usize)?;
+        }
+        Some(Self {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7F,
+            sequence: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            payload: data.get(start..end)?,
+        })
+    }
+}
+
+/// A complete access unit or audio frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub timestamp: u32,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+/// `(vps, sps, pps)` NAL units; H.264 has no VPS.
+pub type ParameterSets<'a> = (Option<&'a [u8]>, Option<&'a [u8]>, Option<&'a [u8]>);
+
+/// Reassembles H.264 or H.265 access units from single NAL unit, aggregation and
+/// fragmentation packets.
+#[derive(Debug)]
+pub struct NalDepacketizer {
+    hevc: bool,
+    nals: Vec<Vec<u8>>,
+    timestamp: u32,
+    fragment: Option<Vec<u8>>,
+    next_sequence: Option<u16>,
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl NalDepacketizer {
+    pub fn new(hevc: bool) -> Self {
+        Self { hevc, nals: Vec::new(), timestamp: 0, fragment: None, next_sequence: None, vps: None, sps: None, pps: None }
+    }
+
+    /// Latest `(vps, sps, pps)` seen in the stream or passed in from the SDP.
+    pub fn parameter_sets(&self) -> ParameterSets<'_> {
+        (self.vps.as_deref(), self.sps.as_deref(), self.pps.as_deref())
+    }
+
+    /// Records parameter sets announced out of band (`sprop-parameter-sets`, `sprop-sps`...).
+    pub fn add_parameter_set(&mut self, nal: &[u8]) {
+        match self.nal_type(nal) {
+            Some(32) if self.hevc => self.vps = Some(nal.to_vec()),
+            Some(33) if self.hevc => self.sps = Some(nal.to_vec()),
+            Some(34) if self.hevc => self.pps = Some(nal.to_vec()),
+            Some(7) if !self.hevc => self.sps = Some(nal.to_vec()),
+            Some(8) if !self.hevc => self.pps = Some(nal.to_vec()),
+            _ => {}
+        }
+    }
+
+    /// Feeds one packet; returns the access units it completed.
+    pub fn push(&mut self, packet: &RtpPacket) -> Vec<Frame> {
+        let mut done = Vec::new();
+        if packet.timestamp != self.timestamp {
+            // The marker bit of the previous access unit's last packet went missing.
+            done.extend(self.take_access_unit());
+        }
+        if self.next_sequence.is_some_and(|next| next != packet.sequence) && self.fragment.take().is_some() {
+            debug!("RTP: Lost part of a fragmented NAL unit before sequence {}", packet.sequence);
+        }
+        self.next_sequence = Some(packet.sequence.wrapping_add(1));
+        self.timestamp = packet.timestamp;
+
+        self.depacketize(packet.payload);
+        if packet.marker {
+            done.extend(self.take_access_unit());
+        }
+        done
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some(packet_type) = self.nal_type(payload) else { return };
+        let header_len = if self.hevc { 2 } else { 1 };
+        match (self.hevc, packet_type) {
+            // STAP-A / AP: 16-bit size before every NAL unit.
+            (false, 24) | (true, 48) => {
+                let mut rest = &payload[header_len.min(payload.len())..];
+                while rest.len() >= 2 {
+                    let size = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    let Some(nal) = rest.get(2..2 + size) else { break };
+                    self.add_nal(nal.to_vec());
+                    rest = &rest[2 + size..];
+                }
+            }
+            // FU-A / FU: the original NAL header is rebuilt from the FU header's type.
+            (false, 28) | (true, 49) => {
+                let Some(&fu_header) = payload.get(header_len) else { return };
+                let (start, end, nal_type) = (fu_header & 0x80 != 0, fu_header & 0x40 != 0, fu_header & 0x3F);
+                let data = &payload[header_len + 1..];
+                if start {
+                    let mut nal = if self.hevc {
+                        vec![(payload[0] & 0x81) | (nal_type << 1), payload[1]]
+                    } else {
+                        vec![(payload[0] & 0xE0) | (nal_type & 0x1F)]
+                    };
+                    nal.extend_from_slice(data);
+                    self.fragment = Some(nal);
+                } else if let Some(fragment) = self.fragment.as_mut() {
+                    fragment.extend_from_slice(data);
+                }
+                if end {
+                    if let Some(nal) = self.fragment.take() {
+                        self.add_nal(nal);
+                    }
+                }
+            }
+            _ => self.add_nal(payload.to_vec()),
+        }
+    }
+
+    fn add_nal(&mut self, nal: Vec<u8>) {
+        self.add_parameter_set(&nal);
+        self.nals.push(nal);
+    }
+
+    fn take_access_unit(&mut self) -> Option<Frame> {
+        if self.nals.is_empty() {
+            return None;
+        }
+        let nals = std::mem::take(&mut self.nals);
+        let keyframe = nals.iter().any(|n| match self.nal_type(n) {
+            Some(t) if self.hevc => (16..=21).contains(&t),
+            Some(t) => t == 5,
+            None => false,
+        });
+        let aud = if self.hevc { 35 } else { 9 };
+        let refs: Vec<&[u8]> = nals.iter().map(Vec::as_slice).collect();
+        let data = codec::nals_to_length_prefixed(&refs, |n| self.nal_type(n) == Some(aud));
+        Some(Frame { timestamp: self.timestamp, keyframe, data })
+    }
+
+    fn nal_type(&self, nal: &[u8]) -> Option<u8> {
+        let header = *nal.first()?;
+        Some(if self.hevc { (header >> 1) & 0x3F } else { header & 0x1F })
+    }
+}
+
+/// Splits RFC 3640 (`mode=AAC-hbr` / `AAC-lbr`) payloads into AAC frames.
+#[derive(Debug)]
+pub struct AacDepacketizer {
+    size_length: u32,
+    index_length: u32,
+    index_delta_length: u32,
+    /// A frame larger than one packet, collected until the packet with the marker bit.
+    fragment: Option<(u32, Vec<u8>, usize)>,
+}
+
+impl AacDepacketizer {
+    pub fn new(size_length: u32, index_length: u32, index_delta_length: u32) -> Self {
+        Self { size_length, index_length, index_delta_length, fragment: None }
+    }
+
+    pub fn push(&mut self, packet: &RtpPacket) -> Vec<Frame> {
+        let payload = packet.payload;
+        if payload.len() < 2 || self.size_length == 0 {
+            return Vec::new();
+        }
+        let headers_bits = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        let headers_len = headers_bits.div_ceil(8);
+        let Some(headers) = payload.get(2..2 + headers_len) else { return Vec::new() };
+        let mut data = &payload[2 + headers_len..];
+
+        let mut reader = codec::BitReader::new(headers);
+        let mut sizes = Vec::new();
+        let mut bits_read = 0usize;
+        while bits_read < headers_bits {
+            let index_bits = if sizes.is_empty() { self.index_length } else { self.index_delta_length };
+            let (Some(size), Some(_)) = (reader.read_bits(self.size_length), reader.read_bits(index_bits)) else { break };
+            sizes.push(size as usize);
+            bits_read += (self.size_length + index_bits) as usize;
+        }
+
+        // One AU header whose frame continues in the next packets.
+        if let Some((timestamp, mut frame, size)) = self.fragment.take() {
+            if timestamp == packet.timestamp {
+                frame.extend_from_slice(data);
+                if frame.len() < size && !packet.marker {
+                    self.fragment = Some((timestamp, frame, size));
+                    return Vec::new();
+                }
+                return vec![Frame { timestamp, keyframe: true, data: frame }];
+            }
+        }
+        if sizes.len() == 1 && sizes[0] > data.len() {
+            self.fragment = Some((packet.timestamp, data.to_vec(), sizes[0]));
+            return Vec::new();
+        }
+
+        let mut frames = Vec::with_capacity(sizes.len());
+        for (i, size) in sizes.into_iter().enumerate() {
+            let Some(frame) = data.get(..size) else { break };
+            frames.push(Frame {
+                timestamp: packet.timestamp.wrapping_add(i as u32 * AAC_FRAME_SAMPLES),
+                keyframe: true,
+                data: frame.to_vec(),
+            });
+            data = &data[size..];
+        }
+        frames
+    }
+}
+
+/// Extends 32-bit RTP timestamps to a continuous 64-bit clock.
+#[derive(Debug, Default)]
+pub struct RtpClock {
+    last: Option<(u32, i64)>,
+}
+
+impl RtpClock {
+    pub fn extend(&mut self, timestamp: u32) -> i64 {
+        let extended = match self.last {
+            Some((raw, extended)) => extended + timestamp.wrapping_sub(raw) as i32 as i64,
+            None => timestamp as i64,
+        };
+        self.last = Some((timestamp, extended));
+        extended
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An RTP packet with a 12-byte header.
+    pub(crate) fn rtp(payload_type: u8, sequence: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, payload_type | if marker { 0x80 } else { 0 }];
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&0x1234_5678u32.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn push(depacketizer: &mut NalDepacketizer, sequence: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<Frame> {
+        let data = rtp(96, sequence, timestamp, marker, payload);
+        depacketizer.push(&RtpPacket::parse(&data).unwrap())
+    }
+
+    #[test]
+    fn test_h264_stap_a_and_fu_a() {
+        let mut h264 = NalDepacketizer::new(false);
+        // STAP-A with SPS and PPS, then an IDR slice in three FU-A fragments.
+        let stap = [0x18, 0, 3, 0x67, 0x42, 0x00, 0, 2, 0x68, 0xCE];
+        assert_eq!(push(&mut h264, 1, 3000, false, &stap), vec![]);
+        assert_eq!(push(&mut h264, 2, 3000, false, &[0x7C, 0x85, 1, 2]), vec![]);
+        assert_eq!(push(&mut h264, 3, 3000, false, &[0x7C, 0x05, 3, 4]), vec![]);
+        let frame = push(&mut h264, 4, 3000, true, &[0x7C, 0x45, 5]).remove(0);
+        assert!(frame.keyframe);
+        assert_eq!(frame.timestamp, 3000);
+        assert_eq!(frame.data, [&[0, 0, 0, 3, 0x67, 0x42, 0x00][..], &[0, 0, 0, 2, 0x68, 0xCE], &[0, 0, 0, 6, 0x65, 1, 2, 3, 4, 5]].concat());
+        assert_eq!(h264.parameter_sets(), (None, Some(&[0x67, 0x42, 0x00][..]), Some(&[0x68, 0xCE][..])));
+
+        // A lost fragment drops that NAL unit; a lost marker is noticed on the next timestamp.
+        assert_eq!(push(&mut h264, 5, 6000, false, &[0x7C, 0x81, 1]), vec![]);
+        assert_eq!(push(&mut h264, 7, 6000, false, &[0x41, 9]), vec![]);
+        let frames = push(&mut h264, 8, 9000, true, &[0x41, 10]);
+        let summary: Vec<(u32, bool, Vec<u8>)> = frames.into_iter().map(|f| (f.timestamp, f.keyframe, f.data)).collect();
+        assert_eq!(summary, vec![(6000, false, vec![0, 0, 0, 2, 0x41, 9]), (9000, false, vec![0, 0, 0, 2, 0x41, 10])]);
+    }
+
+    #[test]
+    fn test_h265_fragmentation_unit() {
+        let mut h265 = NalDepacketizer::new(true);
+        // FU (type 49) carrying an IDR_W_RADL (type 19) NAL unit.
+        assert_eq!(push(&mut h265, 10, 0, false, &[0x62, 0x01, 0x93, 0xAA]), vec![]);
+        let frame = push(&mut h265, 11, 0, true, &[0x62, 0x01, 0x53, 0xBB]).remove(0);
+        assert!(frame.keyframe);
+        assert_eq!(frame.data, vec![0, 0, 0, 4, 0x26, 0x01, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn test_aac_hbr_frames() {
+        let mut aac = AacDepacketizer::new(13, 3, 3);
+        // Two AU headers (16 bits each): sizes 3 and 2.
+        let payload = [0x00, 0x20, 0x00, 0x18, 0x00, 0x10, 1, 2, 3, 4, 5];
+        let packet = rtp(97, 1, 16_000, true, &payload);
+        let frames = aac.push(&RtpPacket::parse(&packet).unwrap());
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].timestamp, frames[0].data.as_slice()), (16_000, &[1, 2, 3][..]));
+        assert_eq!((frames[1].timestamp, frames[1].data.as_slice()), (17_024, &[4, 5][..]));
+
+        let mut clock = RtpClock::default();
+        assert_eq!(clock.extend(u32::MAX - 99), u32::MAX as i64 - 99);
+        assert_eq!(clock.extend(100), u32::MAX as i64 + 101);
+    }
+}
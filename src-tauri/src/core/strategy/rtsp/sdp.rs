@@ -0,0 +1,151 @@
+//! Session descriptions (SDP) returned by RTSP `DESCRIBE`.
+
+use crate::core::error::DownloadError;
+use std::collections::HashMap;
+
+/// One `m=` section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaDescription {
+    /// `video`, `audio`, `application`...
+    pub media: String,
+    pub payload_type: u8,
+    /// Encoding name from `a=rtpmap`, upper-cased (`H264`, `H265`, `MPEG4-GENERIC`).
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+    /// `a=fmtp` parameters, keys lower-cased.
+    pub fmtp: HashMap<String, String>,
+    pub control: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionDescription {
+    pub control: Option<String>,
+    pub media: Vec<MediaDescription>,
+}
+
+impl SessionDescription {
+    pub fn parse(text: &str) -> Result<Self, DownloadError> {
+        let mut session = SessionDescription::default();
+        if !text.trim_start().starts_with("v=") {
+            return Err(DownloadError::Parse("Not a session description".to_string()));
+        }
+
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let Some((kind, value)) = line.split_once('=') else { continue };
+            match kind {
+                "m" => {
+                    let mut fields = value.split_whitespace();
+                    let media = fields.next().unwrap_or_default().to_ascii_lowercase();
+                    // Port and transport profile are not used over interleaved TCP.
+                    let payload_type = fields.nth(2).and_then(|pt| pt.parse().ok()).unwrap_or(0);
+                    session.media.push(MediaDescription {
+                        media,
+                        payload_type,
+                        encoding: static_encoding(payload_type).to_string(),
+                        clock_rate: if payload_type < 96 { 8000 } else { 90_000 },
+                        channels: None,
+                        fmtp: HashMap::new(),
+                        control: None,
+                    });
+                }
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    match session.media.last_mut() {
+                        None if name == "control" => session.control = Some(value.trim().to_string()),
+                        None => {}
+                        Some(media) => media.apply_attribute(name, value.trim()),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+}
+
+impl MediaDescription {
+    fn apply_attribute(&mut self, name: &str, value: &str) {
+        let for_payload = |value: &str| {
+            let (pt, rest) = value.split_once(' ')?;
+            (pt.parse::<u8>().ok()? == self.payload_type).then(|| rest.trim().to_string())
+        };
+        match name {
+            "control" => self.control = Some(value.to_string()),
+            "rtpmap" => {
+                let Some(map) = for_payload(value) else { return };
+                let mut parts = map.split('/');
+                self.encoding = parts.next().unwrap_or_default().to_ascii_uppercase();
+                if let Some(rate) = parts.next().and_then(|r| r.parse().ok()) {
+                    self.clock_rate = rate;
+                }
+                self.channels = parts.next().and_then(|c| c.parse().ok());
+            }
+            "fmtp" => {
+                let Some(params) = for_payload(value) else { return };
+                for param in params.split(';') {
+                    if let Some((key, value)) = param.split_once('=') {
+                        self.fmtp.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Encoding names of the static payload types that can appear without `a=rtpmap`.
+fn static_encoding(payload_type: u8) -> &'static str {
+    match payload_type {
+        0 => "PCMU",
+        8 => "PCMA",
+        14 => "MPA",
+        26 => "JPEG",
+        33 => "MP2T",
+        _ => "",
+    }
+}
+
+/// URL for a track's `SETUP`: its control attribute resolved against the session's base URL.
+pub fn control_url(base: &str, control: Option<&str>) -> String {
+    match control {
+        None | Some("*") | Some("") => base.to_string(),
+        Some(control) if control.contains("://") => control.to_string(),
+        Some(control) if base.ends_with('/') => format!("{}{}", base, control),
+        Some(control) => format!("{}/{}", base, control),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_camera_sdp() {
+        let sdp = "v=0\r\no=- 1 1 IN IP4 192.168.1.64\r\ns=Session streamed by camera\r\nt=0 0\r\na=control:*\r\n\
+            m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+            a=fmtp:96 packetization-mode=1; profile-level-id=420029; sprop-parameter-sets=Z0IAKeKQFAe2AtwEBAaQeJEV,aM48gA==\r\n\
+            a=control:trackID=1\r\n\
+            m=audio 0 RTP/AVP 97\r\na=rtpmap:97 MPEG4-GENERIC/16000/1\r\n\
+            a=fmtp:97 streamtype=5;profile-level-id=15;mode=AAC-hbr;config=1408;SizeLength=13;IndexLength=3;IndexDeltaLength=3\r\n\
+            a=control:rtsp://192.168.1.64/Streaming/Channels/101/trackID=2\r\n\
+            m=application 0 RTP/AVP 107\r\na=rtpmap:107 vnd.onvif.metadata/90000\r\n";
+        let session = SessionDescription::parse(sdp).unwrap();
+        assert_eq!(session.control.as_deref(), Some("*"));
+        assert_eq!(session.media.len(), 3);
+
+        let video = &session.media[0];
+        assert_eq!((video.media.as_str(), video.payload_type, video.encoding.as_str(), video.clock_rate), ("video", 96, "H264", 90_000));
+        assert_eq!(video.fmtp.get("sprop-parameter-sets").map(String::as_str), Some("Z0IAKeKQFAe2AtwEBAaQeJEV,aM48gA=="));
+        assert_eq!(control_url("rtsp://192.168.1.64/Streaming/Channels/101/", video.control.as_deref()), "rtsp://192.168.1.64/Streaming/Channels/101/trackID=1");
+
+        let audio = &session.media[1];
+        assert_eq!((audio.encoding.as_str(), audio.clock_rate, audio.channels), ("MPEG4-GENERIC", 16_000, Some(1)));
+        assert_eq!(audio.fmtp.get("sizelength").map(String::as_str), Some("13"));
+        assert_eq!(control_url("rtsp://ignored/", audio.control.as_deref()), "rtsp://192.168.1.64/Streaming/Channels/101/trackID=2");
+        assert_eq!(session.media[2].encoding, "VND.ONVIF.METADATA");
+
+        assert_eq!(control_url("rtsp://cam/live", Some("*")), "rtsp://cam/live");
+        assert!(SessionDescription::parse("<html>").is_err());
+    }
+}
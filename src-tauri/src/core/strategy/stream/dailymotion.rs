@@ -0,0 +1,149 @@
+//! Dailymotion resolution.
+//!
+//! The player reads `dailymotion.com/player/metadata/video/<id>`, whose `qualities` map lists
+//! an adaptive HLS manifest under `auto` and, for some videos, MP4 files per height. Errors
+//! (geo-blocking, private or deleted videos) come back in the same document.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::platform::{best_progressive, PlatformSource};
+use super::resolver::{fetch_manifest, ResolvedStream, ResolverProbe, StreamResolver};
+use reqwest::Client;
+use serde_json::Value;
+use tracing::info;
+use url::Url;
+
+pub struct DailymotionResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for DailymotionResolver {
+    fn name(&self) -> &'static str {
+        "dailymotion"
+    }
+
+    fn matches(&self, url: &str, _probe: &ResolverProbe) -> bool {
+        video_id(url).is_some()
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn is_platform(&self) -> bool {
+        true
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        let id = video_id(url).ok_or_else(|| DownloadError::Config(format!("Not a Dailymotion video link: {}", url)))?;
+        info!("Dailymotion Resolver: Fetching player metadata for video {}", id);
+        let metadata_url = format!("https://www.dailymotion.com/player/metadata/video/{}", id);
+        let metadata: Value = serde_json::from_str(&fetch_manifest(&metadata_url, client, headers).await?)
+            .map_err(|e| DownloadError::Parse(format!("Invalid Dailymotion metadata: {}", e)))?;
+        let (source, duration) = parse_metadata(&metadata, options.max_height)?;
+        info!("Dailymotion Resolver: Using {:?}", source);
+        source.resolve(client, headers, options, duration).await
+    }
+}
+
+/// Video id (`x` followed by letters and digits) of a Dailymotion link.
+pub fn video_id(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.");
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let candidate = match host {
+        "dai.ly" => segments.first()?.to_string(),
+        "dailymotion.com" => {
+            let position = segments.iter().position(|s| *s == "video")?;
+            segments.get(position + 1)?.to_string()
+        }
+        "geo.dailymotion.com" => url.query_pairs().find(|(k, _)| k == "video")?.1.into_owned(),
+        _ => return None,
+    };
+    // Links may carry a slug after the id: `/video/x8j6n2c_some-title`.
+    let id = candidate.split('_').next()?.to_string();
+    (id.len() > 1 && id.starts_with('x') && id.bytes().all(|b| b.is_ascii_alphanumeric())).then_some(id)
+}
+
+/// Picks a source from player metadata; also returns the video duration in seconds.
+pub fn parse_metadata(metadata: &Value, max_height: Option<u32>) -> Result<(PlatformSource, Option<f64>), DownloadError> {
+    if let Some(error) = metadata.get("error") {
+        let text = |key: &str| error.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+        return Err(DownloadError::Config(format!("Dailymotion refused the video: {} {}", text("title"), text("message")).trim().to_string()));
+    }
+    let qualities = metadata
+        .get("qualities")
+        .and_then(Value::as_object)
+        .ok_or_else(|| DownloadError::Parse("Dailymotion metadata lists no qualities".to_string()))?;
+    let duration = metadata.get("duration").and_then(Value::as_f64);
+
+    let entries = |quality: &Value| -> Vec<(String, String)> {
+        quality
+            .as_array()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|e| Some((e.get("type")?.as_str()?.to_string(), e.get("url")?.as_str()?.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let hls = qualities.values().flat_map(entries).find(|(kind, _)| kind.contains("mpegURL") || kind.contains("mpegurl"));
+    if let Some((_, url)) = hls {
+        return Ok((PlatformSource::Hls(url), duration));
+    }
+
+    let files = qualities
+        .iter()
+        .filter_map(|(name, quality)| {
+            let height = name.parse::<u32>().ok()?;
+            let (_, url) = entries(quality).into_iter().find(|(kind, _)| kind == "video/mp4")?;
+            Some((url, Some(height)))
+        })
+        .collect();
+    best_progressive(files, max_height)
+        .map(|source| (source, duration))
+        .ok_or_else(|| DownloadError::Config("Dailymotion offers no downloadable stream for this video".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_id() {
+        assert_eq!(video_id("https://www.dailymotion.com/video/x8j6n2c").as_deref(), Some("x8j6n2c"));
+        assert_eq!(video_id("https://www.dailymotion.com/video/x8j6n2c_fixture-highlights").as_deref(), Some("x8j6n2c"));
+        assert_eq!(video_id("https://www.dailymotion.com/embed/video/x8j6n2c?autoplay=1").as_deref(), Some("x8j6n2c"));
+        assert_eq!(video_id("https://dai.ly/x8j6n2c").as_deref(), Some("x8j6n2c"));
+        assert_eq!(video_id("https://geo.dailymotion.com/player.html?video=x8j6n2c").as_deref(), Some("x8j6n2c"));
+        assert_eq!(video_id("https://www.dailymotion.com/FixtureChannel"), None);
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let mut metadata: Value = serde_json::from_str(include_str!("fixtures/dailymotion_metadata.json")).unwrap();
+        let (source, duration) = parse_metadata(&metadata, None).unwrap();
+        assert_eq!(duration, Some(95.0));
+        assert!(matches!(source, PlatformSource::Hls(url) if url.contains("/manifest/video/x8j6n2c.m3u8")));
+
+        metadata["qualities"].as_object_mut().unwrap().remove("auto");
+        match parse_metadata(&metadata, Some(720)).unwrap().0 {
+            PlatformSource::Progressive { url, height } => {
+                assert_eq!(height, Some(720));
+                assert!(url.contains("H264-1280x720"));
+            }
+            other => panic!("expected a progressive file, got {:?}", other),
+        }
+
+        let restricted: Value = serde_json::from_str(include_str!("fixtures/dailymotion_restricted.json")).unwrap();
+        let error = parse_metadata(&restricted, None).unwrap_err();
+        assert!(error.to_string().contains("geo-restricted"));
+    }
+}
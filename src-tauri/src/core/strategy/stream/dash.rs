@@ -0,0 +1,485 @@
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::resolver::{fetch_manifest, ResolvedStream, ResolverProbe, StreamResolver, StreamTrack, TrackKind};
+use super::segment::{check_segment_count, ByteRange, InitSection, Segment};
+use super::subtitles;
+use reqwest::Client;
+use roxmltree::{Document, Node};
+use tracing::{debug, info};
+use url::Url;
+
+/// Resolves MPEG-DASH (`.mpd`) manifests into per-adaptation-set segment lists.
+pub struct DashResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for DashResolver {
+    fn name(&self) -> &'static str {
+        "dash"
+    }
+
+    fn matches(&self, url: &str, probe: &ResolverProbe) -> bool {
+        url.to_lowercase().contains(".mpd") || probe.content_type_contains("dash+xml") || probe.head.contains("<MPD")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        debug!("DASH Resolver: Fetching manifest from {}", url);
+
+        let text = fetch_manifest(url, client, headers).await?;
+        let base_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
+        let mut resolved = parse_mpd(&text, &base_url)?;
+        resolved.tracks.retain(|t| {
+            t.kind != TrackKind::Subtitle || subtitles::language_selected(&options.subtitle_languages, t.language.as_deref())
+        });
+
+        info!("DASH Resolver: Resolved {} track(s)", resolved.tracks.len());
+        Ok(resolved)
+    }
+}
+
+/// Selected representation of one adaptation set within a period.
+struct Selection<'a, 'input> {
+    period: Node<'a, 'input>,
+    adaptation_set: Node<'a, 'input>,
+    representation: Node<'a, 'input>,
+    bandwidth: u64,
+}
+
+/// Parses an MPD document and expands the best video and audio representations into segment URLs,
+/// plus one track per WebVTT subtitle adaptation set.
+pub fn parse_mpd(text: &str, manifest_url: &Url) -> Result<ResolvedStream, DownloadError> {
+    let doc = Document::parse(text)
+        .map_err(|e| DownloadError::Parse(format!("Invalid MPD document: {}", e)))?;
+    let mpd = doc.root_element();
+    if mpd.tag_name().name() != "MPD" {
+        return Err(DownloadError::Parse("Manifest root is not an MPD element".to_string()));
+    }
+
+    let mpd_base = join_base_url(manifest_url, mpd)?;
+    let total_duration = mpd.attribute("mediaPresentationDuration").and_then(parse_iso_duration);
+
+    let mut video: Option<StreamTrack> = None;
+    let mut audio: Option<StreamTrack> = None;
+    let mut subtitles: Vec<StreamTrack> = Vec::new();
+
+    let periods: Vec<Node> = mpd.children().filter(|n| n.has_tag_name("Period")).collect();
+    for (i, period) in periods.iter().enumerate() {
+        let period_base = join_base_url(&mpd_base, *period)?;
+        let period_duration = period
+            .attribute("duration")
+            .and_then(parse_iso_duration)
+            .or_else(|| period_duration_from_starts(&periods, i, total_duration));
+
+        for kind in [TrackKind::Video, TrackKind::Audio] {
+            let Some(selection) = select_representation(*period, kind) else { continue };
+            let mut segments = expand_representation(&selection, &period_base, period_duration)?;
+            // Every period may restart timestamps or switch codecs.
+            segments.iter_mut().for_each(|s| s.discontinuity = i as u64);
+            let language = selection.adaptation_set.attribute("lang").map(str::to_string);
+
+            let slot = if kind == TrackKind::Video { &mut video } else { &mut audio };
+            match slot {
+                Some(track) => track.segments.extend(segments),
+                None => *slot = Some(StreamTrack::new(kind, segments, language)),
+            }
+        }
+
+        for selection in subtitle_selections(*period) {
+            let segments = expand_representation(&selection, &period_base, period_duration)?;
+            let language = selection.adaptation_set.attribute("lang").map(str::to_string);
+            match subtitles.iter_mut().find(|t| t.language == language) {
+                Some(track) => track.segments.extend(segments),
+                None => subtitles.push(StreamTrack::new(TrackKind::Subtitle, segments, language)),
+            }
+        }
+    }
+
+    let mut tracks: Vec<StreamTrack> = video.into_iter().chain(audio).collect();
+    if tracks.is_empty() {
+        return Err(DownloadError::Config("No playable representations found in MPD".to_string()));
+    }
+    // A lone representation already carries everything the presentation has.
+    if tracks.len() == 1 {
+        tracks[0].kind = TrackKind::Muxed;
+    }
+    tracks.extend(subtitles);
+
+    Ok(ResolvedStream { tracks, live_playlist: None })
+}
+
+fn content_kind(node: Node) -> Option<TrackKind> {
+    let hint = node
+        .attribute("contentType")
+        .or_else(|| node.attribute("mimeType"))?;
+    if hint.starts_with("video") {
+        Some(TrackKind::Video)
+    } else if hint.starts_with("audio") {
+        Some(TrackKind::Audio)
+    } else {
+        None
+    }
+}
+
+/// Picks the highest-bandwidth representation of the requested kind in a period.
+fn select_representation<'a, 'input>(period: Node<'a, 'input>, kind: TrackKind) -> Option<Selection<'a, 'input>> {
+    period
+        .children()
+        .filter(|n| n.has_tag_name("AdaptationSet"))
+        .flat_map(|set| {
+            set.children()
+                .filter(|n| n.has_tag_name("Representation"))
+                .map(move |rep| (set, rep))
+        })
+        .filter(|(set, rep)| content_kind(*rep).or_else(|| content_kind(*set)) == Some(kind))
+        .map(|(set, rep)| Selection {
+            period,
+            adaptation_set: set,
+            representation: rep,
+            bandwidth: rep.attribute("bandwidth").and_then(|b| b.parse().ok()).unwrap_or(0),
+        })
+        .max_by_key(|s| s.bandwidth)
+}
+
+/// Picks the first representation of every plain WebVTT (`text/vtt`) adaptation set in a period.
+fn subtitle_selections<'a, 'input>(period: Node<'a, 'input>) -> Vec<Selection<'a, 'input>> {
+    period
+        .children()
+        .filter(|n| n.has_tag_name("AdaptationSet"))
+        .filter_map(|set| {
+            let rep = set.children().find(|n| n.has_tag_name("Representation"))?;
+            let mime = rep.attribute("mimeType").or_else(|| set.attribute("mimeType"))?;
+            (mime == "text/vtt").then_some(Selection { period, adaptation_set: set, representation: rep, bandwidth: 0 })
+        })
+        .collect()
+}
+
+/// Finds the nearest `tag` element, looking at the representation first, then its adaptation
+/// set and then the period.
+fn inherited<'a, 'input>(selection: &Selection<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    child(selection.representation, tag)
+        .or_else(|| child(selection.adaptation_set, tag))
+        .or_else(|| child(selection.period, tag))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+fn expand_representation(selection: &Selection, period_base: &Url, period_duration: Option<f64>) -> Result<Vec<Segment>, DownloadError> {
+    let set_base = join_base_url(period_base, selection.adaptation_set)?;
+    let base = join_base_url(&set_base, selection.representation)?;
+    let rep_id = selection.representation.attribute("id").unwrap_or_default();
+
+    if let Some(template) = inherited(selection, "SegmentTemplate") {
+        return expand_template(template, &base, rep_id, selection.bandwidth, period_duration);
+    }
+
+    if let Some(list) = inherited(selection, "SegmentList") {
+        let map = match child(list, "Initialization") {
+            Some(init) => Some(InitSection {
+                url: match init.attribute("sourceURL") {
+                    Some(source) => join(&base, source)?,
+                    None => base.to_string(),
+                },
+                byte_range: init.attribute("range").and_then(parse_range),
+                data: None,
+            }),
+            None => None,
+        };
+        let timescale: f64 = list.attribute("timescale").and_then(|v| v.parse().ok()).unwrap_or(1.0);
+        let duration = list.attribute("duration").and_then(|v| v.parse::<f64>().ok()).map_or(0.0, |d| d / timescale);
+
+        let mut segments = Vec::new();
+        for entry in list.children().filter(|n| n.has_tag_name("SegmentURL")) {
+            let url = match entry.attribute("media") {
+                Some(media) => join(&base, media)?,
+                None => base.to_string(),
+            };
+            let mut segment = Segment::new(url, duration).with_map(map.clone());
+            segment.byte_range = entry.attribute("mediaRange").and_then(parse_range);
+            segments.push(segment);
+        }
+        return Ok(segments);
+    }
+
+    // SegmentBase or bare BaseURL: the whole representation is a single file.
+    Ok(vec![Segment::new(base.to_string(), period_duration.unwrap_or(0.0))])
+}
+
+fn expand_template(template: Node, base: &Url, rep_id: &str, bandwidth: u64, period_duration: Option<f64>) -> Result<Vec<Segment>, DownloadError> {
+    let media = template
+        .attribute("media")
+        .ok_or_else(|| DownloadError::Parse("SegmentTemplate is missing @media".to_string()))?;
+    let timescale: u64 = template.attribute("timescale").and_then(|v| v.parse().ok()).unwrap_or(1);
+    if timescale == 0 {
+        return Err(DownloadError::Parse("SegmentTemplate has a zero @timescale".to_string()));
+    }
+    let start_number: u64 = template.attribute("startNumber").and_then(|v| v.parse().ok()).unwrap_or(1);
+
+    let map = match template.attribute("initialization") {
+        Some(init) => Some(InitSection { url: join(base, &fill_template(init, rep_id, bandwidth, 0, 0))?, byte_range: None, data: None }),
+        None => None,
+    };
+    let mut segments = Vec::new();
+
+    if let Some(timeline) = child(template, "SegmentTimeline") {
+        let mut time: u64 = 0;
+        let mut number = start_number;
+        let entries: Vec<Node> = timeline.children().filter(|n| n.has_tag_name("S")).collect();
+
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(t) = entry.attribute("t").and_then(|v| v.parse().ok()) {
+                time = t;
+            }
+            let duration: u64 = entry
+                .attribute("d")
+                .and_then(|v| v.parse().ok())
+                .filter(|d| *d > 0)
+                .ok_or_else(|| DownloadError::Parse("SegmentTimeline entry is missing a positive @d".to_string()))?;
+            let repeat: i64 = entry.attribute("r").and_then(|v| v.parse().ok()).unwrap_or(0);
+
+            // A negative repeat count means "until the next S@t or the end of the period".
+            let count = if repeat >= 0 {
+                repeat as u64 + 1
+            } else {
+                let end = entries
+                    .get(i + 1)
+                    .and_then(|n| n.attribute("t"))
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .or_else(|| period_duration.map(|d| (d * timescale as f64) as u64))
+                    .unwrap_or(time + duration);
+                end.saturating_sub(time).div_ceil(duration)
+            };
+            check_segment_count(segments.len() as u64, count)?;
+
+            for _ in 0..count {
+                let url = join(base, &fill_template(media, rep_id, bandwidth, number, time))?;
+                segments.push(Segment::new(url, duration as f64 / timescale as f64).with_map(map.clone()));
+                time += duration;
+                number += 1;
+            }
+        }
+        return Ok(segments);
+    }
+
+    let duration: u64 = template
+        .attribute("duration")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| DownloadError::Parse("SegmentTemplate has neither @duration nor a SegmentTimeline".to_string()))?;
+    let period_duration = period_duration
+        .ok_or_else(|| DownloadError::Parse("Cannot expand SegmentTemplate without a period duration".to_string()))?;
+
+    let segment_seconds = duration as f64 / timescale as f64;
+    if segment_seconds <= 0.0 || !period_duration.is_finite() || period_duration < 0.0 {
+        return Err(DownloadError::Parse(format!(
+            "Cannot expand SegmentTemplate with @duration {} over a period of {}s",
+            duration, period_duration
+        )));
+    }
+    let count = (period_duration / segment_seconds).ceil() as u64;
+    check_segment_count(0, count)?;
+    for i in 0..count {
+        let number = start_number + i;
+        let url = join(base, &fill_template(media, rep_id, bandwidth, number, i * duration))?;
+        // The last segment only covers what is left of the period.
+        let seconds = segment_seconds.min(period_duration - i as f64 * segment_seconds);
+        segments.push(Segment::new(url, seconds).with_map(map.clone()));
+    }
+
+    Ok(segments)
+}
+
+/// Parses a DASH `first-last` byte range (`@mediaRange`, `@range`).
+fn parse_range(value: &str) -> Option<ByteRange> {
+    let (first, last) = value.split_once('-')?;
+    let first: u64 = first.trim().parse().ok()?;
+    let last: u64 = last.trim().parse().ok()?;
+    (last >= first).then_some(ByteRange { offset: first, length: last - first + 1 })
+}
+
+/// Substitutes `$RepresentationID$`, `$Bandwidth$`, `$Number$` and `$Time$` (with optional `%0Nd` widths).
+pub fn fill_template(template: &str, rep_id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('$') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+
+        let token = &after[..end];
+        let (name, format) = match token.split_once('%') {
+            Some((name, format)) => (name, Some(format)),
+            None => (token, None),
+        };
+
+        let value = match name {
+            "" => "$".to_string(),
+            "RepresentationID" => rep_id.to_string(),
+            "Bandwidth" => pad(bandwidth, format),
+            "Number" => pad(number, format),
+            "Time" => pad(time, format),
+            _ => format!("${}$", token),
+        };
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn pad(value: u64, format: Option<&str>) -> String {
+    let width = format
+        .and_then(|f| f.trim_end_matches('d').trim_start_matches('0').parse::<usize>().ok())
+        .unwrap_or(0);
+    format!("{:0width$}", value, width = width)
+}
+
+/// Parses an ISO 8601 duration such as `PT1H2M3.5S` into seconds.
+pub fn parse_iso_duration(value: &str) -> Option<f64> {
+    let rest = value.trim().strip_prefix('P')?;
+    let (date, time) = match rest.split_once('T') {
+        Some((d, t)) => (d, t),
+        None => (rest, ""),
+    };
+
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in date.chars() {
+        match c {
+            'Y' => { seconds += number.parse::<f64>().ok()? * 365.0 * 86400.0; number.clear(); }
+            'M' => { seconds += number.parse::<f64>().ok()? * 30.0 * 86400.0; number.clear(); }
+            'W' => { seconds += number.parse::<f64>().ok()? * 7.0 * 86400.0; number.clear(); }
+            'D' => { seconds += number.parse::<f64>().ok()? * 86400.0; number.clear(); }
+            _ => number.push(c),
+        }
+    }
+    for c in time.chars() {
+        match c {
+            'H' => { seconds += number.parse::<f64>().ok()? * 3600.0; number.clear(); }
+            'M' => { seconds += number.parse::<f64>().ok()? * 60.0; number.clear(); }
+            'S' => { seconds += number.parse::<f64>().ok()?; number.clear(); }
+            _ => number.push(c),
+        }
+    }
+
+    seconds.is_finite().then_some(seconds)
+}
+
+fn period_duration_from_starts(periods: &[Node], index: usize, total: Option<f64>) -> Option<f64> {
+    let start = periods[index].attribute("start").and_then(parse_iso_duration).unwrap_or(0.0);
+    let end = periods
+        .get(index + 1)
+        .and_then(|p| p.attribute("start"))
+        .and_then(parse_iso_duration)
+        .or(total)?;
+    Some(end - start)
+}
+
+fn join_base_url(parent: &Url, node: Node) -> Result<Url, DownloadError> {
+    match child(node, "BaseURL").and_then(|n| n.text()).map(str::trim) {
+        Some(base) if !base.is_empty() => parent
+            .join(base)
+            .map_err(|e| DownloadError::Parse(format!("Invalid BaseURL '{}': {}", base, e))),
+        _ => Ok(parent.clone()),
+    }
+}
+
+fn join(base: &Url, path: &str) -> Result<String, DownloadError> {
+    base.join(path)
+        .map(|u| u.to_string())
+        .map_err(|e| DownloadError::Parse(format!("Failed to resolve segment URL '{}': {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_template() {
+        assert_eq!(fill_template("$RepresentationID$/seg-$Number%05d$.m4s", "v1", 0, 42, 0), "v1/seg-00042.m4s");
+        assert_eq!(fill_template("t_$Time$_$Bandwidth$.m4s", "a", 128000, 0, 9000), "t_9000_128000.m4s");
+        assert_eq!(fill_template("cost$$.m4s", "a", 0, 0, 0), "cost$.m4s");
+    }
+
+    #[test]
+    fn test_parse_iso_duration() {
+        assert_eq!(parse_iso_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_iso_duration("PT30S"), Some(30.0));
+        assert_eq!(parse_iso_duration("P1DT1S"), Some(86401.0));
+        assert_eq!(parse_iso_duration("garbage"), None);
+        assert_eq!(parse_iso_duration("PTinfS"), None);
+    }
+
+    #[test]
+    fn test_parse_mpd_with_separate_adaptation_sets() {
+        let mpd = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT8S">
+  <Period>
+    <AdaptationSet contentType="video" mimeType="video/mp4">
+      <SegmentTemplate initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/$Number$.m4s" startNumber="1" timescale="1000" duration="4000"/>
+      <Representation id="v480" bandwidth="1000000" width="854" height="480"/>
+      <Representation id="v720" bandwidth="3000000" width="1280" height="720"/>
+    </AdaptationSet>
+    <AdaptationSet contentType="audio" mimeType="audio/mp4" lang="en">
+      <Representation id="a128" bandwidth="128000">
+        <SegmentTemplate initialization="audio/init.mp4" media="audio/t$Time$.m4s" timescale="48000">
+          <SegmentTimeline><S t="0" d="96000" r="1"/></SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet contentType="text" mimeType="text/vtt" lang="fr">
+      <Representation id="subs-fr" bandwidth="256"><BaseURL>subs/fr.vtt</BaseURL></Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+        let base = Url::parse("https://cdn.example.com/dash/manifest.mpd").unwrap();
+        let resolved = parse_mpd(mpd, &base).unwrap();
+
+        let urls = |track: &StreamTrack| track.segments.iter().map(|s| s.url.clone()).collect::<Vec<_>>();
+
+        let video = resolved.track(TrackKind::Video).unwrap();
+        assert_eq!(urls(video), vec!["https://cdn.example.com/dash/v720/1.m4s", "https://cdn.example.com/dash/v720/2.m4s"]);
+        assert_eq!(video.segments[0].map.as_ref().unwrap().url, "https://cdn.example.com/dash/v720/init.mp4");
+        assert_eq!(video.segments[1].duration, 4.0);
+
+        let audio = resolved.track(TrackKind::Audio).unwrap();
+        assert_eq!(audio.language.as_deref(), Some("en"));
+        assert_eq!(urls(audio), vec!["https://cdn.example.com/dash/audio/t0.m4s", "https://cdn.example.com/dash/audio/t96000.m4s"]);
+        assert_eq!(audio.segments[0].map.as_ref().unwrap().url, "https://cdn.example.com/dash/audio/init.mp4");
+        assert_eq!(audio.segments[0].duration, 2.0);
+
+        let subtitles = resolved.track(TrackKind::Subtitle).unwrap();
+        assert_eq!(subtitles.language.as_deref(), Some("fr"));
+        assert_eq!(urls(subtitles), vec!["https://cdn.example.com/dash/subs/fr.vtt"]);
+    }
+
+    #[test]
+    fn test_period_level_template_and_degenerate_durations() {
+        let mpd = |template: &str, duration: &str| format!(
+            r#"<MPD mediaPresentationDuration="{}"><Period>{}<AdaptationSet contentType="video"><Representation id="v" bandwidth="1"/></AdaptationSet></Period></MPD>"#,
+            duration, template
+        );
+        let base = Url::parse("https://cdn.example.com/dash/manifest.mpd").unwrap();
+
+        let resolved = parse_mpd(&mpd(r#"<SegmentTemplate media="$RepresentationID$/$Number$.m4s" timescale="1" duration="4"/>"#, "PT8S"), &base).unwrap();
+        let urls: Vec<&str> = resolved.tracks[0].segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://cdn.example.com/dash/v/1.m4s", "https://cdn.example.com/dash/v/2.m4s"]);
+
+        assert!(parse_mpd(&mpd(r#"<SegmentTemplate media="$Number$.m4s" duration="0"/>"#, "PT8S"), &base).is_err());
+        assert!(parse_mpd(&mpd(r#"<SegmentTemplate media="$Number$.m4s" duration="4"/>"#, "PTinfS"), &base).is_err());
+        assert!(parse_mpd(&mpd(r#"<SegmentTemplate media="$Number$.m4s" timescale="1000" duration="1"/>"#, "P30D"), &base).is_err());
+        let endless = r#"<SegmentTemplate media="$Time$.m4s"><SegmentTimeline><S t="0" d="1" r="999999999"/></SegmentTimeline></SegmentTemplate>"#;
+        assert!(parse_mpd(&mpd(endless, "PT8S"), &base).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Discontinuity sections (`#EXT-X-DISCONTINUITY` blocks, DASH periods) and heuristics for
+//! recognising server-side inserted ads among them.
+
+use super::resolver::StreamTrack;
+use super::segment::Segment;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use url::Url;
+
+/// Sections longer than this are never treated as ads.
+pub const MAX_AD_SECTION_SECONDS: f64 = 120.0;
+
+/// URL fragments used by common ad servers and SSAI stitchers.
+const AD_URL_PATTERNS: &[&str] = &[
+    "doubleclick.net",
+    "googlesyndication",
+    "imasdk",
+    "fwmrm.net",
+    "freewheel",
+    "spotxchange",
+    "springserve",
+    "yospace",
+    "adserver",
+    "/ads/",
+    "/ad/",
+    "/adverts/",
+    "/creatives/",
+    "_ad_",
+    "-ad-",
+];
+
+/// A run of consecutive segments sharing one discontinuity sequence number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Indices into the track's segment list.
+    pub range: Range<usize>,
+    pub duration: f64,
+}
+
+pub fn sections(segments: &[Segment]) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match sections.last_mut() {
+            Some(section) if segments[section.range.start].discontinuity == segment.discontinuity => {
+                section.range.end = i + 1;
+                section.duration += segment.duration;
+            }
+            _ => sections.push(Section { range: i..i + 1, duration: segment.duration }),
+        }
+    }
+    sections
+}
+
+pub fn is_ad_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    AD_URL_PATTERNS.iter().any(|p| url.contains(p))
+}
+
+fn host(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+/// Flags the sections that look like ads: short, and either served from another host than
+/// the main content or from a known ad URL. The longest section is the main content and is
+/// never flagged.
+pub fn ad_sections(segments: &[Segment], sections: &[Section]) -> Vec<bool> {
+    let Some(main) = sections
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.duration.total_cmp(&b.duration).then(a.range.len().cmp(&b.range.len())))
+        .map(|(i, _)| i)
+    else {
+        return Vec::new();
+    };
+    let main_host = host(&segments[sections[main].range.start].url);
+
+    sections
+        .iter()
+        .enumerate()
+        .map(|(i, section)| {
+            if i == main || section.duration <= 0.0 || section.duration > MAX_AD_SECTION_SECONDS {
+                return false;
+            }
+            let segments = &segments[section.range.clone()];
+            let other_host = segments.iter().any(|s| host(&s.url) != main_host);
+            let ad_url = segments.iter().any(|s| is_ad_url(&s.url));
+            other_host || ad_url
+        })
+        .collect()
+}
+
+/// Removes ad sections from a track and returns how many were dropped.
+pub fn drop_ads(track: &mut StreamTrack) -> usize {
+    let sections = sections(&track.segments);
+    let flags = ad_sections(&track.segments, &sections);
+    let dropped: Vec<&Section> = sections.iter().zip(&flags).filter(|(_, ad)| **ad).map(|(s, _)| s).collect();
+
+    for section in &dropped {
+        info!(
+            kind = ?track.kind,
+            "Dropping {:.1}s ad section (segments {}..{})",
+            section.duration,
+            section.range.start,
+            section.range.end
+        );
+    }
+    let mut index = 0;
+    track.segments.retain(|_| {
+        let keep = !dropped.iter().any(|s| s.range.contains(&index));
+        index += 1;
+        keep
+    });
+    dropped.len()
+}
+
+/// Output path of section `index` (0-based) when splitting, e.g. `movie.ts` -> `movie_part02.ts`.
+pub fn section_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_part{:02}.{}", stem, index + 1, ext.to_string_lossy()),
+        None => format!("{}_part{:02}", stem, index + 1),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::resolver::TrackKind;
+
+    fn segment(url: &str, duration: f64, discontinuity: u64) -> Segment {
+        let mut segment = Segment::new(url.to_string(), duration);
+        segment.discontinuity = discontinuity;
+        segment
+    }
+
+    #[test]
+    fn test_ad_sections_are_dropped() {
+        let mut segments = Vec::new();
+        segments.extend((0..20).map(|i| segment(&format!("https://cdn.example.com/main/{}.ts", i), 6.0, 0)));
+        // Pre-roll-like break from an ad server, then a short same-host bumper that must stay.
+        segments.extend((0..3).map(|i| segment(&format!("https://ads.adserver.net/c/{}.ts", i), 10.0, 1)));
+        segments.extend((20..30).map(|i| segment(&format!("https://cdn.example.com/main/{}.ts", i), 6.0, 2)));
+        segments.push(segment("https://cdn.example.com/main/bumper.ts", 5.0, 3));
+        segments.push(segment("https://cdn.example.com/creatives/spot.ts", 15.0, 4));
+
+        let found = sections(&segments);
+        assert_eq!(found.len(), 5);
+        assert_eq!(found[1].range, 20..23);
+        assert_eq!(ad_sections(&segments, &found), vec![false, true, false, false, true]);
+
+        let mut track = StreamTrack::new(TrackKind::Muxed, segments, None);
+        assert_eq!(drop_ads(&mut track), 2);
+        assert_eq!(track.segments.len(), 31);
+        assert!(track.segments.iter().all(|s| !is_ad_url(&s.url)));
+    }
+
+    #[test]
+    fn test_section_path() {
+        assert_eq!(section_path(Path::new("/dl/movie.ts"), 1), PathBuf::from("/dl/movie_part02.ts"));
+    }
+}
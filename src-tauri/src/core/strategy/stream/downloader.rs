@@ -1,22 +1,69 @@
 use crate::core::error::DownloadError;
-use super::processor::StreamProcessor;
+use crate::core::strategy::TaskSignal;
+use crate::core::state::{DamagedSegment, SubstitutedSegment};
+use super::mux::{self, fmp4};
+use super::processor::{DisguiseHeader, PayloadFormat, StreamProcessor};
+use super::progress::{self, SegmentProgress};
+use super::resolver::StreamTrack;
+use super::hds;
+use super::segment::{ByteRange, FragmentTransform, InitSection, Segment, SegmentAlternate};
+use super::tracker::ProgressTracker;
+use super::validate::{self, TsIssue};
+use futures_util::stream::FuturesUnordered;
 use futures_util::StreamExt;
 use reqwest::Client;
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 use std::collections::HashMap;
 
 pub struct DownloadedSegment {
     pub index: usize,
     pub bytes: Vec<u8>,
+    /// Payload detected in the media part (the init section is not sniffed).
+    pub format: PayloadFormat,
+    pub disguise: Option<DisguiseHeader>,
+    /// Problems left in a TS segment after the re-fetch budget was used up.
+    pub issues: Vec<TsIssue>,
+    /// The alternate it was fetched from, when its own URL kept failing.
+    pub substitute: Option<SegmentAlternate>,
+}
+
+/// How a segment download run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentOutcome {
+    Completed,
+    /// Stopped early by a control signal (1=pause, 2=stop, 3=cancel).
+    Interrupted(u8),
+}
+
+/// Limit on segments held in memory while waiting for an earlier one to arrive.
+/// Whichever bound is hit first stops new fetches from being issued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimit {
+    pub max_segments: usize,
+    pub max_bytes: usize,
+}
+
+impl BufferLimit {
+    pub fn is_full(&self, segments: usize, bytes: usize) -> bool {
+        segments >= self.max_segments || bytes >= self.max_bytes
+    }
 }
 
 pub struct ParallelDownloader {
     client: Arc<Client>,
     processor: Arc<StreamProcessor>,
     max_parallel: usize,
-    high_water_mark: usize,
+    buffer_limit: BufferLimit,
+    /// Extra fetches allowed for a TS segment that fails validation.
+    max_refetches: usize,
+    /// How many times fetching was paused because the out-of-order buffer was full.
+    backpressure_trips: AtomicU64,
+    damaged: Mutex<Vec<DamagedSegment>>,
+    substituted: Mutex<Vec<SubstitutedSegment>>,
 }
 
 impl ParallelDownloader {
@@ -24,82 +71,552 @@ impl ParallelDownloader {
         client: Arc<Client>,
         processor: Arc<StreamProcessor>,
         max_parallel: usize,
-        high_water_mark: usize,
+        buffer_limit: BufferLimit,
+        max_refetches: usize,
     ) -> Self {
         Self {
             client,
             processor,
             max_parallel,
-            high_water_mark,
+            buffer_limit,
+            max_refetches,
+            backpressure_trips: AtomicU64::new(0),
+            damaged: Mutex::new(Vec::new()),
+            substituted: Mutex::new(Vec::new()),
         }
     }
 
+    /// Number of times the buffer limit has stalled fetching since this downloader was created.
+    pub fn backpressure_trips(&self) -> u64 {
+        self.backpressure_trips.load(Ordering::Relaxed)
+    }
+
+    /// Segments written despite failing validation, across every track downloaded so far.
+    pub fn damaged_segments(&self) -> Vec<DamagedSegment> {
+        self.damaged.lock().unwrap().clone()
+    }
+
+    /// Segments fetched from another variant, across every track downloaded so far.
+    pub fn substituted_segments(&self) -> Vec<SubstitutedSegment> {
+        self.substituted.lock().unwrap().clone()
+    }
+
+    /// Downloads the segments of `track` from `resume.next_segment()` on, in order, appending
+    /// them to `output_file`.
+    ///
+    /// Up to `max_parallel` fetches run at once. Segments that finish ahead of the writer are
+    /// buffered; once that buffer reaches the `BufferLimit`, no new fetches are issued until the
+    /// writer catches up. The segment the writer waits for is always already in flight, so this
+    /// cannot deadlock.
+    ///
+    /// The control signal is checked between segments; on a non-zero signal in-flight fetches
+    /// are dropped and the run returns `Interrupted`. After every contiguous write the
+    /// `.progress` sidecar of `output_path` is updated so a later run can append from there,
+    /// and every written segment is reported to `tracker`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn download_segments(
         &self,
-        segment_urls: Vec<String>,
+        track: &StreamTrack,
+        resume: &SegmentProgress,
         header_map: reqwest::header::HeaderMap,
         mut output_file: tokio::fs::File,
-        _cancel_signal: Arc<std::sync::atomic::AtomicU8>,
-    ) -> Result<(), DownloadError> {
-        let _total_segments = segment_urls.len();
-        let client = self.client.clone();
-        let processor = self.processor.clone();
-        
-        let mut segment_stream = futures_util::stream::iter(segment_urls.into_iter().enumerate())
-            .map(|(index, url)| {
-                let client = client.clone();
-                let headers = header_map.clone();
-                let processor = processor.clone();
-                async move {
-                    let mut retry_count = 0;
-                    while retry_count < 3 {
-                        match client.get(&url).headers(headers.clone()).send().await {
-                            Ok(resp) if resp.status().is_success() => {
-                                match resp.bytes().await {
-                                    Ok(bytes) => {
-                                        // Clean the segment before returning it
-                                        let cleaned_bytes = processor.clean_segment(bytes.to_vec());
-                                        return Ok(DownloadedSegment { index, bytes: cleaned_bytes });
-                                    }
-                                    Err(e) => warn!("Failed to read bytes for segment {}: {}", index, e),
-                                }
-                            }
-                            Ok(resp) => warn!("Server returned {} for segment {}", resp.status(), index),
-                            Err(e) => warn!("Network error for segment {}: {}", index, e),
-                        }
-                        retry_count += 1;
-                        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        output_path: &Path,
+        signal: TaskSignal,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        let start_index = resume.next_segment();
+        let mut queue = track.segments.iter().cloned().enumerate().skip(start_index);
+        let mut in_flight = FuturesUnordered::new();
+
+        let mut next_index_to_write = start_index;
+        let mut pending_segments: HashMap<usize, Vec<u8>> = HashMap::new();
+        let mut pending_bytes = 0usize;
+        let mut peak_pending_bytes = 0usize;
+        let mut throttled = false;
+        let mut last_format: Option<PayloadFormat> = None;
+        let mut disguised_segments = 0usize;
+        let mut progress = SegmentProgress {
+            last_written_segment: resume.last_written_segment,
+            bytes_written: resume.bytes_written,
+            total_segments: track.segments.len(),
+        };
+
+        loop {
+            // Issue new fetches while there is room both on the wire and in the buffer.
+            while in_flight.len() < self.max_parallel {
+                // With nothing buffered there is nothing to wait for, whatever the limit says.
+                if !pending_segments.is_empty() && self.buffer_limit.is_full(pending_segments.len(), pending_bytes) {
+                    if !throttled {
+                        throttled = true;
+                        self.backpressure_trips.fetch_add(1, Ordering::Relaxed);
+                        debug!(
+                            segments = pending_segments.len(),
+                            bytes = pending_bytes,
+                            "Backpressure: buffer full, waiting for segment {}",
+                            next_index_to_write
+                        );
                     }
-                    Err(DownloadError::Network(format!("Failed to download segment {} after retries", index)))
+                    break;
                 }
-            })
-            .buffered(self.max_parallel);
+                let Some((index, segment)) = queue.next() else { break };
+                // An init section is written before the first segment that uses it, and again
+                // whenever it changes. This also holds for the first segment of a resumed run.
+                let previous_map = index.checked_sub(1).and_then(|i| track.segments[i].map.as_ref());
+                let init = segment.map.clone().filter(|map| Some(map) != previous_map);
+                in_flight.push(fetch_segment(
+                    self.client.clone(),
+                    self.processor.clone(),
+                    index,
+                    segment,
+                    init,
+                    header_map.clone(),
+                    signal.clone(),
+                    self.max_refetches,
+                ));
+            }
 
-        let mut next_index_to_write = 0;
-        let mut pending_segments: HashMap<usize, Vec<u8>> = HashMap::new();
+            let result = tokio::select! {
+                result = in_flight.next() => result,
+                signal = wait_for_signal(&signal) => {
+                    info!(signal, "Segment download interrupted at segment {}", next_index_to_write);
+                    return Ok(SegmentOutcome::Interrupted(signal));
+                }
+            };
+            let Some(result) = result else { break };
 
-        while let Some(result) = segment_stream.next().await {
+            // A fetch that gave up because of the signal reports an error; let the signal win.
+            let signal_value = signal.load();
+            if signal_value != 0 {
+                info!(signal = signal_value, "Segment download interrupted at segment {}", next_index_to_write);
+                return Ok(SegmentOutcome::Interrupted(signal_value));
+            }
             let segment = result?;
+
+            if last_format != Some(segment.format) {
+                info!(format = ?segment.format, "Segment {} payload detected", segment.index);
+                last_format = Some(segment.format);
+            }
+            if segment.disguise.is_some() {
+                disguised_segments += 1;
+            }
+            if !segment.issues.is_empty() {
+                let issues: Vec<String> = segment.issues.iter().map(ToString::to_string).collect();
+                warn!(issues = ?issues, "Segment {} is still damaged, writing it anyway", segment.index);
+                self.damaged.lock().unwrap().push(DamagedSegment {
+                    track: format!("{:?}", track.kind).to_lowercase(),
+                    index: segment.index,
+                    url: track.segments[segment.index].url.clone(),
+                    issues,
+                });
+            }
             
-            if segment.index == next_index_to_write {
-                output_file.write_all(&segment.bytes).await
-                    .map_err(|e| DownloadError::Config(format!("Failed to write segment {}: {}", segment.index, e)))?;
-                next_index_to_write += 1;
+            if let Some(alternate) = &segment.substitute {
+                self.substituted.lock().unwrap().push(SubstitutedSegment {
+                    track: format!("{:?}", track.kind).to_lowercase(),
+                    index: segment.index,
+                    url: track.segments[segment.index].url.clone(),
+                    substitute_url: alternate.url.clone(),
+                    variant: alternate.variant.clone(),
+                    redundant: alternate.redundant,
+                });
+            }
 
-                while let Some(bytes) = pending_segments.remove(&next_index_to_write) {
-                    output_file.write_all(&bytes).await
-                        .map_err(|e| DownloadError::Config(format!("Failed to write segment {}: {}", next_index_to_write, e)))?;
-                    next_index_to_write += 1;
-                }
-            } else {
+            if segment.index != next_index_to_write {
+                pending_bytes += segment.bytes.len();
+                peak_pending_bytes = peak_pending_bytes.max(pending_bytes);
                 pending_segments.insert(segment.index, segment.bytes);
-                if pending_segments.len() > self.high_water_mark {
-                    debug!("Backpressure: Buffer full ({}), waiting for writer...", pending_segments.len());
+                continue;
+            }
+
+            let mut bytes = segment.bytes;
+            loop {
+                output_file.write_all(&bytes).await
+                    .map_err(|e| DownloadError::Config(format!("Failed to write segment {}: {}", next_index_to_write, e)))?;
+                progress.last_written_segment = Some(next_index_to_write);
+                progress.bytes_written += bytes.len() as u64;
+                tracker.record_segment(bytes.len() as u64, track.duration(next_index_to_write));
+                next_index_to_write += 1;
+
+                match pending_segments.remove(&next_index_to_write) {
+                    Some(next) => {
+                        pending_bytes -= next.len();
+                        bytes = next;
+                    }
+                    None => break,
                 }
             }
+
+            if throttled && !self.buffer_limit.is_full(pending_segments.len(), pending_bytes) {
+                throttled = false;
+                debug!("Backpressure: writer caught up, resuming fetches");
+            }
+
+            // The sidecar must never claim more than what is actually on disk.
+            output_file.flush().await.map_err(|e| DownloadError::Config(e.to_string()))?;
+            progress::save(output_path, &progress).await?;
         }
 
+        debug!(
+            peak_buffer_mb = peak_pending_bytes / 1024 / 1024,
+            backpressure_trips = self.backpressure_trips(),
+            disguised_segments,
+            "Wrote {} segments",
+            next_index_to_write - start_index
+        );
         output_file.flush().await.map_err(|e| DownloadError::Config(e.to_string()))?;
-        Ok(())
+        Ok(SegmentOutcome::Completed)
+    }
+}
+
+/// Fetches one segment (up to 3 attempts), preceded by its init section if given, and strips
+/// any disguising header from the media part.
+///
+/// A TS segment that fails validation is fetched again up to `max_refetches` times; these
+/// re-fetches don't count as failed attempts. If no clean copy turns up, the last damaged one
+/// is returned along with its issues.
+///
+/// When every attempt fails, the segment's alternates (the same media in redundant or other
+/// variants) are tried in order, and the one that worked is recorded on the result.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_segment(
+    client: Arc<Client>,
+    processor: Arc<StreamProcessor>,
+    index: usize,
+    segment: Segment,
+    init: Option<InitSection>,
+    headers: reqwest::header::HeaderMap,
+    signal: TaskSignal,
+    max_refetches: usize,
+) -> Result<DownloadedSegment, DownloadError> {
+    let own = SegmentCopy {
+        url: &segment.url,
+        byte_range: segment.byte_range.as_ref(),
+        init: init.as_ref(),
+        mapped: segment.map.is_some(),
+    };
+    if let Some(downloaded) = fetch_copy(&client, &processor, index, own, segment.transform, &headers, &signal, max_refetches, 3).await {
+        return Ok(downloaded);
+    }
+
+    for alternate in &segment.alternates {
+        if signal.load() != 0 {
+            break;
+        }
+        let kind = if alternate.redundant { "redundant" } else { "alternate" };
+        warn!("Segment {} keeps failing, trying the {} variant {}", index, kind, alternate.variant);
+        // A built-in init section stays; a fetched one comes from the alternate's host.
+        let alternate_init = match (&init, &alternate.map) {
+            (Some(own_init), Some(map)) if own_init.data.is_none() => Some(map),
+            (own_init, _) => own_init.as_ref(),
+        };
+        let copy = SegmentCopy {
+            url: &alternate.url,
+            byte_range: alternate.byte_range.as_ref(),
+            init: alternate_init,
+            mapped: segment.map.is_some() || alternate.map.is_some(),
+        };
+        if let Some(mut downloaded) = fetch_copy(&client, &processor, index, copy, segment.transform, &headers, &signal, max_refetches, 2).await {
+            info!("Segment {} fetched from the {} variant {}", index, kind, alternate.variant);
+            downloaded.substitute = Some(alternate.clone());
+            return Ok(downloaded);
+        }
+    }
+    Err(DownloadError::Network(format!("Failed to download segment {} after retries", index)))
+}
+
+/// Where one copy of a segment is fetched from.
+#[derive(Clone, Copy)]
+struct SegmentCopy<'a> {
+    url: &'a str,
+    byte_range: Option<&'a ByteRange>,
+    init: Option<&'a InitSection>,
+    /// The segment has an `#EXT-X-MAP`, which carries the PAT/PMT instead of the segment.
+    mapped: bool,
+}
+
+/// Fetches one copy of segment `index` with up to `attempts` tries; `None` if they all failed
+/// or the control signal was raised.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_copy(
+    client: &Client,
+    processor: &StreamProcessor,
+    index: usize,
+    copy: SegmentCopy<'_>,
+    transform: Option<FragmentTransform>,
+    headers: &reqwest::header::HeaderMap,
+    signal: &TaskSignal,
+    max_refetches: usize,
+    attempts: usize,
+) -> Option<DownloadedSegment> {
+    let mut retry_count = 0;
+    let mut refetches = 0;
+    let mut damaged: Option<DownloadedSegment> = None;
+    while retry_count < attempts {
+        if signal.load() != 0 {
+            break;
+        }
+        let init_bytes = match copy.init {
+            Some(InitSection { data: Some(data), .. }) => Ok(data.to_vec()),
+            Some(init) => fetch_range(client, &init.url, init.byte_range.as_ref(), headers).await,
+            None => Ok(Vec::new()),
+        };
+        let result = match init_bytes {
+            Ok(init_bytes) => fetch_range(client, copy.url, copy.byte_range, headers)
+                .await
+                .map(|media| (init_bytes, processor.process_segment(media))),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((mut bytes, processed)) => {
+                let mut issues = match processed.format {
+                    PayloadFormat::MpegTs => validate::validate_ts(&processed.bytes),
+                    _ => Vec::new(),
+                };
+                if copy.mapped {
+                    issues.retain(|issue| !issue.is_missing_table());
+                }
+                match (transform, processed.format) {
+                    (Some(FragmentTransform::Smooth { decode_time }), PayloadFormat::Fmp4) => {
+                        bytes.extend(fmp4::normalize_fragment(&processed.bytes, decode_time))
+                    }
+                    (Some(FragmentTransform::F4f), _) => bytes.extend(hds::unpack_fragment(&processed.bytes)),
+                    (Some(FragmentTransform::ExtractAudio), PayloadFormat::MpegTs) => {
+                        // The PAT/PMT may come from the init section; only the audio is written.
+                        let mut segment = std::mem::take(&mut bytes);
+                        segment.extend(processed.bytes);
+                        match mux::audio::packed_audio_from_ts(&segment) {
+                            Ok(Some(audio)) => bytes = audio,
+                            Ok(None) => issues.push(TsIssue::NoAudio),
+                            Err(e) => issues.push(TsIssue::AudioUnreadable(e.to_string())),
+                        }
+                    }
+                    _ => bytes.extend(processed.bytes),
+                }
+                let downloaded = DownloadedSegment { index, bytes, format: processed.format, disguise: processed.disguise, issues, substitute: None };
+                if downloaded.issues.is_empty() || refetches >= max_refetches {
+                    return Some(downloaded);
+                }
+                refetches += 1;
+                warn!(issues = ?downloaded.issues, "Segment {} failed validation, re-fetching ({}/{})", index, refetches, max_refetches);
+                damaged = Some(downloaded);
+                continue;
+            }
+            Err(e) => warn!("Failed to fetch segment {}: {}", index, e),
+        }
+        retry_count += 1;
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+    damaged.filter(|_| signal.load() == 0)
+}
+
+/// GETs `url`, restricted to `range` if given. Servers that ignore `Range` and send the whole
+/// resource are handled by cutting the range out of the body.
+pub(super) async fn fetch_range(
+    client: &Client,
+    url: &str,
+    range: Option<&ByteRange>,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<Vec<u8>, String> {
+    let resp = client
+        .get(url)
+        .headers(ByteRange::apply(range, headers))
+        .send()
+        .await
+        .map_err(|e| format!("network error: {}", e))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(format!("server returned {}", status));
+    }
+    let body = resp.bytes().await.map_err(|e| format!("failed to read body: {}", e))?;
+
+    match range {
+        Some(range) if status != reqwest::StatusCode::PARTIAL_CONTENT => {
+            debug!("Server ignored Range for {}, slicing {} bytes at {}", url, range.length, range.offset);
+            range
+                .slice(&body)
+                .ok_or_else(|| format!("response of {} bytes does not contain the requested range", body.len()))
+        }
+        _ => Ok(body.to_vec()),
+    }
+}
+
+/// Resolves with the control signal once it becomes non-zero.
+pub(crate) async fn wait_for_signal(signal: &TaskSignal) -> u8 {
+    loop {
+        let value = signal.load();
+        if value != 0 {
+            return value;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::DownloadControl;
+    use crate::core::strategy::stream::resolver::TrackKind;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Serves `/seg<i>` as `segment <i>;`, holding back segment 0 so the later ones arrive
+    /// first, and `files` by path. Anything else is a 404.
+    async fn serve_segments(listener: TcpListener, files: Arc<HashMap<String, Vec<u8>>>) {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let files = files.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(socket);
+                let mut request = String::new();
+                reader.read_line(&mut request).await.unwrap();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let body = match path.strip_prefix("/seg") {
+                    Some(index) => {
+                        if index == "0" {
+                            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                        }
+                        Some(format!("segment {};", index).into_bytes())
+                    }
+                    None => files.get(path).cloned(),
+                };
+                let mut response = match &body {
+                    Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                }
+                .into_bytes();
+                response.extend(body.unwrap_or_default());
+                let _ = reader.get_mut().write_all(&response).await;
+            });
+        }
+    }
+
+    async fn start_server(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve_segments(listener, Arc::new(files)));
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    /// Runs `download_segments` over `track` and returns the outcome and the written file.
+    async fn download(downloader: &ParallelDownloader, track: &StreamTrack) -> (SegmentOutcome, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stream.ts");
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        let control = DownloadControl::new();
+        let outcome = downloader
+            .download_segments(
+                track,
+                &SegmentProgress::default(),
+                reqwest::header::HeaderMap::new(),
+                file,
+                &path,
+                TaskSignal::new(&control, 0),
+                &ProgressTracker::new(),
+            )
+            .await
+            .unwrap();
+        (outcome, tokio::fs::read(&path).await.unwrap())
+    }
+
+    fn downloader(max_parallel: usize, buffer_limit: BufferLimit) -> ParallelDownloader {
+        let client = Client::builder().no_proxy().build().unwrap();
+        ParallelDownloader::new(Arc::new(client), Arc::new(StreamProcessor::new(false)), max_parallel, buffer_limit, 0)
+    }
+
+    fn expected(count: usize) -> Vec<u8> {
+        (0..count).map(|i| format!("segment {};", i)).collect::<String>().into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_backpressure_stalls_fetching_and_keeps_order() {
+        let base = start_server(HashMap::new()).await;
+        let segments = (0..8).map(|i| Segment::new(format!("{}/seg{}", base, i), 4.0)).collect();
+        let track = StreamTrack::new(TrackKind::Muxed, segments, None);
+        // Segments 1-3 arrive while 0 is held back, filling the two-segment buffer.
+        let downloader = downloader(4, BufferLimit { max_segments: 2, max_bytes: 1024 * 1024 });
+
+        let (outcome, written) = download(&downloader, &track).await;
+        assert_eq!(outcome, SegmentOutcome::Completed);
+        assert_eq!(written, expected(8));
+        assert!(downloader.backpressure_trips() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_failing_segment_comes_from_its_alternate() {
+        let base = start_server(HashMap::new()).await;
+        let mut segments: Vec<Segment> = (0..3).map(|i| Segment::new(format!("{}/seg{}", base, i), 4.0)).collect();
+        segments[1].url = format!("{}/gone/seg1", base);
+        segments[1].alternates.push(SegmentAlternate {
+            url: format!("{}/seg1", base),
+            byte_range: None,
+            map: None,
+            variant: format!("{}/backup.m3u8", base),
+            redundant: true,
+        });
+        let track = StreamTrack::new(TrackKind::Muxed, segments, None);
+        let downloader = downloader(2, BufferLimit { max_segments: 8, max_bytes: 1024 * 1024 });
+
+        let (outcome, written) = download(&downloader, &track).await;
+        assert_eq!(outcome, SegmentOutcome::Completed);
+        assert_eq!(written, expected(3));
+        let substituted = downloader.substituted_segments();
+        assert_eq!(substituted.len(), 1);
+        assert_eq!((substituted[0].index, substituted[0].url.as_str()), (1, format!("{}/gone/seg1", base).as_str()));
+        assert_eq!(substituted[0].substitute_url, format!("{}/seg1", base));
+        assert!(substituted[0].redundant && downloader.damaged_segments().is_empty());
+    }
+
+    #[test]
+    fn test_buffer_limit_trips_on_either_bound() {
+        let limit = BufferLimit { max_segments: 32, max_bytes: 128 * 1024 * 1024 };
+        assert!(!limit.is_full(31, 10 * 1024 * 1024));
+        assert!(limit.is_full(32, 0));
+        assert!(limit.is_full(3, 128 * 1024 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_audio_is_extracted_with_the_init_section_tables() {
+        use crate::core::strategy::stream::mux::ts::tests::{adts_frame, packetize, pes_packet, psi_packets};
+
+        let mut audio_cc = 0;
+        let media: Vec<u8> = (0..3u64)
+            .flat_map(|i| packetize(0x101, &pes_packet(0xC0, 180_000 + i * 2090, None, &adts_frame(100)), &mut audio_cc))
+            .collect();
+        let files = HashMap::from([
+            ("/audio_init.ts".to_string(), psi_packets(&[(0x0F, 0x101)])),
+            ("/video_init.ts".to_string(), psi_packets(&[(0x1B, 0x100)])),
+            ("/media.ts".to_string(), media.clone()),
+        ]);
+        let base = start_server(files).await;
+        let segment = |init: &str| {
+            let map = InitSection { url: format!("{}/{}", base, init), byte_range: None, data: None };
+            let mut segment = Segment::new(format!("{}/media.ts", base), 4.0).with_map(Some(map));
+            segment.transform = Some(FragmentTransform::ExtractAudio);
+            segment
+        };
+        let downloader = downloader(2, BufferLimit { max_segments: 8, max_bytes: 1024 * 1024 });
+
+        // Only the packed audio is written, not the init section's TS packets.
+        let track = StreamTrack::new(TrackKind::Audio, vec![segment("audio_init.ts")], None);
+        let (_, written) = download(&downloader, &track).await;
+        let mut segment_ts = psi_packets(&[(0x0F, 0x101)]);
+        segment_ts.extend(&media);
+        assert_eq!(written, mux::audio::packed_audio_from_ts(&segment_ts).unwrap().unwrap());
+        assert!(downloader.damaged_segments().is_empty());
+
+        // Without an audio stream the segment is reported instead of silently left out.
+        let track = StreamTrack::new(TrackKind::Audio, vec![segment("video_init.ts")], None);
+        let (_, written) = download(&downloader, &track).await;
+        assert!(written.is_empty());
+        let damaged = downloader.damaged_segments();
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(damaged[0].issues, vec![TsIssue::NoAudio.to_string()]);
     }
 }
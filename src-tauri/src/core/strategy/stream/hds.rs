@@ -0,0 +1,526 @@
+//! Adobe HTTP Dynamic Streaming (`.f4m`) resolution.
+//!
+//! The F4M manifest lists media renditions and their bootstrap info (`abst`). The bootstrap's
+//! segment run table (`asrt`) and fragment run table (`afrt`) give the `SegN-FragM` fragments
+//! to fetch. Each F4F fragment carries plain FLV tags in its `mdat`, so the downloader keeps
+//! just those and the track's init section supplies the FLV header and `onMetaData` tag.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::mux::fmp4::iter_boxes;
+use super::resolver::{fetch_manifest, fetch_manifest_bytes, ResolvedStream, ResolverProbe, StreamResolver, StreamTrack, TrackKind};
+use super::segment::{check_segment_count, FragmentTransform, InitSection, Segment};
+use base64::Engine;
+use reqwest::Client;
+use roxmltree::{Document, Node};
+use std::sync::Arc;
+use tracing::{debug, info};
+use url::Url;
+
+/// FLV file header (audio + video flags) followed by the first, empty, PreviousTagSize.
+const FLV_HEADER: &[u8] = b"FLV\x01\x05\x00\x00\x00\x09\x00\x00\x00\x00";
+const FLV_SCRIPT_TAG: u8 = 18;
+/// F4M 2.0 set-level manifests point at stream-level ones; follow at most this many hops.
+const MAX_MANIFEST_DEPTH: usize = 2;
+
+pub struct HdsResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for HdsResolver {
+    fn name(&self) -> &'static str {
+        "hds"
+    }
+
+    fn matches(&self, url: &str, probe: &ResolverProbe) -> bool {
+        url.to_lowercase().contains(".f4m") || probe.content_type_contains("f4m") || probe.head.contains("ns.adobe.com/f4m")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        _options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        let mut manifest_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
+        for _ in 0..=MAX_MANIFEST_DEPTH {
+            debug!("HDS Resolver: Fetching manifest from {}", manifest_url);
+            let text = fetch_manifest(manifest_url.as_str(), client, headers).await?;
+            let manifest = parse_manifest(&text, &manifest_url)?;
+            let media = manifest.best_media()?;
+
+            if let Some(href) = &media.href {
+                debug!("HDS Resolver: Following set-level manifest entry to {}", href);
+                manifest_url = Url::parse(href).map_err(|e| DownloadError::Parse(format!("Invalid manifest link: {}", e)))?;
+                continue;
+            }
+
+            let bootstrap = match manifest.bootstrap_for(media)? {
+                Bootstrap::Inline(data) => data.clone(),
+                Bootstrap::Url(url) => fetch_manifest_bytes(url, client, headers).await?,
+            };
+            let info = parse_bootstrap(&bootstrap)?;
+            let segments = build_segments(&manifest, media, &info)?;
+            info!(
+                "HDS Resolver: {} fragments of {} ({} kbps)",
+                segments.len(),
+                media.url.as_deref().unwrap_or_default(),
+                media.bitrate
+            );
+            return Ok(ResolvedStream::single(StreamTrack::new(TrackKind::Muxed, segments, None)));
+        }
+        Err(DownloadError::Parse("Too many nested F4M manifests".to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bootstrap {
+    Inline(Vec<u8>),
+    Url(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct F4mMedia {
+    /// Fragment URL prefix; fragments live at `<url>SegN-FragM`.
+    pub url: Option<String>,
+    /// Link to a stream-level manifest (F4M 2.0 set-level manifests).
+    pub href: Option<String>,
+    pub bitrate: u64,
+    pub bootstrap_id: Option<String>,
+    /// AMF-encoded `onMetaData` payload.
+    pub metadata: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct F4mManifest {
+    pub base_url: Url,
+    pub media: Vec<F4mMedia>,
+    /// `(id, bootstrap)`; the id is empty when the manifest didn't give one.
+    pub bootstraps: Vec<(String, Bootstrap)>,
+}
+
+impl F4mManifest {
+    pub fn best_media(&self) -> Result<&F4mMedia, DownloadError> {
+        self.media
+            .iter()
+            .max_by_key(|m| m.bitrate)
+            .ok_or_else(|| DownloadError::Config("F4M manifest lists no media".to_string()))
+    }
+
+    /// Bootstrap referenced by `media`, falling back to the only one in the manifest.
+    pub fn bootstrap_for(&self, media: &F4mMedia) -> Result<&Bootstrap, DownloadError> {
+        let by_id = media
+            .bootstrap_id
+            .as_ref()
+            .and_then(|id| self.bootstraps.iter().find(|(bootstrap_id, _)| bootstrap_id == id));
+        by_id
+            .or_else(|| self.bootstraps.first())
+            .map(|(_, bootstrap)| bootstrap)
+            .ok_or_else(|| DownloadError::Config("F4M manifest has no bootstrap info".to_string()))
+    }
+}
+
+pub fn parse_manifest(text: &str, manifest_url: &Url) -> Result<F4mManifest, DownloadError> {
+    let doc = Document::parse(text).map_err(|e| DownloadError::Parse(format!("Invalid F4M manifest: {}", e)))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "manifest" {
+        return Err(DownloadError::Parse("Manifest root is not an F4M manifest element".to_string()));
+    }
+    if root.children().any(|n| n.has_tag_name("drmAdditionalHeader")) {
+        return Err(DownloadError::Config("HDS stream is DRM-protected; protected streams are not supported".to_string()));
+    }
+
+    let element_text = |node: Node, name: &str| {
+        node.children()
+            .find(|n| n.has_tag_name(name))
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+    };
+    let join = |base: &Url, path: &str| {
+        base.join(path)
+            .map_err(|e| DownloadError::Parse(format!("Failed to resolve F4M URL '{}': {}", path, e)))
+    };
+
+    let base_url = match element_text(root, "baseURL") {
+        // A baseURL names a directory; make sure relative paths are joined inside it.
+        Some(base) => join(manifest_url, &format!("{}/", base.trim_end_matches('/')))?,
+        None => manifest_url.clone(),
+    };
+
+    let mut bootstraps = Vec::new();
+    for node in root.children().filter(|n| n.has_tag_name("bootstrapInfo")) {
+        let id = node.attribute("id").unwrap_or_default().to_string();
+        let bootstrap = match (node.attribute("url"), node.text().map(str::trim).filter(|t| !t.is_empty())) {
+            (Some(url), _) => Bootstrap::Url(join(&base_url, url)?.to_string()),
+            (None, Some(data)) => Bootstrap::Inline(decode_base64(data)?),
+            (None, None) => continue,
+        };
+        bootstraps.push((id, bootstrap));
+    }
+
+    let mut media = Vec::new();
+    for node in root.children().filter(|n| n.has_tag_name("media")) {
+        let metadata = match element_text(node, "metadata") {
+            Some(data) => Some(decode_base64(&data)?),
+            None => None,
+        };
+        media.push(F4mMedia {
+            url: node.attribute("url").map(str::to_string),
+            href: node.attribute("href").map(|href| join(&base_url, href)).transpose()?.map(|u| u.to_string()),
+            bitrate: node.attribute("bitrate").and_then(|b| b.parse().ok()).unwrap_or(0),
+            bootstrap_id: node.attribute("bootstrapInfoId").map(str::to_string),
+            metadata,
+        });
+    }
+
+    Ok(F4mManifest { base_url, media, bootstraps })
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, DownloadError> {
+    let compact: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(compact)
+        .map_err(|e| DownloadError::Parse(format!("Invalid base64 in F4M manifest: {}", e)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FragmentRun {
+    pub first_fragment: u32,
+    pub first_timestamp: u64,
+    /// In the fragment run table's timescale; 0 marks a discontinuity entry.
+    pub duration: u32,
+}
+
+/// The parts of an `abst` box needed to enumerate fragments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapInfo {
+    pub live: bool,
+    pub timescale: u32,
+    /// `(first segment, fragments per segment)` from the first `asrt`.
+    pub segment_runs: Vec<(u32, u32)>,
+    pub fragment_timescale: u32,
+    pub fragment_runs: Vec<FragmentRun>,
+}
+
+impl BootstrapInfo {
+    /// Every `(segment, fragment)` pair in presentation order. Fails when the runs expand to
+    /// more than `MAX_SEGMENTS` fragments or past the last fragment number.
+    pub fn fragments(&self) -> Result<Vec<(u32, u32)>, DownloadError> {
+        let mut next_fragment = self.fragment_runs.first().map_or(1, |run| run.first_fragment);
+        let mut fragments = Vec::new();
+        for (i, &(first_segment, per_segment)) in self.segment_runs.iter().enumerate() {
+            // A run covers segments up to the next run's first segment.
+            let last_segment = self
+                .segment_runs
+                .get(i + 1)
+                .map_or(first_segment, |(next, _)| next.saturating_sub(1).max(first_segment));
+            let count = (last_segment - first_segment) as u64 + 1;
+            check_segment_count(fragments.len() as u64, count.saturating_mul(per_segment as u64))?;
+            for segment in first_segment..=last_segment {
+                for _ in 0..per_segment {
+                    fragments.push((segment, next_fragment));
+                    next_fragment = next_fragment
+                        .checked_add(1)
+                        .ok_or_else(|| DownloadError::Parse("HDS fragment numbers overflow".to_string()))?;
+                }
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// Duration of a fragment in seconds, 0 if the fragment run table doesn't cover it.
+    pub fn fragment_duration(&self, fragment: u32) -> f64 {
+        self.fragment_runs
+            .iter()
+            .filter(|run| run.duration > 0 && run.first_fragment <= fragment)
+            .max_by_key(|run| run.first_fragment)
+            .map_or(0.0, |run| run.duration as f64 / self.fragment_timescale.max(1) as f64)
+    }
+}
+
+/// Minimal cursor over big-endian box fields.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    /// Skips a NUL-terminated string.
+    fn skip_string(&mut self) -> Option<()> {
+        let len = self.data.get(self.pos..)?.iter().position(|b| *b == 0)?;
+        self.pos += len + 1;
+        Some(())
+    }
+
+    /// Skips a count byte followed by that many strings.
+    fn skip_string_table(&mut self) -> Option<()> {
+        for _ in 0..self.u8()? {
+            self.skip_string()?;
+        }
+        Some(())
+    }
+
+    /// Reads a box header, returning its type and body.
+    fn child_box(&mut self) -> Option<([u8; 4], &'a [u8])> {
+        let size = self.u32()? as u64;
+        let kind: [u8; 4] = self.bytes(4)?.try_into().ok()?;
+        let body_len = match size {
+            1 => self.u64()?.checked_sub(16)?,
+            _ => size.checked_sub(8)?,
+        };
+        Some((kind, self.bytes(body_len as usize)?))
+    }
+}
+
+/// Decodes an `abst` box, with or without its box header.
+pub fn parse_bootstrap(data: &[u8]) -> Result<BootstrapInfo, DownloadError> {
+    let body = match data.get(4..8) {
+        Some(b"abst") => iter_boxes(data).next().map(|(_, body)| body).unwrap_or_default(),
+        _ => data,
+    };
+    read_abst(body).ok_or_else(|| DownloadError::Parse("Malformed HDS bootstrap (abst) box".to_string()))
+}
+
+fn read_abst(body: &[u8]) -> Option<BootstrapInfo> {
+    let mut r = Reader { data: body, pos: 0 };
+    r.u32()?; // version + flags
+    r.u32()?; // bootstrap info version
+    let flags = r.u8()?;
+    let timescale = r.u32()?;
+    r.u64()?; // current media time
+    r.u64()?; // SMPTE time code offset
+    r.skip_string()?; // movie identifier
+    r.skip_string_table()?; // servers
+    r.skip_string_table()?; // qualities
+    r.skip_string()?; // DRM data
+    r.skip_string()?; // metadata
+
+    let mut segment_runs = None;
+    for _ in 0..r.u8()? {
+        let (kind, asrt) = r.child_box()?;
+        if &kind == b"asrt" && segment_runs.is_none() {
+            segment_runs = Some(read_asrt(asrt)?);
+        }
+    }
+    let mut fragment_table = None;
+    for _ in 0..r.u8()? {
+        let (kind, afrt) = r.child_box()?;
+        if &kind == b"afrt" && fragment_table.is_none() {
+            fragment_table = Some(read_afrt(afrt)?);
+        }
+    }
+    let (fragment_timescale, fragment_runs) = fragment_table?;
+
+    Some(BootstrapInfo {
+        live: flags & 0x20 != 0,
+        timescale,
+        segment_runs: segment_runs?,
+        fragment_timescale,
+        fragment_runs,
+    })
+}
+
+fn read_asrt(body: &[u8]) -> Option<Vec<(u32, u32)>> {
+    let mut r = Reader { data: body, pos: 0 };
+    r.u32()?;
+    r.skip_string_table()?;
+    (0..r.u32()?).map(|_| Some((r.u32()?, r.u32()?))).collect()
+}
+
+fn read_afrt(body: &[u8]) -> Option<(u32, Vec<FragmentRun>)> {
+    let mut r = Reader { data: body, pos: 0 };
+    r.u32()?;
+    let timescale = r.u32()?;
+    r.skip_string_table()?;
+    let runs = (0..r.u32()?)
+        .map(|_| {
+            let run = FragmentRun { first_fragment: r.u32()?, first_timestamp: r.u64()?, duration: r.u32()? };
+            if run.duration == 0 {
+                r.u8()?; // discontinuity indicator
+            }
+            Some(run)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((timescale, runs))
+}
+
+/// Expands the bootstrap of `media` into fragment segments sharing an FLV header init section.
+fn build_segments(manifest: &F4mManifest, media: &F4mMedia, info: &BootstrapInfo) -> Result<Vec<Segment>, DownloadError> {
+    if info.live {
+        return Err(DownloadError::Config("Live HDS streams are not supported".to_string()));
+    }
+    let prefix = media
+        .url
+        .as_deref()
+        .ok_or_else(|| DownloadError::Parse("F4M media entry has no url".to_string()))?;
+    let base = manifest
+        .base_url
+        .join(prefix)
+        .map_err(|e| DownloadError::Parse(format!("Failed to resolve HDS media URL '{}': {}", prefix, e)))?;
+
+    let map = Some(InitSection {
+        url: base.to_string(),
+        byte_range: None,
+        data: Some(Arc::from(flv_preamble(media.metadata.as_deref()))),
+    });
+
+    let fragments = info.fragments()?;
+    if fragments.is_empty() {
+        return Err(DownloadError::Config("HDS bootstrap lists no fragments".to_string()));
+    }
+    Ok(fragments
+        .into_iter()
+        .map(|(segment, fragment)| {
+            let url = format!("{}Seg{}-Frag{}", base, segment, fragment);
+            let mut segment = Segment::new(url, info.fragment_duration(fragment)).with_map(map.clone());
+            segment.transform = Some(FragmentTransform::F4f);
+            segment
+        })
+        .collect())
+}
+
+/// FLV header, followed by an `onMetaData` script tag when the manifest carries one.
+fn flv_preamble(metadata: Option<&[u8]>) -> Vec<u8> {
+    let mut out = FLV_HEADER.to_vec();
+    if let Some(metadata) = metadata {
+        let len = metadata.len() as u32;
+        out.push(FLV_SCRIPT_TAG);
+        out.extend_from_slice(&len.to_be_bytes()[1..]);
+        out.extend_from_slice(&[0; 7]); // timestamp, extended timestamp, stream id
+        out.extend_from_slice(metadata);
+        out.extend_from_slice(&(len + 11).to_be_bytes());
+    }
+    out
+}
+
+/// Extracts the FLV tags from an F4F fragment (the payload of its `mdat` boxes).
+pub fn unpack_fragment(data: &[u8]) -> Vec<u8> {
+    iter_boxes(data)
+        .filter(|(kind, _)| kind == b"mdat")
+        .flat_map(|(_, body)| body.iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = (body.len() as u32 + 12).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// VOD bootstrap: segment 1 holds 3 fragments of 4 s, segment 2 holds 2 (runs in 1 s units).
+    fn bootstrap() -> Vec<u8> {
+        let mut asrt = vec![0]; // no quality entries
+        asrt.extend_from_slice(&2u32.to_be_bytes());
+        for (first, count) in [(1u32, 3u32), (2, 2)] {
+            asrt.extend_from_slice(&first.to_be_bytes());
+            asrt.extend_from_slice(&count.to_be_bytes());
+        }
+        let mut afrt = 1000u32.to_be_bytes().to_vec();
+        afrt.push(0);
+        afrt.extend_from_slice(&2u32.to_be_bytes());
+        afrt.extend_from_slice(&1u32.to_be_bytes());
+        afrt.extend_from_slice(&0u64.to_be_bytes());
+        afrt.extend_from_slice(&4000u32.to_be_bytes());
+        afrt.extend_from_slice(&6u32.to_be_bytes()); // end-of-presentation marker
+        afrt.extend_from_slice(&20_000u64.to_be_bytes());
+        afrt.extend_from_slice(&0u32.to_be_bytes());
+        afrt.push(0);
+
+        let mut abst = 1u32.to_be_bytes().to_vec();
+        abst.push(0); // profile 0, not live
+        abst.extend_from_slice(&1000u32.to_be_bytes());
+        abst.extend_from_slice(&20_000u64.to_be_bytes());
+        abst.extend_from_slice(&0u64.to_be_bytes());
+        abst.extend_from_slice(&[0, 0, 0, 0, 0]); // movie id, servers, qualities, DRM, metadata
+        abst.push(1);
+        abst.extend(full_box(b"asrt", &asrt));
+        abst.push(1);
+        abst.extend(full_box(b"afrt", &afrt));
+        full_box(b"abst", &abst)
+    }
+
+    #[test]
+    fn test_manifest_and_bootstrap_to_fragment_urls() {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(bootstrap());
+        let manifest = format!(
+            r#"<manifest xmlns="http://ns.adobe.com/f4m/1.0">
+                <baseURL>https://archive.example.com/hds</baseURL>
+                <bootstrapInfo profile="named" id="boot1">{}</bootstrapInfo>
+                <media url="show_500" bitrate="500" bootstrapInfoId="boot1"/>
+                <media url="show_1500" bitrate="1500" bootstrapInfoId="boot1"><metadata>AgAKb25NZXRhRGF0YQ==</metadata></media>
+            </manifest>"#,
+            encoded
+        );
+        let manifest = parse_manifest(&manifest, &Url::parse("https://cdn.example.com/show.f4m").unwrap()).unwrap();
+        let media = manifest.best_media().unwrap();
+        assert_eq!(media.bitrate, 1500);
+
+        let Bootstrap::Inline(data) = manifest.bootstrap_for(media).unwrap() else { panic!("expected inline bootstrap") };
+        let info = parse_bootstrap(data).unwrap();
+        assert_eq!(info.fragments().unwrap(), vec![(1, 1), (1, 2), (1, 3), (2, 4), (2, 5)]);
+        assert_eq!(info.fragment_duration(5), 4.0);
+
+        let segments = build_segments(&manifest, media, &info).unwrap();
+        assert_eq!(segments[3].url, "https://archive.example.com/hds/show_1500Seg2-Frag4");
+        let init = segments[0].map.as_ref().and_then(|m| m.data.clone()).unwrap();
+        assert!(init.starts_with(FLV_HEADER));
+        assert_eq!(init[FLV_HEADER.len()], FLV_SCRIPT_TAG);
+    }
+
+    #[test]
+    fn test_degenerate_bootstrap_fails() {
+        let run = FragmentRun { first_fragment: 1, first_timestamp: 0, duration: 4000 };
+        let info = BootstrapInfo {
+            live: false,
+            timescale: 1000,
+            segment_runs: vec![(1, u32::MAX)],
+            fragment_timescale: 1000,
+            fragment_runs: vec![run],
+        };
+        assert!(info.fragments().is_err());
+
+        let info = BootstrapInfo {
+            segment_runs: vec![(1, 2)],
+            fragment_runs: vec![FragmentRun { first_fragment: u32::MAX, ..run }],
+            ..info
+        };
+        assert!(info.fragments().is_err());
+    }
+
+    #[test]
+    fn test_unpack_fragment_keeps_mdat_payload() {
+        let mut fragment = Vec::new();
+        for (kind, body) in [(b"afra", &[0u8; 5][..]), (b"moof", &[0u8; 8][..]), (b"mdat", &[9u8, 0, 0, 3][..])] {
+            fragment.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+            fragment.extend_from_slice(kind);
+            fragment.extend_from_slice(body);
+        }
+        assert_eq!(unpack_fragment(&fragment), vec![9, 0, 0, 3]);
+    }
+}
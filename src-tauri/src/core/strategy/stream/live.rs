@@ -0,0 +1,354 @@
+//! Live HLS recording.
+//!
+//! A media playlist without `#EXT-X-ENDLIST` is still being written. Rather than downloading the
+//! segments it lists at one moment, the recorder keeps reloading it and appends every new segment
+//! to the output until the stream ends or the download is stopped.
+//!
+//! Low-Latency HLS streams are followed part by part: parts of the segment being published are
+//! written as they appear, the part named by the preload hint is requested before it is listed,
+//! and reloads block on the server (`_HLS_msn`/`_HLS_part`) instead of polling. When a segment
+//! whose first parts are already on disk is completed, only its remaining parts are fetched.
+
+use crate::core::error::DownloadError;
+use super::downloader::{fetch_range, wait_for_signal, SegmentOutcome};
+use crate::core::strategy::TaskSignal;
+use super::playlist::{self, MediaPlaylist, PartialSegment, Playlist, PreloadHint};
+use super::processor::StreamProcessor;
+use super::resolver::fetch_manifest;
+use super::segment::{ByteRange, InitSection, Segment};
+use super::tracker::ProgressTracker;
+use reqwest::header::{HeaderMap, RANGE};
+use reqwest::Client;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Segments behind the live edge where a recording starts.
+const START_SEGMENTS_BEHIND: u64 = 3;
+/// Playlist reloads in a row that may fail before the recording gives up.
+const MAX_RELOAD_FAILURES: u32 = 5;
+
+/// `url` with the blocking reload parameters asking for segment `msn`, or for part `part` of it.
+pub fn blocking_reload_url(url: &Url, msn: u64, part: Option<usize>) -> Url {
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| !k.starts_with("_HLS_"))
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    {
+        let mut query = url.query_pairs_mut();
+        query.clear().extend_pairs(pairs);
+        query.append_pair("_HLS_msn", &msn.to_string());
+        if let Some(part) = part {
+            query.append_pair("_HLS_part", &part.to_string());
+        }
+    }
+    url
+}
+
+/// The next piece to record: segment `msn`, of which `part` parts (`bytes` bytes) are on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+    pub msn: u64,
+    pub part: usize,
+    pub bytes: u64,
+}
+
+impl Cursor {
+    /// Where a recording of `playlist` starts: a few segments behind the live edge.
+    pub fn start(playlist: &MediaPlaylist) -> Self {
+        let msn = playlist.next_sequence().saturating_sub(START_SEGMENTS_BEHIND).max(playlist.media_sequence);
+        Self { msn, ..Default::default() }
+    }
+}
+
+/// Records the live media playlist at `playlist_url` into `output`, appending to what an
+/// earlier, paused run left there.
+///
+/// Returns `Completed` once the playlist ends, or `Interrupted` with the control signal.
+pub async fn record_live_hls(
+    client: &Client,
+    processor: &StreamProcessor,
+    playlist_url: &str,
+    headers: &HeaderMap,
+    output: &Path,
+    signal: &TaskSignal,
+    tracker: &ProgressTracker,
+) -> Result<SegmentOutcome, DownloadError> {
+    let base = Url::parse(playlist_url).map_err(|e| DownloadError::Config(format!("Invalid playlist URL: {}", e)))?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output)
+        .await
+        .map_err(|e| DownloadError::Config(format!("Failed to create output file: {}", e)))?;
+    let mut recorder = LiveRecorder { client, processor, headers, signal, tracker, file, written_map: None };
+
+    let mut playlist = load_playlist(client, &base, headers).await?;
+    let mut cursor = Cursor::start(&playlist);
+    info!(
+        "Live: Recording {} from segment {} (low latency: {}, blocking reload: {})",
+        playlist_url,
+        cursor.msn,
+        playlist.part_target.is_some(),
+        playlist.can_block_reload
+    );
+
+    let mut failures = 0;
+    loop {
+        let outcome = recorder.catch_up(&playlist, &mut cursor).await;
+        recorder.file.flush().await?;
+        outcome?;
+
+        let value = signal.load();
+        if value != 0 {
+            return Ok(SegmentOutcome::Interrupted(value));
+        }
+        if playlist.end_list && cursor.msn >= playlist.next_sequence() {
+            info!("Live: Stream ended at segment {}", cursor.msn);
+            return Ok(SegmentOutcome::Completed);
+        }
+
+        let reload = if playlist.can_block_reload {
+            // The server answers once the next segment or part we need is listed.
+            blocking_reload_url(&base, cursor.msn, playlist.part_target.map(|_| cursor.part))
+        } else {
+            let interval = playlist.part_target.unwrap_or(playlist.target_duration.unwrap_or(4.0) / 2.0);
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs_f64(interval.max(0.1))) => {}
+                value = wait_for_signal(signal) => return Ok(SegmentOutcome::Interrupted(value)),
+            }
+            base.clone()
+        };
+
+        let result = tokio::select! {
+            result = load_playlist(client, &reload, headers) => result,
+            value = wait_for_signal(signal) => return Ok(SegmentOutcome::Interrupted(value)),
+        };
+        match result {
+            Ok(reloaded) => {
+                failures = 0;
+                playlist = reloaded;
+            }
+            Err(e) if failures + 1 < MAX_RELOAD_FAILURES => {
+                failures += 1;
+                warn!("Live: Playlist reload failed ({}/{}): {}", failures, MAX_RELOAD_FAILURES, e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn load_playlist(client: &Client, url: &Url, headers: &HeaderMap) -> Result<MediaPlaylist, DownloadError> {
+    let text = fetch_manifest(url.as_str(), client, headers).await?;
+    match playlist::parse_playlist(&text, url)? {
+        Playlist::Media(media) => Ok(media),
+        Playlist::Master(_) => Err(DownloadError::Parse(format!("Expected a media playlist at {}", url))),
+    }
+}
+
+struct LiveRecorder<'a> {
+    client: &'a Client,
+    processor: &'a StreamProcessor,
+    headers: &'a HeaderMap,
+    signal: &'a TaskSignal,
+    tracker: &'a ProgressTracker,
+    file: File,
+    /// Init section last written; a new one is written wherever it changes.
+    written_map: Option<InitSection>,
+}
+
+impl LiveRecorder<'_> {
+    /// Writes everything `playlist` offers past `cursor`: complete segments, the parts of the
+    /// segment being published, and the hinted next part.
+    async fn catch_up(&mut self, playlist: &MediaPlaylist, cursor: &mut Cursor) -> Result<(), DownloadError> {
+        if cursor.msn < playlist.media_sequence {
+            warn!("Live: {} segment(s) left the playlist before they were recorded", playlist.media_sequence - cursor.msn);
+            *cursor = Cursor { msn: playlist.media_sequence, ..Default::default() };
+        }
+
+        while cursor.msn < playlist.next_sequence() {
+            let segment = &playlist.segments[(cursor.msn - playlist.media_sequence) as usize];
+            if let Some(key) = &segment.key {
+                return Err(DownloadError::Config(format!(
+                    "Stream is encrypted (METHOD={}); encrypted segments are not supported",
+                    key.method
+                )));
+            }
+            self.write_init(segment.map.as_ref()).await?;
+
+            // Finish a segment started from its parts with the parts still missing, if listed.
+            let remaining = playlist.parts.get(&cursor.msn).filter(|parts| cursor.part > 0 && parts.len() >= cursor.part);
+            let done = match remaining {
+                Some(parts) => self.write_parts(&parts[cursor.part..], cursor).await?,
+                None => false,
+            };
+            if !done && !self.write_segment(segment, cursor).await? {
+                return Ok(());
+            }
+            self.tracker.record_segment(cursor.bytes, segment.duration);
+            *cursor = Cursor { msn: cursor.msn + 1, ..Default::default() };
+        }
+
+        let listed = playlist.parts.get(&cursor.msn).map_or(&[][..], Vec::as_slice);
+        if cursor.part == 0 && !listed.is_empty() {
+            self.write_init(playlist.segments.last().and_then(|s| s.map.as_ref())).await?;
+        }
+        if cursor.part < listed.len() && !self.write_parts(&listed[cursor.part..], cursor).await? {
+            return Ok(());
+        }
+        if let Some(hint) = playlist.preload_hints.iter().find(|h| h.hint_type == "PART") {
+            if cursor.part == listed.len() {
+                if cursor.part == 0 {
+                    self.write_init(playlist.segments.last().and_then(|s| s.map.as_ref())).await?;
+                }
+                self.write_hinted_part(hint, cursor).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a complete segment, or only its bytes past `cursor.bytes` if its first parts are
+    /// already on disk. A segment that keeps failing is skipped; `false` means interrupted.
+    async fn write_segment(&mut self, segment: &Segment, cursor: &mut Cursor) -> Result<bool, DownloadError> {
+        let Some(bytes) = self.fetch(&segment.url, segment.byte_range.as_ref()).await else {
+            if self.signal.load() != 0 {
+                return Ok(false);
+            }
+            warn!("Live: Skipping segment {} after repeated failures", cursor.msn);
+            return Ok(true);
+        };
+        let bytes = if cursor.bytes == 0 {
+            self.processor.process_segment(bytes).bytes
+        } else {
+            debug!("Live: Completing segment {} from byte {}", cursor.msn, cursor.bytes);
+            bytes.get(cursor.bytes as usize..).map(<[u8]>::to_vec).unwrap_or_default()
+        };
+        self.write(&bytes, cursor).await?;
+        Ok(true)
+    }
+
+    /// Writes `parts` in order, stepping over gaps. `false` if one could not be fetched.
+    async fn write_parts(&mut self, parts: &[PartialSegment], cursor: &mut Cursor) -> Result<bool, DownloadError> {
+        for part in parts {
+            if part.gap {
+                debug!("Live: Part {}.{} is a gap", cursor.msn, cursor.part);
+            } else {
+                let Some(bytes) = self.fetch(&part.url, part.byte_range.as_ref()).await else {
+                    return Ok(false);
+                };
+                self.write(&bytes, cursor).await?;
+            }
+            cursor.part += 1;
+        }
+        Ok(true)
+    }
+
+    /// Requests the hinted part ahead of its listing; the server holds the response until the
+    /// part exists. A failure is not an error, the part is fetched once it is listed.
+    async fn write_hinted_part(&mut self, hint: &PreloadHint, cursor: &mut Cursor) -> Result<(), DownloadError> {
+        let range = match hint.byte_range_length {
+            Some(length) if length > 0 => Some(format!("bytes={}-{}", hint.byte_range_start, hint.byte_range_start + length - 1)),
+            _ if hint.byte_range_start > 0 => Some(format!("bytes={}-", hint.byte_range_start)),
+            _ => None,
+        };
+        let mut request = self.client.get(&hint.url).headers(self.headers.clone());
+        if let Some(range) = range {
+            request = request.header(RANGE, range);
+        }
+
+        let response = tokio::select! {
+            response = request.send() => response,
+            _ = wait_for_signal(self.signal) => return Ok(()),
+        };
+        let body = match response {
+            Ok(response) if response.status().is_success() => {
+                let partial = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+                response.bytes().await.map(|body| (partial, body)).map_err(|e| e.to_string())
+            }
+            Ok(response) => Err(format!("server returned {}", response.status())),
+            Err(e) => Err(e.to_string()),
+        };
+        let bytes = match body {
+            Ok((partial, body)) if partial || hint.byte_range_start == 0 => body.to_vec(),
+            // The server ignored the range and sent the whole resource.
+            Ok((_, body)) => body.get(hint.byte_range_start as usize..).map(<[u8]>::to_vec).unwrap_or_default(),
+            Err(e) => {
+                debug!("Live: Hinted part {} failed: {}", hint.url, e);
+                return Ok(());
+            }
+        };
+        self.write(&bytes, cursor).await?;
+        cursor.part += 1;
+        Ok(())
+    }
+
+    /// Writes the init section if it differs from the last one written.
+    async fn write_init(&mut self, map: Option<&InitSection>) -> Result<(), DownloadError> {
+        let Some(map) = map.filter(|map| self.written_map.as_ref() != Some(*map)) else {
+            return Ok(());
+        };
+        let bytes = match &map.data {
+            Some(data) => data.to_vec(),
+            None => self
+                .fetch(&map.url, map.byte_range.as_ref())
+                .await
+                .ok_or_else(|| DownloadError::Network(format!("Failed to fetch init section {}", map.url)))?,
+        };
+        self.file.write_all(&bytes).await?;
+        self.written_map = Some(map.clone());
+        Ok(())
+    }
+
+    async fn write(&mut self, bytes: &[u8], cursor: &mut Cursor) -> Result<(), DownloadError> {
+        self.file.write_all(bytes).await?;
+        cursor.bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Fetches `url` (up to 3 attempts); `None` if every attempt failed or the signal was raised.
+    async fn fetch(&self, url: &str, range: Option<&ByteRange>) -> Option<Vec<u8>> {
+        for attempt in 1..=3 {
+            if self.signal.load() != 0 {
+                return None;
+            }
+            match fetch_range(self.client, url, range, self.headers).await {
+                Ok(bytes) => return Some(bytes),
+                Err(e) => warn!("Live: Failed to fetch {} ({}/3): {}", url, attempt, e),
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocking_reload_url() {
+        let url = Url::parse("https://cdn.example.com/live/index.m3u8?token=abc&_HLS_msn=1").unwrap();
+        assert_eq!(
+            blocking_reload_url(&url, 273, Some(2)).as_str(),
+            "https://cdn.example.com/live/index.m3u8?token=abc&_HLS_msn=273&_HLS_part=2"
+        );
+        let url = Url::parse("https://cdn.example.com/live/index.m3u8").unwrap();
+        assert_eq!(blocking_reload_url(&url, 5, None).as_str(), "https://cdn.example.com/live/index.m3u8?_HLS_msn=5");
+    }
+
+    #[test]
+    fn test_cursor_starts_behind_live_edge() {
+        let mut playlist = MediaPlaylist { media_sequence: 100, ..Default::default() };
+        playlist.segments = (0..6).map(|i| Segment::new(format!("https://cdn.example.com/{}.ts", i), 4.0)).collect();
+        assert_eq!(Cursor::start(&playlist), Cursor { msn: 103, part: 0, bytes: 0 });
+
+        playlist.segments.truncate(1);
+        assert_eq!(Cursor::start(&playlist).msn, 100);
+    }
+}
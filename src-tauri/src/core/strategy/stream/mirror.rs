@@ -0,0 +1,427 @@
+//! Offline HLS mirror.
+//!
+//! Instead of joining segments into one file, the stream is archived as served: the master
+//! playlist, the media playlists of the chosen variant and renditions, and every key, init map
+//! and segment they reference. Files are laid out like the URL paths below the master's
+//! directory (other hosts go under `_external/<host>/`), and each playlist is rewritten to point
+//! at the local copies by relative path, so the directory plays back from any static server.
+//!
+//! Only what the mirror contains stays in the playlists: unchosen variants, I-frame playlists
+//! and low-latency parts are dropped. Files already on disk are kept, so a paused mirror picks
+//! up where it stopped; playlists are written last, so a master playlist means a complete mirror.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::downloader::{wait_for_signal, SegmentOutcome};
+use crate::core::strategy::TaskSignal;
+use super::playlist::{self, Playlist};
+use super::resolver::fetch_manifest;
+use super::subtitles;
+use super::tracker::ProgressTracker;
+use futures_util::StreamExt;
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Assigns every mirrored URL a path inside the mirror directory (`/`-separated, relative to it).
+#[derive(Debug)]
+pub struct MirrorLayout {
+    origin: Url,
+    /// Directory part of the master playlist's path; URLs below it keep their relative path.
+    base_path: String,
+    assigned: HashMap<String, String>,
+    taken: HashSet<String>,
+}
+
+impl MirrorLayout {
+    pub fn new(master_url: &Url) -> Self {
+        let path = master_url.path();
+        let base_path = path[..path.rfind('/').map_or(0, |i| i + 1)].to_string();
+        Self { origin: master_url.clone(), base_path, assigned: HashMap::new(), taken: HashSet::new() }
+    }
+
+    /// Local path of `url`, assigning one on first use. Playlists always get an `.m3u8` name.
+    pub fn local_path(&mut self, url: &Url, playlist: bool) -> String {
+        let mut key = url.clone();
+        key.set_fragment(None);
+        if let Some(path) = self.assigned.get(key.as_str()) {
+            return path.clone();
+        }
+
+        let same_origin = url.origin() == self.origin.origin();
+        let relative = match url.path().strip_prefix(&self.base_path) {
+            Some(rest) if same_origin => rest.to_string(),
+            _ => format!("_external/{}{}", url.host_str().unwrap_or("unknown"), url.path()),
+        };
+        let mut parts: Vec<String> = relative
+            .split('/')
+            .filter(|p| !p.is_empty() && *p != "." && *p != "..")
+            .map(sanitize_filename::sanitize)
+            .filter(|p| !p.is_empty())
+            .collect();
+        if relative.ends_with('/') || parts.is_empty() {
+            parts.push("index".to_string());
+        }
+        let name = parts.last_mut().unwrap();
+        if playlist && !name.to_lowercase().ends_with(".m3u8") {
+            name.push_str(".m3u8");
+        }
+
+        // Same path with a different query (tokens, `?n=1`) is a different file.
+        let candidate = parts.join("/");
+        let mut path = candidate.clone();
+        let mut n = 2;
+        while self.taken.contains(&path) {
+            path = match candidate.rsplit_once('.') {
+                Some((stem, ext)) if !ext.contains('/') => format!("{}-{}.{}", stem, n, ext),
+                _ => format!("{}-{}", candidate, n),
+            };
+            n += 1;
+        }
+        self.taken.insert(path.clone());
+        self.assigned.insert(key.to_string(), path.clone());
+        path
+    }
+}
+
+/// `target` as referenced from a playlist at `from` (both relative to the mirror root).
+pub fn relative_path(from: &str, target: &str) -> String {
+    let from_dir: Vec<&str> = from.split('/').collect::<Vec<_>>().split_last().map_or(Vec::new(), |(_, dir)| dir.to_vec());
+    let target_parts: Vec<&str> = target.split('/').collect();
+    let common = from_dir.iter().zip(&target_parts).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<&str> = vec![".."; from_dir.len() - common];
+    parts.extend(&target_parts[common..]);
+    parts.join("/")
+}
+
+/// Rewrites the URIs of a playlist (URI lines and `URI="..."` attributes).
+///
+/// `rewrite` receives each URI resolved against `base` and returns its replacement, or `None`
+/// to drop the line; a dropped variant URI also drops its `#EXT-X-STREAM-INF` line.
+pub fn rewrite_playlist(text: &str, base: &Url, mut rewrite: impl FnMut(&Url) -> Option<String>) -> String {
+    let mut output = Vec::new();
+    let mut pending_stream_inf: Option<&str> = None;
+
+    for line in text.lines().map(str::trim_end) {
+        if line.starts_with("#EXT-X-STREAM-INF") {
+            pending_stream_inf = Some(line);
+            continue;
+        }
+        if line.starts_with('#') {
+            match rewrite_uri_attribute(line, base, &mut rewrite) {
+                Some(line) => output.push(line),
+                None => debug!("Mirror: dropping {}", line),
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            output.push(String::new());
+            continue;
+        }
+        let stream_inf = pending_stream_inf.take();
+        match base.join(line.trim()).ok().and_then(|url| rewrite(&url)) {
+            Some(uri) => {
+                output.extend(stream_inf.map(str::to_string));
+                output.push(uri);
+            }
+            None => debug!("Mirror: dropping {}", line),
+        }
+    }
+
+    let mut text = output.join("\n");
+    text.push('\n');
+    text
+}
+
+fn rewrite_uri_attribute(line: &str, base: &Url, rewrite: &mut impl FnMut(&Url) -> Option<String>) -> Option<String> {
+    let Some(start) = line.find("URI=\"").map(|i| i + 5) else {
+        return Some(line.to_string());
+    };
+    let end = start + line[start..].find('"')?;
+    let url = base.join(&line[start..end]).ok()?;
+    let uri = rewrite(&url)?;
+    Some(format!("{}{}{}", &line[..start], uri, &line[end..]))
+}
+
+fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// A file the mirror needs, with the media time it covers (0 for keys and maps).
+struct Resource {
+    url: String,
+    path: String,
+    duration: f64,
+}
+
+/// Mirrors the HLS stream at `url` into `root`; returns the master (or only) playlist's path
+/// on completion.
+#[allow(clippy::too_many_arguments)]
+pub async fn mirror_hls(
+    client: &Arc<Client>,
+    url: &str,
+    headers: &reqwest::header::HeaderMap,
+    options: &StreamOptions,
+    root: &Path,
+    max_parallel: usize,
+    signal: TaskSignal,
+    tracker: &ProgressTracker,
+) -> Result<(SegmentOutcome, PathBuf), DownloadError> {
+    let master_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
+    let master_text = fetch_manifest(url, client, headers).await?;
+    let mut layout = MirrorLayout::new(&master_url);
+    let entry_path = layout.local_path(&master_url, true);
+
+    // Media playlists to mirror: (URL, body, local path).
+    let mut media_playlists = Vec::new();
+    let mut playlists: Vec<(String, String)> = Vec::new();
+    match playlist::parse_playlist(&master_text, &master_url)? {
+        Playlist::Media(_) => media_playlists.push((master_url.clone(), master_text, entry_path.clone())),
+        Playlist::Master(master) => {
+            let variant = master.best_variant().ok_or_else(|| DownloadError::Config("Master playlist has no variants".to_string()))?;
+            let mut chosen = vec![variant.uri.clone()];
+            chosen.extend(master.audio_rendition_for(variant).and_then(|r| r.uri.clone()));
+            chosen.extend(
+                master
+                    .subtitle_renditions_for(variant)
+                    .into_iter()
+                    .filter(|r| subtitles::language_selected(&options.subtitle_languages, r.language.as_deref()))
+                    .filter_map(|r| r.uri.clone()),
+            );
+            info!("Mirror: keeping {} of the master playlist's media playlists", chosen.len());
+
+            for uri in &chosen {
+                let media_url = Url::parse(uri).map_err(|e| DownloadError::Parse(format!("Invalid playlist URL {}: {}", uri, e)))?;
+                let text = fetch_manifest(uri, client, headers).await?;
+                let path = layout.local_path(&media_url, true);
+                media_playlists.push((media_url, text, path));
+            }
+            let rewritten = rewrite_playlist(&master_text, &master_url, |url| {
+                let url = url.as_str().to_string();
+                chosen.contains(&url).then(|| {
+                    let path = media_playlists.iter().find(|(u, _, _)| u.as_str() == url).map(|(_, _, p)| p.as_str()).unwrap_or_default();
+                    relative_path(&entry_path, path)
+                })
+            });
+            playlists.push((entry_path.clone(), rewritten));
+        }
+    }
+
+    // Every key, init map and segment, once each (byte-range segments share one file).
+    let mut resources: Vec<Resource> = Vec::new();
+    for (media_url, text, path) in &media_playlists {
+        let Playlist::Media(media) = playlist::parse_playlist(text, media_url)? else {
+            return Err(DownloadError::Parse(format!("Expected a media playlist at {}", media_url)));
+        };
+        if !media.end_list {
+            warn!("Mirror: {} is a live playlist, mirroring the {} segments it lists now", media_url, media.segments.len());
+        }
+        let mut add = |url: &str, duration: f64, resources: &mut Vec<Resource>| {
+            let Ok(parsed) = Url::parse(url) else { return };
+            if !is_http(&parsed) {
+                return;
+            }
+            let local = layout.local_path(&parsed, false);
+            match resources.iter_mut().find(|r| r.path == local) {
+                Some(existing) => existing.duration += duration,
+                None => resources.push(Resource { url: url.to_string(), path: local, duration }),
+            }
+        };
+        for segment in &media.segments {
+            if let Some(uri) = segment.key.as_ref().and_then(|k| k.uri.as_deref()) {
+                add(uri, 0.0, &mut resources);
+            }
+            if let Some(map) = &segment.map {
+                add(&map.url, 0.0, &mut resources);
+            }
+            add(&segment.url, segment.duration, &mut resources);
+        }
+
+        let mirrored: HashSet<String> = resources.iter().map(|r| r.url.clone()).collect();
+        let rewritten = rewrite_playlist(text, media_url, |url| {
+            if !is_http(url) {
+                // `data:` and DRM key URIs (`skd://`) stay as they are.
+                return Some(url.to_string());
+            }
+            mirrored.contains(url.as_str()).then(|| relative_path(path, &layout.local_path(url, false)))
+        });
+        playlists.push((path.clone(), rewritten));
+    }
+
+    if options.clip_range().is_some() {
+        warn!("Mirror: clipping is not applied to mirrored streams");
+    }
+    info!("Mirror: {} playlists and {} files into {}", playlists.len(), resources.len(), root.display());
+
+    let outcome = download_resources(client, &resources, headers, root, max_parallel, signal, tracker).await?;
+    if outcome != SegmentOutcome::Completed {
+        return Ok((outcome, root.join(&entry_path)));
+    }
+
+    // Written last: the entry playlist only appears once everything it references is there.
+    for (path, text) in playlists.iter().rev() {
+        let target = root.join(path);
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&target, text).await?;
+    }
+    Ok((SegmentOutcome::Completed, root.join(entry_path)))
+}
+
+/// Fetches every resource not already on disk, `max_parallel` at a time.
+async fn download_resources(
+    client: &Arc<Client>,
+    resources: &[Resource],
+    headers: &reqwest::header::HeaderMap,
+    root: &Path,
+    max_parallel: usize,
+    signal: TaskSignal,
+    tracker: &ProgressTracker,
+) -> Result<SegmentOutcome, DownloadError> {
+    let durations: Vec<f64> = resources.iter().map(|r| r.duration).collect();
+    tracker.add_track(resources.len(), &durations);
+
+    // (URL, target file, duration) of everything still to fetch.
+    let mut missing: Vec<(String, PathBuf, f64)> = Vec::new();
+    for resource in resources {
+        let target = root.join(&resource.path);
+        match tokio::fs::metadata(&target).await {
+            Ok(meta) => tracker.add_existing(1, resource.duration, meta.len()),
+            Err(_) => missing.push((resource.url.clone(), target, resource.duration)),
+        }
+    }
+    debug!("Mirror: {} of {} files already on disk", resources.len() - missing.len(), resources.len());
+
+    let mut fetches = futures_util::stream::iter(missing)
+        .map(|(url, target, duration)| {
+            let client = client.clone();
+            let headers = headers.clone();
+            let signal = signal.clone();
+            async move {
+                let bytes = fetch_resource(&client, &url, &headers, &signal).await?;
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // Written under a temporary name so a file on disk is always complete.
+                let partial = target.with_extension("mirror-part");
+                tokio::fs::write(&partial, &bytes).await?;
+                tokio::fs::rename(&partial, &target).await?;
+                Ok::<_, DownloadError>((bytes.len() as u64, duration))
+            }
+        })
+        .buffer_unordered(max_parallel.max(1));
+
+    loop {
+        let result = tokio::select! {
+            result = fetches.next() => result,
+            signal = wait_for_signal(&signal) => return Ok(SegmentOutcome::Interrupted(signal)),
+        };
+        let Some(result) = result else { break };
+        let signal_value = signal.load();
+        if signal_value != 0 {
+            return Ok(SegmentOutcome::Interrupted(signal_value));
+        }
+        let (bytes, duration) = result?;
+        tracker.record_segment(bytes, duration);
+    }
+    Ok(SegmentOutcome::Completed)
+}
+
+/// GETs a whole resource, with up to 3 attempts.
+async fn fetch_resource(client: &Client, url: &str, headers: &reqwest::header::HeaderMap, signal: &TaskSignal) -> Result<Vec<u8>, DownloadError> {
+    let mut last_error = String::new();
+    for _ in 0..3 {
+        if signal.load() != 0 {
+            break;
+        }
+        match client.get(url).headers(headers.clone()).send().await {
+            Ok(response) if response.status().is_success() => match response.bytes().await {
+                Ok(body) => return Ok(body.to_vec()),
+                Err(e) => last_error = format!("failed to read body: {}", e),
+            },
+            Ok(response) => last_error = format!("server returned {}", response.status()),
+            Err(e) => last_error = format!("network error: {}", e),
+        }
+        warn!("Mirror: failed to fetch {}: {}", url, last_error);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    }
+    Err(DownloadError::Network(format!("Failed to mirror {}: {}", url, last_error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_and_relative_paths() {
+        let master = Url::parse("https://cdn.example.com/show/ep1/master.m3u8?token=abc").unwrap();
+        let mut layout = MirrorLayout::new(&master);
+        let mut path = |url: &str, playlist| layout.local_path(&Url::parse(url).unwrap(), playlist);
+
+        assert_eq!(path("https://cdn.example.com/show/ep1/master.m3u8?token=abc", true), "master.m3u8");
+        assert_eq!(path("https://cdn.example.com/show/ep1/720p/index.m3u8", true), "720p/index.m3u8");
+        assert_eq!(path("https://cdn.example.com/show/ep1/720p/seg1.ts?n=1", false), "720p/seg1.ts");
+        assert_eq!(path("https://cdn.example.com/show/ep1/720p/seg1.ts?n=2", false), "720p/seg1-2.ts");
+        assert_eq!(path("https://cdn.example.com/show/ep1/720p/seg1.ts?n=1", false), "720p/seg1.ts");
+        assert_eq!(path("https://keys.example.net/k/1", false), "_external/keys.example.net/k/1");
+        assert_eq!(path("https://cdn.example.com/audio/play", true), "_external/cdn.example.com/audio/play.m3u8");
+
+        assert_eq!(relative_path("master.m3u8", "720p/index.m3u8"), "720p/index.m3u8");
+        assert_eq!(relative_path("720p/index.m3u8", "720p/seg1.ts"), "seg1.ts");
+        assert_eq!(relative_path("720p/index.m3u8", "_external/keys.example.net/k/1"), "../_external/keys.example.net/k/1");
+    }
+
+    #[test]
+    fn test_rewrite_master_keeps_chosen_playlists() {
+        let base = Url::parse("https://cdn.example.com/v/master.m3u8").unwrap();
+        let text = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",DEFAULT=YES,URI=\"audio/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"French\",URI=\"audio/fr.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID=\"cc\",NAME=\"CC1\",INSTREAM-ID=\"CC1\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,AUDIO=\"aud\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,AUDIO=\"aud\"\n\
+            high/index.m3u8\n\
+            #EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=90000,URI=\"high/iframe.m3u8\"\n";
+        let chosen = ["https://cdn.example.com/v/high/index.m3u8", "https://cdn.example.com/v/audio/en.m3u8"];
+        let rewritten = rewrite_playlist(text, &base, |url| {
+            chosen.contains(&url.as_str()).then(|| url.path().trim_start_matches("/v/").to_string())
+        });
+        assert_eq!(
+            rewritten,
+            "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",DEFAULT=YES,URI=\"audio/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID=\"cc\",NAME=\"CC1\",INSTREAM-ID=\"CC1\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,AUDIO=\"aud\"\n\
+            high/index.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_media_playlist_to_local_paths() {
+        let base = Url::parse("https://cdn.example.com/v/high/index.m3u8").unwrap();
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example.net/k/1\",IV=0x01\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\nseg1.m4s?n=1\n\
+            #EXT-X-PART:DURATION=1.0,URI=\"seg2.part0.m4s\"\n\
+            #EXTINF:6.0,\n../shared/seg2.m4s\n#EXT-X-ENDLIST\n";
+        let mut layout = MirrorLayout::new(&Url::parse("https://cdn.example.com/v/master.m3u8").unwrap());
+        let playlist_path = layout.local_path(&base, true);
+        let rewritten = rewrite_playlist(text, &base, |url| {
+            (!url.path().contains(".part")).then(|| relative_path(&playlist_path, &layout.local_path(url, false)))
+        });
+        assert_eq!(
+            rewritten,
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"../_external/keys.example.net/k/1\",IV=0x01\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\nseg1.m4s\n\
+            #EXTINF:6.0,\n../shared/seg2.m4s\n#EXT-X-ENDLIST\n"
+        );
+    }
+}
@@ -1,26 +1,58 @@
+pub mod dailymotion;
+pub mod dash;
+pub mod discontinuity;
 pub mod downloader;
+pub mod hds;
+pub mod live;
+pub mod mirror;
+pub mod mux;
+pub mod platform;
+pub mod playlist;
 pub mod processor;
+pub mod progress;
+pub mod registry;
 pub mod resolver;
+pub mod segment;
+pub mod smooth;
+pub mod subtitles;
+pub mod tracker;
+pub mod validate;
+pub mod vimeo;
+pub mod webpage;
 pub mod youtube;
 
 use super::{DownloadContext, DownloadStrategy};
 use crate::commands::DownloadCommandResult;
 use crate::core::error::DownloadError;
+use crate::core::persistence;
+use crate::network::headers;
+use crate::core::state::DiscontinuityMode;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use reqwest::Client;
-use downloader::ParallelDownloader;
+use downloader::{BufferLimit, ParallelDownloader, SegmentOutcome};
+use mux::{Container, MediaKind, MuxInput};
 use processor::StreamProcessor;
-use resolver::{StreamResolver, HlsResolver};
-use youtube::YoutubeResolver;
+use registry::ResolverRegistry;
+use resolver::{ResolvedStream, StreamTrack, TrackKind};
+use tauri::Emitter;
+use tracker::ProgressTracker;
 use tokio::fs::OpenOptions;
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
 
 pub struct StreamingConfig {
     pub enable_parallel_segments: bool,
     pub enable_header_stripping: bool,
     pub enable_platform_resolvers: bool,
+    /// Mux separate audio/video renditions into one Matroska file (otherwise both are kept side by side).
+    pub enable_native_muxing: bool,
     pub max_parallel_connections: usize,
+    /// Out-of-order segments buffered in memory before new fetches are held back.
     pub buffer_high_water_mark: usize,
+    /// Same limit in bytes; whichever is reached first applies.
+    pub buffer_high_water_bytes: usize,
+    /// Times a TS segment that fails validation is fetched again before it is kept as damaged.
+    pub max_segment_refetches: usize,
 }
 
 impl Default for StreamingConfig {
@@ -29,8 +61,11 @@ impl Default for StreamingConfig {
             enable_parallel_segments: true,
             enable_header_stripping: true,
             enable_platform_resolvers: true,
+            enable_native_muxing: true,
             max_parallel_connections: 16,
             buffer_high_water_mark: 32,
+            buffer_high_water_bytes: 128 * 1024 * 1024,
+            max_segment_refetches: 2,
         }
     }
 }
@@ -40,8 +75,7 @@ pub struct UniversalStreamingStrategy {
     client: Arc<Client>,
     downloader: Arc<ParallelDownloader>,
     processor: Arc<StreamProcessor>,
-    hls_resolver: Arc<HlsResolver>,
-    youtube_resolver: Arc<YoutubeResolver>,
+    registry: ResolverRegistry,
 }
 
 impl UniversalStreamingStrategy {
@@ -49,13 +83,16 @@ impl UniversalStreamingStrategy {
         let config = config.unwrap_or_default();
         let client = Arc::new(crate::network::client::create_client().unwrap_or_else(|_| Client::new()));
         let processor = Arc::new(StreamProcessor::new(config.enable_header_stripping));
-        let hls_resolver = Arc::new(HlsResolver);
-        let youtube_resolver = Arc::new(YoutubeResolver);
+        let registry = ResolverRegistry::from_config(&config);
         let downloader = Arc::new(ParallelDownloader::new(
             client.clone(),
             processor.clone(),
             config.max_parallel_connections,
-            config.buffer_high_water_mark,
+            BufferLimit {
+                max_segments: config.buffer_high_water_mark,
+                max_bytes: config.buffer_high_water_bytes,
+            },
+            config.max_segment_refetches,
         ));
 
         Self {
@@ -63,8 +100,519 @@ impl UniversalStreamingStrategy {
             client,
             downloader,
             processor,
-            hls_resolver,
-            youtube_resolver,
+            registry,
+        }
+    }
+}
+
+impl UniversalStreamingStrategy {
+    /// Downloads every segment of a track, in order, into `path`.
+    ///
+    /// If `path` has a `.progress` sidecar from an earlier paused or stopped run, the file is cut
+    /// back to the recorded length and the download continues with the next segment.
+    async fn download_track(
+        &self,
+        track: &StreamTrack,
+        path: &str,
+        header_map: reqwest::header::HeaderMap,
+        context: &DownloadContext,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        if let Some(key) = track.segments.iter().find_map(|s| s.key.as_ref()) {
+            return Err(DownloadError::Config(format!(
+                "Stream is encrypted (METHOD={}, key {}); encrypted segments are not supported",
+                key.method,
+                key.uri.as_deref().unwrap_or("unspecified")
+            )));
+        }
+
+        let output_path = Path::new(path);
+        let mut resume = progress::load(output_path).unwrap_or_default();
+        if resume.next_segment() > track.segments.len() {
+            warn!(
+                download_id = %context.download_id,
+                recorded = resume.total_segments,
+                current = track.segments.len(),
+                "Playlist is shorter than the recorded progress, starting over"
+            );
+            resume = Default::default();
+        }
+        let start_index = resume.next_segment();
+        let durations: Vec<f64> = track.segments.iter().map(|s| s.duration).collect();
+        tracker.add_track(track.segments.len(), &durations);
+        tracker.add_existing(start_index, (0..start_index).map(|i| track.duration(i)).sum(), resume.bytes_written);
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(start_index == 0)
+            .open(path)
+            .await
+            .map_err(|e| DownloadError::Config(format!("Failed to create output file: {}", e)))?;
+
+        if start_index > 0 {
+            // Drop anything written after the last recorded segment, then append.
+            file.set_len(resume.bytes_written).await?;
+            info!(download_id = %context.download_id, kind = ?track.kind, "Resuming track at segment {}/{}", start_index, track.segments.len());
+        }
+        tokio::io::AsyncSeekExt::seek(&mut file, std::io::SeekFrom::End(0)).await?;
+
+        debug!(download_id = %context.download_id, kind = ?track.kind, "Downloading {} segments to {}", track.segments.len() - start_index, path);
+
+        let outcome = self.downloader.download_segments(
+            track,
+            &resume,
+            header_map,
+            file,
+            output_path,
+            context.signal(),
+            tracker,
+        ).await?;
+
+        if outcome == SegmentOutcome::Completed {
+            progress::delete(output_path).await;
+        }
+        Ok(outcome)
+    }
+
+    /// Downloads separate video and audio renditions concurrently and muxes them into one Matroska file.
+    async fn download_and_mux(
+        &self,
+        context: &DownloadContext,
+        video: &StreamTrack,
+        audio: &StreamTrack,
+        header_map: reqwest::header::HeaderMap,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        let filepath = &context.metadata.filepath;
+        let video_path = format!("{}.video.part", filepath);
+        let audio_path = format!("{}.audio.part", filepath);
+
+        info!(
+            download_id = %context.download_id,
+            video_segments = video.segments.len(),
+            audio_segments = audio.segments.len(),
+            "Downloading separate video and audio renditions"
+        );
+
+        let outcomes = tokio::try_join!(
+            self.download_track(video, &video_path, header_map.clone(), context, tracker),
+            self.download_track(audio, &audio_path, header_map, context, tracker),
+        )?;
+        if let (SegmentOutcome::Interrupted(signal), _) | (_, SegmentOutcome::Interrupted(signal)) = outcomes {
+            if signal == 3 {
+                for path in [&video_path, &audio_path] {
+                    let _ = tokio::fs::remove_file(path).await;
+                    progress::delete(Path::new(path)).await;
+                }
+            }
+            return Ok(SegmentOutcome::Interrupted(signal));
+        }
+
+        if self.config.enable_native_muxing {
+            let container = if context.metadata.stream_options.remux_to_mp4 { Container::Mp4 } else { Container::Matroska };
+            let output = Path::new(filepath).with_extension(container.extension());
+            let inputs = vec![
+                MuxInput { path: video_path.clone().into(), kind: Some(MediaKind::Video), language: None },
+                MuxInput { path: audio_path.clone().into(), kind: Some(MediaKind::Audio), language: audio.language.clone() },
+            ];
+
+            let mux_output = output.clone();
+            match tokio::task::spawn_blocking(move || mux::mux_files(&inputs, &mux_output, container)).await? {
+                Ok(()) => {
+                    let _ = tokio::fs::remove_file(&video_path).await;
+                    let _ = tokio::fs::remove_file(&audio_path).await;
+
+                    let output = output.to_string_lossy().to_string();
+                    self.set_output_path(context, &output).await;
+                    info!(download_id = %context.download_id, "Muxed audio and video into {}", output);
+                    return Ok(SegmentOutcome::Completed);
+                }
+                Err(e) => {
+                    warn!(download_id = %context.download_id, error = %e, "Native muxing failed, keeping renditions as separate files");
+                    let _ = tokio::fs::remove_file(&output).await;
+                }
+            }
+        }
+
+        // Keep both renditions: video under the requested name, audio next to it.
+        let path = Path::new(filepath);
+        let extension = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+        let audio_target = path.with_extension(format!("audio.{}", extension));
+        tokio::fs::rename(&video_path, filepath).await?;
+        tokio::fs::rename(&audio_path, &audio_target).await?;
+        Ok(SegmentOutcome::Completed)
+    }
+
+    /// Remuxes a finished MPEG-TS capture into MP4 and returns the resulting path.
+    /// The `.ts` is only deleted once the remux succeeded.
+    async fn remux_to_mp4(&self, context: &DownloadContext, path: &str) -> Result<String, DownloadError> {
+        let input = Path::new(path).to_path_buf();
+        let mut head = [0u8; 1];
+        let is_ts = matches!(std::fs::File::open(&input).and_then(|mut f| std::io::Read::read(&mut f, &mut head)), Ok(1) if head[0] == 0x47);
+        if !is_ts {
+            debug!(download_id = %context.download_id, "Output is not MPEG-TS, skipping MP4 remux");
+            return Ok(path.to_string());
+        }
+
+        let output = input.with_extension("mp4");
+        info!(download_id = %context.download_id, "Remuxing {} to MP4", path);
+
+        let (remux_input, remux_output) = (input.clone(), output.clone());
+        match tokio::task::spawn_blocking(move || mux::remux_ts_to_mp4(&remux_input, &remux_output)).await? {
+            Ok(()) => {
+                tokio::fs::remove_file(&input).await?;
+                let output = output.to_string_lossy().to_string();
+                self.set_output_path(context, &output).await;
+                info!(download_id = %context.download_id, "Remuxed capture into {}", output);
+                Ok(output)
+            }
+            Err(e) => {
+                warn!(download_id = %context.download_id, error = %e, "MP4 remux failed, keeping the original TS file");
+                let _ = tokio::fs::remove_file(&output).await;
+                Ok(path.to_string())
+            }
+        }
+    }
+
+    /// Downloads subtitle tracks and saves them next to the video as `<name>.<lang>.vtt|srt`,
+    /// shifted to a clip starting `clip_offset` seconds into the stream. Failures are logged and
+    /// never fail the download itself.
+    async fn save_subtitles(&self, context: &DownloadContext, tracks: &[&StreamTrack], clip_offset: f64, header_map: &reqwest::header::HeaderMap) {
+        let as_srt = context.metadata.stream_options.subtitles_as_srt;
+        let extension = if as_srt { "srt" } else { "vtt" };
+        let video_path = Path::new(&context.metadata.filepath);
+        let mut used: Vec<PathBuf> = Vec::new();
+
+        for track in tracks {
+            let cues = match subtitles::download_cues(&self.client, track, header_map).await {
+                Ok(cues) => subtitles::shift_cues(cues, (clip_offset * 1000.0).round() as i64),
+                Err(e) => {
+                    warn!(download_id = %context.download_id, language = ?track.language, error = %e, "Failed to download subtitles");
+                    continue;
+                }
+            };
+            if cues.is_empty() {
+                warn!(download_id = %context.download_id, language = ?track.language, "Subtitle track has no cues");
+                continue;
+            }
+
+            let language = track.language.as_deref().unwrap_or("und");
+            let mut path = subtitles::subtitle_path(video_path, language, extension);
+            let mut n = 2;
+            while used.contains(&path) {
+                path = subtitles::subtitle_path(video_path, &format!("{}-{}", language, n), extension);
+                n += 1;
+            }
+
+            let body = if as_srt { subtitles::to_srt(&cues) } else { subtitles::to_webvtt(&cues) };
+            match tokio::fs::write(&path, body).await {
+                Ok(()) => info!(download_id = %context.download_id, "Saved {} subtitle cues to {}", cues.len(), path.display()),
+                Err(e) => warn!(download_id = %context.download_id, error = %e, "Failed to write subtitles to {}", path.display()),
+            }
+            used.push(path);
+        }
+    }
+
+    /// Downloads the resolved media tracks: separate video/audio are muxed, otherwise the single
+    /// playable track is written to the output path.
+    async fn download_resolved(
+        &self,
+        context: &DownloadContext,
+        resolved: &ResolvedStream,
+        header_map: &reqwest::header::HeaderMap,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        let filepath = &context.metadata.filepath;
+
+        if context.metadata.stream_options.audio_only {
+            return self.download_audio_only(context, resolved, header_map, tracker).await;
+        }
+
+        let split = context.metadata.stream_options.discontinuity_mode == DiscontinuityMode::Split;
+
+        if let (Some(video), Some(audio)) = (resolved.track(TrackKind::Video), resolved.track(TrackKind::Audio)) {
+            if split {
+                warn!(download_id = %context.download_id, "Splitting at discontinuities is not supported for separate audio/video renditions, writing one file");
+            }
+            return self.download_and_mux(context, video, audio, header_map.clone(), tracker).await;
+        }
+
+        let track = resolved.tracks.iter().find(|t| t.kind != TrackKind::Subtitle)
+            .ok_or_else(|| DownloadError::Config("Resolver returned no tracks".to_string()))?;
+        if split {
+            let sections = discontinuity::sections(&track.segments);
+            if sections.len() > 1 {
+                return self.download_sections(context, track, &sections, header_map, tracker).await;
+            }
+        }
+
+        let outcome = self.download_track(track, filepath, header_map.clone(), context, tracker).await?;
+        if outcome == SegmentOutcome::Interrupted(3) {
+            // cancel_download removes the output itself; only the sidecar is ours to clean up.
+            progress::delete(Path::new(filepath)).await;
+        }
+        if outcome == SegmentOutcome::Completed && context.metadata.stream_options.remux_to_mp4 {
+            self.remux_to_mp4(context, filepath).await?;
+        }
+        Ok(outcome)
+    }
+
+    /// Downloads only the audio: a separate audio track if the stream has one, otherwise the
+    /// muxed track with the audio cut out of each TS segment. The result is saved as
+    /// `.m4a`/`.aac`/`.mp3` next to the requested path.
+    async fn download_audio_only(
+        &self,
+        context: &DownloadContext,
+        resolved: &ResolvedStream,
+        header_map: &reqwest::header::HeaderMap,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        let filepath = &context.metadata.filepath;
+        let mut track = match resolved.track(TrackKind::Audio).or_else(|| resolved.track(TrackKind::Muxed)) {
+            Some(track) => track.clone(),
+            None => return Err(DownloadError::Config("Stream has no audio to download".to_string())),
+        };
+        info!(download_id = %context.download_id, kind = ?track.kind, "Downloading audio only");
+        for segment in track.segments.iter_mut().filter(|s| s.transform.is_none()) {
+            segment.transform = Some(segment::FragmentTransform::ExtractAudio);
+        }
+
+        let part_path = format!("{}.audio.part", filepath);
+        let outcome = self.download_track(&track, &part_path, header_map.clone(), context, tracker).await?;
+        if outcome == SegmentOutcome::Interrupted(3) {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            progress::delete(Path::new(&part_path)).await;
+        }
+        if outcome != SegmentOutcome::Completed {
+            return Ok(outcome);
+        }
+
+        let container = context.metadata.stream_options.audio_container;
+        let (input, output) = (PathBuf::from(&part_path), PathBuf::from(filepath));
+        match tokio::task::spawn_blocking(move || mux::audio::write_audio_file(&input, &output, container)).await? {
+            Ok(output) => {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                let output = output.to_string_lossy().to_string();
+                self.set_output_path(context, &output).await;
+                info!(download_id = %context.download_id, "Saved audio to {}", output);
+            }
+            Err(e) => {
+                warn!(download_id = %context.download_id, error = %e, "Could not write the audio file, keeping the downloaded audio as is");
+                tokio::fs::rename(&part_path, filepath).await?;
+            }
+        }
+        Ok(SegmentOutcome::Completed)
+    }
+
+    /// Downloads every continuous section of `track` into its own file (`movie_part01.ts`, ...).
+    ///
+    /// A section whose file is non-empty and has no `.progress` sidecar was finished by an
+    /// earlier run and is skipped on resume.
+    async fn download_sections(
+        &self,
+        context: &DownloadContext,
+        track: &StreamTrack,
+        sections: &[discontinuity::Section],
+        header_map: &reqwest::header::HeaderMap,
+        tracker: &ProgressTracker,
+    ) -> Result<SegmentOutcome, DownloadError> {
+        let filepath = Path::new(&context.metadata.filepath);
+        let paths: Vec<String> = (0..sections.len())
+            .map(|i| discontinuity::section_path(filepath, i).to_string_lossy().to_string())
+            .collect();
+        info!(download_id = %context.download_id, "Splitting stream into {} sections", sections.len());
+
+        for (section, path) in sections.iter().zip(&paths) {
+            let part = StreamTrack::new(track.kind, track.segments[section.range.clone()].to_vec(), track.language.clone());
+
+            let finished = progress::load(Path::new(path)).is_none()
+                && tokio::fs::metadata(path).await.is_ok_and(|m| m.len() > 0);
+            if finished {
+                let size = tokio::fs::metadata(path).await?.len();
+                let durations: Vec<f64> = part.segments.iter().map(|s| s.duration).collect();
+                tracker.add_track(part.segments.len(), &durations);
+                tracker.add_existing(part.segments.len(), section.duration, size);
+                debug!(download_id = %context.download_id, "Section {} already complete", path);
+                continue;
+            }
+
+            if let SegmentOutcome::Interrupted(signal) = self.download_track(&part, path, header_map.clone(), context, tracker).await? {
+                if signal == 3 {
+                    for path in &paths {
+                        let _ = tokio::fs::remove_file(path).await;
+                        progress::delete(Path::new(path)).await;
+                    }
+                }
+                return Ok(SegmentOutcome::Interrupted(signal));
+            }
+        }
+
+        let mut outputs = paths;
+        if context.metadata.stream_options.remux_to_mp4 {
+            for output in &mut outputs {
+                *output = self.remux_to_mp4(context, output).await?;
+            }
+        }
+        // The first section stands in for the download in the manager.
+        self.set_output_path(context, &outputs[0]).await;
+        Ok(SegmentOutcome::Completed)
+    }
+
+    /// Emits `download-progress-detail` twice a second and keeps the manager's byte counters
+    /// current, so pausing saves an accurate `downloaded_bytes`.
+    fn spawn_progress_reporter(&self, context: &DownloadContext, tracker: Arc<ProgressTracker>) -> tokio::task::JoinHandle<()> {
+        let app = context.app.clone();
+        let manager = context.manager.clone();
+        let control = context.control.clone();
+        let signal = context.signal();
+        let download_id = context.download_id.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                if signal.load() != 0 {
+                    break;
+                }
+                let snapshot = tracker.snapshot(&download_id);
+                control.downloaded_bytes.store(snapshot.downloaded, std::sync::atomic::Ordering::Relaxed);
+                if let Some(mut meta) = manager.get_download(&download_id).await {
+                    meta.downloaded_bytes = snapshot.downloaded;
+                    meta.total_size = snapshot.total;
+                    manager.update_download(&download_id, meta).await;
+                }
+                let _ = app.emit("download-progress-detail", snapshot);
+            }
+        })
+    }
+
+    /// Adds segments that stayed damaged or came from another variant to the download record.
+    /// Returns whether there was anything to report.
+    async fn add_segment_report(&self, context: &DownloadContext) -> bool {
+        let damaged = self.downloader.damaged_segments();
+        let substituted = self.downloader.substituted_segments();
+        if damaged.is_empty() && substituted.is_empty() {
+            return false;
+        }
+        if !damaged.is_empty() {
+            warn!(download_id = %context.download_id, "{} segment(s) were still damaged after re-fetching", damaged.len());
+        }
+        if !substituted.is_empty() {
+            warn!(download_id = %context.download_id, "{} segment(s) were fetched from a redundant or alternate variant", substituted.len());
+        }
+
+        let Some(mut meta) = context.manager.get_download(&context.download_id).await else { return false };
+        // Entries from an earlier run of a resumed download stay; re-downloaded segments are replaced.
+        meta.damaged_segments
+            .retain(|old| !damaged.iter().any(|new| new.track == old.track && new.index == old.index && new.url == old.url));
+        meta.damaged_segments.extend(damaged);
+        meta.substituted_segments
+            .retain(|old| !substituted.iter().any(|new| new.track == old.track && new.index == old.index && new.url == old.url));
+        meta.substituted_segments.extend(substituted);
+        context.manager.update_download(&context.download_id, meta).await;
+        true
+    }
+
+    /// Records a live HLS stream into the output file until the playlist ends or the download is
+    /// stopped; a stopped recording is kept as the finished file. Resuming a paused recording
+    /// appends to the same file.
+    async fn record_live(&self, context: &DownloadContext, playlist_url: &str, header_map: &reqwest::header::HeaderMap) -> Result<DownloadCommandResult, DownloadError> {
+        let filepath = &context.metadata.filepath;
+        info!(download_id = %context.download_id, "Recording live stream {} into {}", playlist_url, filepath);
+
+        let tracker = Arc::new(ProgressTracker::new());
+        let reporter = self.spawn_progress_reporter(context, tracker.clone());
+        let result = live::record_live_hls(
+            &self.client,
+            &self.processor,
+            playlist_url,
+            header_map,
+            Path::new(filepath),
+            &context.signal(),
+            &tracker,
+        )
+        .await;
+        reporter.abort();
+        let outcome = result?;
+
+        let status = match outcome {
+            // A live playlist usually never ends, so stopping is how a recording finishes.
+            SegmentOutcome::Completed | SegmentOutcome::Interrupted(2) => {
+                if context.metadata.stream_options.remux_to_mp4 {
+                    self.remux_to_mp4(context, filepath).await?;
+                }
+                info!(download_id = %context.download_id, bytes = tracker.bytes_done(), "Live recording finished");
+                self.finish(context, &tracker).await;
+                "completed"
+            }
+            SegmentOutcome::Interrupted(1) => "paused",
+            SegmentOutcome::Interrupted(_) => "cancelled",
+        };
+        Ok(DownloadCommandResult {
+            id: context.download_id.clone(),
+            status: status.to_string(),
+        })
+    }
+
+    /// Saves the HLS stream as served into a directory named after the output file
+    /// (`movie.ts` -> `movie/`); the manager then points at the mirrored entry playlist.
+    async fn mirror(&self, context: &DownloadContext, header_map: &reqwest::header::HeaderMap) -> Result<DownloadCommandResult, DownloadError> {
+        let root = Path::new(&context.metadata.filepath).with_extension("");
+        tokio::fs::create_dir_all(&root).await?;
+        info!(download_id = %context.download_id, "Mirroring HLS stream into {}", root.display());
+
+        let tracker = Arc::new(ProgressTracker::new());
+        let reporter = self.spawn_progress_reporter(context, tracker.clone());
+        let result = mirror::mirror_hls(
+            &self.client,
+            &context.metadata.url,
+            header_map,
+            &context.metadata.stream_options,
+            &root,
+            self.config.max_parallel_connections,
+            context.signal(),
+            &tracker,
+        )
+        .await;
+        reporter.abort();
+        let (outcome, entry) = result?;
+
+        let status = match outcome {
+            SegmentOutcome::Completed => {
+                self.set_output_path(context, &entry.to_string_lossy()).await;
+                info!(download_id = %context.download_id, bytes = tracker.bytes_done(), "Mirror complete: {}", entry.display());
+                self.finish(context, &tracker).await;
+                "completed"
+            }
+            SegmentOutcome::Interrupted(1) => "paused",
+            SegmentOutcome::Interrupted(2) => "stopped",
+            SegmentOutcome::Interrupted(_) => {
+                let _ = tokio::fs::remove_dir_all(&root).await;
+                "cancelled"
+            }
+        };
+        Ok(DownloadCommandResult {
+            id: context.download_id.clone(),
+            status: status.to_string(),
+        })
+    }
+
+    /// Sends the final progress snapshot to the frontend, then marks the download completed.
+    async fn finish(&self, context: &DownloadContext, tracker: &ProgressTracker) {
+        let mut snapshot = tracker.snapshot(&context.download_id);
+        snapshot.progress = 100.0;
+        snapshot.total = snapshot.downloaded;
+        snapshot.eta = 0;
+        let bytes = snapshot.downloaded;
+        let _ = context.app.emit("download-progress-detail", snapshot);
+        context.complete(bytes).await;
+    }
+
+    /// Records the final output path after post-processing changed the file name.
+    async fn set_output_path(&self, context: &DownloadContext, path: &str) {
+        if let Some(mut meta) = context.manager.get_download(&context.download_id).await {
+            meta.filepath = path.to_string();
+            context.manager.update_download(&context.download_id, meta).await;
         }
     }
 }
@@ -76,64 +624,95 @@ impl DownloadStrategy for UniversalStreamingStrategy {
         context: &DownloadContext,
     ) -> Result<DownloadCommandResult, DownloadError> {
         let url = &context.metadata.url;
-        let filepath = &context.metadata.filepath;
+        let options = &context.metadata.stream_options;
 
         info!(download_id = %context.download_id, "Universal Engine: Starting download for {}", url);
 
-        // 1. Prepare Headers
-        let mut header_map = reqwest::header::HeaderMap::new();
-        for (k, v) in &context.metadata.headers {
-            if let (Ok(name), Ok(val)) = (
-                reqwest::header::HeaderName::from_bytes(k.as_bytes()),
-                reqwest::header::HeaderValue::from_str(v),
-            ) {
-                header_map.insert(name, val);
+        // 1. Prepare Headers (the client sets the User-Agent)
+        let header_map = headers::request_headers(&context.metadata.headers, context.metadata.referrer.as_deref());
+
+        // 2. Routing Logic
+        let resolver = self.registry.resolver_for(url, &self.client, &header_map).await?;
+        info!(download_id = %context.download_id, "Using {} resolver", resolver.name());
+
+        if options.mirror {
+            if !resolver.supports_mirror() {
+                return Err(DownloadError::Config(format!("Mirror mode only supports HLS streams, not {}", resolver.name())));
             }
+            return self.mirror(context, &header_map).await;
         }
+        let mut resolved = resolver.resolve(url, &self.client, &header_map, options).await?;
 
-        // Add standard User-Agent and Referer if present
-        header_map.insert(
-            reqwest::header::USER_AGENT,
-            reqwest::header::HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"),
-        );
-        if let Some(ref_url) = &context.metadata.referrer {
-            if let Ok(val) = reqwest::header::HeaderValue::from_str(ref_url) {
-                header_map.insert(reqwest::header::REFERER, val);
+        debug!(download_id = %context.download_id, "Resolved {} track(s)", resolved.tracks.len());
+
+        if let Some(playlist_url) = resolved.live_playlist.clone() {
+            if options.clip_range().is_some() {
+                warn!(download_id = %context.download_id, "Clipping does not apply to live recordings, ignoring it");
             }
+            return self.record_live(context, &playlist_url, &header_map).await;
         }
 
-        // 2. Routing Logic
-        let segment_urls = if url.contains("youtube.com") || url.contains("youtu.be") {
-            if !self.config.enable_platform_resolvers {
-                return Err(DownloadError::Config("Platform resolvers are currently disabled".to_string()));
-            }
-            self.youtube_resolver.resolve(url, &self.client, &header_map).await?
-        } else {
-            // Default to HLS
-            self.hls_resolver.resolve(url, &self.client, &header_map).await?
-        };
+        // Time-range clipping. The window lives in the metadata, so a resume selects the same segments.
+        let mut clip_offset = 0.0;
+        if let Some((start, end)) = options.clip_range() {
+            info!(download_id = %context.download_id, "Clipping stream to {}s..{:?}s", start, end);
+            clip_offset = resolved.clip(start, end)?;
+        }
 
-        debug!(download_id = %context.download_id, "Resolved {} segments", segment_urls.len());
+        for track in resolved.tracks.iter_mut().filter(|t| t.kind != TrackKind::Subtitle) {
+            let sections = discontinuity::sections(&track.segments).len();
+            if sections > 1 {
+                info!(download_id = %context.download_id, kind = ?track.kind, "Track has {} discontinuity sections", sections);
+            }
+            if options.discontinuity_mode == DiscontinuityMode::DropAds && sections > 1 {
+                let dropped = discontinuity::drop_ads(track);
+                info!(download_id = %context.download_id, kind = ?track.kind, "Dropped {} ad section(s)", dropped);
+            }
+        }
 
-        // 3. Prepare File
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(filepath)
-            .await
-            .map_err(|e| DownloadError::Config(format!("Failed to create output file: {}", e)))?;
+        // 3. Download
+        let tracker = Arc::new(ProgressTracker::new());
+        let reporter = self.spawn_progress_reporter(context, tracker.clone());
+        let result = self.download_resolved(context, &resolved, &header_map, &tracker).await;
+        reporter.abort();
+        let reported = self.add_segment_report(context).await;
+        let outcome = result?;
 
-        // 4. Download
-        self.downloader.download_segments(
-            segment_urls,
-            header_map,
-            file,
-            context.control.signal.clone()
-        ).await?;
+        if let SegmentOutcome::Interrupted(signal) = outcome {
+            info!(download_id = %context.download_id, "Universal Engine: Download interrupted by signal {}", signal);
+            // The state file written on pause predates the report; a resumed run builds on it.
+            if reported {
+                if let Some(meta) = context.manager.get_download(&context.download_id).await {
+                    if let Err(e) = persistence::save_state(&meta) {
+                        warn!(download_id = %context.download_id, error = %e, "Failed to save the segment report");
+                    }
+                }
+            }
+            let status = match signal {
+                1 => "paused",
+                2 => "stopped",
+                _ => "cancelled",
+            };
+            return Ok(DownloadCommandResult {
+                id: context.download_id.clone(),
+                status: status.to_string(),
+            });
+        }
+
+        // 4. Subtitles
+        let subtitle_tracks: Vec<&StreamTrack> = resolved.tracks.iter().filter(|t| t.kind == TrackKind::Subtitle).collect();
+        if !subtitle_tracks.is_empty() {
+            self.save_subtitles(context, &subtitle_tracks, clip_offset, &header_map).await;
+        }
+
+        info!(
+            download_id = %context.download_id,
+            bytes = tracker.bytes_done(),
+            backpressure_trips = self.downloader.backpressure_trips(),
+            "Universal Engine: Download complete"
+        );
+        self.finish(context, &tracker).await;
 
-        info!(download_id = %context.download_id, "Universal Engine: Download complete");
-        
         Ok(DownloadCommandResult {
             id: context.download_id.clone(),
             status: "completed".to_string(),
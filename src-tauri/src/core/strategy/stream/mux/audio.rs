@@ -0,0 +1,165 @@
+//! Audio-only output.
+//!
+//! While an audio-only download runs, the audio elementary stream is cut out of every MPEG-TS
+//! segment as it arrives and stored as HLS packed audio (ADTS or MP3 frames behind an ID3
+//! timestamp tag), so the part file never holds video. Once complete, the part file (or a
+//! downloaded audio rendition in any supported container) is written as `.m4a`, `.aac` or `.mp3`.
+
+use super::{mux_files, open_source, ts, Codec, Container, MediaKind, MuxInput, SampleSource};
+use crate::core::error::DownloadError;
+use crate::core::state::AudioContainer;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// The first audio stream of a TS segment as packed audio, or `None` if it carries no audio.
+pub fn packed_audio_from_ts(segment: &[u8]) -> Result<Option<Vec<u8>>, DownloadError> {
+    let mut source = ts::TsSource::new(Cursor::new(segment), Some(MediaKind::Audio));
+    let Some(track) = source.tracks()?.into_iter().next() else {
+        return Ok(None);
+    };
+
+    let mut output = Vec::with_capacity(segment.len() / 8);
+    while let Some(sample) = source.next_sample()? {
+        if sample.track != 0 {
+            continue;
+        }
+        if output.is_empty() {
+            let pts = sample.pts * 90_000 / track.timescale as i64;
+            output.extend(id3_timestamp_tag(pts as u64));
+        }
+        if track.codec == Codec::Aac {
+            let header = adts_header(&track.codec_private, sample.data.len())
+                .ok_or_else(|| DownloadError::Parse("Invalid AAC configuration in TS segment".to_string()))?;
+            output.extend_from_slice(&header);
+        }
+        output.extend(sample.data);
+    }
+    Ok(Some(output))
+}
+
+/// Writes the audio of `input` next to `output` (whose extension is replaced to match the
+/// codec) and returns the path written.
+pub fn write_audio_file(input: &Path, output: &Path, container: AudioContainer) -> Result<PathBuf, DownloadError> {
+    let mut source = open_source(input, Some(MediaKind::Audio))?;
+    let track = source
+        .tracks()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| DownloadError::Parse(format!("No audio stream found in {}", input.display())))?;
+
+    let target = match (track.codec, container) {
+        (Codec::Aac, AudioContainer::M4a) => {
+            let target = output.with_extension("m4a");
+            drop(source);
+            let inputs = [MuxInput { path: input.to_path_buf(), kind: Some(MediaKind::Audio), language: None }];
+            mux_files(&inputs, &target, Container::Mp4)?;
+            return Ok(target);
+        }
+        (Codec::Aac, AudioContainer::Raw) => output.with_extension("aac"),
+        (Codec::Mp3, _) => output.with_extension("mp3"),
+        (codec, _) => return Err(DownloadError::Parse(format!("{:?} is not an audio codec", codec))),
+    };
+
+    let mut writer = BufWriter::new(File::create(&target)?);
+    let mut frames = 0u64;
+    while let Some(sample) = source.next_sample()? {
+        if track.codec == Codec::Aac {
+            let header = adts_header(&track.codec_private, sample.data.len())
+                .ok_or_else(|| DownloadError::Parse("Invalid AAC configuration".to_string()))?;
+            writer.write_all(&header)?;
+        }
+        writer.write_all(&sample.data)?;
+        frames += 1;
+    }
+    writer.flush()?;
+    info!(output = ?target, frames, "Wrote audio elementary stream");
+    Ok(target)
+}
+
+/// 7-byte ADTS header for a raw AAC frame, from the stream's `AudioSpecificConfig`.
+pub fn adts_header(config: &[u8], payload_len: usize) -> Option<[u8; 7]> {
+    let (&first, &second) = (config.first()?, config.get(1)?);
+    let object_type = first >> 3;
+    let sample_rate_index = ((first & 0x7) << 1) | (second >> 7);
+    let channel_config = (second >> 3) & 0xF;
+    let frame_len = payload_len + 7;
+    if !(1..=4).contains(&object_type) || sample_rate_index > 12 || frame_len >= 1 << 13 {
+        return None;
+    }
+    Some([
+        0xFF,
+        0xF1,
+        ((object_type - 1) << 6) | (sample_rate_index << 2) | (channel_config >> 2),
+        ((channel_config & 0x3) << 6) | (frame_len >> 11) as u8,
+        (frame_len >> 3) as u8,
+        (((frame_len & 0x7) as u8) << 5) | 0x1F,
+        0xFC,
+    ])
+}
+
+/// ID3v2.4 tag with the `PRIV` frame HLS packed audio uses for its 90 kHz start timestamp.
+pub fn id3_timestamp_tag(timestamp: u64) -> Vec<u8> {
+    const OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+    let frame_len = OWNER.len() + 8;
+    let mut tag = Vec::with_capacity(20 + frame_len);
+    tag.extend_from_slice(b"ID3\x04\x00\x00");
+    tag.extend(syncsafe((10 + frame_len) as u32));
+    tag.extend_from_slice(b"PRIV");
+    tag.extend(syncsafe(frame_len as u32));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend_from_slice(OWNER);
+    tag.extend_from_slice(&(timestamp & 0x1_FFFF_FFFF).to_be_bytes());
+    tag
+}
+
+fn syncsafe(value: u32) -> [u8; 4] {
+    [(value >> 21) as u8 & 0x7F, (value >> 14) as u8 & 0x7F, (value >> 7) as u8 & 0x7F, value as u8 & 0x7F]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::codec;
+    use super::super::packed_audio::PackedAudioSource;
+    use super::super::ts::tests::{adts_frame, h264_keyframe, packetize, pes_packet, psi_packets};
+    use super::*;
+
+    #[test]
+    fn test_adts_header_and_id3_tag() {
+        let header = adts_header(&[0x12, 0x10], 93).unwrap();
+        let parsed = codec::parse_adts_header(&header).unwrap();
+        assert_eq!((parsed.object_type, parsed.sample_rate(), parsed.channel_config, parsed.frame_len), (2, 44100, 2, 100));
+
+        let tag = id3_timestamp_tag(900_000);
+        assert_eq!(codec::id3_tag_len(&tag), Some(tag.len()));
+        assert_eq!(codec::id3_transport_timestamp(&tag), Some(900_000));
+    }
+
+    #[test]
+    fn test_packed_audio_from_ts_drops_video() {
+        let mut segment = psi_packets(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        let (mut video_cc, mut audio_cc) = (0u8, 0u8);
+        segment.extend(packetize(0x100, &pes_packet(0xE0, 180_000, Some(180_000), &h264_keyframe()), &mut video_cc));
+        for i in 0..3u64 {
+            segment.extend(packetize(0x101, &pes_packet(0xC0, 180_000 + i * 2090, None, &adts_frame(100)), &mut audio_cc));
+        }
+
+        let packed = packed_audio_from_ts(&segment).unwrap().unwrap();
+        assert_eq!(codec::id3_transport_timestamp(&packed), Some(180_000));
+
+        let mut source = PackedAudioSource::new(Cursor::new(packed));
+        let tracks = source.tracks().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].codec, Codec::Aac);
+        let mut frames = 0;
+        while let Some(sample) = source.next_sample().unwrap() {
+            assert_eq!(sample.data.len(), 100);
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+
+        let video_only = psi_packets(&[(0x1B, 0x100)]);
+        assert_eq!(packed_audio_from_ts(&video_only).unwrap(), None);
+    }
+}
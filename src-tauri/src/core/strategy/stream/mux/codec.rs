@@ -0,0 +1,542 @@
+//! Elementary-stream helpers: NAL unit handling, SPS parsing and audio frame headers.
+
+/// MSB-first bit reader over an RBSP (emulation prevention bytes already removed).
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - (self.pos % 8))) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Some(value)
+    }
+
+    /// Unsigned Exp-Golomb code.
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.read_bits(zeros)?)
+    }
+
+    /// Signed Exp-Golomb code.
+    pub fn read_se(&mut self) -> Option<i32> {
+        let code = self.read_ue()? as i64;
+        Some(if code % 2 == 1 { ((code + 1) / 2) as i32 } else { -(code / 2) as i32 })
+    }
+}
+
+/// Strips `00 00 03` emulation prevention bytes from a NAL unit payload.
+pub fn nal_to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// Splits an Annex B byte stream into NAL units (start codes removed).
+pub fn split_annexb(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut i = 0;
+
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(s) = start {
+                let mut end = i;
+                while end > s && data[end - 1] == 0 {
+                    end -= 1;
+                }
+                units.push(&data[s..end]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(s) = start {
+        if s < data.len() {
+            units.push(&data[s..]);
+        }
+    }
+
+    units
+}
+
+/// Dimensions and profile information from an H.264 sequence parameter set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H264Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parses an H.264 SPS NAL unit (including its one-byte NAL header).
+pub fn parse_h264_sps(nal: &[u8]) -> Option<H264Sps> {
+    let rbsp = nal_to_rbsp(nal.get(1..)?);
+    let mut r = BitReader::new(&rbsp);
+
+    let profile_idc = r.read_bits(8)? as u8;
+    let constraint_flags = r.read_bits(8)? as u8;
+    let level_idc = r.read_bits(8)? as u8;
+    r.read_ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = 0;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = r.read_ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_bit()?;
+        }
+        r.read_ue()?; // bit_depth_luma_minus8
+        r.read_ue()?; // bit_depth_chroma_minus8
+        r.read_bit()?; // qpprime_y_zero_transform_bypass_flag
+        if r.read_bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.read_bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.read_ue()?; // log2_max_frame_num_minus4
+    match r.read_ue()? {
+        0 => {
+            r.read_ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.read_bit()?;
+            r.read_se()?;
+            r.read_se()?;
+            for _ in 0..r.read_ue()? {
+                r.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    r.read_ue()?; // max_num_ref_frames
+    r.read_bit()?; // gaps_in_frame_num_value_allowed_flag
+
+    let width_mbs = r.read_ue()? + 1;
+    let height_map_units = r.read_ue()? + 1;
+    let frame_mbs_only = r.read_bit()?;
+    if frame_mbs_only == 0 {
+        r.read_bit()?; // mb_adaptive_frame_field_flag
+    }
+    r.read_bit()?; // direct_8x8_inference_flag
+
+    let mut width = width_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * height_map_units * 16;
+
+    if r.read_bit()? == 1 {
+        let (left, right, top, bottom) = (r.read_ue()?, r.read_ue()?, r.read_ue()?, r.read_ue()?);
+        let (crop_x, crop_y) = match (chroma_format_idc, separate_colour_plane) {
+            (0, _) | (_, 1) => (1, 2 - frame_mbs_only),
+            (1, _) => (2, 2 * (2 - frame_mbs_only)),
+            (2, _) => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        width = width.saturating_sub((left + right) * crop_x);
+        height = height.saturating_sub((top + bottom) * crop_y);
+    }
+
+    Some(H264Sps { profile_idc, constraint_flags, level_idc, width, height })
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8i32;
+    let mut next = 8i32;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.read_se()? + 256) % 256;
+        }
+        last = if next == 0 { last } else { next };
+    }
+    Some(())
+}
+
+/// Builds an `AVCDecoderConfigurationRecord` (the `avcC` payload / Matroska CodecPrivate).
+pub fn avc_decoder_config(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut out = vec![1, sps[1], sps[2], sps[3], 0xFF, 0xE1];
+    out.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    out.extend_from_slice(sps);
+    out.push(1);
+    out.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    out.extend_from_slice(pps);
+    out
+}
+
+/// Fields of an H.265 sequence parameter set needed for `hvcC` and the track header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct H265Sps {
+    /// general_profile_space .. general_level_idc, exactly as coded in profile_tier_level().
+    pub general_profile: [u8; 12],
+    pub num_temporal_layers: u8,
+    pub temporal_id_nested: bool,
+    pub chroma_format_idc: u8,
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parses an H.265 SPS NAL unit (including its two-byte NAL header).
+pub fn parse_h265_sps(nal: &[u8]) -> Option<H265Sps> {
+    let rbsp = nal_to_rbsp(nal.get(2..)?);
+    let general_profile: [u8; 12] = rbsp.get(1..13)?.try_into().ok()?;
+    let mut r = BitReader::new(&rbsp);
+
+    r.read_bits(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.read_bits(3)?;
+    let temporal_id_nested = r.read_bit()? == 1;
+
+    // profile_tier_level(): the general part was copied above.
+    r.read_bits(32)?;
+    r.read_bits(32)?;
+    r.read_bits(32)?;
+    let mut sub_layer_flags = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((r.read_bit()?, r.read_bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            r.read_bits(2)?;
+        }
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present == 1 {
+            r.read_bits(32)?;
+            r.read_bits(32)?;
+            r.read_bits(24)?;
+        }
+        if level_present == 1 {
+            r.read_bits(8)?;
+        }
+    }
+
+    r.read_ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.read_ue()?;
+    let mut separate_colour_plane = 0;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = r.read_bit()?;
+    }
+    let mut width = r.read_ue()?;
+    let mut height = r.read_ue()?;
+
+    if r.read_bit()? == 1 {
+        let (left, right, top, bottom) = (r.read_ue()?, r.read_ue()?, r.read_ue()?, r.read_ue()?);
+        let (sub_width, sub_height) = match (chroma_format_idc, separate_colour_plane) {
+            (1, 0) => (2, 2),
+            (2, 0) => (2, 1),
+            _ => (1, 1),
+        };
+        width = width.saturating_sub((left + right) * sub_width);
+        height = height.saturating_sub((top + bottom) * sub_height);
+    }
+
+    let bit_depth_luma_minus8 = r.read_ue()? as u8;
+    let bit_depth_chroma_minus8 = r.read_ue()? as u8;
+
+    Some(H265Sps {
+        general_profile,
+        num_temporal_layers: max_sub_layers_minus1 as u8 + 1,
+        temporal_id_nested,
+        chroma_format_idc: chroma_format_idc as u8,
+        bit_depth_luma_minus8,
+        bit_depth_chroma_minus8,
+        width,
+        height,
+    })
+}
+
+/// Builds an `HEVCDecoderConfigurationRecord` (the `hvcC` payload / Matroska CodecPrivate).
+pub fn hevc_decoder_config(vps: &[u8], sps: &[u8], pps: &[u8], info: &H265Sps) -> Vec<u8> {
+    let mut out = vec![1];
+    out.extend_from_slice(&info.general_profile);
+    out.extend_from_slice(&[0xF0, 0x00]); // min_spatial_segmentation_idc
+    out.push(0xFC); // parallelismType unknown
+    out.push(0xFC | (info.chroma_format_idc & 0x3));
+    out.push(0xF8 | (info.bit_depth_luma_minus8 & 0x7));
+    out.push(0xF8 | (info.bit_depth_chroma_minus8 & 0x7));
+    out.extend_from_slice(&[0, 0]); // avgFrameRate
+    out.push(((info.num_temporal_layers & 0x7) << 3) | ((info.temporal_id_nested as u8) << 2) | 0x3);
+    out.push(3);
+    for (nal_type, nal) in [(32u8, vps), (33, sps), (34, pps)] {
+        out.push(0x80 | nal_type);
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+/// Parameter set NAL units stored in an `avcC` or `hvcC` record.
+pub fn decoder_config_nals(config: &[u8], hevc: bool) -> Vec<Vec<u8>> {
+    let mut nals = Vec::new();
+    let mut pos = if hevc { 23 } else { 5 };
+    let read_nal = |pos: &mut usize, nals: &mut Vec<Vec<u8>>| -> Option<()> {
+        let len = u16::from_be_bytes([*config.get(*pos)?, *config.get(*pos + 1)?]) as usize;
+        nals.push(config.get(*pos + 2..*pos + 2 + len)?.to_vec());
+        *pos += 2 + len;
+        Some(())
+    };
+
+    if hevc {
+        let arrays = config.get(22).copied().unwrap_or(0);
+        for _ in 0..arrays {
+            let Some(count) = config.get(pos + 1..pos + 3) else { break };
+            let count = u16::from_be_bytes([count[0], count[1]]);
+            pos += 3;
+            for _ in 0..count {
+                if read_nal(&mut pos, &mut nals).is_none() {
+                    return nals;
+                }
+            }
+        }
+    } else {
+        let sps_count = config.get(pos).map_or(0, |b| b & 0x1F);
+        pos += 1;
+        for _ in 0..sps_count {
+            if read_nal(&mut pos, &mut nals).is_none() {
+                return nals;
+            }
+        }
+        let pps_count = config.get(pos).copied().unwrap_or(0);
+        pos += 1;
+        for _ in 0..pps_count {
+            if read_nal(&mut pos, &mut nals).is_none() {
+                return nals;
+            }
+        }
+    }
+    nals
+}
+
+/// Converts NAL units to 4-byte length-prefixed (AVCC) form, dropping access unit delimiters.
+pub fn nals_to_length_prefixed(nals: &[&[u8]], is_delimiter: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nals.iter().map(|n| n.len() + 4).sum());
+    for nal in nals.iter().filter(|n| !n.is_empty() && !is_delimiter(n)) {
+        out.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+        out.extend_from_slice(nal);
+    }
+    out
+}
+
+pub const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Fixed header fields of an ADTS frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// MPEG-4 audio object type (ADTS profile + 1).
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    pub channel_config: u8,
+    pub header_len: usize,
+    pub frame_len: usize,
+}
+
+impl AdtsHeader {
+    pub fn sample_rate(&self) -> u32 {
+        AAC_SAMPLE_RATES.get(self.sample_rate_index as usize).copied().unwrap_or(44100)
+    }
+
+    /// Two-byte `AudioSpecificConfig` for this stream.
+    pub fn audio_specific_config(&self) -> Vec<u8> {
+        audio_specific_config(self.object_type, self.sample_rate_index, self.channel_config)
+    }
+}
+
+pub fn parse_adts_header(data: &[u8]) -> Option<AdtsHeader> {
+    if data.len() < 7 || data[0] != 0xFF || data[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    let protection_absent = data[1] & 1 == 1;
+    let object_type = ((data[2] >> 6) & 0x3) + 1;
+    let sample_rate_index = (data[2] >> 2) & 0xF;
+    let channel_config = ((data[2] & 1) << 2) | (data[3] >> 6);
+    let frame_len = (((data[3] & 0x3) as usize) << 11) | ((data[4] as usize) << 3) | ((data[5] as usize) >> 5);
+    let header_len = if protection_absent { 7 } else { 9 };
+
+    if sample_rate_index > 12 || frame_len < header_len {
+        return None;
+    }
+
+    Some(AdtsHeader { object_type, sample_rate_index, channel_config, header_len, frame_len })
+}
+
+pub fn audio_specific_config(object_type: u8, sample_rate_index: u8, channel_config: u8) -> Vec<u8> {
+    let value: u16 = ((object_type as u16) << 11) | ((sample_rate_index as u16) << 7) | ((channel_config as u16) << 3);
+    value.to_be_bytes().to_vec()
+}
+
+/// Reads `(sample_rate, channels)` from an `AudioSpecificConfig`.
+pub fn parse_audio_specific_config(config: &[u8]) -> Option<(u32, u16)> {
+    let mut r = BitReader::new(config);
+    let mut object_type = r.read_bits(5)?;
+    if object_type == 31 {
+        object_type = 32 + r.read_bits(6)?;
+    }
+    let _ = object_type;
+    let index = r.read_bits(4)?;
+    let sample_rate = if index == 15 {
+        r.read_bits(24)?
+    } else {
+        *AAC_SAMPLE_RATES.get(index as usize)?
+    };
+    let channels = r.read_bits(4)? as u16;
+    Some((sample_rate, channels))
+}
+
+/// Header of an MPEG-1/2 Layer III (MP3) frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpegAudioHeader {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples_per_frame: u32,
+    pub frame_len: usize,
+}
+
+pub fn parse_mpeg_audio_header(data: &[u8]) -> Option<MpegAudioHeader> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (data[1] >> 3) & 0x3; // 3 = MPEG-1, 2 = MPEG-2, 0 = MPEG-2.5
+    let layer = (data[1] >> 1) & 0x3; // 1 = Layer III
+    if version == 1 || layer != 1 {
+        return None;
+    }
+
+    let bitrate_index = (data[2] >> 4) as usize;
+    let rate_index = ((data[2] >> 2) & 0x3) as usize;
+    let padding = ((data[2] >> 1) & 1) as usize;
+    if bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    const V1_BITRATES: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const V2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const RATES: [u32; 3] = [44100, 48000, 32000];
+
+    let (bitrate, sample_rate, samples_per_frame) = match version {
+        3 => (V1_BITRATES[bitrate_index], RATES[rate_index], 1152),
+        2 => (V2_BITRATES[bitrate_index], RATES[rate_index] / 2, 576),
+        _ => (V2_BITRATES[bitrate_index], RATES[rate_index] / 4, 576),
+    };
+    let frame_len = (samples_per_frame / 8 * bitrate * 1000 / sample_rate) as usize + padding;
+    let channels = if data[3] >> 6 == 3 { 1 } else { 2 };
+
+    Some(MpegAudioHeader { sample_rate, channels, samples_per_frame, frame_len })
+}
+
+/// Length of an ID3v2 tag at the start of `data`, if one is present.
+pub fn id3_tag_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return None;
+    }
+    let size = data[6..10].iter().fold(0usize, |acc, b| (acc << 7) | (*b as usize & 0x7F));
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+/// Extracts the 90 kHz timestamp that HLS packed audio carries in an ID3 `PRIV` frame.
+pub fn id3_transport_timestamp(tag: &[u8]) -> Option<u64> {
+    const OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+    let pos = tag.windows(OWNER.len()).position(|w| w == OWNER)?;
+    let ts = tag.get(pos + OWNER.len()..pos + OWNER.len() + 8)?;
+    Some(u64::from_be_bytes(ts.try_into().ok()?) & 0x1_FFFF_FFFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_h264_sps_1080p() {
+        // High profile 1920x1080 SPS (1088 coded lines cropped by 8).
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0x84, 0x00, 0x00,
+            0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xF0, 0x3C, 0x60, 0xC6, 0x58,
+        ];
+        let info = parse_h264_sps(&sps).unwrap();
+        assert_eq!(info.profile_idc, 100);
+        assert_eq!((info.width, info.height), (1920, 1080));
+    }
+
+    #[test]
+    fn test_parse_h265_sps_and_hvcc() {
+        // Main profile, level 4.0, 1920x1080 SPS.
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+            0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80, 0x10, 0xE5, 0x96, 0x66, 0x69, 0x24, 0xCA, 0xE0,
+        ];
+        let info = parse_h265_sps(&sps).unwrap();
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.general_profile[0], 0x01);
+        assert_eq!(info.general_profile[11], 0x78);
+        assert_eq!(info.chroma_format_idc, 1);
+        assert_eq!(info.num_temporal_layers, 1);
+
+        let hvcc = hevc_decoder_config(&[0x40, 0x01], &sps, &[0x44, 0x01], &info);
+        assert_eq!(hvcc[0], 1);
+        assert_eq!(&hvcc[1..13], &info.general_profile);
+        assert_eq!(hvcc[22], 3); // three parameter set arrays
+        assert_eq!(hvcc[23], 0x80 | 32);
+        assert_eq!(decoder_config_nals(&hvcc, true), vec![vec![0x40, 0x01], sps.to_vec(), vec![0x44, 0x01]]);
+        let avcc = avc_decoder_config(&[0x67, 0x64, 0x00, 0x28], &[0x68, 0xEB]);
+        assert_eq!(decoder_config_nals(&avcc, false), vec![vec![0x67, 0x64, 0x00, 0x28], vec![0x68, 0xEB]]);
+    }
+
+    #[test]
+    fn test_split_annexb() {
+        let data = [0, 0, 0, 1, 0x09, 0xF0, 0, 0, 1, 0x65, 0x88, 0x84, 0, 0, 0, 1, 0x41, 0x9A];
+        let nals = split_annexb(&data);
+        assert_eq!(nals, vec![&[0x09, 0xF0][..], &[0x65, 0x88, 0x84][..], &[0x41, 0x9A][..]]);
+    }
+
+    #[test]
+    fn test_adts_round_trip() {
+        // AAC-LC, 44.1 kHz, stereo, 100-byte frame.
+        let header = [0xFF, 0xF1, 0x50, 0x80, 0x0C, 0x9F, 0xFC];
+        let adts = parse_adts_header(&header).unwrap();
+        assert_eq!(adts.object_type, 2);
+        assert_eq!(adts.sample_rate(), 44100);
+        assert_eq!(adts.channel_config, 2);
+        assert_eq!(adts.frame_len, 100);
+        assert_eq!(adts.audio_specific_config(), vec![0x12, 0x10]);
+        assert_eq!(parse_audio_specific_config(&[0x12, 0x10]), Some((44100, 2)));
+    }
+}
@@ -0,0 +1,601 @@
+use super::codec;
+use super::mp4::{full_box, mp4_box};
+use super::{Codec, MediaKind, Sample, SampleSource, TrackInfo};
+use crate::core::error::DownloadError;
+use std::collections::VecDeque;
+use std::io::Read;
+use tracing::{debug, warn};
+
+/// Track state taken from `moov` (plus `trex` defaults from `mvex`).
+#[derive(Debug, Clone)]
+struct Fmp4Track {
+    track_id: u32,
+    info: TrackInfo,
+    default_duration: u32,
+    default_size: u32,
+    default_flags: u32,
+    /// Decode time of the next sample when a fragment has no `tfdt`.
+    next_dts: i64,
+}
+
+/// Demuxes concatenated init + media segments of a fragmented MP4 rendition.
+pub struct Fmp4Source<R: Read> {
+    reader: R,
+    kind: Option<MediaKind>,
+    tracks: Vec<Fmp4Track>,
+    ready: VecDeque<Sample>,
+    /// Last `moof` waiting for its `mdat`, with its absolute file offset.
+    pending_moof: Option<(u64, Vec<u8>)>,
+    position: u64,
+    eof: bool,
+}
+
+impl<R: Read + Send> Fmp4Source<R> {
+    /// `kind` restricts the demuxer to one media type; `None` keeps every supported track.
+    pub fn new(reader: R, kind: Option<MediaKind>) -> Self {
+        Self {
+            reader,
+            kind,
+            tracks: Vec::new(),
+            ready: VecDeque::new(),
+            pending_moof: None,
+            position: 0,
+            eof: false,
+        }
+    }
+
+    /// Reads one top-level box and processes it. Returns `false` at end of input.
+    fn read_top_level_box(&mut self) -> Result<bool, DownloadError> {
+        let start = self.position;
+        let mut header = [0u8; 8];
+        if !read_exact_or_eof(&mut self.reader, &mut header)? {
+            return Ok(false);
+        }
+        let mut size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let box_type: [u8; 4] = header[4..8].try_into().unwrap();
+        let mut header_len = 8u64;
+
+        if size == 1 {
+            let mut large = [0u8; 8];
+            if !read_exact_or_eof(&mut self.reader, &mut large)? {
+                return Ok(false);
+            }
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        }
+
+        let body_len = if size == 0 {
+            None
+        } else if size < header_len {
+            return Err(DownloadError::Parse(format!("Invalid MP4 box size {} at offset {}", size, start)));
+        } else {
+            Some(size - header_len)
+        };
+        self.position += header_len;
+
+        match &box_type {
+            b"moov" | b"moof" | b"mdat" => {
+                let body = self.read_body(body_len)?;
+                match &box_type {
+                    b"moov" => self.parse_moov(&body)?,
+                    b"moof" => self.pending_moof = Some((start, body)),
+                    _ => {
+                        if let Some((moof_start, moof)) = self.pending_moof.take() {
+                            self.parse_fragment(moof_start, &moof, start + header_len, &body);
+                        }
+                    }
+                }
+            }
+            _ => {
+                // ftyp, styp, sidx, emsg, free, prft... carry nothing we need.
+                match body_len {
+                    Some(len) => {
+                        std::io::copy(&mut (&mut self.reader).take(len), &mut std::io::sink())?;
+                        self.position += len;
+                    }
+                    None => self.eof = true,
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn read_body(&mut self, len: Option<u64>) -> Result<Vec<u8>, DownloadError> {
+        let mut body = Vec::new();
+        match len {
+            Some(len) => {
+                (&mut self.reader).take(len).read_to_end(&mut body)?;
+            }
+            None => {
+                self.reader.read_to_end(&mut body)?;
+            }
+        }
+        self.position += body.len() as u64;
+        Ok(body)
+    }
+
+    fn parse_moov(&mut self, moov: &[u8]) -> Result<(), DownloadError> {
+        let mut trex = Vec::new();
+        if let Some(mvex) = find_box(moov, b"mvex") {
+            for (kind, body) in iter_boxes(mvex) {
+                if &kind == b"trex" && body.len() >= 24 {
+                    trex.push((be32(body, 4), be32(body, 12), be32(body, 16), be32(body, 20)));
+                }
+            }
+        }
+
+        for (kind, trak) in iter_boxes(moov) {
+            if &kind != b"trak" {
+                continue;
+            }
+            let Some(mut track) = parse_trak(trak) else { continue };
+            if self.kind.is_some_and(|kind| kind != track.info.kind) {
+                continue;
+            }
+            if let Some((_, duration, size, flags)) = trex.iter().find(|t| t.0 == track.track_id) {
+                track.default_duration = *duration;
+                track.default_size = *size;
+                track.default_flags = *flags;
+            }
+
+            // A repeated init segment (e.g. after a discontinuity) refreshes the existing track.
+            match self.tracks.iter_mut().find(|t| t.info.kind == track.info.kind) {
+                Some(existing) => {
+                    let next_dts = existing.next_dts;
+                    *existing = track;
+                    existing.next_dts = next_dts;
+                }
+                None => self.tracks.push(track),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_fragment(&mut self, moof_start: u64, moof: &[u8], mdat_start: u64, mdat: &[u8]) {
+        for (kind, traf) in iter_boxes(moof) {
+            if &kind != b"traf" {
+                continue;
+            }
+            let Some(tfhd) = find_box(traf, b"tfhd") else { continue };
+            if tfhd.len() < 8 {
+                continue;
+            }
+            let tf_flags = be32(tfhd, 0) & 0xFF_FFFF;
+            let track_id = be32(tfhd, 4);
+            let Some(index) = self.tracks.iter().position(|t| t.track_id == track_id) else { continue };
+            let track = &mut self.tracks[index];
+
+            let mut pos = 8;
+            let mut base_offset = moof_start;
+            if tf_flags & 0x1 != 0 {
+                base_offset = be64(tfhd, pos);
+                pos += 8;
+            }
+            if tf_flags & 0x2 != 0 {
+                pos += 4;
+            }
+            let mut default_duration = track.default_duration;
+            let mut default_size = track.default_size;
+            let mut default_flags = track.default_flags;
+            if tf_flags & 0x8 != 0 {
+                default_duration = be32(tfhd, pos);
+                pos += 4;
+            }
+            if tf_flags & 0x10 != 0 {
+                default_size = be32(tfhd, pos);
+                pos += 4;
+            }
+            if tf_flags & 0x20 != 0 {
+                default_flags = be32(tfhd, pos);
+            }
+
+            if let Some(tfdt) = find_box(traf, b"tfdt") {
+                track.next_dts = if tfdt.first() == Some(&1) { be64(tfdt, 4) as i64 } else { be32(tfdt, 4) as i64 };
+            }
+
+            for (kind, trun) in iter_boxes(traf) {
+                if &kind != b"trun" || trun.len() < 8 {
+                    continue;
+                }
+                let version = trun[0];
+                let flags = be32(trun, 0) & 0xFF_FFFF;
+                let count = be32(trun, 4) as usize;
+                let mut pos = 8;
+                let mut data_offset = base_offset;
+                if flags & 0x1 != 0 {
+                    data_offset = (base_offset as i64 + be32(trun, pos) as i32 as i64) as u64;
+                    pos += 4;
+                }
+                let mut first_flags = None;
+                if flags & 0x4 != 0 {
+                    first_flags = Some(be32(trun, pos));
+                    pos += 4;
+                }
+
+                for i in 0..count {
+                    let mut duration = default_duration;
+                    let mut size = default_size;
+                    let mut sample_flags = if i == 0 { first_flags.unwrap_or(default_flags) } else { default_flags };
+                    let mut cto = 0i64;
+                    if flags & 0x100 != 0 {
+                        duration = be32(trun, pos);
+                        pos += 4;
+                    }
+                    if flags & 0x200 != 0 {
+                        size = be32(trun, pos);
+                        pos += 4;
+                    }
+                    if flags & 0x400 != 0 {
+                        sample_flags = be32(trun, pos);
+                        pos += 4;
+                    }
+                    if flags & 0x800 != 0 {
+                        let raw = be32(trun, pos);
+                        cto = if version == 0 { raw as i64 } else { raw as i32 as i64 };
+                        pos += 4;
+                    }
+                    if pos > trun.len() {
+                        warn!(track_id = track_id, "Truncated trun box");
+                        break;
+                    }
+
+                    let start = data_offset.saturating_sub(mdat_start) as usize;
+                    let Some(data) = mdat.get(start..start + size as usize) else {
+                        warn!(track_id = track_id, "Sample data lies outside of mdat");
+                        break;
+                    };
+                    data_offset += size as u64;
+
+                    let dts = track.next_dts;
+                    track.next_dts += duration as i64;
+                    let keyframe = track.info.kind == MediaKind::Audio || sample_flags & 0x1_0000 == 0;
+                    self.ready.push_back(Sample {
+                        track: index,
+                        dts,
+                        pts: dts + cto,
+                        keyframe,
+                        data: data.to_vec(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read + Send> SampleSource for Fmp4Source<R> {
+    fn tracks(&mut self) -> Result<Vec<TrackInfo>, DownloadError> {
+        while self.tracks.is_empty() && !self.eof {
+            if !self.read_top_level_box()? {
+                self.eof = true;
+            }
+        }
+        Ok(self.tracks.iter().map(|t| t.info.clone()).collect())
+    }
+
+    fn next_sample(&mut self) -> Result<Option<Sample>, DownloadError> {
+        while self.ready.is_empty() && !self.eof {
+            if !self.read_top_level_box()? {
+                self.eof = true;
+            }
+        }
+        Ok(self.ready.pop_front())
+    }
+}
+
+fn parse_trak(trak: &[u8]) -> Option<Fmp4Track> {
+    let tkhd = find_box(trak, b"tkhd")?;
+    let track_id = if tkhd.first() == Some(&1) { be32(tkhd, 20) } else { be32(tkhd, 12) };
+
+    let mdia = find_box(trak, b"mdia")?;
+    let mdhd = find_box(mdia, b"mdhd")?;
+    let timescale = if mdhd.first() == Some(&1) { be32(mdhd, 20) } else { be32(mdhd, 12) };
+    let hdlr = find_box(mdia, b"hdlr")?;
+    let kind = match hdlr.get(8..12)? {
+        b"vide" => MediaKind::Video,
+        b"soun" => MediaKind::Audio,
+        _ => return None,
+    };
+    let language = mdhd
+        .get(if mdhd.first() == Some(&1) { 32 } else { 20 }..)
+        .and_then(|b| decode_language(u16::from_be_bytes([*b.first()?, *b.get(1)?])));
+
+    let stsd = find_box(find_box(find_box(mdia, b"minf")?, b"stbl")?, b"stsd")?;
+    let (entry_type, entry) = iter_boxes(stsd.get(8..)?).next()?;
+
+    let mut info = TrackInfo {
+        kind,
+        codec: Codec::Aac,
+        timescale,
+        codec_private: Vec::new(),
+        width: 0,
+        height: 0,
+        sample_rate: 0,
+        channels: 0,
+        language,
+    };
+
+    match (&entry_type, kind) {
+        (b"avc1" | b"avc3", MediaKind::Video) => {
+            info.codec = Codec::H264;
+            info.width = u16::from_be_bytes([*entry.get(24)?, *entry.get(25)?]) as u32;
+            info.height = u16::from_be_bytes([*entry.get(26)?, *entry.get(27)?]) as u32;
+            info.codec_private = find_box(entry.get(78..)?, b"avcC")?.to_vec();
+        }
+        (b"hvc1" | b"hev1", MediaKind::Video) => {
+            info.codec = Codec::H265;
+            info.width = u16::from_be_bytes([*entry.get(24)?, *entry.get(25)?]) as u32;
+            info.height = u16::from_be_bytes([*entry.get(26)?, *entry.get(27)?]) as u32;
+            info.codec_private = find_box(entry.get(78..)?, b"hvcC")?.to_vec();
+        }
+        (b"mp4a", MediaKind::Audio) => {
+            info.channels = u16::from_be_bytes([*entry.get(16)?, *entry.get(17)?]);
+            info.sample_rate = be32(entry, 24) >> 16;
+            let esds = find_box(entry.get(28..)?, b"esds")?;
+            match esds_decoder_config(esds.get(4..)?) {
+                Some((0x40, config)) => {
+                    if let Some((rate, channels)) = codec::parse_audio_specific_config(&config) {
+                        info.sample_rate = rate;
+                        info.channels = channels;
+                    }
+                    info.codec_private = config;
+                }
+                Some((0x69 | 0x6B, _)) => info.codec = Codec::Mp3,
+                _ => return None,
+            }
+        }
+        (other, _) => {
+            debug!(sample_entry = %String::from_utf8_lossy(other), "Unsupported MP4 sample entry");
+            return None;
+        }
+    }
+
+    Some(Fmp4Track {
+        track_id,
+        info,
+        default_duration: 0,
+        default_size: 0,
+        default_flags: 0,
+        next_dts: 0,
+    })
+}
+
+/// Walks an ES_Descriptor, returning `(objectTypeIndication, DecoderSpecificInfo)`.
+fn esds_decoder_config(mut data: &[u8]) -> Option<(u8, Vec<u8>)> {
+    let mut object_type = 0;
+    while !data.is_empty() {
+        let tag = data[0];
+        let mut len = 0usize;
+        let mut i = 1;
+        loop {
+            let b = *data.get(i)?;
+            len = (len << 7) | (b & 0x7F) as usize;
+            i += 1;
+            if b & 0x80 == 0 || i > 4 {
+                break;
+            }
+        }
+        let body = data.get(i..i + len)?;
+        match tag {
+            0x03 => {
+                // ES_ID(2) + flags(1) + optional dependsOn/URL/OCR fields.
+                let flags = *body.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + *body.get(skip)? as usize;
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                data = body.get(skip..)?;
+                continue;
+            }
+            0x04 => {
+                object_type = *body.first()?;
+                data = body.get(13..)?;
+                continue;
+            }
+            0x05 => return Some((object_type, body.to_vec())),
+            _ => data = data.get(i + len..)?,
+        }
+    }
+    (object_type != 0).then_some((object_type, Vec::new()))
+}
+
+fn decode_language(code: u16) -> Option<String> {
+    let chars: String = [(code >> 10) & 0x1F, (code >> 5) & 0x1F, code & 0x1F]
+        .iter()
+        .map(|c| (*c as u8 + 0x60) as char)
+        .collect();
+    (chars.chars().all(|c| c.is_ascii_lowercase()) && chars != "und").then_some(chars)
+}
+
+/// Iterates over `(type, body)` of the boxes contained in `data`.
+pub fn iter_boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        if pos + 8 > data.len() {
+            return None;
+        }
+        let mut size = be32(data, pos) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().ok()?;
+        let mut header = 8;
+        if size == 1 {
+            size = be64(data, pos + 8) as usize;
+            header = 16;
+        } else if size == 0 {
+            size = data.len() - pos;
+        }
+        if size < header || pos + size > data.len() {
+            return None;
+        }
+        let body = &data[pos + header..pos + size];
+        pos += size;
+        Some((kind, body))
+    })
+}
+
+pub fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    iter_boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// Size of a version 1 `tfdt` box.
+const TFDT_LEN: i64 = 20;
+
+/// Rewrites a bare fragment (`moof` + `mdat`, as served by Smooth Streaming) for a single-track
+/// fragmented MP4: every `traf` is moved to track 1 and gets a `tfdt` with `decode_time` if it
+/// has none. `trun` data offsets are shifted by the bytes inserted into `moof`.
+pub fn normalize_fragment(data: &[u8], decode_time: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 64);
+    for (kind, body) in iter_boxes(data) {
+        if &kind == b"moof" {
+            normalize_moof(&mut out, body, decode_time);
+        } else {
+            mp4_box(&mut out, &kind, body);
+        }
+    }
+    out
+}
+
+fn normalize_moof(out: &mut Vec<u8>, moof: &[u8], decode_time: u64) {
+    let trafs = || iter_boxes(moof).filter(|(kind, _)| kind == b"traf").map(|(_, traf)| traf);
+    let growth = trafs().filter(|traf| find_box(traf, b"tfdt").is_none()).count() as i64 * TFDT_LEN;
+
+    let mut body = Vec::new();
+    for (kind, child) in iter_boxes(moof) {
+        if &kind != b"traf" {
+            mp4_box(&mut body, &kind, child);
+            continue;
+        }
+        let has_tfdt = find_box(child, b"tfdt").is_some();
+        // With an explicit base offset, trun offsets don't depend on where the moof starts.
+        let explicit_base = find_box(child, b"tfhd").is_some_and(|tfhd| be32(tfhd, 0) & 0x1 != 0);
+
+        let mut traf = Vec::new();
+        for (kind, entry) in iter_boxes(child) {
+            match &kind {
+                b"tfhd" if entry.len() >= 8 => {
+                    let mut tfhd = entry.to_vec();
+                    tfhd[4..8].copy_from_slice(&1u32.to_be_bytes());
+                    mp4_box(&mut traf, b"tfhd", &tfhd);
+                    if !has_tfdt {
+                        full_box(&mut traf, b"tfdt", 1, 0, &decode_time.to_be_bytes());
+                    }
+                }
+                b"trun" if entry.len() >= 12 && be32(entry, 0) & 0x1 != 0 && !explicit_base => {
+                    let mut trun = entry.to_vec();
+                    let offset = be32(entry, 8) as i32 as i64 + growth;
+                    trun[8..12].copy_from_slice(&(offset as i32).to_be_bytes());
+                    mp4_box(&mut traf, b"trun", &trun);
+                }
+                _ => mp4_box(&mut traf, &kind, entry),
+            }
+        }
+        mp4_box(&mut body, b"traf", &traf);
+    }
+    mp4_box(out, b"moof", &body);
+}
+
+/// Segment index (`sidx`) of a single-file fragmented MP4.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentIndex {
+    pub timescale: u32,
+    /// Distance from the end of the `sidx` box to the first subsegment.
+    pub first_offset: u64,
+    /// `(size in bytes, duration in timescale units)` of each subsegment, in order.
+    pub references: Vec<(u32, u32)>,
+}
+
+/// Parses the body of a `sidx` box.
+pub fn parse_sidx(body: &[u8]) -> Option<SegmentIndex> {
+    let version = *body.first()?;
+    let timescale = be32(body, 8);
+    let (first_offset, mut pos) = match version {
+        0 => (be32(body, 16) as u64, 20),
+        _ => (be64(body, 20), 28),
+    };
+    let count = u16::from_be_bytes(body.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+    pos += 4;
+    let mut references = Vec::with_capacity(count);
+    for _ in 0..count {
+        let entry = body.get(pos..pos + 12)?;
+        // References to nested indexes (top bit set) do not occur in single-file media.
+        references.push((be32(entry, 0) & 0x7FFF_FFFF, be32(entry, 4)));
+        pos += 12;
+    }
+    (timescale > 0).then_some(SegmentIndex { timescale, first_offset, references })
+}
+
+fn be32(data: &[u8], pos: usize) -> u32 {
+    data.get(pos..pos + 4).map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn be64(data: &[u8], pos: usize) -> u64 {
+    data.get(pos..pos + 8).map_or(0, |b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, DownloadError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_fragment_adds_tfdt() {
+        let mut tfhd = vec![0, 0, 0, 0];
+        tfhd.extend_from_slice(&2u32.to_be_bytes());
+        let mut trun = vec![0, 0, 0, 0x01];
+        trun.extend_from_slice(&0u32.to_be_bytes()); // sample count
+        trun.extend_from_slice(&100u32.to_be_bytes()); // data offset
+        let mut traf = Vec::new();
+        mp4_box(&mut traf, b"tfhd", &tfhd);
+        mp4_box(&mut traf, b"trun", &trun);
+        let mut moof = Vec::new();
+        mp4_box(&mut moof, b"traf", &traf);
+        let mut fragment = Vec::new();
+        mp4_box(&mut fragment, b"moof", &moof);
+        mp4_box(&mut fragment, b"mdat", &[1, 2, 3]);
+
+        let normalized = normalize_fragment(&fragment, 20_000_000);
+        assert_eq!(normalized.len(), fragment.len() + 20);
+        let traf = find_box(find_box(&normalized, b"moof").unwrap(), b"traf").unwrap();
+        let kinds: Vec<[u8; 4]> = iter_boxes(traf).map(|(k, _)| k).collect();
+        assert_eq!(kinds, vec![*b"tfhd", *b"tfdt", *b"trun"]);
+        assert_eq!(&find_box(traf, b"tfhd").unwrap()[4..8], &1u32.to_be_bytes());
+        assert_eq!(&find_box(traf, b"tfdt").unwrap()[4..12], &20_000_000u64.to_be_bytes());
+        assert_eq!(&find_box(traf, b"trun").unwrap()[8..12], &120u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_parse_sidx() {
+        let mut sidx = vec![0, 0, 0, 0];
+        sidx.extend_from_slice(&1u32.to_be_bytes()); // reference ID
+        sidx.extend_from_slice(&90_000u32.to_be_bytes());
+        sidx.extend_from_slice(&0u32.to_be_bytes()); // earliest presentation time
+        sidx.extend_from_slice(&16u32.to_be_bytes()); // first offset
+        sidx.extend_from_slice(&[0, 0, 0, 2]);
+        for (size, duration) in [(5000u32, 450_000u32), (4200, 180_000)] {
+            sidx.extend_from_slice(&size.to_be_bytes());
+            sidx.extend_from_slice(&duration.to_be_bytes());
+            sidx.extend_from_slice(&0x9000_0000u32.to_be_bytes());
+        }
+        let index = parse_sidx(&sidx).unwrap();
+        assert_eq!(index.timescale, 90_000);
+        assert_eq!(index.first_offset, 16);
+        assert_eq!(index.references, vec![(5000, 450_000), (4200, 180_000)]);
+    }
+}
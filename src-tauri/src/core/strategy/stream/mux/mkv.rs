@@ -0,0 +1,404 @@
+use super::{Codec, MediaKind, Sample, TrackInfo};
+use crate::core::error::DownloadError;
+use std::io::{Seek, SeekFrom, Write};
+
+const EBML: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const LANGUAGE: u32 = 0x22_B59C;
+const LANGUAGE_IETF: u32 = 0x22_B59D;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+const VOID: u32 = 0xEC;
+
+/// Space reserved after the segment header for the SeekHead written on finish.
+const SEEK_HEAD_RESERVED: usize = 96;
+/// Clusters are cut on video keyframes once they span at least this long (ms).
+const CLUSTER_TARGET_MS: i64 = 1000;
+const CLUSTER_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Streaming Matroska writer. Timestamps are written in milliseconds.
+pub struct MkvWriter<W: Write + Seek> {
+    out: W,
+    tracks: Vec<TrackInfo>,
+    segment_size_pos: u64,
+    segment_data_start: u64,
+    seek_head_pos: u64,
+    duration_pos: u64,
+    info_pos: u64,
+    tracks_pos: u64,
+    cluster: Vec<u8>,
+    cluster_start: Option<i64>,
+    cluster_has_keyframe: bool,
+    cues: Vec<(i64, u64)>,
+    max_timestamp: i64,
+    video_track: Option<usize>,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    /// Writes the EBML header, segment info and track entries.
+    pub fn new(mut out: W, tracks: Vec<TrackInfo>) -> Result<Self, DownloadError> {
+        let mut header = Vec::new();
+        let mut ebml = Vec::new();
+        uint_element(&mut ebml, 0x4286, 1);
+        uint_element(&mut ebml, 0x42F7, 1);
+        uint_element(&mut ebml, 0x42F2, 4);
+        uint_element(&mut ebml, 0x42F3, 8);
+        string_element(&mut ebml, 0x4282, "matroska");
+        uint_element(&mut ebml, 0x4287, 4);
+        uint_element(&mut ebml, 0x4285, 2);
+        element(&mut header, EBML, &ebml);
+
+        // Segment with an 8-byte size, patched in `finish`.
+        write_id(&mut header, SEGMENT);
+        let segment_size_pos = header.len() as u64;
+        header.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let segment_data_start = header.len() as u64;
+
+        let seek_head_pos = header.len() as u64;
+        void_element(&mut header, SEEK_HEAD_RESERVED);
+
+        let info_pos = header.len() as u64;
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        string_element(&mut info, MUXING_APP, "pirate-downloader");
+        string_element(&mut info, WRITING_APP, "Pirate Downloader");
+        let duration_offset = info.len();
+        float_element(&mut info, DURATION, 0.0);
+        write_id(&mut header, INFO);
+        write_size(&mut header, info.len() as u64);
+        let duration_pos = header.len() as u64 + duration_offset as u64 + 3;
+        header.extend_from_slice(&info);
+
+        let tracks_pos = header.len() as u64;
+        let mut entries = Vec::new();
+        for (i, track) in tracks.iter().enumerate() {
+            entries.extend(track_entry(i, track));
+        }
+        element(&mut header, TRACKS, &entries);
+
+        out.write_all(&header)?;
+
+        let video_track = tracks.iter().position(|t| t.kind == MediaKind::Video);
+        Ok(Self {
+            out,
+            tracks,
+            segment_size_pos,
+            segment_data_start,
+            seek_head_pos,
+            duration_pos,
+            info_pos,
+            tracks_pos,
+            cluster: Vec::new(),
+            cluster_start: None,
+            cluster_has_keyframe: false,
+            cues: Vec::new(),
+            max_timestamp: 0,
+            video_track,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        let timescale = self.tracks[sample.track].timescale.max(1) as i64;
+        let timestamp = (sample.pts * 1000).div_euclid(timescale).max(0);
+        let is_video_key = Some(sample.track) == self.video_track && sample.keyframe;
+
+        if let Some(start) = self.cluster_start {
+            let relative = timestamp - start;
+            let span_reached = match self.video_track {
+                Some(_) => is_video_key && relative >= CLUSTER_TARGET_MS,
+                None => relative >= 2 * CLUSTER_TARGET_MS,
+            };
+            if span_reached || !(-30_000..=30_000).contains(&relative) || self.cluster.len() > CLUSTER_MAX_BYTES {
+                self.flush_cluster()?;
+            }
+        }
+
+        let start = *self.cluster_start.get_or_insert(timestamp);
+        if self.cluster.is_empty() {
+            self.cluster_has_keyframe = is_video_key || self.video_track.is_none();
+        }
+
+        let relative = (timestamp - start) as i16;
+        let mut block = Vec::with_capacity(sample.data.len() + 4);
+        block.push(0x80 | (sample.track as u8 + 1));
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if sample.keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(&sample.data);
+        element(&mut self.cluster, SIMPLE_BLOCK, &block);
+
+        self.max_timestamp = self.max_timestamp.max(timestamp);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> Result<(), DownloadError> {
+        let Some(start) = self.cluster_start.take() else { return Ok(()) };
+        let position = self.out.stream_position()? - self.segment_data_start;
+        if self.cluster_has_keyframe {
+            self.cues.push((start, position));
+        }
+
+        let mut body = Vec::new();
+        uint_element(&mut body, TIMESTAMP, start as u64);
+        let mut header = Vec::new();
+        write_id(&mut header, CLUSTER);
+        write_size(&mut header, (body.len() + self.cluster.len()) as u64);
+
+        self.out.write_all(&header)?;
+        self.out.write_all(&body)?;
+        self.out.write_all(&self.cluster)?;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    /// Flushes the last cluster and writes cues, seek head, duration and segment size.
+    pub fn finish(mut self) -> Result<W, DownloadError> {
+        self.flush_cluster()?;
+
+        let cues_pos = self.out.stream_position()?;
+        let cue_track = self.video_track.unwrap_or(0) as u64 + 1;
+        let mut cues = Vec::new();
+        for (time, position) in &self.cues {
+            let mut positions = Vec::new();
+            uint_element(&mut positions, CUE_TRACK, cue_track);
+            uint_element(&mut positions, CUE_CLUSTER_POSITION, *position);
+            let mut point = Vec::new();
+            uint_element(&mut point, CUE_TIME, *time as u64);
+            element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            element(&mut cues, CUE_POINT, &point);
+        }
+        if !cues.is_empty() {
+            let mut out = Vec::new();
+            element(&mut out, CUES, &cues);
+            self.out.write_all(&out)?;
+        }
+        let end = self.out.stream_position()?;
+
+        let mut seeks = Vec::new();
+        let mut targets = vec![(INFO, self.info_pos), (TRACKS, self.tracks_pos)];
+        if !cues.is_empty() {
+            targets.push((CUES, cues_pos));
+        }
+        for (id, position) in targets {
+            let mut seek = Vec::new();
+            let mut id_bytes = Vec::new();
+            write_id(&mut id_bytes, id);
+            element(&mut seek, SEEK_ID, &id_bytes);
+            write_id(&mut seek, SEEK_POSITION);
+            write_size(&mut seek, 8);
+            seek.extend_from_slice(&(position - self.segment_data_start).to_be_bytes());
+            element(&mut seeks, SEEK, &seek);
+        }
+        let mut seek_head = Vec::new();
+        element(&mut seek_head, SEEK_HEAD, &seeks);
+        let padding = SEEK_HEAD_RESERVED - seek_head.len();
+        void_element(&mut seek_head, padding);
+
+        self.out.seek(SeekFrom::Start(self.seek_head_pos))?;
+        self.out.write_all(&seek_head)?;
+
+        self.out.seek(SeekFrom::Start(self.duration_pos))?;
+        self.out.write_all(&(self.max_timestamp as f64).to_be_bytes())?;
+
+        self.out.seek(SeekFrom::Start(self.segment_size_pos))?;
+        let size = end - self.segment_data_start;
+        let mut size_bytes = size.to_be_bytes();
+        size_bytes[0] = 0x01;
+        self.out.write_all(&size_bytes)?;
+
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn track_entry(index: usize, track: &TrackInfo) -> Vec<u8> {
+    let mut entry = Vec::new();
+    uint_element(&mut entry, TRACK_NUMBER, index as u64 + 1);
+    uint_element(&mut entry, TRACK_UID, index as u64 + 1);
+    uint_element(&mut entry, TRACK_TYPE, if track.kind == MediaKind::Video { 1 } else { 2 });
+    uint_element(&mut entry, FLAG_LACING, 0);
+    // `Language` only accepts ISO 639-2 codes; HLS/DASH use BCP 47 tags like "en" or "pt-BR".
+    match track.language.as_deref() {
+        Some(lang) if lang.len() == 3 && lang.chars().all(|c| c.is_ascii_lowercase()) => {
+            string_element(&mut entry, LANGUAGE, lang);
+        }
+        Some(lang) => {
+            string_element(&mut entry, LANGUAGE, "und");
+            string_element(&mut entry, LANGUAGE_IETF, lang);
+        }
+        None => string_element(&mut entry, LANGUAGE, "und"),
+    }
+    let codec_id = match track.codec {
+        Codec::H264 => "V_MPEG4/ISO/AVC",
+        Codec::H265 => "V_MPEGH/ISO/HEVC",
+        Codec::Aac => "A_AAC",
+        Codec::Mp3 => "A_MPEG/L3",
+    };
+    string_element(&mut entry, CODEC_ID, codec_id);
+    if !track.codec_private.is_empty() {
+        element(&mut entry, CODEC_PRIVATE, &track.codec_private);
+    }
+
+    let mut settings = Vec::new();
+    match track.kind {
+        MediaKind::Video => {
+            uint_element(&mut settings, PIXEL_WIDTH, track.width as u64);
+            uint_element(&mut settings, PIXEL_HEIGHT, track.height as u64);
+            element(&mut entry, VIDEO, &settings);
+        }
+        MediaKind::Audio => {
+            float_element(&mut settings, SAMPLING_FREQUENCY, track.sample_rate as f64);
+            uint_element(&mut settings, CHANNELS, track.channels.max(1) as u64);
+            element(&mut entry, AUDIO, &settings);
+        }
+    }
+
+    let mut out = Vec::new();
+    element(&mut out, TRACK_ENTRY, &entry);
+    out
+}
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(3);
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Writes an EBML variable-length size using the fewest bytes possible.
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    let mut len = 1;
+    while len < 8 && size >= (1u64 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let marked = size | (1u64 << (7 * len));
+    buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn element(buf: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, payload.len() as u64);
+    buf.extend_from_slice(payload);
+}
+
+fn uint_element(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+    element(buf, id, &bytes[skip..]);
+}
+
+fn float_element(buf: &mut Vec<u8>, id: u32, value: f64) {
+    element(buf, id, &value.to_be_bytes());
+}
+
+fn string_element(buf: &mut Vec<u8>, id: u32, value: &str) {
+    element(buf, id, value.as_bytes());
+}
+
+/// Writes a Void element occupying exactly `total` bytes (at least 2).
+fn void_element(buf: &mut Vec<u8>, total: usize) {
+    write_id(buf, VOID);
+    let payload = total.saturating_sub(2);
+    write_size(buf, payload as u64);
+    buf.extend(std::iter::repeat_n(0, payload));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Reads an EBML element header at `pos`, returning `(id, size, header_len)`.
+    fn read_header(data: &[u8], pos: usize) -> (u32, u64, usize) {
+        let id_len = data[pos].leading_zeros() as usize + 1;
+        let id = data[pos..pos + id_len].iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let first = data[pos + id_len];
+        let size_len = first.leading_zeros() as usize + 1;
+        let mut size = (first as u64) & ((1u64 << (8 - size_len)) - 1);
+        for b in &data[pos + id_len + 1..pos + id_len + size_len] {
+            size = (size << 8) | *b as u64;
+        }
+        (id, size, id_len + size_len)
+    }
+
+    #[test]
+    fn test_write_size_lengths() {
+        let mut buf = Vec::new();
+        write_size(&mut buf, 5);
+        assert_eq!(buf, vec![0x85]);
+        buf.clear();
+        write_size(&mut buf, 200);
+        assert_eq!(buf, vec![0x40, 0xC8]);
+    }
+
+    #[test]
+    fn test_segment_layout() {
+        let track = TrackInfo {
+            kind: MediaKind::Audio,
+            codec: Codec::Aac,
+            timescale: 1000,
+            codec_private: vec![0x12, 0x10],
+            width: 0,
+            height: 0,
+            sample_rate: 44100,
+            channels: 2,
+            language: Some("eng".to_string()),
+        };
+        let mut writer = MkvWriter::new(Cursor::new(Vec::new()), vec![track]).unwrap();
+        for i in 0..100 {
+            writer
+                .write_sample(&Sample { track: 0, dts: i * 23, pts: i * 23, keyframe: true, data: vec![0xAB; 10] })
+                .unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let (id, size, header_len) = read_header(&data, 0);
+        assert_eq!(id, EBML);
+        let segment_pos = header_len + size as usize;
+        let (id, size, header_len) = read_header(&data, segment_pos);
+        assert_eq!(id, SEGMENT);
+        assert_eq!(segment_pos + header_len + size as usize, data.len());
+
+        // Walk top-level children of the segment.
+        let mut pos = segment_pos + header_len;
+        let mut ids = Vec::new();
+        while pos < data.len() {
+            let (id, size, header_len) = read_header(&data, pos);
+            ids.push(id);
+            pos += header_len + size as usize;
+        }
+        assert_eq!(pos, data.len());
+        assert_eq!(&ids[..4], &[SEEK_HEAD, VOID, INFO, TRACKS]);
+        assert!(ids.contains(&CLUSTER));
+        assert_eq!(*ids.last().unwrap(), CUES);
+    }
+}
@@ -0,0 +1,270 @@
+//! Native (FFmpeg-free) demuxing and muxing of downloaded stream renditions.
+//!
+//! Demuxers turn a downloaded rendition (MPEG-TS, fragmented MP4 or packed
+//! ADTS/MP3 audio) into timestamped samples; the Matroska and MP4 writers
+//! interleave samples from one or more renditions into a single file.
+
+pub mod audio;
+pub mod codec;
+pub mod fmp4;
+pub mod mkv;
+pub mod mp4;
+pub mod packed_audio;
+pub mod ts;
+
+use crate::core::error::DownloadError;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Video,
+    Audio,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+    Aac,
+    Mp3,
+}
+
+/// Description of one elementary stream, as needed by a container writer.
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    pub kind: MediaKind,
+    pub codec: Codec,
+    /// Units per second of the sample timestamps.
+    pub timescale: u32,
+    /// `avcC` for H.264, `hvcC` for H.265, `AudioSpecificConfig` for AAC, empty for MP3.
+    pub codec_private: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub language: Option<String>,
+}
+
+/// One access unit / audio frame. Video payloads are 4-byte length-prefixed NAL units.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub track: usize,
+    pub dts: i64,
+    pub pts: i64,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+/// A demuxer producing samples in decode order.
+pub trait SampleSource: Send {
+    /// Reads ahead until every track's codec configuration is known.
+    fn tracks(&mut self) -> Result<Vec<TrackInfo>, DownloadError>;
+
+    fn next_sample(&mut self) -> Result<Option<Sample>, DownloadError>;
+
+    /// Timestamp discontinuities the demuxer has smoothed over.
+    fn discontinuities(&self) -> u32 {
+        0
+    }
+}
+
+/// Output container for muxed downloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Matroska,
+    Mp4,
+    Ts,
+}
+
+impl Container {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Matroska => "mkv",
+            Container::Mp4 => "mp4",
+            Container::Ts => "ts",
+        }
+    }
+}
+
+/// A container writer fed with interleaved samples.
+pub trait ContainerWriter: Send {
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError>;
+    fn finish(self: Box<Self>) -> Result<(), DownloadError>;
+}
+
+impl<W: Write + Seek + Send> ContainerWriter for mkv::MkvWriter<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        mkv::MkvWriter::write_sample(self, sample)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), DownloadError> {
+        mkv::MkvWriter::finish(*self).map(|_| ())
+    }
+}
+
+impl<W: Write + Seek + Send> ContainerWriter for mp4::Mp4Writer<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        mp4::Mp4Writer::write_sample(self, sample)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), DownloadError> {
+        mp4::Mp4Writer::finish(*self).map(|_| ())
+    }
+}
+
+impl<W: Write + Send> ContainerWriter for ts::TsWriter<W> {
+    fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        ts::TsWriter::write_sample(self, sample)
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), DownloadError> {
+        ts::TsWriter::finish(*self).map(|_| ())
+    }
+}
+
+/// Starts a `container` file with the given tracks on `out`.
+pub fn container_writer<W: Write + Seek + Send + 'static>(
+    out: W,
+    tracks: Vec<TrackInfo>,
+    container: Container,
+) -> Result<Box<dyn ContainerWriter>, DownloadError> {
+    Ok(match container {
+        Container::Matroska => Box::new(mkv::MkvWriter::new(out, tracks)?),
+        Container::Mp4 => Box::new(mp4::Mp4Writer::new(out, tracks)?),
+        Container::Ts => Box::new(ts::TsWriter::new(out, tracks)?),
+    })
+}
+
+/// A downloaded rendition to be muxed, with the kind of media to take from it
+/// (`None` takes every supported stream).
+#[derive(Debug, Clone)]
+pub struct MuxInput {
+    pub path: PathBuf,
+    pub kind: Option<MediaKind>,
+    pub language: Option<String>,
+}
+
+/// Opens a rendition file and picks a demuxer by sniffing its first bytes.
+pub fn open_source(path: &Path, kind: Option<MediaKind>) -> Result<Box<dyn SampleSource>, DownloadError> {
+    let mut head = vec![0u8; 4096];
+    let mut file = File::open(path)?;
+    let read = file.read(&mut head)?;
+    head.truncate(read);
+
+    let reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+
+    if head.len() >= 8 && matches!(&head[4..8], b"ftyp" | b"styp" | b"moov" | b"moof" | b"sidx") {
+        debug!(path = ?path, "Detected fragmented MP4 input");
+        return Ok(Box::new(fmp4::Fmp4Source::new(reader, kind)));
+    }
+    if head.first() == Some(&0x47) && (head.len() <= 188 || head[188] == 0x47) {
+        debug!(path = ?path, "Detected MPEG-TS input");
+        return Ok(Box::new(ts::TsSource::new(reader, kind)));
+    }
+
+    let audio_start = codec::id3_tag_len(&head).unwrap_or(0);
+    if let Some(frame) = head.get(audio_start..) {
+        if codec::parse_adts_header(frame).is_some() || codec::parse_mpeg_audio_header(frame).is_some() {
+            debug!(path = ?path, "Detected packed audio input");
+            return Ok(Box::new(packed_audio::PackedAudioSource::new(reader)));
+        }
+    }
+
+    Err(DownloadError::Parse(format!("Unrecognised container in {}", path.display())))
+}
+
+/// Interleaves the given renditions by timestamp into a single file.
+pub fn mux_files(inputs: &[MuxInput], output: &Path, container: Container) -> Result<(), DownloadError> {
+    let mut sources = Vec::new();
+    let mut track_maps: Vec<Vec<usize>> = Vec::new();
+    let mut all_tracks: Vec<TrackInfo> = Vec::new();
+
+    for input in inputs {
+        let mut source = open_source(&input.path, input.kind)?;
+        let tracks = source.tracks()?;
+        if tracks.is_empty() {
+            return Err(DownloadError::Parse(format!(
+                "No {} stream found in {}",
+                input.kind.map_or("supported".to_string(), |k| format!("{:?}", k)),
+                input.path.display()
+            )));
+        }
+
+        let mut map = Vec::new();
+        for mut track in tracks {
+            if track.language.is_none() {
+                track.language = input.language.clone();
+            }
+            map.push(all_tracks.len());
+            all_tracks.push(track);
+        }
+        track_maps.push(map);
+        sources.push(source);
+    }
+
+    let mut heads: Vec<Option<Sample>> = Vec::with_capacity(sources.len());
+    for source in sources.iter_mut() {
+        heads.push(source.next_sample()?);
+    }
+
+    // Renditions of one presentation share a clock; shift them together so the output starts at zero.
+    let seconds = |sample: &Sample, idx: usize| {
+        let track = &all_tracks[track_maps[idx][sample.track]];
+        sample.pts as f64 / track.timescale as f64
+    };
+    let start = heads
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.as_ref().map(|s| seconds(s, i)))
+        .fold(f64::INFINITY, f64::min);
+    let start = if start.is_finite() { start } else { 0.0 };
+    let offsets: Vec<i64> = all_tracks
+        .iter()
+        .map(|t| (start * t.timescale as f64).round() as i64)
+        .collect();
+
+    let file = BufWriter::new(File::create(output)?);
+    let mut writer = container_writer(file, all_tracks.clone(), container)?;
+    let mut written = 0u64;
+
+    loop {
+        let next = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| {
+                let s = s.as_ref()?;
+                let track = &all_tracks[track_maps[i][s.track]];
+                Some((i, s.dts as f64 / track.timescale as f64))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i);
+
+        let Some(idx) = next else { break };
+        let mut sample = heads[idx].take().expect("selected head exists");
+        sample.track = track_maps[idx][sample.track];
+        sample.pts -= offsets[sample.track];
+        sample.dts -= offsets[sample.track];
+
+        writer.write_sample(&sample)?;
+        written += 1;
+        heads[idx] = sources[idx].next_sample()?;
+    }
+
+    writer.finish()?;
+
+    let discontinuities: u32 = sources.iter().map(|s| s.discontinuities()).sum();
+    if discontinuities > 0 {
+        warn!(output = ?output, discontinuities = discontinuities, "Corrected timestamp discontinuities while muxing");
+    }
+    info!(output = ?output, container = ?container, tracks = all_tracks.len(), samples = written, "Muxed renditions");
+    Ok(())
+}
+
+/// Remuxes a downloaded MPEG-TS capture into MP4 without re-encoding.
+pub fn remux_ts_to_mp4(input: &Path, output: &Path) -> Result<(), DownloadError> {
+    let inputs = [MuxInput { path: input.to_path_buf(), kind: None, language: None }];
+    mux_files(&inputs, output, Container::Mp4)
+}
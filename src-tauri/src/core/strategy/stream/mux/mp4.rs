@@ -0,0 +1,568 @@
+use super::{Codec, MediaKind, Sample, TrackInfo};
+use crate::core::error::DownloadError;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Movie-level timescale (milliseconds) used by `mvhd`, `tkhd` and `elst`.
+const MOVIE_TIMESCALE: u64 = 1000;
+/// `ftyp` + 16-byte `mdat` header with a 64-bit size.
+const MDAT_HEADER_LEN: u64 = 16;
+
+/// Sample tables collected for one track while its data is written to `mdat`.
+struct Mp4Track {
+    info: TrackInfo,
+    /// Media timescale; audio is rescaled to its sample rate so frame durations are exact.
+    timescale: u32,
+    sizes: Vec<u32>,
+    dts: Vec<i64>,
+    composition_offsets: Vec<i64>,
+    sync_samples: Vec<u32>,
+    /// `(file offset, first sample number, sample count)` per chunk.
+    chunks: Vec<(u64, u32, u32)>,
+    min_pts: Option<i64>,
+}
+
+impl Mp4Track {
+    fn rescale(&self, value: i64) -> i64 {
+        let source = self.info.timescale.max(1) as i64;
+        if source == self.timescale as i64 {
+            value
+        } else {
+            (value * self.timescale as i64).div_euclid(source)
+        }
+    }
+
+    /// Durations between consecutive DTS values; the last sample repeats the previous one.
+    fn durations(&self) -> Vec<u32> {
+        let mut durations: Vec<u32> = self.dts.windows(2).map(|w| (w[1] - w[0]).max(0) as u32).collect();
+        if !self.dts.is_empty() {
+            let fallback = match self.info.kind {
+                MediaKind::Audio if self.info.codec == Codec::Aac => 1024 * self.timescale / self.info.sample_rate.max(1),
+                MediaKind::Audio => 1152 * self.timescale / self.info.sample_rate.max(1),
+                MediaKind::Video => self.timescale / 30,
+            };
+            durations.push(durations.last().copied().unwrap_or(fallback));
+        }
+        durations
+    }
+}
+
+/// Progressive MP4 writer: samples go straight into one `mdat`, and `moov` (with the
+/// sample tables built along the way) is appended by [`Mp4Writer::finish`].
+pub struct Mp4Writer<W: Write + Seek> {
+    out: W,
+    tracks: Vec<Mp4Track>,
+    mdat_pos: u64,
+    position: u64,
+    last_track: Option<usize>,
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    /// Writes `ftyp` and the `mdat` header.
+    pub fn new(mut out: W, tracks: Vec<TrackInfo>) -> Result<Self, DownloadError> {
+        let mut header = Vec::new();
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(b"isom");
+        ftyp.extend_from_slice(&0x200u32.to_be_bytes());
+        for brand in [b"isom", b"iso2", b"avc1", b"mp41"] {
+            ftyp.extend_from_slice(brand);
+        }
+        mp4_box(&mut header, b"ftyp", &ftyp);
+
+        let mdat_pos = out.stream_position()? + header.len() as u64;
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(b"mdat");
+        header.extend_from_slice(&0u64.to_be_bytes());
+        out.write_all(&header)?;
+
+        let tracks = tracks
+            .into_iter()
+            .map(|info| Mp4Track {
+                timescale: match info.kind {
+                    MediaKind::Audio if info.sample_rate > 0 => info.sample_rate,
+                    _ => info.timescale.max(1),
+                },
+                info,
+                sizes: Vec::new(),
+                dts: Vec::new(),
+                composition_offsets: Vec::new(),
+                sync_samples: Vec::new(),
+                chunks: Vec::new(),
+                min_pts: None,
+            })
+            .collect();
+
+        Ok(Self {
+            out,
+            tracks,
+            mdat_pos,
+            position: mdat_pos + MDAT_HEADER_LEN,
+            last_track: None,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        let position = self.position;
+        let track = self
+            .tracks
+            .get_mut(sample.track)
+            .ok_or_else(|| DownloadError::Parse(format!("Sample for unknown track {}", sample.track)))?;
+
+        let dts = track.rescale(sample.dts);
+        let pts = track.rescale(sample.pts);
+        let sample_number = track.sizes.len() as u32 + 1;
+
+        // A new chunk starts whenever samples of another track were written in between.
+        match track.chunks.last_mut() {
+            Some(chunk) if self.last_track == Some(sample.track) => chunk.2 += 1,
+            _ => track.chunks.push((position, sample_number, 1)),
+        }
+        track.sizes.push(sample.data.len() as u32);
+        track.dts.push(dts);
+        track.composition_offsets.push(pts - dts);
+        if sample.keyframe {
+            track.sync_samples.push(sample_number);
+        }
+        track.min_pts = Some(track.min_pts.map_or(pts, |min| min.min(pts)));
+
+        self.out.write_all(&sample.data)?;
+        self.position += sample.data.len() as u64;
+        self.last_track = Some(sample.track);
+        Ok(())
+    }
+
+    /// Patches the `mdat` size and appends `moov`.
+    pub fn finish(mut self) -> Result<W, DownloadError> {
+        let end = self.position;
+        self.out.seek(SeekFrom::Start(self.mdat_pos + 8))?;
+        self.out.write_all(&(end - self.mdat_pos).to_be_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+
+        let moov = self.build_moov();
+        self.out.write_all(&moov)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn build_moov(&self) -> Vec<u8> {
+        let traks: Vec<(Vec<u8>, u64)> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !t.sizes.is_empty())
+            .map(|(i, t)| trak(i as u32 + 1, t))
+            .collect();
+        let movie_duration = traks.iter().map(|(_, d)| *d).max().unwrap_or(0);
+
+        let mut body = Vec::new();
+        mvhd(&mut body, movie_duration, self.tracks.len() as u32 + 1);
+
+        for (trak, _) in traks {
+            body.extend(trak);
+        }
+
+        let mut moov = Vec::new();
+        mp4_box(&mut moov, b"moov", &body);
+        moov
+    }
+}
+
+/// Init segment (`ftyp` + `moov` with `mvex`) of a fragmented MP4 holding `info` as track 1,
+/// for renditions whose manifest describes the codec but serves no init segment. Fragments
+/// are expected in `info.timescale` units.
+pub fn fragmented_init(info: &TrackInfo) -> Vec<u8> {
+    let track = Mp4Track {
+        info: info.clone(),
+        timescale: info.timescale.max(1),
+        sizes: Vec::new(),
+        dts: Vec::new(),
+        composition_offsets: Vec::new(),
+        sync_samples: Vec::new(),
+        chunks: Vec::new(),
+        min_pts: None,
+    };
+
+    let mut out = Vec::new();
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"iso6");
+    ftyp.extend_from_slice(&0u32.to_be_bytes());
+    for brand in [b"iso6", b"isom", b"iso2", b"mp41"] {
+        ftyp.extend_from_slice(brand);
+    }
+    mp4_box(&mut out, b"ftyp", &ftyp);
+
+    let mut trak_body = Vec::new();
+    tkhd(&mut trak_body, 1, 0, info);
+    mp4_box(&mut trak_body, b"mdia", &mdia(&track, 0, &stbl(&track, &[])));
+
+    let mut trex = Vec::new();
+    trex.extend_from_slice(&1u32.to_be_bytes()); // track_ID
+    trex.extend_from_slice(&1u32.to_be_bytes()); // default_sample_description_index
+    trex.extend_from_slice(&[0; 12]); // default duration, size, flags
+    let mut mvex = Vec::new();
+    full_box(&mut mvex, b"trex", 0, 0, &trex);
+
+    let mut body = Vec::new();
+    mvhd(&mut body, 0, 2);
+    mp4_box(&mut body, b"trak", &trak_body);
+    mp4_box(&mut body, b"mvex", &mvex);
+    mp4_box(&mut out, b"moov", &body);
+    out
+}
+
+fn mvhd(buf: &mut Vec<u8>, duration: u64, next_track_id: u32) {
+    let mut mvhd = Vec::new();
+    mvhd.extend_from_slice(&[0; 8]); // creation / modification time
+    mvhd.extend_from_slice(&(MOVIE_TIMESCALE as u32).to_be_bytes());
+    mvhd.extend_from_slice(&(duration as u32).to_be_bytes());
+    mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate 1.0
+    mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume 1.0
+    mvhd.extend_from_slice(&[0; 10]);
+    mvhd.extend_from_slice(&unity_matrix());
+    mvhd.extend_from_slice(&[0; 24]);
+    mvhd.extend_from_slice(&next_track_id.to_be_bytes());
+    full_box(buf, b"mvhd", 0, 0, &mvhd);
+}
+
+fn tkhd(buf: &mut Vec<u8>, track_id: u32, duration: u64, info: &TrackInfo) {
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&track_id.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 4]);
+    tkhd.extend_from_slice(&(duration as u32).to_be_bytes());
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&[0; 4]); // layer, alternate group
+    tkhd.extend_from_slice(&(if info.kind == MediaKind::Video { 0u16 } else { 0x0100 }).to_be_bytes());
+    tkhd.extend_from_slice(&[0; 2]);
+    tkhd.extend_from_slice(&unity_matrix());
+    tkhd.extend_from_slice(&(info.width << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(info.height << 16).to_be_bytes());
+    full_box(buf, b"tkhd", 0, 0x3, &tkhd);
+}
+
+/// Builds a `trak` box, returning it with the track duration in movie timescale.
+fn trak(track_id: u32, track: &Mp4Track) -> (Vec<u8>, u64) {
+    let timescale = track.timescale as u64;
+    let durations = track.durations();
+    let media_duration: u64 = durations.iter().map(|d| *d as u64).sum();
+
+    // The muxer shifts timestamps so the presentation starts at zero; a track that starts
+    // later gets an empty edit, and media before its first presented sample is skipped.
+    let first_dts = track.dts[0];
+    let min_pts = track.min_pts.unwrap_or(first_dts);
+    let media_start = (min_pts - first_dts).max(0) as u64;
+    let delay = (min_pts.max(0) as u64) * MOVIE_TIMESCALE / timescale;
+    let presented = media_duration.saturating_sub(media_start) * MOVIE_TIMESCALE / timescale;
+    let track_duration = delay + presented;
+
+    let mut body = Vec::new();
+    tkhd(&mut body, track_id, track_duration, &track.info);
+
+    let mut elst = Vec::new();
+    let mut entries = Vec::new();
+    if delay > 0 {
+        entries.push((delay as u32, -1i32));
+    }
+    entries.push((presented as u32, media_start as i32));
+    elst.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (duration, media_time) in entries {
+        elst.extend_from_slice(&duration.to_be_bytes());
+        elst.extend_from_slice(&media_time.to_be_bytes());
+        elst.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+    }
+    let mut edts = Vec::new();
+    full_box(&mut edts, b"elst", 0, 0, &elst);
+    mp4_box(&mut body, b"edts", &edts);
+    mp4_box(&mut body, b"mdia", &mdia(track, media_duration, &stbl(track, &durations)));
+
+    let mut out = Vec::new();
+    mp4_box(&mut out, b"trak", &body);
+    (out, track_duration)
+}
+
+/// `mdia` contents: media header, handler and media information around `stbl`.
+fn mdia(track: &Mp4Track, media_duration: u64, stbl: &[u8]) -> Vec<u8> {
+    let is_video = track.info.kind == MediaKind::Video;
+    let mut mdia = Vec::new();
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0; 8]);
+    mdhd.extend_from_slice(&track.timescale.to_be_bytes());
+    mdhd.extend_from_slice(&(media_duration as u32).to_be_bytes());
+    mdhd.extend_from_slice(&encode_language(track.info.language.as_deref()).to_be_bytes());
+    mdhd.extend_from_slice(&[0; 2]);
+    full_box(&mut mdia, b"mdhd", 0, 0, &mdhd);
+
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(if is_video { b"VideoHandler\0" } else { b"SoundHandler\0" });
+    full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+
+    let mut minf = Vec::new();
+    if is_video {
+        full_box(&mut minf, b"vmhd", 0, 1, &[0; 8]);
+    } else {
+        full_box(&mut minf, b"smhd", 0, 0, &[0; 4]);
+    }
+    let mut dref = Vec::new();
+    dref.extend_from_slice(&1u32.to_be_bytes());
+    full_box(&mut dref, b"url ", 0, 1, &[]);
+    let mut dinf = Vec::new();
+    full_box(&mut dinf, b"dref", 0, 0, &dref);
+    mp4_box(&mut minf, b"dinf", &dinf);
+    mp4_box(&mut minf, b"stbl", stbl);
+    mp4_box(&mut mdia, b"minf", &minf);
+    mdia
+}
+
+fn stbl(track: &Mp4Track, durations: &[u32]) -> Vec<u8> {
+    let mut stbl = Vec::new();
+
+    let mut stsd = Vec::new();
+    stsd.extend_from_slice(&1u32.to_be_bytes());
+    stsd.extend(sample_entry(&track.info));
+    full_box(&mut stbl, b"stsd", 0, 0, &stsd);
+
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for duration in durations {
+        match runs.last_mut() {
+            Some((count, delta)) if delta == duration => *count += 1,
+            _ => runs.push((1, *duration)),
+        }
+    }
+    let mut stts = Vec::new();
+    stts.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+    for (count, delta) in runs {
+        stts.extend_from_slice(&count.to_be_bytes());
+        stts.extend_from_slice(&delta.to_be_bytes());
+    }
+    full_box(&mut stbl, b"stts", 0, 0, &stts);
+
+    if track.composition_offsets.iter().any(|o| *o != 0) {
+        let mut runs: Vec<(u32, i64)> = Vec::new();
+        for offset in &track.composition_offsets {
+            match runs.last_mut() {
+                Some((count, value)) if value == offset => *count += 1,
+                _ => runs.push((1, *offset)),
+            }
+        }
+        let version = if track.composition_offsets.iter().any(|o| *o < 0) { 1 } else { 0 };
+        let mut ctts = Vec::new();
+        ctts.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+        for (count, offset) in runs {
+            ctts.extend_from_slice(&count.to_be_bytes());
+            ctts.extend_from_slice(&(offset as i32).to_be_bytes());
+        }
+        full_box(&mut stbl, b"ctts", version, 0, &ctts);
+    }
+
+    if track.info.kind == MediaKind::Video && track.sync_samples.len() < track.sizes.len() {
+        let mut stss = Vec::new();
+        stss.extend_from_slice(&(track.sync_samples.len() as u32).to_be_bytes());
+        for sample in &track.sync_samples {
+            stss.extend_from_slice(&sample.to_be_bytes());
+        }
+        full_box(&mut stbl, b"stss", 0, 0, &stss);
+    }
+
+    let mut stsc_entries: Vec<(u32, u32)> = Vec::new();
+    for (index, (_, _, count)) in track.chunks.iter().enumerate() {
+        if stsc_entries.last().map(|(_, c)| *c) != Some(*count) {
+            stsc_entries.push((index as u32 + 1, *count));
+        }
+    }
+    let mut stsc = Vec::new();
+    stsc.extend_from_slice(&(stsc_entries.len() as u32).to_be_bytes());
+    for (first_chunk, count) in stsc_entries {
+        stsc.extend_from_slice(&first_chunk.to_be_bytes());
+        stsc.extend_from_slice(&count.to_be_bytes());
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+    }
+    full_box(&mut stbl, b"stsc", 0, 0, &stsc);
+
+    let mut stsz = Vec::new();
+    stsz.extend_from_slice(&0u32.to_be_bytes());
+    stsz.extend_from_slice(&(track.sizes.len() as u32).to_be_bytes());
+    for size in &track.sizes {
+        stsz.extend_from_slice(&size.to_be_bytes());
+    }
+    full_box(&mut stbl, b"stsz", 0, 0, &stsz);
+
+    let large = track.chunks.last().is_some_and(|(offset, _, _)| *offset > u32::MAX as u64);
+    let mut offsets = Vec::new();
+    offsets.extend_from_slice(&(track.chunks.len() as u32).to_be_bytes());
+    for (offset, _, _) in &track.chunks {
+        if large {
+            offsets.extend_from_slice(&offset.to_be_bytes());
+        } else {
+            offsets.extend_from_slice(&(*offset as u32).to_be_bytes());
+        }
+    }
+    full_box(&mut stbl, if large { b"co64" } else { b"stco" }, 0, 0, &offsets);
+
+    stbl
+}
+
+fn sample_entry(info: &TrackInfo) -> Vec<u8> {
+    let mut entry = vec![0; 6];
+    entry.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+
+    let mut out = Vec::new();
+    match info.codec {
+        Codec::H264 | Codec::H265 => {
+            entry.extend_from_slice(&[0; 16]);
+            entry.extend_from_slice(&(info.width as u16).to_be_bytes());
+            entry.extend_from_slice(&(info.height as u16).to_be_bytes());
+            entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+            entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+            entry.extend_from_slice(&[0; 4]);
+            entry.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+            entry.extend_from_slice(&[0; 32]); // compressorname
+            entry.extend_from_slice(&0x0018u16.to_be_bytes());
+            entry.extend_from_slice(&(-1i16).to_be_bytes());
+            if info.codec == Codec::H264 {
+                mp4_box(&mut entry, b"avcC", &info.codec_private);
+                mp4_box(&mut out, b"avc1", &entry);
+            } else {
+                mp4_box(&mut entry, b"hvcC", &info.codec_private);
+                mp4_box(&mut out, b"hvc1", &entry);
+            }
+        }
+        Codec::Aac | Codec::Mp3 => {
+            entry.extend_from_slice(&[0; 8]);
+            entry.extend_from_slice(&info.channels.max(1).to_be_bytes());
+            entry.extend_from_slice(&16u16.to_be_bytes());
+            entry.extend_from_slice(&[0; 4]);
+            entry.extend_from_slice(&(info.sample_rate.min(0xFFFF) << 16).to_be_bytes());
+            full_box(&mut entry, b"esds", 0, 0, &es_descriptor(info));
+            mp4_box(&mut out, b"mp4a", &entry);
+        }
+    }
+    out
+}
+
+/// ES_Descriptor with the decoder configuration for AAC (0x40) or MP3 (0x6B).
+fn es_descriptor(info: &TrackInfo) -> Vec<u8> {
+    let mut decoder_config = vec![if info.codec == Codec::Aac { 0x40 } else { 0x6B }, 0x15];
+    decoder_config.extend_from_slice(&[0; 11]); // buffer size, max and average bitrate
+    if !info.codec_private.is_empty() {
+        descriptor(&mut decoder_config, 0x05, &info.codec_private);
+    }
+
+    let mut es = vec![0, 0, 0]; // ES_ID, flags
+    descriptor(&mut es, 0x04, &decoder_config);
+    descriptor(&mut es, 0x06, &[0x02]);
+
+    let mut out = Vec::new();
+    descriptor(&mut out, 0x03, &es);
+    out
+}
+
+fn descriptor(buf: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    let len = body.len() as u32;
+    buf.push(tag);
+    buf.extend_from_slice(&[0x80 | ((len >> 21) as u8 & 0x7F), 0x80 | ((len >> 14) as u8 & 0x7F), 0x80 | ((len >> 7) as u8 & 0x7F), len as u8 & 0x7F]);
+    buf.extend_from_slice(body);
+}
+
+/// Packs an ISO 639-2 code into the 15-bit `mdhd` form (`und` for anything else).
+fn encode_language(language: Option<&str>) -> u16 {
+    let code = language
+        .filter(|l| l.len() == 3 && l.bytes().all(|b| b.is_ascii_lowercase()))
+        .unwrap_or("und");
+    code.bytes().fold(0u16, |acc, b| (acc << 5) | (b - 0x60) as u16)
+}
+
+fn unity_matrix() -> [u8; 36] {
+    let mut matrix = [0u8; 36];
+    matrix[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    matrix[16..20].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    matrix[32..36].copy_from_slice(&0x4000_0000u32.to_be_bytes());
+    matrix
+}
+
+pub(super) fn mp4_box(buf: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    buf.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(body);
+}
+
+pub(super) fn full_box(buf: &mut Vec<u8>, kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) {
+    let mut full = Vec::with_capacity(body.len() + 4);
+    full.push(version);
+    full.extend_from_slice(&flags.to_be_bytes()[1..]);
+    full.extend_from_slice(body);
+    mp4_box(buf, kind, &full);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fmp4::{find_box, iter_boxes};
+    use super::*;
+    use std::io::Cursor;
+
+    fn track(kind: MediaKind, codec: Codec) -> TrackInfo {
+        TrackInfo {
+            kind,
+            codec,
+            timescale: 90_000,
+            codec_private: if codec == Codec::Aac { vec![0x12, 0x10] } else { vec![1, 0x64, 0, 0x28, 0xFF, 0xE0, 0] },
+            width: 1920,
+            height: 1080,
+            sample_rate: if kind == MediaKind::Audio { 48_000 } else { 0 },
+            channels: 2,
+            language: None,
+        }
+    }
+
+    #[test]
+    fn test_interleaved_sample_tables() {
+        let tracks = vec![track(MediaKind::Video, Codec::H264), track(MediaKind::Audio, Codec::Aac)];
+        let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), tracks).unwrap();
+        for i in 0..4i64 {
+            // Video with a constant 3000-tick composition offset, audio in 1920-tick (1024-sample) frames.
+            writer
+                .write_sample(&Sample { track: 0, dts: i * 3000, pts: i * 3000 + 3000, keyframe: i == 0, data: vec![1; 100] })
+                .unwrap();
+            writer
+                .write_sample(&Sample { track: 1, dts: i * 1920, pts: i * 1920, keyframe: true, data: vec![2; 10] })
+                .unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let boxes: Vec<[u8; 4]> = iter_boxes(&data).map(|(kind, _)| kind).collect();
+        assert_eq!(boxes, vec![*b"ftyp", *b"mdat", *b"moov"]);
+        let mdat = find_box(&data, b"mdat").unwrap();
+        assert_eq!(mdat.len(), 4 * 110);
+
+        let moov = find_box(&data, b"moov").unwrap();
+        let traks: Vec<&[u8]> = iter_boxes(moov).filter(|(k, _)| k == b"trak").map(|(_, b)| b).collect();
+        assert_eq!(traks.len(), 2);
+
+        let stbl = |trak: &[u8]| -> Vec<u8> {
+            let minf = find_box(find_box(trak, b"mdia").unwrap(), b"minf").unwrap();
+            find_box(minf, b"stbl").unwrap().to_vec()
+        };
+        let video = stbl(traks[0]);
+        // Four chunks of one sample each, first at the start of the mdat payload.
+        let stco = find_box(&video, b"stco").unwrap();
+        assert_eq!(&stco[4..8], &4u32.to_be_bytes());
+        let ftyp_len = 8 + 4 + 4 + 16;
+        assert_eq!(&stco[8..12], &(ftyp_len + 16u32).to_be_bytes());
+        assert!(find_box(&video, b"ctts").is_some());
+        let stss = find_box(&video, b"stss").unwrap();
+        assert_eq!(&stss[4..12], &[0, 0, 0, 1, 0, 0, 0, 1]);
+
+        // Audio is rescaled to its 48 kHz sample rate: 1920 ticks at 90 kHz = 1024 samples.
+        let audio = stbl(traks[1]);
+        let stts = find_box(&audio, b"stts").unwrap();
+        assert_eq!(&stts[4..16], &[0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 4, 0]);
+        assert!(find_box(&audio, b"stss").is_none());
+    }
+
+    #[test]
+    fn test_encode_language() {
+        assert_eq!(encode_language(Some("eng")), 0x15C7);
+        assert_eq!(encode_language(Some("en")), encode_language(Some("und")));
+    }
+}
@@ -0,0 +1,142 @@
+use super::codec;
+use super::{Codec, MediaKind, Sample, SampleSource, TrackInfo};
+use crate::core::error::DownloadError;
+use std::io::Read;
+use tracing::warn;
+
+const ID3_TIMESCALE: i64 = 90_000;
+
+/// Demuxes HLS "packed audio" renditions: raw ADTS or MP3 frames, each segment
+/// prefixed by an ID3 tag carrying its 90 kHz transport stream timestamp.
+pub struct PackedAudioSource<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    track: Option<TrackInfo>,
+    /// Timestamp (in samples) of the next frame.
+    next_ts: i64,
+    /// 90 kHz timestamp from the last ID3 tag, applied to the frame that follows it.
+    id3_ts: Option<i64>,
+    pending: Option<Sample>,
+    eof: bool,
+}
+
+impl<R: Read + Send> PackedAudioSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            track: None,
+            next_ts: 0,
+            id3_ts: None,
+            pending: None,
+            eof: false,
+        }
+    }
+
+    /// Makes sure at least `len` bytes are buffered (unless the input ends first).
+    fn fill(&mut self, len: usize) -> Result<(), DownloadError> {
+        let mut chunk = [0u8; 64 * 1024];
+        while self.buffer.len() < len && !self.eof {
+            let n = self.reader.read(&mut chunk)?;
+            if n == 0 {
+                self.eof = true;
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Sample>, DownloadError> {
+        loop {
+            self.fill(10)?;
+            if self.buffer.is_empty() {
+                return Ok(None);
+            }
+
+            if let Some(tag_len) = codec::id3_tag_len(&self.buffer) {
+                self.fill(tag_len)?;
+                let tag_len = tag_len.min(self.buffer.len());
+                if let Some(ts) = codec::id3_transport_timestamp(&self.buffer[..tag_len]) {
+                    self.id3_ts = Some(ts as i64);
+                }
+                self.buffer.drain(..tag_len);
+                continue;
+            }
+
+            if let Some(header) = codec::parse_adts_header(&self.buffer) {
+                self.fill(header.frame_len)?;
+                if self.buffer.len() < header.frame_len {
+                    warn!("Truncated ADTS frame at end of packed audio");
+                    self.buffer.clear();
+                    return Ok(None);
+                }
+                self.ensure_track(Codec::Aac, header.sample_rate(), header.channel_config as u16, header.audio_specific_config());
+                let data = self.buffer[header.header_len..header.frame_len].to_vec();
+                self.buffer.drain(..header.frame_len);
+                return Ok(Some(self.emit(data, 1024)));
+            }
+
+            if let Some(header) = codec::parse_mpeg_audio_header(&self.buffer) {
+                self.fill(header.frame_len)?;
+                if self.buffer.len() < header.frame_len {
+                    warn!("Truncated MP3 frame at end of packed audio");
+                    self.buffer.clear();
+                    return Ok(None);
+                }
+                self.ensure_track(Codec::Mp3, header.sample_rate, header.channels, Vec::new());
+                let data = self.buffer[..header.frame_len].to_vec();
+                self.buffer.drain(..header.frame_len);
+                return Ok(Some(self.emit(data, header.samples_per_frame as i64)));
+            }
+
+            // Not a frame boundary: skip ahead to the next possible sync word.
+            let skip = self.buffer[1..]
+                .iter()
+                .position(|b| *b == 0xFF || *b == b'I')
+                .map_or(self.buffer.len(), |p| p + 1);
+            self.buffer.drain(..skip);
+        }
+    }
+
+    fn ensure_track(&mut self, codec: Codec, sample_rate: u32, channels: u16, codec_private: Vec<u8>) {
+        if self.track.is_some() {
+            return;
+        }
+        self.track = Some(TrackInfo {
+            kind: MediaKind::Audio,
+            codec,
+            timescale: sample_rate,
+            codec_private,
+            width: 0,
+            height: 0,
+            sample_rate,
+            channels,
+            language: None,
+        });
+    }
+
+    fn emit(&mut self, data: Vec<u8>, duration: i64) -> Sample {
+        if let (Some(ts), Some(track)) = (self.id3_ts.take(), &self.track) {
+            self.next_ts = ts * track.sample_rate as i64 / ID3_TIMESCALE;
+        }
+        let ts = self.next_ts;
+        self.next_ts += duration;
+        Sample { track: 0, dts: ts, pts: ts, keyframe: true, data }
+    }
+}
+
+impl<R: Read + Send> SampleSource for PackedAudioSource<R> {
+    fn tracks(&mut self) -> Result<Vec<TrackInfo>, DownloadError> {
+        if self.track.is_none() && self.pending.is_none() {
+            self.pending = self.read_frame()?;
+        }
+        Ok(self.track.clone().into_iter().collect())
+    }
+
+    fn next_sample(&mut self) -> Result<Option<Sample>, DownloadError> {
+        if let Some(sample) = self.pending.take() {
+            return Ok(Some(sample));
+        }
+        self.read_frame()
+    }
+}
@@ -0,0 +1,938 @@
+use super::{audio, codec};
+use super::{Codec, MediaKind, Sample, SampleSource, TrackInfo};
+use crate::core::error::DownloadError;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use tracing::{debug, warn};
+
+pub const TS_PACKET_SIZE: usize = 188;
+const TS_TIMESCALE: u32 = 90_000;
+const PTS_WRAP: i64 = 1 << 33;
+/// Largest forward DTS gap (10 s) still treated as continuous; anything beyond, or any
+/// backwards step, is a timestamp discontinuity (e.g. spliced ads or encoder restarts).
+const MAX_DTS_GAP: i64 = 10 * TS_TIMESCALE as i64;
+/// How much input `tracks()` may consume looking for codec configuration.
+const PROBE_LIMIT: usize = 16 * 1024 * 1024;
+/// PIDs `TsWriter` uses for the program map and the first elementary stream.
+const PMT_PID: u16 = 0x1000;
+const FIRST_ES_PID: u16 = 0x100;
+/// Timestamps written by `TsWriter` start this far in, so players never see a negative DTS.
+const WRITER_TS_OFFSET: i64 = TS_TIMESCALE as i64;
+
+/// Per-PID elementary stream state.
+struct EsStream {
+    codec: Codec,
+    track: Option<usize>,
+    pes: Vec<u8>,
+    last_ts: Option<i64>,
+    /// Last DTS handed out (after discontinuity correction) and the step that led to it.
+    last_out: Option<i64>,
+    last_step: i64,
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    /// Samples of a stream whose configuration is not known yet are dropped until then.
+    waiting_for_keyframe: bool,
+}
+
+/// Offset applied to every stream so timestamps stay continuous across discontinuities.
+#[derive(Debug, Default)]
+struct TimelineCorrection {
+    offset: i64,
+    /// Offset in use before the latest discontinuity, for packets of other streams that
+    /// were muxed before the splice point but arrive after it.
+    previous: i64,
+    discontinuities: u32,
+}
+
+impl TimelineCorrection {
+    /// Returns the offset to add to `dts` so that it follows `last` (a stream's previous output DTS).
+    fn offset_for(&mut self, last: Option<i64>, step: i64, dts: i64) -> i64 {
+        let Some(last) = last else { return self.offset };
+        let continuous = |offset: i64| (0..=MAX_DTS_GAP).contains(&(dts + offset - last));
+        if continuous(self.offset) {
+            return self.offset;
+        }
+        if continuous(self.previous) {
+            return self.previous;
+        }
+
+        self.previous = self.offset;
+        self.offset = last + step.max(1) - dts;
+        self.discontinuities += 1;
+        debug!(from = last, to = dts, "Timestamp discontinuity, shifting by {}", self.offset - self.previous);
+        self.offset
+    }
+}
+
+/// Demuxes H.264 / H.265 / AAC / MP3 elementary streams out of an MPEG transport stream.
+pub struct TsSource<R: Read> {
+    reader: R,
+    kind: Option<MediaKind>,
+    timeline: TimelineCorrection,
+    pmt_pids: Vec<u16>,
+    streams: HashMap<u16, EsStream>,
+    tracks: Vec<TrackInfo>,
+    ready: VecDeque<Sample>,
+    consumed: usize,
+    eof: bool,
+}
+
+impl<R: Read + Send> TsSource<R> {
+    /// `kind` selects which elementary streams are kept (a video rendition may also carry
+    /// audio); `None` keeps all of them.
+    pub fn new(reader: R, kind: Option<MediaKind>) -> Self {
+        Self {
+            reader,
+            kind,
+            timeline: TimelineCorrection::default(),
+            pmt_pids: Vec::new(),
+            streams: HashMap::new(),
+            tracks: Vec::new(),
+            ready: VecDeque::new(),
+            consumed: 0,
+            eof: false,
+        }
+    }
+
+    /// Reads the next 188-byte packet, resynchronising on the sync byte if needed.
+    fn read_packet(&mut self, packet: &mut [u8; TS_PACKET_SIZE]) -> Result<bool, DownloadError> {
+        if !read_full(&mut self.reader, packet)? {
+            return Ok(false);
+        }
+        self.consumed += TS_PACKET_SIZE;
+
+        while packet[0] != 0x47 {
+            match packet.iter().position(|b| *b == 0x47) {
+                Some(offset) => {
+                    packet.copy_within(offset.., 0);
+                    if !read_full(&mut self.reader, &mut packet[TS_PACKET_SIZE - offset..])? {
+                        return Ok(false);
+                    }
+                }
+                None => {
+                    if !read_full(&mut self.reader, packet)? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Processes packets until at least one sample is ready or the input ends.
+    fn pump(&mut self) -> Result<(), DownloadError> {
+        let mut packet = [0u8; TS_PACKET_SIZE];
+        while self.ready.is_empty() && !self.eof {
+            if !self.read_packet(&mut packet)? {
+                self.eof = true;
+                let pids: Vec<u16> = self.streams.keys().copied().collect();
+                for pid in pids {
+                    self.flush_pes(pid);
+                }
+                break;
+            }
+            self.handle_packet(&packet);
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, packet: &[u8; TS_PACKET_SIZE]) {
+        let pusi = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        let adaptation = (packet[3] >> 4) & 0x3;
+
+        let mut offset = 4;
+        if adaptation & 0x2 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation & 0x1 == 0 || offset >= TS_PACKET_SIZE {
+            return;
+        }
+        let payload = &packet[offset..];
+
+        if pid == 0 {
+            if pusi {
+                self.parse_pat(payload);
+            }
+        } else if self.pmt_pids.contains(&pid) {
+            if pusi {
+                self.parse_pmt(payload);
+            }
+        } else if self.streams.contains_key(&pid) {
+            if pusi {
+                self.flush_pes(pid);
+            }
+            if let Some(stream) = self.streams.get_mut(&pid) {
+                stream.pes.extend_from_slice(payload);
+            }
+        }
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else { return };
+        // Program loop: 4 bytes per entry between the 8-byte header and the CRC.
+        let end = section.len().saturating_sub(4);
+        let mut pos = 8;
+        while pos + 4 <= end {
+            let program = u16::from_be_bytes([section[pos], section[pos + 1]]);
+            let pid = (((section[pos + 2] & 0x1F) as u16) << 8) | section[pos + 3] as u16;
+            if program != 0 && !self.pmt_pids.contains(&pid) {
+                self.pmt_pids.push(pid);
+            }
+            pos += 4;
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else { return };
+        if section.len() < 12 {
+            return;
+        }
+        let program_info_len = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+        let end = section.len().saturating_sub(4);
+        let mut pos = 12 + program_info_len;
+
+        while pos + 5 <= end {
+            let stream_type = section[pos];
+            let pid = (((section[pos + 1] & 0x1F) as u16) << 8) | section[pos + 2] as u16;
+            let info_len = (((section[pos + 3] & 0x0F) as usize) << 8) | section[pos + 4] as usize;
+            pos += 5 + info_len;
+
+            let (codec, kind) = match stream_type {
+                0x1B => (Codec::H264, MediaKind::Video),
+                0x24 => (Codec::H265, MediaKind::Video),
+                0x0F => (Codec::Aac, MediaKind::Audio),
+                0x03 | 0x04 => (Codec::Mp3, MediaKind::Audio),
+                0x15 => continue, // timed ID3 metadata
+                other => {
+                    if !self.streams.contains_key(&pid) {
+                        debug!(pid = pid, stream_type = other, "Skipping unsupported elementary stream");
+                    }
+                    continue;
+                }
+            };
+            if self.kind.is_some_and(|wanted| wanted != kind) {
+                continue;
+            }
+
+            self.streams.entry(pid).or_insert(EsStream {
+                codec,
+                track: None,
+                pes: Vec::new(),
+                last_ts: None,
+                last_out: None,
+                last_step: 0,
+                vps: None,
+                sps: None,
+                pps: None,
+                waiting_for_keyframe: kind == MediaKind::Video,
+            });
+        }
+    }
+
+    fn flush_pes(&mut self, pid: u16) {
+        let Some(stream) = self.streams.get_mut(&pid) else { return };
+        if stream.pes.is_empty() {
+            return;
+        }
+        let pes = std::mem::take(&mut stream.pes);
+        let Some((pts, dts, payload)) = parse_pes(&pes) else {
+            warn!(pid = pid, "Dropping malformed PES packet");
+            return;
+        };
+
+        let Some(pts) = pts else {
+            warn!(pid = pid, "Dropping PES packet without a timestamp");
+            return;
+        };
+        let dts = dts.unwrap_or(pts);
+        let raw_dts = unwrap_timestamp(&mut stream.last_ts, dts);
+        let pts = raw_dts + wrap_delta(raw_dts, pts);
+
+        let offset = self.timeline.offset_for(stream.last_out, stream.last_step, raw_dts);
+        let (dts, pts) = (raw_dts + offset, pts + offset);
+        if let Some(last) = stream.last_out {
+            if dts > last {
+                stream.last_step = dts - last;
+            }
+        }
+        stream.last_out = Some(dts);
+
+        match stream.codec {
+            Codec::H264 | Codec::H265 => self.push_video(pid, pts, dts, payload),
+            Codec::Aac => self.push_adts(pid, pts, payload),
+            Codec::Mp3 => self.push_mp3(pid, pts, payload),
+        }
+    }
+
+    fn push_video(&mut self, pid: u16, pts: i64, dts: i64, payload: &[u8]) {
+        let nals = codec::split_annexb(payload);
+        let Some(stream) = self.streams.get_mut(&pid) else { return };
+        let hevc = stream.codec == Codec::H265;
+        let nal_type = |nal: &[u8]| match nal.first() {
+            Some(h) if hevc => (h >> 1) & 0x3F,
+            Some(h) => h & 0x1F,
+            None => 0,
+        };
+        let (vps_type, sps_type, pps_type, aud_type) = if hevc { (32, 33, 34, 35) } else { (0, 7, 8, 9) };
+        // IDR for H.264; BLA/IDR/CRA (IRAP) for H.265.
+        let keyframe = nals.iter().any(|n| if hevc { (16..=21).contains(&nal_type(n)) } else { nal_type(n) == 5 });
+
+        for nal in &nals {
+            match nal_type(nal) {
+                t if hevc && t == vps_type => stream.vps = Some(nal.to_vec()),
+                t if t == sps_type => stream.sps = Some(nal.to_vec()),
+                t if t == pps_type => stream.pps = Some(nal.to_vec()),
+                _ => {}
+            }
+        }
+
+        if stream.track.is_none() {
+            let (Some(sps), Some(pps)) = (&stream.sps, &stream.pps) else { return };
+            let (codec_private, width, height) = if hevc {
+                let Some(vps) = &stream.vps else { return };
+                let Some(info) = codec::parse_h265_sps(sps) else {
+                    warn!(pid = pid, "Failed to parse H.265 SPS");
+                    return;
+                };
+                (codec::hevc_decoder_config(vps, sps, pps, &info), info.width, info.height)
+            } else {
+                let Some(info) = codec::parse_h264_sps(sps) else {
+                    warn!(pid = pid, "Failed to parse H.264 SPS");
+                    return;
+                };
+                (codec::avc_decoder_config(sps, pps), info.width, info.height)
+            };
+            stream.track = Some(self.tracks.len());
+            self.tracks.push(TrackInfo {
+                kind: MediaKind::Video,
+                codec: stream.codec,
+                timescale: TS_TIMESCALE,
+                codec_private,
+                width,
+                height,
+                sample_rate: 0,
+                channels: 0,
+                language: None,
+            });
+        }
+
+        if stream.waiting_for_keyframe {
+            if !keyframe {
+                return;
+            }
+            stream.waiting_for_keyframe = false;
+        }
+
+        let data = codec::nals_to_length_prefixed(&nals, |n| nal_type(n) == aud_type);
+        if let Some(track) = stream.track {
+            self.ready.push_back(Sample { track, dts, pts, keyframe, data });
+        }
+    }
+
+    fn push_adts(&mut self, pid: u16, pts: i64, mut payload: &[u8]) {
+        let mut frame_index = 0i64;
+        while let Some(header) = codec::parse_adts_header(payload) {
+            if header.frame_len > payload.len() {
+                break;
+            }
+            let Some(stream) = self.streams.get_mut(&pid) else { return };
+            let track = match stream.track {
+                Some(track) => track,
+                None => {
+                    let track = self.tracks.len();
+                    stream.track = Some(track);
+                    self.tracks.push(TrackInfo {
+                        kind: MediaKind::Audio,
+                        codec: Codec::Aac,
+                        timescale: TS_TIMESCALE,
+                        codec_private: header.audio_specific_config(),
+                        width: 0,
+                        height: 0,
+                        sample_rate: header.sample_rate(),
+                        channels: header.channel_config as u16,
+                        language: None,
+                    });
+                    track
+                }
+            };
+
+            let ts = pts + frame_index * 1024 * TS_TIMESCALE as i64 / header.sample_rate() as i64;
+            self.ready.push_back(Sample {
+                track,
+                dts: ts,
+                pts: ts,
+                keyframe: true,
+                data: payload[header.header_len..header.frame_len].to_vec(),
+            });
+            payload = &payload[header.frame_len..];
+            frame_index += 1;
+        }
+    }
+
+    fn push_mp3(&mut self, pid: u16, pts: i64, mut payload: &[u8]) {
+        let mut offset_samples = 0i64;
+        while let Some(header) = codec::parse_mpeg_audio_header(payload) {
+            if header.frame_len > payload.len() {
+                break;
+            }
+            let Some(stream) = self.streams.get_mut(&pid) else { return };
+            let track = match stream.track {
+                Some(track) => track,
+                None => {
+                    let track = self.tracks.len();
+                    stream.track = Some(track);
+                    self.tracks.push(TrackInfo {
+                        kind: MediaKind::Audio,
+                        codec: Codec::Mp3,
+                        timescale: TS_TIMESCALE,
+                        codec_private: Vec::new(),
+                        width: 0,
+                        height: 0,
+                        sample_rate: header.sample_rate,
+                        channels: header.channels,
+                        language: None,
+                    });
+                    track
+                }
+            };
+
+            let ts = pts + offset_samples * TS_TIMESCALE as i64 / header.sample_rate as i64;
+            self.ready.push_back(Sample {
+                track,
+                dts: ts,
+                pts: ts,
+                keyframe: true,
+                data: payload[..header.frame_len].to_vec(),
+            });
+            payload = &payload[header.frame_len..];
+            offset_samples += header.samples_per_frame as i64;
+        }
+    }
+
+    fn all_configured(&self) -> bool {
+        !self.streams.is_empty() && self.streams.values().all(|s| s.track.is_some())
+    }
+}
+
+impl<R: Read + Send> SampleSource for TsSource<R> {
+    fn tracks(&mut self) -> Result<Vec<TrackInfo>, DownloadError> {
+        let mut packet = [0u8; TS_PACKET_SIZE];
+        while !self.eof && !self.all_configured() && self.consumed < PROBE_LIMIT {
+            if !self.read_packet(&mut packet)? {
+                self.eof = true;
+                let pids: Vec<u16> = self.streams.keys().copied().collect();
+                for pid in pids {
+                    self.flush_pes(pid);
+                }
+                break;
+            }
+            self.handle_packet(&packet);
+        }
+        Ok(self.tracks.clone())
+    }
+
+    fn next_sample(&mut self) -> Result<Option<Sample>, DownloadError> {
+        if self.ready.is_empty() {
+            self.pump()?;
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    fn discontinuities(&self) -> u32 {
+        self.timeline.discontinuities
+    }
+}
+
+/// Muxes samples into an MPEG transport stream with one program and one PID per track.
+/// Video is written as Annex B with an access unit delimiter and, ahead of keyframes, the
+/// parameter sets from the codec private data; AAC gets ADTS headers. PAT and PMT are
+/// repeated at every video keyframe (or every second of audio-only streams).
+pub struct TsWriter<W: Write> {
+    out: W,
+    tracks: Vec<TrackInfo>,
+    /// Parameter set NAL units of each video track.
+    parameter_sets: Vec<Vec<Vec<u8>>>,
+    continuity: Vec<u8>,
+    psi_continuity: [u8; 2],
+    pcr_track: usize,
+    last_psi: Option<i64>,
+}
+
+impl<W: Write> TsWriter<W> {
+    pub fn new(out: W, tracks: Vec<TrackInfo>) -> Result<Self, DownloadError> {
+        if tracks.is_empty() {
+            return Err(DownloadError::Config("Nothing to write: no tracks".to_string()));
+        }
+        let parameter_sets = tracks
+            .iter()
+            .map(|t| match t.codec {
+                Codec::H264 | Codec::H265 => codec::decoder_config_nals(&t.codec_private, t.codec == Codec::H265),
+                _ => Vec::new(),
+            })
+            .collect();
+        let pcr_track = tracks.iter().position(|t| t.kind == MediaKind::Video).unwrap_or(0);
+        let continuity = vec![0; tracks.len()];
+        Ok(Self { out, tracks, parameter_sets, continuity, psi_continuity: [0; 2], pcr_track, last_psi: None })
+    }
+
+    pub fn write_sample(&mut self, sample: &Sample) -> Result<(), DownloadError> {
+        let (timescale, kind, codec) = {
+            let track = &self.tracks[sample.track];
+            (track.timescale.max(1) as i64, track.kind, track.codec)
+        };
+        let to_90k = |ts: i64| ts * TS_TIMESCALE as i64 / timescale + WRITER_TS_OFFSET;
+        let (pts, dts) = (to_90k(sample.pts), to_90k(sample.dts));
+        let is_pcr_track = sample.track == self.pcr_track;
+        let video = kind == MediaKind::Video;
+
+        let psi_due = match self.last_psi {
+            None => true,
+            Some(_) if video => is_pcr_track && sample.keyframe,
+            Some(last) => is_pcr_track && dts - last >= TS_TIMESCALE as i64,
+        };
+        if psi_due {
+            self.write_psi()?;
+            self.last_psi = Some(dts);
+        }
+
+        let payload = match codec {
+            Codec::H264 | Codec::H265 => self.annexb(sample),
+            Codec::Aac => {
+                let header = audio::adts_header(&self.tracks[sample.track].codec_private, sample.data.len())
+                    .ok_or_else(|| DownloadError::Parse("AAC configuration cannot be written as ADTS".to_string()))?;
+                [&header[..], &sample.data].concat()
+            }
+            Codec::Mp3 => sample.data.clone(),
+        };
+
+        let stream_id = if video { 0xE0 } else { 0xC0 };
+        let mut header = Vec::with_capacity(19);
+        if pts != dts {
+            header.push(0xC0);
+            header.push(10);
+            header.extend(encode_pes_timestamp(0x3, pts));
+            header.extend(encode_pes_timestamp(0x1, dts));
+        } else {
+            header.push(0x80);
+            header.push(5);
+            header.extend(encode_pes_timestamp(0x2, pts));
+        }
+        let length = 1 + header.len() + payload.len();
+        // Video PES packets may exceed 64 KiB, where the length field is left at 0 (unbounded).
+        let length = if video || length > u16::MAX as usize { 0 } else { length as u16 };
+        let mut pes = Vec::with_capacity(payload.len() + 20);
+        pes.extend_from_slice(&[0, 0, 1, stream_id]);
+        pes.extend_from_slice(&length.to_be_bytes());
+        pes.push(0x80);
+        pes.extend_from_slice(&header);
+        pes.extend_from_slice(&payload);
+
+        let pid = FIRST_ES_PID + sample.track as u16;
+        let pcr = is_pcr_track.then_some(dts.max(0) as u64);
+        let mut cc = self.continuity[sample.track];
+        self.write_packets(pid, &mut cc, &pes, pcr, sample.keyframe && video)?;
+        self.continuity[sample.track] = cc;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, DownloadError> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// The sample's length-prefixed NAL units as Annex B, led by an access unit delimiter.
+    fn annexb(&self, sample: &Sample) -> Vec<u8> {
+        let hevc = self.tracks[sample.track].codec == Codec::H265;
+        let nal_type = |nal: &[u8]| nal.first().map_or(0, |h| if hevc { (h >> 1) & 0x3F } else { h & 0x1F });
+        let mut nals = Vec::new();
+        let mut data = &sample.data[..];
+        while data.len() >= 4 {
+            let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let Some(nal) = data.get(4..4 + len) else { break };
+            nals.push(nal);
+            data = &data[4 + len..];
+        }
+
+        let mut out = Vec::with_capacity(sample.data.len() + 64);
+        out.extend_from_slice(if hevc { &[0, 0, 0, 1, 0x46, 0x01, 0x50][..] } else { &[0, 0, 0, 1, 0x09, 0xF0][..] });
+        let sps_type = if hevc { 33 } else { 7 };
+        if sample.keyframe && !nals.iter().any(|n| nal_type(n) == sps_type) {
+            for nal in &self.parameter_sets[sample.track] {
+                out.extend_from_slice(&[0, 0, 0, 1]);
+                out.extend_from_slice(nal);
+            }
+        }
+        for nal in nals {
+            out.extend_from_slice(&[0, 0, 0, 1]);
+            out.extend_from_slice(nal);
+        }
+        out
+    }
+
+    fn write_psi(&mut self) -> Result<(), DownloadError> {
+        let mut pat = vec![0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01];
+        pat.extend_from_slice(&(0xE000 | PMT_PID).to_be_bytes());
+
+        let pcr_pid = FIRST_ES_PID + self.pcr_track as u16;
+        let mut pmt = vec![0x02, 0xB0, 0x00, 0x00, 0x01, 0xC1, 0x00, 0x00];
+        pmt.extend_from_slice(&(0xE000 | pcr_pid).to_be_bytes());
+        pmt.extend_from_slice(&[0xF0, 0x00]);
+        for (i, track) in self.tracks.iter().enumerate() {
+            let stream_type = match track.codec {
+                Codec::H264 => 0x1B,
+                Codec::H265 => 0x24,
+                Codec::Aac => 0x0F,
+                Codec::Mp3 => 0x03,
+            };
+            pmt.push(stream_type);
+            pmt.extend_from_slice(&(0xE000 | (FIRST_ES_PID + i as u16)).to_be_bytes());
+            pmt.extend_from_slice(&[0xF0, 0x00]);
+        }
+        pmt[2] = (pmt.len() - 3 + 4) as u8;
+
+        for (i, (pid, mut section)) in [(0u16, pat), (PMT_PID, pmt)].into_iter().enumerate() {
+            section.extend_from_slice(&crc32_mpeg2(&section).to_be_bytes());
+            let mut packet = vec![0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x10 | self.psi_continuity[i], 0x00];
+            packet.extend_from_slice(&section);
+            packet.resize(TS_PACKET_SIZE, 0xFF);
+            self.out.write_all(&packet)?;
+            self.psi_continuity[i] = (self.psi_continuity[i] + 1) & 0xF;
+        }
+        Ok(())
+    }
+
+    /// Splits a PES packet into TS packets; the first carries the PCR and random access flag,
+    /// the last is padded with adaptation field stuffing.
+    fn write_packets(&mut self, pid: u16, cc: &mut u8, pes: &[u8], pcr: Option<u64>, random_access: bool) -> Result<(), DownloadError> {
+        let mut pos = 0;
+        while pos < pes.len() {
+            let first = pos == 0;
+            let mut adaptation = Vec::new();
+            if first && (pcr.is_some() || random_access) {
+                adaptation.push((if random_access { 0x40 } else { 0 }) | (if pcr.is_some() { 0x10 } else { 0 }));
+                if let Some(pcr) = pcr {
+                    let base = pcr & ((1 << 33) - 1);
+                    adaptation.extend_from_slice(&[(base >> 25) as u8, (base >> 17) as u8, (base >> 9) as u8, (base >> 1) as u8, (((base & 1) as u8) << 7) | 0x7E, 0]);
+                }
+            }
+            let mut has_adaptation = !adaptation.is_empty();
+            let room = TS_PACKET_SIZE - 4 - if has_adaptation { 1 + adaptation.len() } else { 0 };
+            let take = room.min(pes.len() - pos);
+            if take < room {
+                // Stuffing fills the adaptation field up to the packet size.
+                let field_len = TS_PACKET_SIZE - 4 - take - 1;
+                if field_len > 0 && adaptation.is_empty() {
+                    adaptation.push(0);
+                }
+                adaptation.resize(field_len, 0xFF);
+                has_adaptation = true;
+            }
+
+            let mut packet = Vec::with_capacity(TS_PACKET_SIZE);
+            packet.push(0x47);
+            packet.push((if first { 0x40 } else { 0 }) | ((pid >> 8) as u8 & 0x1F));
+            packet.push(pid as u8);
+            packet.push((if has_adaptation { 0x30 } else { 0x10 }) | *cc);
+            if has_adaptation {
+                packet.push(adaptation.len() as u8);
+                packet.extend_from_slice(&adaptation);
+            }
+            packet.extend_from_slice(&pes[pos..pos + take]);
+            self.out.write_all(&packet)?;
+            *cc = (*cc + 1) & 0xF;
+            pos += take;
+        }
+        Ok(())
+    }
+}
+
+fn encode_pes_timestamp(marker: u8, ts: i64) -> [u8; 5] {
+    let ts = (ts.max(0) as u64) & (PTS_WRAP as u64 - 1);
+    [
+        (marker << 4) | (((ts >> 30) as u8 & 0x07) << 1) | 1,
+        (ts >> 22) as u8,
+        (((ts >> 15) as u8) << 1) | 1,
+        (ts >> 7) as u8,
+        ((ts as u8) << 1) | 1,
+    ]
+}
+
+/// CRC-32/MPEG-2 of a PSI section.
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Returns the PSI section following the pointer field of a payload-unit-start packet.
+pub(crate) fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let length = ((((*section.get(1)? & 0x0F) as usize) << 8) | *section.get(2)? as usize) + 3;
+    section.get(..length.min(section.len()))
+}
+
+/// Parses a PES header, returning `(pts, dts, payload)`.
+pub fn parse_pes(pes: &[u8]) -> Option<(Option<u64>, Option<u64>, &[u8])> {
+    if pes.len() < 9 || pes[0] != 0 || pes[1] != 0 || pes[2] != 1 {
+        return None;
+    }
+    let flags = pes[7] >> 6;
+    let header_len = pes[8] as usize;
+    let payload = pes.get(9 + header_len..)?;
+
+    let pts = if flags & 0x2 != 0 { pes.get(9..14).map(read_timestamp) } else { None };
+    let dts = if flags == 0x3 { pes.get(14..19).map(read_timestamp) } else { None };
+    Some((pts, dts, payload))
+}
+
+fn read_timestamp(b: &[u8]) -> u64 {
+    (((b[0] as u64 >> 1) & 0x07) << 30)
+        | ((b[1] as u64) << 22)
+        | (((b[2] as u64) >> 1) << 15)
+        | ((b[3] as u64) << 7)
+        | ((b[4] as u64) >> 1)
+}
+
+/// Signed distance from `reference` to the 33-bit timestamp `raw`, taking wrap-around into account.
+fn wrap_delta(reference: i64, raw: u64) -> i64 {
+    let diff = (raw as i64 - reference.rem_euclid(PTS_WRAP)).rem_euclid(PTS_WRAP);
+    if diff >= PTS_WRAP / 2 { diff - PTS_WRAP } else { diff }
+}
+
+/// Extends a 33-bit timestamp to a monotonic 64-bit one using the previous value.
+fn unwrap_timestamp(last: &mut Option<i64>, raw: u64) -> i64 {
+    let value = match *last {
+        Some(prev) => prev + wrap_delta(prev, raw),
+        None => raw as i64,
+    };
+    *last = Some(value);
+    value
+}
+
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, DownloadError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn encode_timestamp(marker: u8, ts: u64) -> [u8; 5] {
+        [
+            (marker << 4) | (((ts >> 30) as u8 & 0x07) << 1) | 1,
+            (ts >> 22) as u8,
+            (((ts >> 15) as u8) << 1) | 1,
+            (ts >> 7) as u8,
+            ((ts as u8) << 1) | 1,
+        ]
+    }
+
+    /// Splits a PES packet into 188-byte TS packets on `pid`, padding the last one with stuffing.
+    pub(crate) fn packetize(pid: u16, pes: &[u8], cc: &mut u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut first = true;
+        for chunk in pes.chunks(184) {
+            let mut packet = vec![0x47, ((pid >> 8) as u8 & 0x1F) | if first { 0x40 } else { 0 }, pid as u8];
+            if chunk.len() == 184 {
+                packet.push(0x10 | (*cc & 0xF));
+            } else {
+                let stuffing = 184 - chunk.len() - 1;
+                packet.push(0x30 | (*cc & 0xF));
+                packet.push(stuffing as u8);
+                if stuffing > 0 {
+                    packet.push(0x00);
+                    packet.extend(std::iter::repeat_n(0xFF, stuffing - 1));
+                }
+            }
+            packet.extend_from_slice(chunk);
+            out.extend(packet);
+            *cc = cc.wrapping_add(1);
+            first = false;
+        }
+        out
+    }
+
+    pub(crate) fn pes_packet(stream_id: u8, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        match dts {
+            Some(dts) => {
+                header.extend(encode_timestamp(0x3, pts));
+                header.extend(encode_timestamp(0x1, dts));
+            }
+            None => header.extend(encode_timestamp(0x2, pts)),
+        }
+        let flags = if dts.is_some() { 0xC0 } else { 0x80 };
+        let mut pes = vec![0, 0, 1, stream_id, 0, 0, 0x80, flags, header.len() as u8];
+        pes.extend(header);
+        pes.extend_from_slice(payload);
+        pes
+    }
+
+    /// PAT + PMT for program 1 with the given `(stream_type, pid)` entries.
+    pub(crate) fn psi_packets(streams: &[(u8, u16)]) -> Vec<u8> {
+        let pat_section = [0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00, 0, 0, 0, 0];
+        let mut pmt_section = vec![0x02, 0xB0, 0x00, 0x00, 0x01, 0xC1, 0x00, 0x00, 0xE1, 0x00, 0xF0, 0x00];
+        for (stream_type, pid) in streams {
+            pmt_section.extend([*stream_type, 0xE0 | (pid >> 8) as u8, *pid as u8, 0xF0, 0x00]);
+        }
+        pmt_section.extend([0, 0, 0, 0]);
+        let len = pmt_section.len() - 3;
+        pmt_section[2] = len as u8;
+
+        let mut out = Vec::new();
+        for (pid, section) in [(0u16, pat_section.to_vec()), (0x1000, pmt_section)] {
+            let mut payload = vec![0u8];
+            payload.extend(section);
+            payload.resize(184, 0xFF);
+            out.extend([0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x10]);
+            out.extend(payload);
+        }
+        out
+    }
+
+    /// A tiny H.264 access unit (AUD + SPS + PPS + IDR slice) for 1920x1080 High profile.
+    pub(crate) fn h264_keyframe() -> Vec<u8> {
+        let mut au = vec![0, 0, 0, 1, 0x09, 0xF0];
+        au.extend([0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0x84, 0x00, 0x00,
+            0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xF0, 0x3C, 0x60, 0xC6, 0x58]);
+        au.extend([0, 0, 0, 1, 0x68, 0xEB, 0xE3, 0xCB, 0x22, 0xC0]);
+        au.extend([0, 0, 0, 1, 0x65, 0x88, 0x84, 0x00, 0x33, 0xFF]);
+        au
+    }
+
+    pub(crate) fn adts_frame(payload_len: usize) -> Vec<u8> {
+        let frame_len = payload_len + 7;
+        let mut frame = vec![
+            0xFF, 0xF1, 0x50, 0x80 | ((frame_len >> 11) as u8 & 0x3),
+            (frame_len >> 3) as u8, (((frame_len & 0x7) as u8) << 5) | 0x1F, 0xFC,
+        ];
+        frame.extend(std::iter::repeat_n(0x21, payload_len));
+        frame
+    }
+
+    /// One H.264 keyframe (PTS 3000 ticks after DTS) and two AAC frames.
+    fn ts_fixture() -> Vec<u8> {
+        let mut ts = psi_packets(&[(0x1B, 0x100), (0x0F, 0x101)]);
+        let (mut vcc, mut acc) = (0u8, 0u8);
+        ts.extend(packetize(0x100, &pes_packet(0xE0, 183_000, Some(180_000), &h264_keyframe()), &mut vcc));
+        let mut audio = adts_frame(50);
+        audio.extend(adts_frame(60));
+        ts.extend(packetize(0x101, &pes_packet(0xC0, 180_000, None, &audio), &mut acc));
+        ts
+    }
+
+    #[test]
+    fn test_unwrap_timestamp_across_33_bit_boundary() {
+        let mut last = None;
+        assert_eq!(unwrap_timestamp(&mut last, (1 << 33) - 3000), (1 << 33) - 3000);
+        assert_eq!(unwrap_timestamp(&mut last, 600), (1 << 33) + 600);
+    }
+
+    #[test]
+    fn test_demux_video_and_audio() {
+        let ts = ts_fixture();
+
+        let mut video = TsSource::new(std::io::Cursor::new(ts.clone()), Some(MediaKind::Video));
+        let tracks = video.tracks().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!((tracks[0].width, tracks[0].height), (1920, 1080));
+        let sample = video.next_sample().unwrap().unwrap();
+        assert!(sample.keyframe);
+        assert_eq!((sample.pts, sample.dts), (183_000, 180_000));
+        // AUD removed, SPS/PPS/IDR kept with 4-byte length prefixes.
+        assert_eq!(&sample.data[..5], &[0, 0, 0, 26, 0x67]);
+        assert!(video.next_sample().unwrap().is_none());
+
+        let mut audio = TsSource::new(std::io::Cursor::new(ts), Some(MediaKind::Audio));
+        let tracks = audio.tracks().unwrap();
+        assert_eq!(tracks[0].codec, Codec::Aac);
+        assert_eq!(tracks[0].codec_private, vec![0x12, 0x10]);
+        let first = audio.next_sample().unwrap().unwrap();
+        let second = audio.next_sample().unwrap().unwrap();
+        assert_eq!(first.data.len(), 50);
+        assert_eq!(second.pts - first.pts, 1024 * 90_000 / 44_100);
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let mut source = TsSource::new(std::io::Cursor::new(ts_fixture()), None);
+        let tracks = source.tracks().unwrap();
+        let video_track = tracks.iter().position(|t| t.kind == MediaKind::Video).unwrap();
+        let mut samples = Vec::new();
+        while let Some(sample) = source.next_sample().unwrap() {
+            samples.push(sample);
+        }
+
+        // Parameter sets come from the codec private data when a keyframe lacks them.
+        let mut writer = TsWriter::new(Vec::new(), tracks.clone()).unwrap();
+        for sample in &samples {
+            let mut sample = sample.clone();
+            if sample.track == video_track {
+                sample.data = codec::nals_to_length_prefixed(&[&[0x65, 0x88, 0x84, 0x00, 0x33, 0xFF]], |_| false);
+            }
+            writer.write_sample(&sample).unwrap();
+        }
+        let written = writer.finish().unwrap();
+        assert_eq!(written.len() % TS_PACKET_SIZE, 0);
+        assert_eq!(crc32_mpeg2(&[0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00]), 0x2AB1_04B2);
+
+        let mut reread = TsSource::new(std::io::Cursor::new(written), None);
+        let reread_tracks = reread.tracks().unwrap();
+        let summary = |tracks: &[TrackInfo]| {
+            let mut summary: Vec<(Codec, Vec<u8>)> = tracks.iter().map(|t| (t.codec, t.codec_private.clone())).collect();
+            summary.sort_by_key(|(codec, _)| *codec as u8);
+            summary
+        };
+        assert_eq!(summary(&reread_tracks), summary(&tracks));
+
+        let reread_video = reread_tracks.iter().position(|t| t.kind == MediaKind::Video).unwrap();
+        let mut reread_samples = Vec::new();
+        while let Some(sample) = reread.next_sample().unwrap() {
+            reread_samples.push(sample);
+        }
+        assert_eq!(reread_samples.len(), samples.len());
+        let video = reread_samples.iter().find(|s| s.track == reread_video).unwrap();
+        assert_eq!(&video.data[..5], &[0, 0, 0, 26, 0x67]);
+        assert_eq!(video.pts - video.dts, 3000);
+        let audio = |samples: &[Sample], video: usize| -> Vec<Vec<u8>> {
+            samples.iter().filter(|s| s.track != video).map(|s| s.data.clone()).collect()
+        };
+        assert_eq!(audio(&reread_samples, reread_video), audio(&samples, video_track));
+    }
+
+    #[test]
+    fn test_timestamp_discontinuity_is_smoothed() {
+        let mut ts = psi_packets(&[(0x1B, 0x100)]);
+        let mut cc = 0u8;
+        // Two frames 3000 ticks apart, then the encoder restarts its clock at 900.
+        for dts in [180_000, 183_000, 900, 3_900] {
+            ts.extend(packetize(0x100, &pes_packet(0xE0, dts, None, &h264_keyframe()), &mut cc));
+        }
+
+        let mut video = TsSource::new(std::io::Cursor::new(ts), None);
+        video.tracks().unwrap();
+        let mut dts = Vec::new();
+        while let Some(sample) = video.next_sample().unwrap() {
+            dts.push(sample.dts);
+        }
+        assert_eq!(dts, vec![180_000, 183_000, 186_000, 189_000]);
+        assert_eq!(video.discontinuities(), 1);
+    }
+}
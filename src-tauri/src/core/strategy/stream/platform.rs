@@ -0,0 +1,49 @@
+//! Shared by the site resolvers (Vimeo, Dailymotion) that read a player configuration and then
+//! hand the stream they chose to the HLS or DASH path.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::dash::DashResolver;
+use super::resolver::{HlsResolver, ResolvedStream, StreamResolver, StreamTrack, TrackKind};
+use super::segment::Segment;
+use reqwest::Client;
+use tracing::info;
+
+/// The stream a player configuration offers for a video.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlatformSource {
+    Hls(String),
+    Dash(String),
+    /// A plain media file with audio and video.
+    Progressive { url: String, height: Option<u32> },
+}
+
+impl PlatformSource {
+    /// Resolves the chosen source; `duration` is only used for progressive files.
+    pub async fn resolve(
+        &self,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+        duration: Option<f64>,
+    ) -> Result<ResolvedStream, DownloadError> {
+        match self {
+            Self::Hls(url) => HlsResolver.resolve(url, client, headers, options).await,
+            Self::Dash(url) => DashResolver.resolve(url, client, headers, options).await,
+            Self::Progressive { url, height } => {
+                info!("Platform source is a progressive file ({:?}p)", height);
+                let segment = Segment::new(url.clone(), duration.unwrap_or(0.0));
+                Ok(ResolvedStream::single(StreamTrack::new(TrackKind::Muxed, vec![segment], None)))
+            }
+        }
+    }
+}
+
+/// Tallest progressive file no taller than `max_height`, or the smallest one if none fits.
+pub fn best_progressive(files: Vec<(String, Option<u32>)>, max_height: Option<u32>) -> Option<PlatformSource> {
+    let height = |file: &(String, Option<u32>)| file.1.unwrap_or(0);
+    let fitting = files.iter().filter(|f| max_height.is_none_or(|max| height(f) <= max)).max_by_key(|f| height(f));
+    fitting
+        .or_else(|| files.iter().min_by_key(|f| height(f)))
+        .map(|(url, height)| PlatformSource::Progressive { url: url.clone(), height: *height })
+}
@@ -0,0 +1,766 @@
+use crate::core::error::DownloadError;
+use super::segment::{ByteRange, InitSection, Segment, SegmentAlternate, SegmentKey};
+use std::collections::{BTreeMap, HashMap};
+use url::Url;
+
+/// A variant stream declared by `#EXT-X-STREAM-INF` in a master playlist.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub uri: String,
+    pub bandwidth: u64,
+    pub resolution: Option<(u32, u32)>,
+    pub codecs: Option<String>,
+    /// GROUP-ID of the `#EXT-X-MEDIA TYPE=AUDIO` renditions this variant plays with.
+    pub audio_group: Option<String>,
+    /// GROUP-ID of the `#EXT-X-MEDIA TYPE=SUBTITLES` renditions available for this variant.
+    pub subtitle_group: Option<String>,
+}
+
+impl Variant {
+    /// Whether `CODECS` lists only audio codecs.
+    pub fn is_audio_only(&self) -> bool {
+        const AUDIO_CODECS: [&str; 5] = ["mp4a", "mp3", "ac-3", "ec-3", "opus"];
+        self.codecs.as_deref().is_some_and(|codecs| {
+            codecs.split(',').all(|c| AUDIO_CODECS.iter().any(|a| c.trim().to_lowercase().starts_with(a)))
+        })
+    }
+
+    /// Codec families in `CODECS` (`avc1.64001f` -> `avc1`), sorted; profiles and levels may
+    /// differ between bitrates, the codec itself may not.
+    fn codec_families(&self) -> Vec<String> {
+        let mut families: Vec<String> = self
+            .codecs
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|c| c.trim().split('.').next().unwrap_or_default().to_lowercase())
+            .filter(|c| !c.is_empty())
+            .map(|c| match c.as_str() {
+                "avc3" => "avc1".to_string(),
+                "hev1" => "hvc1".to_string(),
+                _ => c,
+            })
+            .collect();
+        families.sort();
+        families
+    }
+
+    /// Whether segments of `other` can be spliced into this variant's output: the same kind of
+    /// stream, codecs and separate audio.
+    fn can_stand_in_for(&self, other: &Variant) -> bool {
+        self.is_audio_only() == other.is_audio_only()
+            && self.codec_families() == other.codec_families()
+            && self.audio_group == other.audio_group
+    }
+
+    /// Same stream under another URI (`#EXT-X-STREAM-INF` repeated for a backup host).
+    fn is_redundant_with(&self, other: &Variant) -> bool {
+        self.uri != other.uri
+            && self.bandwidth == other.bandwidth
+            && self.resolution == other.resolution
+            && self.codecs == other.codecs
+            && self.audio_group == other.audio_group
+    }
+}
+
+/// An alternative rendition declared by `#EXT-X-MEDIA`.
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    pub media_type: String,
+    pub group_id: String,
+    pub name: Option<String>,
+    pub language: Option<String>,
+    /// Absent when the rendition is already muxed into the variant stream.
+    pub uri: Option<String>,
+    pub default: bool,
+    pub autoselect: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MasterPlaylist {
+    pub variants: Vec<Variant>,
+    pub renditions: Vec<Rendition>,
+}
+
+/// A piece of a segment published before the whole segment is (`#EXT-X-PART`, Low-Latency HLS).
+/// The parts of a segment, in order, add up to exactly the segment's bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialSegment {
+    pub url: String,
+    pub byte_range: Option<ByteRange>,
+    pub duration: f64,
+    /// Starts with a key frame (`INDEPENDENT=YES`).
+    pub independent: bool,
+    /// Listed but unavailable (`GAP=YES`); it must not be requested.
+    pub gap: bool,
+}
+
+/// A resource the server is about to publish (`#EXT-X-PRELOAD-HINT`). Requesting it early is
+/// allowed: the server holds the response until the resource exists.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreloadHint {
+    /// `PART` or `MAP`.
+    pub hint_type: String,
+    pub url: String,
+    /// `BYTERANGE-START`, 0 when absent.
+    pub byte_range_start: u64,
+    /// `BYTERANGE-LENGTH`; `None` means up to the end of the resource.
+    pub byte_range_length: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MediaPlaylist {
+    /// Media segments in playback order, with absolute URLs.
+    pub segments: Vec<Segment>,
+    pub target_duration: Option<f64>,
+    /// Sequence number of the first segment (`#EXT-X-MEDIA-SEQUENCE`).
+    pub media_sequence: u64,
+    /// No segments will be added: `#EXT-X-ENDLIST`, or `#EXT-X-PLAYLIST-TYPE:VOD`.
+    pub end_list: bool,
+    /// Partial segments keyed by their parent's media sequence number. The parts after the
+    /// last segment belong to the segment still being published, one past the last.
+    pub parts: BTreeMap<u64, Vec<PartialSegment>>,
+    /// `PART-TARGET` of `#EXT-X-PART-INF`, in seconds.
+    pub part_target: Option<f64>,
+    /// The server answers `_HLS_msn`/`_HLS_part` requests once that segment or part is listed
+    /// (`CAN-BLOCK-RELOAD=YES` in `#EXT-X-SERVER-CONTROL`).
+    pub can_block_reload: bool,
+    pub preload_hints: Vec<PreloadHint>,
+}
+
+impl MediaPlaylist {
+    /// Playback duration in seconds, summed from `#EXTINF`.
+    pub fn total_duration(&self) -> f64 {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+
+    /// Media sequence number one past the last complete segment.
+    pub fn next_sequence(&self) -> u64 {
+        self.media_sequence + self.segments.len() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Playlist {
+    Master(MasterPlaylist),
+    Media(MediaPlaylist),
+}
+
+impl MasterPlaylist {
+    /// Picks the highest-bandwidth variant (Adaptive Stream Selection).
+    pub fn best_variant(&self) -> Option<&Variant> {
+        self.variants.iter().max_by_key(|v| v.bandwidth)
+    }
+
+    /// Picks the highest-bandwidth variant whose `CODECS` lists only audio codecs.
+    pub fn best_audio_only_variant(&self) -> Option<&Variant> {
+        self.variants.iter().filter(|v| v.is_audio_only()).max_by_key(|v| v.bandwidth)
+    }
+
+    /// Variants that can stand in for `variant` when its segments fail, flagged `true` when
+    /// redundant: redundant copies first, then other bitrates with the same codecs and audio
+    /// group from the closest one below down, then those above from the closest up.
+    pub fn failover_variants_for(&self, variant: &Variant) -> Vec<(&Variant, bool)> {
+        let mut seen = vec![variant.uri.as_str()];
+        let mut candidates: Vec<(&Variant, bool)> = Vec::new();
+        for other in self.variants.iter().filter(|v| v.is_redundant_with(variant)) {
+            if !seen.contains(&other.uri.as_str()) {
+                seen.push(&other.uri);
+                candidates.push((other, true));
+            }
+        }
+
+        let mut others: Vec<&Variant> = self
+            .variants
+            .iter()
+            .filter(|v| !seen.contains(&v.uri.as_str()) && v.can_stand_in_for(variant))
+            .collect();
+        others.sort_by_key(|v| (v.bandwidth > variant.bandwidth, v.bandwidth.abs_diff(variant.bandwidth)));
+        for other in others {
+            if !seen.contains(&other.uri.as_str()) {
+                seen.push(&other.uri);
+                candidates.push((other, false));
+            }
+        }
+        candidates
+    }
+
+    /// Audio renditions that can stand in for `rendition` when its segments fail: the same
+    /// name and language in another group (a backup host, flagged `true`) first, then other
+    /// renditions in the same language.
+    pub fn failover_renditions_for(&self, rendition: &Rendition) -> Vec<(&Rendition, bool)> {
+        let mut candidates: Vec<(&Rendition, bool)> = self
+            .renditions
+            .iter()
+            .filter(|r| r.media_type == "AUDIO" && r.uri.is_some() && r.uri != rendition.uri && r.language == rendition.language)
+            .map(|r| (r, r.name == rendition.name))
+            .collect();
+        candidates.sort_by_key(|(_, redundant)| !redundant);
+        candidates
+    }
+
+    /// Finds the separate audio rendition a variant should be played with, if any.
+    ///
+    /// Renditions without a URI are carried inside the variant itself and are ignored.
+    pub fn audio_rendition_for(&self, variant: &Variant) -> Option<&Rendition> {
+        let group = variant.audio_group.as_deref()?;
+        let candidates: Vec<&Rendition> = self
+            .renditions
+            .iter()
+            .filter(|r| r.media_type == "AUDIO" && r.group_id == group && r.uri.is_some())
+            .collect();
+
+        candidates
+            .iter()
+            .find(|r| r.default)
+            .or_else(|| candidates.iter().find(|r| r.autoselect))
+            .or_else(|| candidates.first())
+            .copied()
+    }
+
+    /// Lists the WebVTT subtitle renditions available for a variant.
+    pub fn subtitle_renditions_for(&self, variant: &Variant) -> Vec<&Rendition> {
+        let Some(group) = variant.subtitle_group.as_deref() else { return Vec::new() };
+        self.renditions
+            .iter()
+            .filter(|r| r.media_type == "SUBTITLES" && r.group_id == group && r.uri.is_some())
+            .collect()
+    }
+}
+
+/// Parses an M3U8 document, resolving every URI against `base_url`.
+pub fn parse_playlist(text: &str, base_url: &Url) -> Result<Playlist, DownloadError> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty()).peekable();
+
+    if lines.peek().is_some_and(|l| l.starts_with('<')) {
+        return Err(DownloadError::Parse(
+            "Expected an M3U8 playlist but received an HTML/XML document".to_string(),
+        ));
+    }
+
+    let mut master = MasterPlaylist::default();
+    let mut media = MediaPlaylist::default();
+    let mut pending_variant: Option<Variant> = None;
+    let mut current_map: Option<InitSection> = None;
+    let mut current_key: Option<SegmentKey> = None;
+    let mut discontinuity: u64 = 0;
+    let mut pending_duration: Option<f64> = None;
+    let mut pending_range: Option<String> = None;
+    // End of the previous sub-range, for `#EXT-X-BYTERANGE` tags without an offset.
+    let mut previous_range_end: Option<(String, u64)> = None;
+    let mut pending_parts: Vec<PartialSegment> = Vec::new();
+    let mut previous_part_end: Option<(String, u64)> = None;
+
+    for line in lines {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let attrs = parse_attributes(attrs);
+            pending_variant = Some(Variant {
+                uri: String::new(),
+                bandwidth: attrs
+                    .get("BANDWIDTH")
+                    .and_then(|b| b.parse().ok())
+                    .unwrap_or(0),
+                resolution: attrs.get("RESOLUTION").and_then(|r| {
+                    let (w, h) = r.split_once('x')?;
+                    Some((w.parse().ok()?, h.parse().ok()?))
+                }),
+                codecs: attrs.get("CODECS").cloned(),
+                audio_group: attrs.get("AUDIO").cloned(),
+                subtitle_group: attrs.get("SUBTITLES").cloned(),
+            });
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = parse_attributes(attrs);
+            let uri = match attrs.get("URI") {
+                Some(u) => Some(resolve(base_url, u)?),
+                None => None,
+            };
+            master.renditions.push(Rendition {
+                media_type: attrs.get("TYPE").cloned().unwrap_or_default(),
+                group_id: attrs.get("GROUP-ID").cloned().unwrap_or_default(),
+                name: attrs.get("NAME").cloned(),
+                language: attrs.get("LANGUAGE").cloned(),
+                uri,
+                default: attrs.get("DEFAULT").is_some_and(|v| v == "YES"),
+                autoselect: attrs.get("AUTOSELECT").is_some_and(|v| v == "YES"),
+            });
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            let attrs = parse_attributes(attrs);
+            if let Some(uri) = attrs.get("URI") {
+                current_map = Some(InitSection {
+                    url: resolve(base_url, uri)?,
+                    byte_range: attrs.get("BYTERANGE").and_then(|r| ByteRange::parse(r, Some(0))),
+                    data: None,
+                });
+            }
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+            let attrs = parse_attributes(attrs);
+            current_key = match attrs.get("METHOD").map(String::as_str) {
+                None | Some("NONE") => None,
+                Some(method) => Some(SegmentKey {
+                    method: method.to_string(),
+                    uri: attrs.get("URI").map(|u| resolve(base_url, u)).transpose()?,
+                }),
+            };
+        } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            // Resolved with the segment URI, since a missing offset continues the previous range.
+            pending_range = Some(value.to_string());
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-PART:") {
+            let attrs = parse_attributes(attrs);
+            let Some(uri) = attrs.get("URI") else { continue };
+            let url = resolve(base_url, uri)?;
+            let byte_range = match attrs.get("BYTERANGE") {
+                Some(value) => {
+                    let previous_end = previous_part_end.as_ref().filter(|(u, _)| *u == url).map(|(_, end)| *end);
+                    let range = ByteRange::parse(value, previous_end).ok_or_else(|| {
+                        DownloadError::Parse(format!("Invalid BYTERANGE '{}' for part {}", value, url))
+                    })?;
+                    previous_part_end = Some((url.clone(), range.end()));
+                    Some(range)
+                }
+                None => None,
+            };
+            pending_parts.push(PartialSegment {
+                url,
+                byte_range,
+                duration: attrs.get("DURATION").and_then(|d| d.parse().ok()).unwrap_or(0.0),
+                independent: attrs.get("INDEPENDENT").is_some_and(|v| v == "YES"),
+                gap: attrs.get("GAP").is_some_and(|v| v == "YES"),
+            });
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-PRELOAD-HINT:") {
+            let attrs = parse_attributes(attrs);
+            if let Some(uri) = attrs.get("URI") {
+                media.preload_hints.push(PreloadHint {
+                    hint_type: attrs.get("TYPE").cloned().unwrap_or_default(),
+                    url: resolve(base_url, uri)?,
+                    byte_range_start: attrs.get("BYTERANGE-START").and_then(|v| v.parse().ok()).unwrap_or(0),
+                    byte_range_length: attrs.get("BYTERANGE-LENGTH").and_then(|v| v.parse().ok()),
+                });
+            }
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-PART-INF:") {
+            media.part_target = parse_attributes(attrs).get("PART-TARGET").and_then(|v| v.parse().ok());
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-SERVER-CONTROL:") {
+            media.can_block_reload = parse_attributes(attrs).get("CAN-BLOCK-RELOAD").is_some_and(|v| v == "YES");
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            media.media_sequence = value.trim().parse().unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            media.target_duration = value.trim().parse().ok();
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let duration = value.split(',').next().unwrap_or_default();
+            pending_duration = duration.trim().parse().ok();
+        } else if let Some(value) = line.strip_prefix("#EXT-X-DISCONTINUITY-SEQUENCE:") {
+            discontinuity = value.trim().parse().unwrap_or(0);
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity += 1;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PLAYLIST-TYPE:") {
+            // A VOD playlist is final even when the server leaves out ENDLIST.
+            media.end_list |= value.trim() == "VOD";
+        } else if line == "#EXT-X-ENDLIST" {
+            media.end_list = true;
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(mut variant) = pending_variant.take() {
+            variant.uri = resolve(base_url, line)?;
+            master.variants.push(variant);
+        } else {
+            let url = resolve(base_url, line)?;
+            let byte_range = match pending_range.take() {
+                Some(value) => {
+                    let previous_end = previous_range_end.as_ref().filter(|(u, _)| *u == url).map(|(_, end)| *end);
+                    let range = ByteRange::parse(&value, previous_end).ok_or_else(|| {
+                        DownloadError::Parse(format!("Invalid #EXT-X-BYTERANGE '{}' for {}", value, url))
+                    })?;
+                    previous_range_end = Some((url.clone(), range.end()));
+                    Some(range)
+                }
+                None => None,
+            };
+            if !pending_parts.is_empty() {
+                media.parts.insert(media.next_sequence(), std::mem::take(&mut pending_parts));
+            }
+            media.segments.push(Segment {
+                url,
+                byte_range,
+                key: current_key.clone(),
+                map: current_map.clone(),
+                duration: pending_duration.take().unwrap_or(0.0),
+                discontinuity,
+                transform: None,
+                alternates: Vec::new(),
+            });
+        }
+    }
+
+    if !pending_parts.is_empty() {
+        media.parts.insert(media.next_sequence(), pending_parts);
+    }
+
+    if !master.variants.is_empty() {
+        Ok(Playlist::Master(master))
+    } else {
+        Ok(Playlist::Media(media))
+    }
+}
+
+/// Attaches the segment with the same media sequence number in `alternate` to each segment
+/// of `media` and returns how many got one.
+///
+/// Durations must agree to within half a second. Other bitrates only stand in for segments
+/// without an init section, as their fragments would need that variant's initialization.
+pub fn attach_alternates(media: &mut MediaPlaylist, alternate: &MediaPlaylist, variant: &str, redundant: bool) -> usize {
+    let mut attached = 0;
+    for (i, segment) in media.segments.iter_mut().enumerate() {
+        let sequence = media.media_sequence + i as u64;
+        let Some(other) = sequence
+            .checked_sub(alternate.media_sequence)
+            .and_then(|offset| alternate.segments.get(offset as usize))
+        else {
+            continue;
+        };
+        let durations_match = (segment.duration - other.duration).abs() <= 0.5;
+        let maps_usable = redundant || (segment.map.is_none() && other.map.is_none());
+        if !durations_match || !maps_usable || other.url == segment.url {
+            continue;
+        }
+        segment.alternates.push(SegmentAlternate {
+            url: other.url.clone(),
+            byte_range: other.byte_range,
+            map: other.map.clone(),
+            variant: variant.to_string(),
+            redundant,
+        });
+        attached += 1;
+    }
+    attached
+}
+
+/// Parses an HLS attribute list (`KEY=VALUE,KEY="quoted,value"`).
+pub fn parse_attributes(input: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = input.trim();
+
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else { break };
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq + 1..];
+
+        let value;
+        if let Some(stripped) = rest.strip_prefix('"') {
+            let end = stripped.find('"').unwrap_or(stripped.len());
+            value = stripped[..end].to_string();
+            rest = stripped.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+        }
+
+        attrs.insert(key, value);
+        rest = rest.trim_start_matches(',').trim_start();
+    }
+
+    attrs
+}
+
+fn resolve(base_url: &Url, uri: &str) -> Result<String, DownloadError> {
+    base_url
+        .join(uri)
+        .map(|u| u.to_string())
+        .map_err(|e| DownloadError::Parse(format!("Failed to resolve playlist URI '{}': {}", uri, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/video/master.m3u8").unwrap()
+    }
+
+    #[test]
+    fn test_parse_attributes_with_quoted_commas() {
+        let attrs = parse_attributes(r#"BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2",AUDIO="aac""#);
+        assert_eq!(attrs.get("BANDWIDTH").unwrap(), "1280000");
+        assert_eq!(attrs.get("CODECS").unwrap(), "avc1.4d401f,mp4a.40.2");
+        assert_eq!(attrs.get("AUDIO").unwrap(), "aac");
+    }
+
+    #[test]
+    fn test_master_with_separate_audio() {
+        let text = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,URI=\"audio/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"French\",LANGUAGE=\"fr\",URI=\"audio/fr.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"English\",LANGUAGE=\"en\",URI=\"subs/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=CLOSED-CAPTIONS,GROUP-ID=\"cc\",NAME=\"CC1\",INSTREAM-ID=\"CC1\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360,AUDIO=\"aud\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1280x720,AUDIO=\"aud\",SUBTITLES=\"subs\",CLOSED-CAPTIONS=\"cc\"\n\
+            high/index.m3u8\n";
+
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+
+        let best = master.best_variant().unwrap();
+        assert_eq!(best.uri, "https://cdn.example.com/video/high/index.m3u8");
+        assert_eq!(best.resolution, Some((1280, 720)));
+
+        let audio = master.audio_rendition_for(best).unwrap();
+        assert_eq!(audio.language.as_deref(), Some("en"));
+        assert_eq!(audio.uri.as_deref(), Some("https://cdn.example.com/video/audio/en.m3u8"));
+
+        let subtitles = master.subtitle_renditions_for(best);
+        assert_eq!(subtitles.len(), 1);
+        assert_eq!(subtitles[0].uri.as_deref(), Some("https://cdn.example.com/video/subs/en.m3u8"));
+        assert!(master.subtitle_renditions_for(&master.variants[0]).is_empty());
+        assert!(master.best_audio_only_variant().is_none());
+    }
+
+    #[test]
+    fn test_plain_media_playlist() {
+        let text = "#EXTM3U\r\n#EXT-X-VERSION:3\r\n#EXT-X-TARGETDURATION:10\r\n#EXT-X-MEDIA-SEQUENCE:42\r\n\r\n\
+            #EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:00Z\r\n#EXTINF:10,\r\nseg42.ts?token=a\r\n\
+            #EXTINF:9.5,Title, with comma\r\n/abs/seg43.ts\r\n\
+            #EXTINF:8.0\r\nhttps://other.example.com/seg44.ts\r\n";
+
+        let Playlist::Media(media) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+
+        assert!(!media.end_list);
+        assert_eq!(media.media_sequence, 42);
+        assert_eq!(media.next_sequence(), 45);
+        let urls: Vec<&str> = media.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://cdn.example.com/video/seg42.ts?token=a",
+                "https://cdn.example.com/abs/seg43.ts",
+                "https://other.example.com/seg44.ts",
+            ]
+        );
+        let durations: Vec<f64> = media.segments.iter().map(|s| s.duration).collect();
+        assert_eq!(durations, vec![10.0, 9.5, 8.0]);
+        assert!(media.segments.iter().all(|s| s.key.is_none() && s.map.is_none() && s.byte_range.is_none()));
+        assert!(media.parts.is_empty() && media.preload_hints.is_empty() && !media.can_block_reload);
+
+        let end_list = |kind: &str| {
+            let text = format!("#EXTM3U\n#EXT-X-PLAYLIST-TYPE:{}\n#EXTINF:4,\na.ts\n", kind);
+            let Playlist::Media(media) = parse_playlist(&text, &base()).unwrap() else { panic!("expected media playlist") };
+            media.end_list
+        };
+        assert!(end_list("VOD"));
+        assert!(!end_list("EVENT"));
+    }
+
+    #[test]
+    fn test_discontinuities_and_key_changes() {
+        let text = "#EXTM3U\n#EXT-X-DISCONTINUITY-SEQUENCE:7\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"k1\"\n#EXTINF:4,\na.ts\n\
+            #EXT-X-DISCONTINUITY\n#EXT-X-KEY:METHOD=NONE\n#EXTINF:4,\nb.ts\n\
+            #EXTINF:4,\nc.ts\n#EXT-X-DISCONTINUITY\n#EXTINF:4,\nd.ts\n#EXT-X-ENDLIST\n";
+
+        let Playlist::Media(media) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+
+        let sequences: Vec<u64> = media.segments.iter().map(|s| s.discontinuity).collect();
+        assert_eq!(sequences, vec![7, 8, 8, 9]);
+        assert_eq!(media.segments[0].key.as_ref().unwrap().uri.as_deref(), Some("https://cdn.example.com/video/k1"));
+        assert!(media.segments[1..].iter().all(|s| s.key.is_none()));
+        assert!(media.end_list);
+    }
+
+    #[test]
+    fn test_master_ignores_iframe_playlists_and_missing_attributes() {
+        let text = "#EXTM3U\n\
+            #EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=90000,URI=\"iframe.m3u8\"\n\
+            #EXT-X-STREAM-INF:PROGRAM-ID=1\nbare.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=500000,RESOLUTION=bogus\n\n\
+            https://cdn2.example.com/mid.m3u8\n";
+
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+
+        assert_eq!(master.variants.len(), 2);
+        assert_eq!(master.variants[0].bandwidth, 0);
+        assert_eq!(master.variants[1].resolution, None);
+        assert_eq!(master.best_variant().unwrap().uri, "https://cdn2.example.com/mid.m3u8");
+        assert!(master.renditions.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_invalid_documents() {
+        assert!(parse_playlist("<html><body>Not found</body></html>", &base()).is_err());
+        // A byte range without an offset needs an earlier range of the same resource.
+        assert!(parse_playlist("#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:100\na.ts\n", &base()).is_err());
+        assert!(parse_playlist("#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:abc@0\na.ts\n", &base()).is_err());
+
+        let Playlist::Media(empty) = parse_playlist("#EXTM3U\n#EXT-X-TARGETDURATION:6\n", &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+        assert!(empty.segments.is_empty());
+    }
+
+    #[test]
+    fn test_best_audio_only_variant() {
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,CODECS=\"avc1.4d401f,mp4a.40.2\"\nhigh.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.5\"\naudio_lo.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\naudio_hi.m3u8\n";
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+        assert_eq!(master.best_audio_only_variant().unwrap().uri, "https://cdn.example.com/video/audio_hi.m3u8");
+    }
+
+    #[test]
+    fn test_failover_skips_other_codecs_and_audio_groups() {
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,CODECS=\"avc1.64001f,mp4a.40.2\",AUDIO=\"aac\"\nhttps://a.example.com/avc_720.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2000000,CODECS=\"hvc1.1.6.L120.90,mp4a.40.2\",AUDIO=\"aac\"\nhttps://a.example.com/hevc_1080.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1800000,CODECS=\"avc1.64001f,mp4a.40.2\",AUDIO=\"ac3\"\nhttps://a.example.com/avc_ac3.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc3.4d401e,mp4a.40.5\",AUDIO=\"aac\"\nhttps://a.example.com/avc_360.m3u8\n";
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+        let failover: Vec<&str> = master.failover_variants_for(&master.variants[0]).iter().map(|(v, _)| v.uri.as_str()).collect();
+        assert_eq!(failover, vec!["https://a.example.com/avc_360.m3u8"]);
+    }
+
+    #[test]
+    fn test_failover_variants_and_alternates() {
+        let text = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360\nhttps://a.example.com/low.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1280x720\nhttps://a.example.com/high.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=4800000,RESOLUTION=1920x1080\nhttps://a.example.com/full.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1280x720\nhttps://b.example.com/high.m3u8\n";
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+        let failover: Vec<(&str, bool)> =
+            master.failover_variants_for(&master.variants[1]).iter().map(|(v, redundant)| (v.uri.as_str(), *redundant)).collect();
+        assert_eq!(
+            failover,
+            vec![
+                ("https://b.example.com/high.m3u8", true),
+                ("https://a.example.com/low.m3u8", false),
+                ("https://a.example.com/full.m3u8", false),
+            ]
+        );
+
+        let media = |text: &str, host: &str| match parse_playlist(text, &Url::parse(host).unwrap()).unwrap() {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => panic!("expected media playlist"),
+        };
+        let mut primary = media("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:10\n#EXTINF:6.0,\ns10.ts\n#EXTINF:6.0,\ns11.ts\n#EXTINF:6.0,\ns12.ts\n", "https://a.example.com/high.m3u8");
+        // Starts one segment later and has a shorter last segment.
+        let backup = media("#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:11\n#EXTINF:6.0,\nb11.ts\n#EXTINF:4.0,\nb12.ts\n", "https://b.example.com/high.m3u8");
+        assert_eq!(attach_alternates(&mut primary, &backup, "https://b.example.com/high.m3u8", true), 1);
+        assert!(primary.segments[0].alternates.is_empty());
+        assert_eq!(primary.segments[1].alternates[0].url, "https://b.example.com/b11.ts");
+        assert!(primary.segments[2].alternates.is_empty());
+
+        let text = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"a\",NAME=\"English\",LANGUAGE=\"en\",URI=\"https://a.example.com/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"a\",NAME=\"French\",LANGUAGE=\"fr\",URI=\"https://a.example.com/fr.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"lo\",NAME=\"English (low)\",LANGUAGE=\"en\",URI=\"https://a.example.com/en_lo.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"b\",NAME=\"English\",LANGUAGE=\"en\",URI=\"https://b.example.com/en.m3u8\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,AUDIO=\"a\"\nhttps://a.example.com/low.m3u8\n";
+        let Playlist::Master(master) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected master playlist");
+        };
+        let failover: Vec<(&str, bool)> = master
+            .failover_renditions_for(&master.renditions[0])
+            .iter()
+            .map(|(r, redundant)| (r.uri.as_deref().unwrap(), *redundant))
+            .collect();
+        assert_eq!(failover, vec![("https://b.example.com/en.m3u8", true), ("https://a.example.com/en_lo.m3u8", false)]);
+
+        let mut fragmented = media("#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:6.0,\ns0.m4s\n", "https://a.example.com/high.m3u8");
+        let other_bitrate = media("#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:6.0,\ns0.m4s\n", "https://a.example.com/low/index.m3u8");
+        assert_eq!(attach_alternates(&mut fragmented, &other_bitrate, "https://a.example.com/low/index.m3u8", false), 0);
+    }
+
+    #[test]
+    fn test_media_playlist_with_init_map() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXTINF:6.0,\nseg1.m4s\n#EXTINF:4.5,title\nseg2.m4s\n#EXT-X-ENDLIST\n";
+
+        let Playlist::Media(media) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+
+        assert!(media.end_list);
+        assert_eq!(media.target_duration, Some(6.0));
+        let urls: Vec<&str> = media.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://cdn.example.com/video/seg1.m4s", "https://cdn.example.com/video/seg2.m4s"]);
+        let map = media.segments[1].map.as_ref().unwrap();
+        assert_eq!(map.url, "https://cdn.example.com/video/init.mp4");
+        assert_eq!(media.total_duration(), 10.5);
+    }
+
+    #[test]
+    fn test_media_playlist_with_byte_ranges_and_key() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n\
+            #EXT-X-MAP:URI=\"main.mp4\",BYTERANGE=\"720@0\"\n\
+            #EXTINF:4.0,\n#EXT-X-BYTERANGE:1000@720\nmain.mp4\n\
+            #EXTINF:4.0,\n#EXT-X-BYTERANGE:1500\nmain.mp4\n\
+            #EXT-X-DISCONTINUITY\n#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+            #EXTINF:2.0,\nclear.ts\n#EXT-X-ENDLIST\n";
+
+        let Playlist::Media(media) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+
+        assert_eq!(media.segments.len(), 3);
+        assert_eq!(media.segments[0].byte_range, Some(ByteRange { offset: 720, length: 1000 }));
+        assert_eq!(media.segments[1].byte_range, Some(ByteRange { offset: 1720, length: 1500 }));
+        assert_eq!(media.segments[0].map.as_ref().unwrap().byte_range, Some(ByteRange { offset: 0, length: 720 }));
+        assert_eq!(media.segments[1].key, None);
+
+        let key = media.segments[2].key.as_ref().unwrap();
+        assert_eq!(key.method, "AES-128");
+        assert_eq!(key.uri.as_deref(), Some("https://cdn.example.com/video/key.bin"));
+        assert_eq!(media.segments[2].byte_range, None);
+        assert_eq!(media.segments[1].discontinuity, 0);
+        assert_eq!(media.segments[2].discontinuity, 1);
+    }
+
+    #[test]
+    fn test_low_latency_parts_and_hints() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:266\n\
+            #EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.0\n#EXT-X-PART-INF:PART-TARGET=0.33334\n\
+            #EXTINF:4.0,\nfileSequence266.mp4\n\
+            #EXT-X-PART:DURATION=0.33334,URI=\"filePart267.0.mp4\",INDEPENDENT=YES\n\
+            #EXT-X-PART:DURATION=0.33334,URI=\"filePart267.1.mp4\"\n\
+            #EXTINF:0.66668,\nfileSequence267.mp4\n\
+            #EXT-X-PART:DURATION=0.33334,URI=\"live.mp4\",BYTERANGE=\"1000@0\",INDEPENDENT=YES\n\
+            #EXT-X-PART:DURATION=0.33334,URI=\"live.mp4\",BYTERANGE=\"800\",GAP=YES\n\
+            #EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"live.mp4\",BYTERANGE-START=1800\n";
+
+        let Playlist::Media(media) = parse_playlist(text, &base()).unwrap() else {
+            panic!("expected media playlist");
+        };
+
+        assert!(media.can_block_reload);
+        assert_eq!(media.part_target, Some(0.33334));
+        assert_eq!(media.next_sequence(), 268);
+        assert_eq!(media.parts.keys().copied().collect::<Vec<_>>(), vec![267, 268]);
+        assert_eq!(media.parts[&267][1].url, "https://cdn.example.com/video/filePart267.1.mp4");
+        assert!(media.parts[&267][0].independent && !media.parts[&267][1].independent);
+
+        let trailing = &media.parts[&268];
+        assert_eq!(trailing[1].byte_range, Some(ByteRange { offset: 1000, length: 800 }));
+        assert!(trailing[1].gap);
+        assert_eq!(
+            media.preload_hints,
+            vec![PreloadHint {
+                hint_type: "PART".to_string(),
+                url: "https://cdn.example.com/video/live.mp4".to_string(),
+                byte_range_start: 1800,
+                byte_range_length: None,
+            }]
+        );
+    }
+}
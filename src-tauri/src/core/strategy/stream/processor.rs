@@ -1,5 +1,50 @@
+//! Segment payload sniffing. Some hosts disguise media segments as images by prepending a
+//! PNG or JPEG; the real payload (MPEG-TS, fragmented MP4 or ADTS audio) follows it.
+
+use super::mux::codec;
 use tracing::{debug, warn};
 
+const TS_PACKET_SIZE: usize = 188;
+/// Consecutive TS packets whose sync bytes must line up before a match is trusted.
+const TS_PACKETS_TO_VERIFY: usize = 5;
+/// How far into a segment we look for a payload behind unrecognised junk.
+const JUNK_SEARCH_LIMIT: usize = 64 * 1024;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// Box types that can start a fragment; `afra`/`abst` open Adobe F4F fragments.
+const FMP4_BOXES: &[&[u8; 4]] = &[b"ftyp", b"styp", b"moof", b"moov", b"sidx", b"emsg", b"afra", b"abst"];
+
+/// Container of the media payload in a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    MpegTs,
+    Fmp4,
+    /// Raw ADTS/AAC frames, possibly after an ID3 tag (HLS packed audio).
+    Adts,
+    /// Raw MPEG audio (MP3) frames, possibly after an ID3 tag.
+    MpegAudio,
+    Unknown,
+}
+
+/// Image a segment was disguised as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisguiseHeader {
+    Png,
+    Jpeg,
+    /// Bytes of no recognised format before the payload.
+    Junk,
+}
+
+/// Result of processing one segment: the cleaned bytes plus what was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessedSegment {
+    pub bytes: Vec<u8>,
+    pub format: PayloadFormat,
+    pub disguise: Option<DisguiseHeader>,
+    /// Bytes removed from the front of the segment.
+    pub stripped: usize,
+}
+
 pub struct StreamProcessor {
     enable_header_stripping: bool,
 }
@@ -9,37 +54,220 @@ impl StreamProcessor {
         Self { enable_header_stripping }
     }
 
-    /// Cleans the segment by searching for the first occurrence of the MPEG-TS 
-    /// sync byte (0x47) within the first 1024 bytes and stripping everything before it.
-    pub fn clean_segment(&self, bytes: Vec<u8>) -> Vec<u8> {
-        if !self.enable_header_stripping {
-            return bytes;
+    /// Detects the payload format and strips any disguising header in front of it.
+    /// Segments whose payload cannot be identified are returned untouched.
+    pub fn process_segment(&self, bytes: Vec<u8>) -> ProcessedSegment {
+        let (offset, disguise, format) = match detect_format(&bytes) {
+            PayloadFormat::Unknown => match find_payload(&bytes) {
+                Some(found) => found,
+                None => (0, None, PayloadFormat::Unknown),
+            },
+            format => (0, None, format),
+        };
+
+        if format == PayloadFormat::Unknown && !bytes.is_empty() {
+            warn!("Could not identify the payload of a {} byte segment, keeping it as-is", bytes.len());
+        }
+        if offset == 0 || !self.enable_header_stripping {
+            return ProcessedSegment { bytes, format, disguise, stripped: 0 };
         }
 
-        // Check if it already starts with the sync byte (most common case).
-        if bytes.is_empty() || bytes[0] == 0x47 {
-            return bytes;
+        debug!(?disguise, ?format, "Stripped {} bytes of disguise header from segment", offset);
+        ProcessedSegment {
+            bytes: bytes[offset..].to_vec(),
+            format,
+            disguise,
+            stripped: offset,
         }
+    }
+}
 
-        // Search for the sync byte in the first 1024 bytes.
-        let search_limit = std::cmp::min(bytes.len(), 1024);
-        let mut sync_offset = None;
+/// Identifies a payload that starts exactly at the beginning of `data`.
+pub fn detect_format(data: &[u8]) -> PayloadFormat {
+    if is_ts(data) {
+        PayloadFormat::MpegTs
+    } else if is_fmp4(data) {
+        PayloadFormat::Fmp4
+    } else if is_adts(data) {
+        PayloadFormat::Adts
+    } else if is_mpeg_audio(data) {
+        PayloadFormat::MpegAudio
+    } else {
+        PayloadFormat::Unknown
+    }
+}
 
-        for i in 0..search_limit {
-            if bytes[i] == 0x47 {
-                sync_offset = Some(i);
-                break;
+/// Locates the payload behind a disguise: after a PNG's `IEND` chunk, after a JPEG `EOI`
+/// marker, or, failing both, at the first offset where a known format verifies.
+fn find_payload(data: &[u8]) -> Option<(usize, Option<DisguiseHeader>, PayloadFormat)> {
+    if data.starts_with(PNG_SIGNATURE) {
+        if let Some(end) = png_end(data) {
+            let format = detect_format(&data[end..]);
+            if format != PayloadFormat::Unknown {
+                return Some((end, Some(DisguiseHeader::Png), format));
             }
         }
+    }
 
-        if let Some(offset) = sync_offset {
-            debug!("Stripped {} bytes of junk header from segment", offset);
-            bytes[offset..].to_vec()
-        } else {
-            // If we didn't find the sync byte, it might not be a standard MPEG-TS segment.
-            // In this case, we return it as-is instead of discarding it.
-            warn!("MPEG-TS sync byte (0x47) not found in the first 1024 bytes of the segment.");
-            bytes
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        // Entropy-coded data can contain FF D9 by accident, so take the first EOI that is
+        // actually followed by a recognisable payload.
+        let found = data
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| *w == [0xFF, 0xD9])
+            .map(|(i, _)| i + 2)
+            .find_map(|end| match detect_format(&data[end..]) {
+                PayloadFormat::Unknown => None,
+                format => Some((end, Some(DisguiseHeader::Jpeg), format)),
+            });
+        if found.is_some() {
+            return found;
         }
     }
+
+    let limit = data.len().min(JUNK_SEARCH_LIMIT);
+    (1..limit).find_map(|offset| match detect_format(&data[offset..]) {
+        // A lone audio sync word is too weak a signal inside arbitrary junk.
+        PayloadFormat::Unknown | PayloadFormat::Adts | PayloadFormat::MpegAudio => None,
+        format => Some((offset, Some(DisguiseHeader::Junk), format)),
+    })
+}
+
+/// End offset of a PNG image, found by walking its chunks up to and including `IEND`.
+fn png_end(data: &[u8]) -> Option<usize> {
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 12 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let end = pos.checked_add(12)?.checked_add(len)?;
+        if chunk_type == b"IEND" {
+            return (end <= data.len()).then_some(end);
+        }
+        pos = end;
+    }
+    // Broken chunk lengths: fall back to the IEND tag itself plus its CRC.
+    data.windows(4).position(|w| w == b"IEND").map(|i| i + 8).filter(|end| *end <= data.len())
+}
+
+/// MPEG-TS: sync bytes every 188 bytes for several packets (or as many as the data holds).
+fn is_ts(data: &[u8]) -> bool {
+    if data.len() < TS_PACKET_SIZE || data[0] != 0x47 {
+        return false;
+    }
+    let packets = (data.len() / TS_PACKET_SIZE).min(TS_PACKETS_TO_VERIFY);
+    (0..packets).all(|i| data[i * TS_PACKET_SIZE] == 0x47)
+}
+
+/// Fragmented MP4: a plausible top-level box of a type that can start a segment.
+fn is_fmp4(data: &[u8]) -> bool {
+    if data.len() < 8 {
+        return false;
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    // 1 = 64-bit size follows; 0 = box runs to the end of the data.
+    let plausible = size == 0 || size == 1 || size >= 8;
+    plausible && FMP4_BOXES.iter().any(|b| &data[4..8] == *b)
+}
+
+fn skip_id3(data: &[u8]) -> &[u8] {
+    match codec::id3_tag_len(data) {
+        Some(len) => data.get(len..).unwrap_or_default(),
+        None => data,
+    }
+}
+
+/// ADTS audio, optionally behind an ID3 tag. When the data holds a second frame its sync
+/// word must line up as well.
+fn is_adts(data: &[u8]) -> bool {
+    let data = skip_id3(data);
+    let Some(header) = codec::parse_adts_header(data) else { return false };
+    match data.get(header.frame_len..) {
+        Some(next) if next.len() >= 7 => codec::parse_adts_header(next).is_some(),
+        _ => true,
+    }
+}
+
+/// MPEG audio frames, with the same ID3 and second-frame rules as ADTS.
+fn is_mpeg_audio(data: &[u8]) -> bool {
+    let data = skip_id3(data);
+    let Some(header) = codec::parse_mpeg_audio_header(data) else { return false };
+    match data.get(header.frame_len..) {
+        Some(next) if next.len() >= 4 => codec::parse_mpeg_audio_header(next).is_some(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts_packets(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|_| {
+                let mut packet = vec![0xFFu8; TS_PACKET_SIZE];
+                packet[0] = 0x47;
+                packet
+            })
+            .collect()
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        // IHDR whose data happens to contain 0x47 bytes.
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&[0, 0, 0, 0x47, 0, 0, 0, 0x47, 8, 2, 0, 0, 0]);
+        png.extend_from_slice(&[0x47, 0, 0, 0]);
+        png.extend_from_slice(&0u32.to_be_bytes());
+        png.extend_from_slice(b"IEND");
+        png.extend_from_slice(&[0xAE, 0x42, 0x60, 0x82]);
+        png
+    }
+
+    #[test]
+    fn test_png_disguise_is_stripped_at_iend() {
+        let png = png();
+        let mut data = png.clone();
+        data.extend(ts_packets(6));
+
+        let result = StreamProcessor::new(true).process_segment(data);
+        assert_eq!(result.format, PayloadFormat::MpegTs);
+        assert_eq!(result.disguise, Some(DisguiseHeader::Png));
+        assert_eq!(result.stripped, png.len());
+        assert_eq!(result.bytes, ts_packets(6));
+    }
+
+    #[test]
+    fn test_jpeg_disguise_with_false_eoi() {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0x47, 0xFF, 0xD9, 0x12, 0x47];
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        let header_len = data.len();
+        data.extend(ts_packets(3));
+
+        let result = StreamProcessor::new(true).process_segment(data);
+        assert_eq!(result.disguise, Some(DisguiseHeader::Jpeg));
+        assert_eq!(result.stripped, header_len);
+        assert_eq!(result.format, PayloadFormat::MpegTs);
+    }
+
+    #[test]
+    fn test_detects_fmp4_and_adts() {
+        let mut moof = 16u32.to_be_bytes().to_vec();
+        moof.extend_from_slice(b"moof");
+        moof.extend_from_slice(&[0; 8]);
+        assert_eq!(detect_format(&moof), PayloadFormat::Fmp4);
+
+        // Two 9-byte ADTS frames (protection absent, 44.1 kHz, stereo).
+        let frame = [0xFF, 0xF1, 0x50, 0x80, 0x01, 0x3F, 0xFC, 0xAA, 0xBB];
+        let adts: Vec<u8> = frame.iter().chain(frame.iter()).copied().collect();
+        assert_eq!(detect_format(&adts), PayloadFormat::Adts);
+
+        // A stray 0x47 without the packet stride is not TS.
+        let mut junk = vec![0x47; 1];
+        junk.extend(vec![0u8; 400]);
+        let result = StreamProcessor::new(true).process_segment(junk.clone());
+        assert_eq!(result.format, PayloadFormat::Unknown);
+        assert_eq!(result.bytes, junk);
+    }
 }
@@ -0,0 +1,78 @@
+//! `.progress` sidecar: how far a segmented track has been written, so a paused or stopped
+//! stream can be resumed by appending instead of starting over.
+
+use crate::core::error::DownloadError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentProgress {
+    /// Index of the last segment written contiguously from the start (`None` if nothing was written yet).
+    pub last_written_segment: Option<usize>,
+    /// Bytes on disk up to and including that segment.
+    pub bytes_written: u64,
+    /// Segment count of the playlist when the track was started.
+    pub total_segments: usize,
+}
+
+impl SegmentProgress {
+    /// Index of the first segment that still has to be downloaded.
+    pub fn next_segment(&self) -> usize {
+        self.last_written_segment.map_or(0, |i| i + 1)
+    }
+}
+
+/// Sidecar path for a track output, e.g. `movie.ts` -> `movie.ts.progress`.
+pub fn progress_path(output: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.progress", output.display()))
+}
+
+/// Loads the sidecar for `output`, if one exists and is readable.
+pub fn load(output: &Path) -> Option<SegmentProgress> {
+    let path = progress_path(output);
+    let json = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(progress) => Some(progress),
+        Err(e) => {
+            warn!(path = ?path, error = %e, "Ignoring unreadable progress sidecar");
+            None
+        }
+    }
+}
+
+pub async fn save(output: &Path, progress: &SegmentProgress) -> Result<(), DownloadError> {
+    let json = serde_json::to_string(progress).map_err(|e| DownloadError::Serialization(e.to_string()))?;
+    tokio::fs::write(progress_path(output), json)
+        .await
+        .map_err(|e| DownloadError::FileSystem(format!("Failed to write progress sidecar: {}", e)))
+}
+
+pub async fn delete(output: &Path) {
+    let path = progress_path(output);
+    if tokio::fs::remove_file(&path).await.is_ok() {
+        debug!(path = ?path, "Deleted progress sidecar");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress_round_trip() {
+        let output = std::env::temp_dir().join(format!("progress-test-{}.ts", uuid::Uuid::new_v4()));
+        assert_eq!(load(&output), None);
+
+        let progress = SegmentProgress { last_written_segment: Some(4), bytes_written: 9400, total_segments: 20 };
+        save(&output, &progress).await.unwrap();
+        let loaded = load(&output).unwrap();
+        assert_eq!(loaded, progress);
+        assert_eq!(loaded.next_segment(), 5);
+
+        delete(&output).await;
+        assert!(!progress_path(&output).exists());
+        assert_eq!(SegmentProgress::default().next_segment(), 0);
+    }
+}
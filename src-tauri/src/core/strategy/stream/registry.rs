@@ -0,0 +1,117 @@
+//! Selection of the resolver that handles a streaming URL.
+//!
+//! Every resolver says which URLs it accepts through [`StreamResolver::matches`]. Selection
+//! first looks at the URL alone; only when nothing claims it is the start of the response
+//! fetched and offered to the resolvers again, so links without a telling extension still
+//! reach the right one. Unclaimed URLs go to HLS, which is what most such links turn out to be.
+
+use super::dailymotion::DailymotionResolver;
+use super::dash::DashResolver;
+use super::hds::HdsResolver;
+use super::resolver::{HlsResolver, ResolverProbe, StreamResolver};
+use super::smooth::SmoothResolver;
+use super::vimeo::VimeoResolver;
+use super::youtube::YoutubeResolver;
+use super::StreamingConfig;
+use crate::core::error::DownloadError;
+use reqwest::Client;
+use std::sync::Arc;
+use tracing::debug;
+
+pub struct ResolverRegistry {
+    resolvers: Vec<Arc<dyn StreamResolver>>,
+    fallback: Arc<dyn StreamResolver>,
+    platform_resolvers_enabled: bool,
+}
+
+impl ResolverRegistry {
+    /// Registry with every built-in resolver.
+    pub fn from_config(config: &StreamingConfig) -> Self {
+        let hls: Arc<dyn StreamResolver> = Arc::new(HlsResolver);
+        let mut registry = Self {
+            resolvers: Vec::new(),
+            fallback: hls.clone(),
+            platform_resolvers_enabled: config.enable_platform_resolvers,
+        };
+        registry.register(hls);
+        registry.register(Arc::new(DashResolver));
+        registry.register(Arc::new(SmoothResolver));
+        registry.register(Arc::new(HdsResolver));
+        registry.register(Arc::new(YoutubeResolver));
+        registry.register(Arc::new(VimeoResolver));
+        registry.register(Arc::new(DailymotionResolver));
+        registry
+    }
+
+    pub fn register(&mut self, resolver: Arc<dyn StreamResolver>) {
+        self.resolvers.push(resolver);
+    }
+
+    /// Highest-priority resolver matching `url`; on a tie the one registered first wins.
+    pub fn select(&self, url: &str, probe: &ResolverProbe) -> Option<Arc<dyn StreamResolver>> {
+        self.resolvers
+            .iter()
+            .filter(|r| r.matches(url, probe))
+            .fold(None, |best: Option<&Arc<dyn StreamResolver>>, r| match best {
+                Some(b) if b.priority() >= r.priority() => Some(b),
+                _ => Some(r),
+            })
+            .cloned()
+    }
+
+    /// Resolver for `url`, probing the response only if the URL alone is not enough.
+    pub async fn resolver_for(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+    ) -> Result<Arc<dyn StreamResolver>, DownloadError> {
+        let resolver = match self.select(url, &ResolverProbe::default()) {
+            Some(resolver) => resolver,
+            None => {
+                debug!("No resolver claimed {} by URL; probing the response", url);
+                let probe = ResolverProbe::fetch(url, client, headers).await;
+                self.select(url, &probe).unwrap_or_else(|| self.fallback.clone())
+            }
+        };
+        if resolver.is_platform() && !self.platform_resolvers_enabled {
+            return Err(DownloadError::Config("Platform resolvers are currently disabled".to_string()));
+        }
+        Ok(resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(content_type: Option<&str>, head: &str) -> ResolverProbe {
+        ResolverProbe { content_type: content_type.map(str::to_string), head: head.to_string() }
+    }
+
+    #[test]
+    fn test_select_by_url_and_probe() {
+        let registry = ResolverRegistry::from_config(&StreamingConfig::default());
+        let name = |url: &str, probe: &ResolverProbe| registry.select(url, probe).map(|r| r.name());
+
+        let empty = ResolverProbe::default();
+        assert_eq!(name("https://cdn.example.com/live/index.m3u8?token=1", &empty), Some("hls"));
+        assert_eq!(name("https://cdn.example.com/v/manifest.MPD", &empty), Some("dash"));
+        assert_eq!(name("https://cdn.example.com/v.ism/Manifest", &empty), Some("smooth"));
+        assert_eq!(name("https://cdn.example.com/v/manifest.f4m", &empty), Some("hds"));
+        // The platform resolver outranks the extension match.
+        assert_eq!(name("https://www.youtube.com/watch?v=abc&fmt=.m3u8", &empty), Some("youtube"));
+        assert_eq!(name("https://vimeo.com/76979871", &empty), Some("vimeo"));
+        assert_eq!(name("https://dai.ly/x8j6n2c", &empty), Some("dailymotion"));
+        assert_eq!(name("https://cdn.example.com/play?id=7", &empty), None);
+
+        let url = "https://cdn.example.com/play?id=7";
+        assert_eq!(name(url, &probe(Some("application/vnd.apple.mpegurl"), "")), Some("hls"));
+        assert_eq!(name(url, &probe(None, "<?xml version=\"1.0\"?>\n<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\">")), Some("dash"));
+        assert_eq!(name(url, &probe(Some("text/xml"), "<SmoothStreamingMedia MajorVersion=\"2\">")), Some("smooth"));
+
+        let mirrors = |url: &str| registry.select(url, &empty).is_some_and(|r| r.supports_mirror());
+        assert!(mirrors("https://cdn.example.com/live/index.m3u8"));
+        assert!(!mirrors("https://cdn.example.com/v/manifest.mpd"));
+    }
+}
@@ -1,59 +1,435 @@
 use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::playlist::{self, MasterPlaylist, MediaPlaylist, Playlist, Rendition, Variant};
+use super::segment::Segment;
+use super::subtitles;
 use reqwest::Client;
 use url::Url;
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
+
+/// What a resolved track carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    /// Audio and video interleaved in the same segments (classic HLS TS).
+    Muxed,
+    Video,
+    Audio,
+    /// Segmented WebVTT, saved as a sidecar file rather than muxed.
+    Subtitle,
+}
+
+/// A single downloadable rendition: an ordered list of segments.
+#[derive(Debug, Clone)]
+pub struct StreamTrack {
+    pub kind: TrackKind,
+    pub segments: Vec<Segment>,
+    pub language: Option<String>,
+}
+
+impl StreamTrack {
+    pub fn new(kind: TrackKind, segments: Vec<Segment>, language: Option<String>) -> Self {
+        Self { kind, segments, language }
+    }
+
+    fn from_media(kind: TrackKind, media: MediaPlaylist, language: Option<String>) -> Self {
+        Self::new(kind, media.segments, language)
+    }
+
+    /// Duration of segment `index` in seconds, 0 when unknown.
+    pub fn duration(&self, index: usize) -> f64 {
+        self.segments.get(index).map_or(0.0, |s| s.duration)
+    }
+}
+
+/// Everything a resolver found for a stream URL.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedStream {
+    pub tracks: Vec<StreamTrack>,
+    /// Media playlist of a live HLS stream, recorded as it grows instead of downloading the
+    /// segments listed so far.
+    pub live_playlist: Option<String>,
+}
+
+impl ResolvedStream {
+    /// A stream made of a single muxed rendition.
+    pub fn single(track: StreamTrack) -> Self {
+        Self { tracks: vec![track], live_playlist: None }
+    }
+
+    /// A single muxed rendition of a live media playlist, recorded from `url`.
+    fn live(track: StreamTrack, url: &str) -> Self {
+        Self { tracks: vec![track], live_playlist: Some(url.to_string()) }
+    }
+
+    pub fn track(&self, kind: TrackKind) -> Option<&StreamTrack> {
+        self.tracks.iter().find(|t| t.kind == kind)
+    }
+
+    /// Keeps only the segments overlapping `[start, end)` seconds, using cumulative segment
+    /// durations. Tracks whose manifest gives no durations are left whole, and so are subtitle
+    /// tracks, whose cues are cheap to fetch and get shifted instead.
+    ///
+    /// Returns where the kept media begins in seconds, the segment boundary at or before
+    /// `start`; subtitle cues are shifted back by it.
+    pub fn clip(&mut self, start: f64, end: Option<f64>) -> Result<f64, DownloadError> {
+        if end.is_some_and(|end| end <= start) {
+            return Err(DownloadError::Config(format!("Clip end {:?}s must be after its start {}s", end, start)));
+        }
+        let end = end.unwrap_or(f64::INFINITY);
+
+        let mut offset = None;
+        for track in self.tracks.iter_mut().filter(|t| t.kind != TrackKind::Subtitle) {
+            if track.segments.iter().all(|s| s.duration <= 0.0) {
+                warn!(kind = ?track.kind, "Track has no segment durations, cannot clip it");
+                continue;
+            }
+
+            let before = track.segments.len();
+            let mut position = 0.0;
+            let mut first_kept = None;
+            track.segments.retain(|segment| {
+                let (segment_start, segment_end) = (position, position + segment.duration);
+                position = segment_end;
+                let keep = segment_end > start && segment_start < end;
+                if keep && first_kept.is_none() {
+                    first_kept = Some(segment_start);
+                }
+                keep
+            });
+            info!(kind = ?track.kind, "Clipped track to {}/{} segments", track.segments.len(), before);
+
+            let Some(first_kept) = first_kept else {
+                return Err(DownloadError::Config(format!(
+                    "Clip starting at {}s is past the end of the stream ({:.1}s)",
+                    start, position
+                )));
+            };
+            // Tracks come video first; the output's timeline starts with it.
+            offset.get_or_insert(first_kept);
+        }
+        Ok(offset.unwrap_or(0.0))
+    }
+}
+
+/// What a quick look at a URL's response revealed, for resolvers that can't tell from the URL
+/// alone (extension-less manifest links, redirectors).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResolverProbe {
+    pub content_type: Option<String>,
+    /// Start of the body, lossily decoded.
+    pub head: String,
+}
+
+impl ResolverProbe {
+    /// Bytes of the body kept in `head`.
+    const HEAD_LEN: usize = 4096;
+
+    /// Fetches the start of `url`. Failures yield an empty probe; the caller just has less to go on.
+    pub async fn fetch(url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Self {
+        let range = format!("bytes=0-{}", Self::HEAD_LEN - 1);
+        let Ok(response) = client.get(url).headers(headers.clone()).header(reqwest::header::RANGE, range).send().await else {
+            return Self::default();
+        };
+        if !response.status().is_success() {
+            return Self::default();
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_lowercase);
+        let body = response.bytes().await.unwrap_or_default();
+        let head = String::from_utf8_lossy(&body[..body.len().min(Self::HEAD_LEN)]).into_owned();
+        Self { content_type, head }
+    }
+
+    pub fn content_type_contains(&self, needle: &str) -> bool {
+        self.content_type.as_deref().is_some_and(|t| t.contains(needle))
+    }
+}
 
 #[async_trait::async_trait]
 pub trait StreamResolver: Send + Sync {
-    async fn resolve(&self, url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<Vec<String>, DownloadError>;
+    /// Short identifier reported to the frontend and in logs.
+    fn name(&self) -> &'static str;
+
+    /// Whether this resolver can handle `url`. `probe` is empty on the first, URL-only pass.
+    fn matches(&self, url: &str, probe: &ResolverProbe) -> bool;
+
+    /// Among matching resolvers the highest priority wins.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Platform resolvers scrape a website rather than read a manifest, and can be turned off.
+    fn is_platform(&self) -> bool {
+        false
+    }
+
+    /// Whether the stream can be saved as served (playlists rewritten to local files) by
+    /// mirror mode.
+    fn supports_mirror(&self) -> bool {
+        false
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError>;
+}
+
+/// Fetches a manifest body as text, mapping failures to `DownloadError::Network`.
+pub(crate) async fn fetch_manifest(url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<String, DownloadError> {
+    request_manifest(url, client, headers).await?.text().await
+        .map_err(|e| DownloadError::Network(format!("Failed to read manifest body: {}", e)))
+}
+
+/// Fetches a manifest body as raw bytes, for formats that may not be UTF-8.
+pub(crate) async fn fetch_manifest_bytes(url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<Vec<u8>, DownloadError> {
+    let body = request_manifest(url, client, headers).await?.bytes().await
+        .map_err(|e| DownloadError::Network(format!("Failed to read manifest body: {}", e)))?;
+    Ok(body.to_vec())
+}
+
+async fn request_manifest(url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<reqwest::Response, DownloadError> {
+    let response = client.get(url)
+        .headers(headers.clone())
+        .send()
+        .await
+        .map_err(|e| DownloadError::Network(format!("Failed to fetch manifest: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(DownloadError::Network(format!("Server returned error: {}", response.status())));
+    }
+    Ok(response)
 }
 
 pub struct HlsResolver;
 
+/// Other variants or renditions whose playlists are fetched so failing segments can be taken from them.
+const MAX_FAILOVER_VARIANTS: usize = 3;
+
+impl HlsResolver {
+    /// Lets the segments of `media` fall back to the media playlists in `candidates`, each
+    /// flagged `true` when it is a redundant copy of the same stream. Playlists that can't be
+    /// fetched are skipped; failover is only a safety net.
+    async fn attach_failover(&self, candidates: Vec<(&str, bool)>, media: &mut MediaPlaylist, client: &Client, headers: &reqwest::header::HeaderMap) {
+        for (uri, redundant) in candidates.into_iter().take(MAX_FAILOVER_VARIANTS) {
+            match self.resolve_media(uri, client, headers).await {
+                Ok(alternate_media) => {
+                    let attached = playlist::attach_alternates(media, &alternate_media, uri, redundant);
+                    debug!("HLS Resolver: {}/{} segments can fail over to {} (redundant: {})", attached, media.segments.len(), uri, redundant);
+                }
+                Err(e) => warn!("HLS Resolver: Skipping failover playlist {}: {}", uri, e),
+            }
+        }
+    }
+
+    /// Redundant copies and other bitrates of `variant`.
+    fn variant_failover<'a>(master: &'a MasterPlaylist, variant: &Variant) -> Vec<(&'a str, bool)> {
+        master.failover_variants_for(variant).into_iter().map(|(v, redundant)| (v.uri.as_str(), redundant)).collect()
+    }
+
+    /// Backup copies and other renditions in the language of `rendition`.
+    fn rendition_failover<'a>(master: &'a MasterPlaylist, rendition: &Rendition) -> Vec<(&'a str, bool)> {
+        master
+            .failover_renditions_for(rendition)
+            .into_iter()
+            .filter_map(|(r, redundant)| Some((r.uri.as_deref()?, redundant)))
+            .collect()
+    }
+
+    /// Fetches a media playlist that must contain at least one segment.
+    async fn resolve_media(&self, url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<MediaPlaylist, DownloadError> {
+        let text = fetch_manifest(url, client, headers).await?;
+        let base_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
+
+        match playlist::parse_playlist(&text, &base_url)? {
+            Playlist::Media(media) if !media.segments.is_empty() => {
+                debug!("HLS Resolver: {} segments, {:.1}s at {}", media.segments.len(), media.total_duration(), url);
+                Ok(media)
+            }
+            Playlist::Media(_) => Err(DownloadError::Config("No segments found in HLS manifest".to_string())),
+            Playlist::Master(_) => Err(DownloadError::Parse(format!("Expected a media playlist at {}", url))),
+        }
+    }
+
+    /// An audio-only variant or the best variant's separate audio rendition, if the master has one.
+    async fn resolve_audio_only(&self, master: &MasterPlaylist, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<Option<ResolvedStream>, DownloadError> {
+        if let Some(variant) = master.best_audio_only_variant() {
+            info!("HLS Resolver: Using audio-only variant {} ({} bps, codecs {:?})", variant.uri, variant.bandwidth, variant.codecs);
+            let mut media = self.resolve_media(&variant.uri, client, headers).await?;
+            check_audio_not_live(&media)?;
+            self.attach_failover(Self::variant_failover(master, variant), &mut media, client, headers).await;
+            return Ok(Some(ResolvedStream::single(StreamTrack::from_media(TrackKind::Audio, media, None))));
+        }
+        let Some(rendition) = master.best_variant().and_then(|v| master.audio_rendition_for(v)) else {
+            return Ok(None);
+        };
+        let uri = rendition.uri.as_deref().unwrap_or_default();
+        info!("HLS Resolver: Using audio rendition {:?} at {}", rendition.name, uri);
+        let mut media = self.resolve_media(uri, client, headers).await?;
+        check_audio_not_live(&media)?;
+        self.attach_failover(Self::rendition_failover(master, rendition), &mut media, client, headers).await;
+        Ok(Some(ResolvedStream::single(StreamTrack::from_media(TrackKind::Audio, media, rendition.language.clone()))))
+    }
+}
+
+/// Audio-only downloads need the whole playlist; only muxed live streams can be recorded.
+fn check_audio_not_live(media: &MediaPlaylist) -> Result<(), DownloadError> {
+    if media.end_list {
+        return Ok(());
+    }
+    Err(DownloadError::Config("Audio-only download is not supported for live streams".to_string()))
+}
+
 #[async_trait::async_trait]
 impl StreamResolver for HlsResolver {
-    async fn resolve(&self, url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<Vec<String>, DownloadError> {
-        debug!("HLS Resolver: Fetching manifest from {}", url);
-        
-        let response = client.get(url)
-            .headers(headers.clone())
-            .send()
-            .await
-            .map_err(|e| DownloadError::Network(format!("Failed to fetch HLS manifest: {}", e)))?;
+    fn name(&self) -> &'static str {
+        "hls"
+    }
 
-        if !response.status().is_success() {
-            return Err(DownloadError::Network(format!("Server returned error: {}", response.status())));
-        }
+    fn supports_mirror(&self) -> bool {
+        true
+    }
 
-        let text = response.text().await
-            .map_err(|e| DownloadError::Network(format!("Failed to read manifest body: {}", e)))?;
+    fn matches(&self, url: &str, probe: &ResolverProbe) -> bool {
+        url.to_lowercase().contains(".m3u8")
+            || probe.content_type_contains("mpegurl")
+            || probe.head.trim_start().starts_with("#EXTM3U")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        debug!("HLS Resolver: Fetching manifest from {}", url);
 
+        let text = fetch_manifest(url, client, headers).await?;
         let base_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
-        let mut segment_urls = Vec::new();
-
-        for line in text.lines() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed.starts_with('#') {
-                // Check if it's a pointer to a sub-manifest (Quality Selection)
-                if trimmed.contains(".m3u8") && !trimmed.starts_with("#EXT") {
-                    let sub_url = base_url.join(trimmed).map_err(|e| DownloadError::Config(e.to_string()))?;
-                    info!("HLS Resolver: Detected sub-manifest, recursing to: {}", sub_url);
-                    // Recurse to get the actual segments from the sub-manifest
-                    return Box::pin(self.resolve(sub_url.as_str(), client, headers)).await;
+
+        let master = match playlist::parse_playlist(&text, &base_url)? {
+            Playlist::Media(media) => {
+                if media.segments.is_empty() {
+                    return Err(DownloadError::Config("No segments found in HLS manifest".to_string()));
                 }
-                continue;
+                if !media.end_list {
+                    if options.audio_only {
+                        check_audio_not_live(&media)?;
+                    }
+                    info!("HLS Resolver: {} is a live playlist", url);
+                    return Ok(ResolvedStream::live(StreamTrack::from_media(TrackKind::Muxed, media, None), url));
+                }
+                return Ok(ResolvedStream::single(StreamTrack::from_media(TrackKind::Muxed, media, None)));
+            }
+            Playlist::Master(master) => master,
+        };
+
+        if options.audio_only {
+            if let Some(audio) = self.resolve_audio_only(&master, client, headers).await? {
+                return Ok(audio);
             }
+            info!("HLS Resolver: No audio-only rendition, audio will be extracted from the full stream");
+        }
 
-            // Resolve relative URLs
-            let full_url = base_url.join(trimmed)
-                .map_err(|e| DownloadError::Config(format!("Failed to resolve segment URL: {}", e)))?;
-            segment_urls.push(full_url.to_string());
+        let variant = master.best_variant()
+            .ok_or_else(|| DownloadError::Config("Master playlist has no variants".to_string()))?;
+        info!(
+            "HLS Resolver: Selected variant {} ({} bps, {:?}, codecs {:?})",
+            variant.uri, variant.bandwidth, variant.resolution, variant.codecs
+        );
+
+        let mut video_media = self.resolve_media(&variant.uri, client, headers).await?;
+        let live = !video_media.end_list;
+        if !live {
+            self.attach_failover(Self::variant_failover(&master, variant), &mut video_media, client, headers).await;
         }
 
-        if segment_urls.is_empty() {
-            return Err(DownloadError::Config("No segments found in HLS manifest".to_string()));
+        let mut resolved = match master.audio_rendition_for(variant) {
+            Some(audio) => {
+                let audio_uri = audio.uri.as_deref().unwrap_or_default();
+                info!("HLS Resolver: Variant uses separate audio rendition {:?} at {}", audio.name, audio_uri);
+                if live {
+                    return Err(DownloadError::Config(
+                        "Live recording of streams with a separate audio rendition is not supported".to_string(),
+                    ));
+                }
+                let mut audio_media = self.resolve_media(audio_uri, client, headers).await?;
+                self.attach_failover(Self::rendition_failover(&master, audio), &mut audio_media, client, headers).await;
+                ResolvedStream {
+                    tracks: vec![
+                        StreamTrack::from_media(TrackKind::Video, video_media, None),
+                        StreamTrack::from_media(TrackKind::Audio, audio_media, audio.language.clone()),
+                    ],
+                    live_playlist: None,
+                }
+            }
+            None if live => {
+                info!("HLS Resolver: {} is a live playlist", variant.uri);
+                ResolvedStream::live(StreamTrack::from_media(TrackKind::Muxed, video_media, None), &variant.uri)
+            }
+            None => ResolvedStream::single(StreamTrack::from_media(TrackKind::Muxed, video_media, None)),
+        };
+
+        for rendition in master.subtitle_renditions_for(variant) {
+            if !subtitles::language_selected(&options.subtitle_languages, rendition.language.as_deref()) {
+                continue;
+            }
+            let uri = rendition.uri.as_deref().unwrap_or_default();
+            // Subtitles are optional extras; a broken rendition must not fail the download.
+            match self.resolve_media(uri, client, headers).await {
+                Ok(media) => resolved.tracks.push(StreamTrack::from_media(TrackKind::Subtitle, media, rendition.language.clone())),
+                Err(e) => warn!("HLS Resolver: Skipping subtitle rendition {:?}: {}", rendition.name, e),
+            }
         }
 
-        Ok(segment_urls)
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(kind: TrackKind, durations: &[f64]) -> StreamTrack {
+        let segments = durations
+            .iter()
+            .enumerate()
+            .map(|(i, d)| Segment::new(format!("https://cdn.example.com/{}.ts", i), *d))
+            .collect();
+        StreamTrack::new(kind, segments, None)
+    }
+
+    #[test]
+    fn test_clip_keeps_overlapping_segments() {
+        let mut resolved = ResolvedStream {
+            tracks: vec![track(TrackKind::Video, &[6.0; 10]), track(TrackKind::Audio, &[4.0; 15]), track(TrackKind::Subtitle, &[0.0; 3])],
+            live_playlist: None,
+        };
+        assert_eq!(resolved.clip(13.0, Some(25.0)).unwrap(), 12.0);
+
+        let urls = |kind| resolved.track(kind).unwrap().segments.iter().map(|s| s.url.clone()).collect::<Vec<_>>();
+        // Video segments cover [12,18) [18,24) [24,30); audio [12,16) .. [24,28).
+        assert_eq!(urls(TrackKind::Video), vec!["https://cdn.example.com/2.ts", "https://cdn.example.com/3.ts", "https://cdn.example.com/4.ts"]);
+        assert_eq!(resolved.track(TrackKind::Audio).unwrap().segments.len(), 4);
+        // Subtitles are kept whole and shifted when saved.
+        assert_eq!(resolved.track(TrackKind::Subtitle).unwrap().segments.len(), 3);
+
+        assert!(resolved.clip(100.0, None).is_err());
+        assert!(ResolvedStream::default().clip(5.0, Some(5.0)).is_err());
+    }
+
+    #[test]
+    fn test_audio_only_rejects_live_playlists() {
+        let mut media = MediaPlaylist { segments: vec![Segment::new("https://cdn.example.com/a.aac".to_string(), 4.0)], ..Default::default() };
+        assert!(matches!(check_audio_not_live(&media), Err(DownloadError::Config(_))));
+        media.end_list = true;
+        assert!(check_audio_not_live(&media).is_ok());
     }
 }
@@ -0,0 +1,148 @@
+//! One downloadable piece of a track, as described by an HLS or DASH manifest.
+
+use crate::core::error::DownloadError;
+use reqwest::header::{HeaderMap, HeaderValue, RANGE};
+use std::sync::Arc;
+
+/// Most segments one track may expand to from a manifest, so a degenerate one (zero
+/// durations, endless repeats) fails instead of exhausting memory.
+pub const MAX_SEGMENTS: u64 = 200_000;
+
+/// Fails when adding `count` segments to `existing` ones would pass `MAX_SEGMENTS`.
+pub fn check_segment_count(existing: u64, count: u64) -> Result<(), DownloadError> {
+    if existing.saturating_add(count) > MAX_SEGMENTS {
+        return Err(DownloadError::Parse(format!("Manifest expands to more than {} segments", MAX_SEGMENTS)));
+    }
+    Ok(())
+}
+
+/// A byte sub-range of a resource (`#EXT-X-BYTERANGE`, `@mediaRange`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl ByteRange {
+    /// Parses `<length>[@<offset>]`; a missing offset continues right after `previous_end`.
+    pub fn parse(value: &str, previous_end: Option<u64>) -> Option<Self> {
+        let (length, offset) = match value.trim().split_once('@') {
+            Some((length, offset)) => (length, Some(offset.trim().parse().ok()?)),
+            None => (value, None),
+        };
+        Some(Self {
+            length: length.trim().parse().ok()?,
+            offset: offset.or(previous_end)?,
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+
+    /// Adds the matching `Range` header to a copy of `headers`.
+    pub fn apply(range: Option<&ByteRange>, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        if let Some(range) = range.filter(|r| r.length > 0) {
+            let value = format!("bytes={}-{}", range.offset, range.end() - 1);
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(RANGE, value);
+            }
+        }
+        headers
+    }
+
+    /// Cuts the range out of a full response body, for servers that ignore `Range` and answer 200.
+    pub fn slice(&self, body: &[u8]) -> Option<Vec<u8>> {
+        body.get(self.offset as usize..self.end() as usize).map(<[u8]>::to_vec)
+    }
+}
+
+/// Encryption applied to a segment (`#EXT-X-KEY` with a method other than `NONE`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    pub method: String,
+    pub uri: Option<String>,
+}
+
+/// Initialization section that has to precede a segment (`#EXT-X-MAP`, DASH `Initialization`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitSection {
+    pub url: String,
+    pub byte_range: Option<ByteRange>,
+    /// Init segment built by the resolver instead of fetched from `url` (Smooth Streaming).
+    pub data: Option<Arc<[u8]>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub url: String,
+    pub byte_range: Option<ByteRange>,
+    pub key: Option<SegmentKey>,
+    pub map: Option<InitSection>,
+    /// Playback duration in seconds, 0 when the manifest doesn't say.
+    pub duration: f64,
+    /// Discontinuity sequence number; it changes wherever timestamps or codec parameters may
+    /// jump (`#EXT-X-DISCONTINUITY`, a new DASH period).
+    pub discontinuity: u64,
+    /// Rewrite the downloader applies to the fetched media before writing it.
+    pub transform: Option<FragmentTransform>,
+    /// Copies of this segment in other variants, tried in order once its own URL keeps failing.
+    pub alternates: Vec<SegmentAlternate>,
+}
+
+/// The same media in another variant of the stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentAlternate {
+    pub url: String,
+    pub byte_range: Option<ByteRange>,
+    /// Init section of the alternate variant, used instead of the segment's own.
+    pub map: Option<InitSection>,
+    /// Media playlist of the variant, for the download report.
+    pub variant: String,
+    /// A redundant copy of the same stream (on another host) rather than another bitrate.
+    pub redundant: bool,
+}
+
+/// Fragment formats that are not playable as served and get rewritten after download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentTransform {
+    /// Bare Smooth Streaming `moof` + `mdat`: moved to track 1 and given a `tfdt` with this
+    /// decode time (in track timescale units).
+    Smooth { decode_time: u64 },
+    /// Adobe F4F fragment: only the FLV tags carried in its `mdat` are kept.
+    F4f,
+    /// Audio-only download: an MPEG-TS segment is reduced to its audio as packed audio.
+    ExtractAudio,
+}
+
+impl Segment {
+    pub fn new(url: String, duration: f64) -> Self {
+        Self { url, byte_range: None, key: None, map: None, duration, discontinuity: 0, transform: None, alternates: Vec::new() }
+    }
+
+    pub fn with_map(mut self, map: Option<InitSection>) -> Self {
+        self.map = map;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range_parsing_and_header() {
+        let first = ByteRange::parse("1000@0", None).unwrap();
+        let second = ByteRange::parse("500", Some(first.end())).unwrap();
+        assert_eq!(second, ByteRange { offset: 1000, length: 500 });
+        assert_eq!(ByteRange::parse("500", None), None);
+
+        let headers = ByteRange::apply(Some(&second), &HeaderMap::new());
+        assert_eq!(headers.get(RANGE).unwrap(), "bytes=1000-1499");
+
+        let body: Vec<u8> = (0..=255).cycle().take(2000).map(|b| b as u8).collect();
+        assert_eq!(second.slice(&body).unwrap().len(), 500);
+        assert_eq!(ByteRange { offset: 1900, length: 200 }.slice(&body), None);
+    }
+}
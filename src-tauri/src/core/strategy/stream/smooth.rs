@@ -0,0 +1,354 @@
+//! Microsoft Smooth Streaming (`.ism/Manifest`) resolution.
+//!
+//! Smooth Streaming serves bare `moof` + `mdat` fragments and no init segment. The resolver
+//! builds one per track from the manifest's `CodecPrivateData`, and tags every fragment with
+//! its start time so the downloader can give it a `tfdt`; the result is a playable fragmented MP4.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::mux::{codec, mp4, Codec, MediaKind, TrackInfo};
+use super::resolver::{fetch_manifest_bytes, ResolvedStream, ResolverProbe, StreamResolver, StreamTrack, TrackKind};
+use super::segment::{check_segment_count, FragmentTransform, InitSection, Segment};
+use reqwest::Client;
+use roxmltree::{Document, Node};
+use std::sync::Arc;
+use tracing::{debug, info};
+use url::Url;
+
+/// Default `TimeScale` of a Smooth Streaming manifest (100 ns units).
+const DEFAULT_TIMESCALE: u64 = 10_000_000;
+
+pub struct SmoothResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for SmoothResolver {
+    fn name(&self) -> &'static str {
+        "smooth"
+    }
+
+    fn matches(&self, url: &str, probe: &ResolverProbe) -> bool {
+        url.to_lowercase().contains(".ism") || probe.head.contains("<SmoothStreamingMedia")
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        _options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        let manifest_url = manifest_url(url);
+        debug!("Smooth Resolver: Fetching manifest from {}", manifest_url);
+
+        let body = fetch_manifest_bytes(&manifest_url, client, headers).await?;
+        let base_url = Url::parse(&manifest_url).map_err(|e| DownloadError::Config(format!("Invalid base URL: {}", e)))?;
+        let resolved = parse_manifest(&decode_manifest(&body), &base_url)?;
+
+        info!("Smooth Resolver: Resolved {} track(s)", resolved.tracks.len());
+        Ok(resolved)
+    }
+}
+
+/// A publishing point URL (`/video.ism`) serves its manifest at `/video.ism/Manifest`.
+fn manifest_url(url: &str) -> String {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    let lower = path.to_lowercase();
+    if !(lower.ends_with(".ism") || lower.ends_with(".isml") || lower.ends_with(".ism/") || lower.ends_with(".isml/")) {
+        return url.to_string();
+    }
+    let manifest = format!("{}/Manifest", path.trim_end_matches('/'));
+    match query {
+        Some(query) => format!("{}?{}", manifest, query),
+        None => manifest,
+    }
+}
+
+/// IIS often serves manifests as UTF-16 with a byte order mark.
+fn decode_manifest(body: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], to_u16: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| to_u16([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match body {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(body).into_owned(),
+    }
+}
+
+/// Parses a `SmoothStreamingMedia` document, picking the highest-bitrate video and audio
+/// `QualityLevel` and expanding their `c` timelines into fragment URLs.
+pub fn parse_manifest(text: &str, manifest_url: &Url) -> Result<ResolvedStream, DownloadError> {
+    let doc = Document::parse(text)
+        .map_err(|e| DownloadError::Parse(format!("Invalid Smooth Streaming manifest: {}", e)))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "SmoothStreamingMedia" {
+        return Err(DownloadError::Parse("Manifest root is not a SmoothStreamingMedia element".to_string()));
+    }
+    if root.children().any(|n| n.has_tag_name("Protection")) {
+        return Err(DownloadError::Config(
+            "Smooth Streaming manifest is DRM-protected (PlayReady); protected streams are not supported".to_string(),
+        ));
+    }
+    if root.attribute("IsLive").is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+        return Err(DownloadError::Config("Live Smooth Streaming is not supported, only on-demand manifests".to_string()));
+    }
+    let timescale: u64 = root.attribute("TimeScale").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_TIMESCALE);
+
+    let mut tracks = Vec::new();
+    for (kind, media_kind) in [(TrackKind::Video, MediaKind::Video), (TrackKind::Audio, MediaKind::Audio)] {
+        let type_name = if media_kind == MediaKind::Video { "video" } else { "audio" };
+        let selected = root
+            .children()
+            .filter(|n| n.has_tag_name("StreamIndex") && n.attribute("Type") == Some(type_name))
+            .find_map(|stream| {
+                let (level, bitrate, info) = select_quality_level(stream, media_kind, timescale)?;
+                Some((stream, level, bitrate, info))
+            });
+        let Some((stream, level, bitrate, info)) = selected else { continue };
+
+        debug!(
+            "Smooth Resolver: Selected {} level {:?} ({} bps, {:?})",
+            type_name,
+            level.attribute("Index"),
+            bitrate,
+            info.codec
+        );
+        let segments = expand_fragments(stream, &info, bitrate, manifest_url)?;
+        let language = stream.attribute("Language").map(str::to_string);
+        tracks.push(StreamTrack::new(kind, segments, language));
+    }
+
+    if tracks.is_empty() {
+        return Err(DownloadError::Config("No supported video or audio streams found in Smooth Streaming manifest".to_string()));
+    }
+    if tracks.len() == 1 {
+        tracks[0].kind = TrackKind::Muxed;
+    }
+    Ok(ResolvedStream { tracks, live_playlist: None })
+}
+
+/// Picks the highest-bitrate quality level of a stream whose codec we can describe.
+fn select_quality_level<'a, 'input>(
+    stream: Node<'a, 'input>,
+    kind: MediaKind,
+    manifest_timescale: u64,
+) -> Option<(Node<'a, 'input>, u64, TrackInfo)> {
+    let timescale = stream.attribute("TimeScale").and_then(|v| v.parse().ok()).unwrap_or(manifest_timescale);
+    stream
+        .children()
+        .filter(|n| n.has_tag_name("QualityLevel"))
+        .filter_map(|level| {
+            let mut info = track_info(level, stream, kind)?;
+            info.timescale = timescale as u32;
+            let bitrate = level.attribute("Bitrate").and_then(|b| b.parse().ok()).unwrap_or(0);
+            Some((level, bitrate, info))
+        })
+        .max_by_key(|(_, bitrate, _)| *bitrate)
+}
+
+/// Builds the track description from a quality level's codec attributes.
+fn track_info(level: Node, stream: Node, kind: MediaKind) -> Option<TrackInfo> {
+    let attribute = |name: &str| level.attribute(name).or_else(|| stream.attribute(name));
+    let number = |name: &str| attribute(name).and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let fourcc = attribute("FourCC").unwrap_or_default().to_uppercase();
+    let private = decode_hex(attribute("CodecPrivateData").unwrap_or_default())?;
+
+    let mut info = TrackInfo {
+        kind,
+        codec: Codec::Aac,
+        timescale: 0,
+        codec_private: Vec::new(),
+        width: number("MaxWidth").max(number("Width")),
+        height: number("MaxHeight").max(number("Height")),
+        sample_rate: number("SamplingRate"),
+        channels: number("Channels") as u16,
+        language: stream.attribute("Language").map(str::to_string),
+    };
+
+    match (kind, fourcc.as_str()) {
+        (MediaKind::Video, "H264" | "AVC1" | "DAVC") => {
+            let nals = codec::split_annexb(&private);
+            let sps = nals.iter().find(|n| n.first().is_some_and(|b| b & 0x1F == 7))?;
+            let pps = nals.iter().find(|n| n.first().is_some_and(|b| b & 0x1F == 8))?;
+            if let Some(parsed) = codec::parse_h264_sps(sps) {
+                info.width = parsed.width;
+                info.height = parsed.height;
+            }
+            info.codec = Codec::H264;
+            info.codec_private = codec::avc_decoder_config(sps, pps);
+        }
+        (MediaKind::Video, "HEVC" | "H265" | "HVC1" | "HEV1") => {
+            let nals = codec::split_annexb(&private);
+            let find = |nal_type: u8| nals.iter().find(|n| n.first().is_some_and(|b| (b >> 1) & 0x3F == nal_type));
+            let (vps, sps, pps) = (find(32)?, find(33)?, find(34)?);
+            let parsed = codec::parse_h265_sps(sps)?;
+            info.width = parsed.width;
+            info.height = parsed.height;
+            info.codec = Codec::H265;
+            info.codec_private = codec::hevc_decoder_config(vps, sps, pps, &parsed);
+        }
+        (MediaKind::Audio, "AACL" | "AACH" | "AACP" | "MP4A") => {
+            info.codec_private = if private.is_empty() {
+                // Without CodecPrivateData, describe plain AAC-LC from the sampling attributes.
+                let index = codec::AAC_SAMPLE_RATES.iter().position(|r| *r == info.sample_rate)?;
+                codec::audio_specific_config(2, index as u8, info.channels as u8)
+            } else {
+                private
+            };
+            if let Some((rate, channels)) = codec::parse_audio_specific_config(&info.codec_private) {
+                info.sample_rate = rate;
+                info.channels = channels;
+            }
+        }
+        _ => {
+            debug!("Smooth Resolver: Skipping unsupported FourCC {:?}", fourcc);
+            return None;
+        }
+    }
+    Some(info)
+}
+
+/// Expands the `c` elements of a stream into one segment per fragment, all sharing a
+/// generated init section.
+fn expand_fragments(stream: Node, info: &TrackInfo, bitrate: u64, base: &Url) -> Result<Vec<Segment>, DownloadError> {
+    let template = stream
+        .attribute("Url")
+        .ok_or_else(|| DownloadError::Parse("StreamIndex is missing @Url".to_string()))?;
+    let timescale = info.timescale.max(1) as f64;
+    let map = Some(InitSection {
+        url: base.to_string(),
+        byte_range: None,
+        data: Some(Arc::from(mp4::fragmented_init(info))),
+    });
+
+    let chunks: Vec<Node> = stream.children().filter(|n| n.has_tag_name("c")).collect();
+    let mut segments = Vec::new();
+    let mut time: u64 = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        if let Some(t) = chunk.attribute("t").and_then(|v| v.parse().ok()) {
+            time = t;
+        }
+        // Without @d a fragment lasts until the next one starts.
+        let duration: u64 = match chunk.attribute("d").and_then(|v| v.parse().ok()) {
+            Some(d) => d,
+            None => chunks
+                .get(i + 1)
+                .and_then(|n| n.attribute("t"))
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|next| next.saturating_sub(time))
+                .ok_or_else(|| DownloadError::Parse("Smooth Streaming chunk has neither @d nor a following @t".to_string()))?,
+        };
+        // @r counts every occurrence, including the first.
+        let repeat: u64 = chunk.attribute("r").and_then(|v| v.parse().ok()).unwrap_or(1).max(1);
+        check_segment_count(segments.len() as u64, repeat)?;
+
+        for _ in 0..repeat {
+            let path = fill_template(template, bitrate, time);
+            let url = base
+                .join(&path)
+                .map_err(|e| DownloadError::Parse(format!("Failed to resolve fragment URL '{}': {}", path, e)))?;
+            let mut segment = Segment::new(url.to_string(), duration as f64 / timescale).with_map(map.clone());
+            segment.transform = Some(FragmentTransform::Smooth { decode_time: time });
+            segments.push(segment);
+            time = time
+                .checked_add(duration)
+                .ok_or_else(|| DownloadError::Parse("Smooth Streaming timeline overflows".to_string()))?;
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(DownloadError::Config("Smooth Streaming stream has no fragments".to_string()));
+    }
+    Ok(segments)
+}
+
+fn fill_template(template: &str, bitrate: u64, time: u64) -> String {
+    template
+        .replace("{bitrate}", &bitrate.to_string())
+        .replace("{Bitrate}", &bitrate.to_string())
+        .replace("{start time}", &time.to_string())
+        .replace("{start_time}", &time.to_string())
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::strategy::stream::mux::fmp4::find_box;
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<SmoothStreamingMedia MajorVersion="2" MinorVersion="2" Duration="60000000" TimeScale="10000000">
+  <StreamIndex Type="video" Name="video" Chunks="4" QualityLevels="2" Url="QualityLevels({bitrate})/Fragments(video={start time})">
+    <QualityLevel Index="0" Bitrate="800000" FourCC="H264" MaxWidth="640" MaxHeight="360"
+      CodecPrivateData="0000000167640028ACD940780227E584000003000400000300F03C60C6580000000168EBECB22C" />
+    <QualityLevel Index="1" Bitrate="3000000" FourCC="H264" MaxWidth="1920" MaxHeight="1080"
+      CodecPrivateData="0000000167640028ACD940780227E584000003000400000300F03C60C6580000000168EBECB22C" />
+    <c t="0" d="20000000" r="2" />
+    <c d="10000000" />
+    <c d="10000000" />
+  </StreamIndex>
+  <StreamIndex Type="audio" Language="eng" Url="QualityLevels({bitrate})/Fragments(audio={start time})">
+    <QualityLevel Index="0" Bitrate="128000" FourCC="AACL" SamplingRate="48000" Channels="2" CodecPrivateData="" />
+    <c t="0" d="60000000" />
+  </StreamIndex>
+</SmoothStreamingMedia>"#;
+
+    #[test]
+    fn test_parse_manifest_expands_chunks() {
+        let base = Url::parse("https://cdn.example.com/live/video.ism/Manifest").unwrap();
+        let resolved = parse_manifest(MANIFEST, &base).unwrap();
+
+        let video = resolved.track(TrackKind::Video).unwrap();
+        let urls: Vec<&str> = video.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://cdn.example.com/live/video.ism/QualityLevels(3000000)/Fragments(video=0)",
+                "https://cdn.example.com/live/video.ism/QualityLevels(3000000)/Fragments(video=20000000)",
+                "https://cdn.example.com/live/video.ism/QualityLevels(3000000)/Fragments(video=40000000)",
+                "https://cdn.example.com/live/video.ism/QualityLevels(3000000)/Fragments(video=50000000)",
+            ]
+        );
+        assert_eq!(video.segments[3].transform, Some(FragmentTransform::Smooth { decode_time: 50_000_000 }));
+        assert_eq!(video.segments[1].duration, 2.0);
+
+        // The generated init segment describes a 1080p avc1 track in the manifest timescale.
+        let init = video.segments[0].map.as_ref().and_then(|m| m.data.clone()).unwrap();
+        let moov = find_box(&init, b"moov").unwrap();
+        assert!(find_box(moov, b"mvex").is_some());
+        let mdia = find_box(find_box(moov, b"trak").unwrap(), b"mdia").unwrap();
+        assert_eq!(&find_box(mdia, b"mdhd").unwrap()[12..16], &10_000_000u32.to_be_bytes());
+
+        let audio = resolved.track(TrackKind::Audio).unwrap();
+        assert_eq!(audio.language.as_deref(), Some("eng"));
+        assert_eq!(audio.segments.len(), 1);
+        assert_eq!(manifest_url("https://cdn.example.com/live/video.ism?token=1"), "https://cdn.example.com/live/video.ism/Manifest?token=1");
+    }
+
+    #[test]
+    fn test_rejects_live_and_degenerate_manifests() {
+        let base = Url::parse("https://cdn.example.com/video.ism/Manifest").unwrap();
+        let live = MANIFEST.replace("TimeScale=\"10000000\">", "TimeScale=\"10000000\" IsLive=\"TRUE\">");
+        assert!(matches!(parse_manifest(&live, &base), Err(DownloadError::Config(_))));
+
+        let endless = MANIFEST.replace(r#"<c t="0" d="20000000" r="2" />"#, r#"<c t="0" d="1" r="4000000000" />"#);
+        assert!(matches!(parse_manifest(&endless, &base), Err(DownloadError::Parse(_))));
+        let overflowing = MANIFEST.replace(r#"<c t="0" d="20000000" r="2" />"#, r#"<c t="18446744073709551615" d="20000000" r="2" />"#);
+        assert!(matches!(parse_manifest(&overflowing, &base), Err(DownloadError::Parse(_))));
+    }
+}
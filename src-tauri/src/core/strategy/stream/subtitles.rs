@@ -0,0 +1,334 @@
+//! Subtitle tracks: stitching segmented WebVTT into a single file and converting it to SRT.
+
+use crate::core::error::DownloadError;
+use super::resolver::{fetch_manifest, StreamTrack};
+use super::segment::ByteRange;
+use futures_util::StreamExt;
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// `X-TIMESTAMP-MAP` MPEGTS values are 33-bit, 90 kHz.
+const MPEGTS_WRAP: i64 = 1 << 33;
+/// Recent cues checked for duplicates (cues spanning a segment boundary are repeated).
+const DEDUP_WINDOW: usize = 32;
+
+/// One subtitle cue with times in milliseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: i64,
+    pub end: i64,
+    /// WebVTT cue settings (`line:0 align:start` ...), kept for `.vtt` output.
+    pub settings: String,
+    pub text: String,
+}
+
+/// Whether a track in `language` was asked for. `*` selects every language; otherwise a
+/// requested tag matches exactly or by primary subtag (`en` selects `en-US`).
+pub fn language_selected(requested: &[String], language: Option<&str>) -> bool {
+    if requested.iter().any(|r| r == "*") {
+        return true;
+    }
+    let Some(language) = language else { return false };
+    let language = language.to_ascii_lowercase();
+    let primary = language.split(['-', '_']).next().unwrap_or_default();
+
+    requested.iter().any(|r| {
+        let r = r.to_ascii_lowercase();
+        r == language || r == primary
+    })
+}
+
+/// Collects cues from consecutive WebVTT segments onto one timeline.
+#[derive(Debug, Default)]
+pub struct WebVttStitcher {
+    cues: Vec<Cue>,
+    /// Offset (ms) of the first segment's `X-TIMESTAMP-MAP`, which becomes time zero.
+    base_offset: Option<i64>,
+    last_mpegts: Option<i64>,
+}
+
+impl WebVttStitcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the cues of one WebVTT segment, shifting them by its `X-TIMESTAMP-MAP`.
+    pub fn add_segment(&mut self, text: &str) {
+        let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+        let mut blocks = text.split("\n\n").map(|b| b.trim_matches('\n'));
+        let header = blocks.next().unwrap_or_default();
+
+        let shift = match header.lines().find_map(|l| l.strip_prefix("X-TIMESTAMP-MAP=")) {
+            Some(map) => match self.segment_offset(map) {
+                Some(offset) => offset - *self.base_offset.get_or_insert(offset),
+                None => 0,
+            },
+            None => 0,
+        };
+
+        // The header block itself may hold the first cue if the segment has no blank line after it.
+        let cue_blocks = std::iter::once(header.lines().skip_while(|l| !l.contains("-->")).collect::<Vec<_>>().join("\n"))
+            .chain(blocks.map(str::to_string));
+
+        for block in cue_blocks {
+            if let Some(mut cue) = parse_cue(&block) {
+                cue.start += shift;
+                cue.end += shift;
+                let duplicate = self.cues.iter().rev().take(DEDUP_WINDOW).any(|c| *c == cue);
+                if !duplicate {
+                    self.cues.push(cue);
+                }
+            }
+        }
+    }
+
+    /// Media time (ms) that local time zero of a segment corresponds to.
+    fn segment_offset(&mut self, map: &str) -> Option<i64> {
+        let mut mpegts = None;
+        let mut local = None;
+        for part in map.split(',') {
+            if let Some(v) = part.trim().strip_prefix("MPEGTS:") {
+                mpegts = v.trim().parse::<i64>().ok();
+            } else if let Some(v) = part.trim().strip_prefix("LOCAL:") {
+                local = parse_timestamp(v.trim());
+            }
+        }
+        let mut mpegts = mpegts?;
+        if let Some(last) = self.last_mpegts {
+            while mpegts - last < -MPEGTS_WRAP / 2 {
+                mpegts += MPEGTS_WRAP;
+            }
+        }
+        self.last_mpegts = Some(mpegts);
+        Some(mpegts / 90 - local.unwrap_or(0))
+    }
+
+    /// Returns the cues ordered by start time.
+    pub fn finish(mut self) -> Vec<Cue> {
+        self.cues.sort_by_key(|c| c.start);
+        self.cues
+    }
+}
+
+fn parse_cue(block: &str) -> Option<Cue> {
+    let mut lines = block.lines();
+    let mut timing = lines.next()?;
+    if !timing.contains("-->") {
+        // Optional cue identifier; NOTE / STYLE / REGION blocks never have a timing line.
+        timing = lines.next()?;
+    }
+    let (start, rest) = timing.split_once("-->")?;
+    let rest = rest.trim();
+    let (end, settings) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    Some(Cue {
+        start: parse_timestamp(start.trim())?,
+        end: parse_timestamp(end.trim())?,
+        settings: settings.trim().to_string(),
+        text: lines.collect::<Vec<_>>().join("\n"),
+    })
+}
+
+/// Parses `hh:mm:ss.ttt` or `mm:ss.ttt` (a `,` separator is accepted too) into milliseconds.
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let (clock, millis) = value.split_once(['.', ',']).unwrap_or((value, "0"));
+    let mut seconds = 0i64;
+    for part in clock.split(':') {
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds * 60 + part.parse::<i64>().ok()?;
+    }
+    if !millis.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis: i64 = format!("{:0<3}", millis).get(..3)?.parse().ok()?;
+    Some(seconds * 1000 + millis)
+}
+
+fn format_timestamp(ms: i64, separator: char) -> String {
+    let ms = ms.max(0);
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// Moves cues `offset` ms earlier, for output that starts `offset` ms into the stream. Cues
+/// that ended before then are dropped; one still showing is cut to start at zero.
+pub fn shift_cues(cues: Vec<Cue>, offset: i64) -> Vec<Cue> {
+    cues.into_iter()
+        .filter(|cue| cue.end > offset)
+        .map(|cue| Cue { start: (cue.start - offset).max(0), end: cue.end - offset, ..cue })
+        .collect()
+}
+
+pub fn to_webvtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        out.push_str(&format_timestamp(cue.start, '.'));
+        out.push_str(" --> ");
+        out.push_str(&format_timestamp(cue.end, '.'));
+        if !cue.settings.is_empty() {
+            out.push(' ');
+            out.push_str(&cue.settings);
+        }
+        out.push('\n');
+        out.push_str(&cue.text);
+        out.push_str("\n\n");
+    }
+    out
+}
+
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_timestamp(cue.start, ','),
+            format_timestamp(cue.end, ','),
+            srt_text(&cue.text)
+        ));
+    }
+    out
+}
+
+/// Drops WebVTT-only markup (voice, class, ruby and timestamp tags) and decodes entities.
+fn srt_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        let tag = &rest[open + 1..open + close];
+        let name = tag.trim_start_matches('/').split(['.', ' ']).next().unwrap_or_default();
+        if matches!(name, "i" | "b" | "u") {
+            out.push('<');
+            if tag.starts_with('/') {
+                out.push('/');
+            }
+            out.push_str(name);
+            out.push('>');
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+/// Output path for a subtitle next to `video_path`, e.g. `movie.ts` -> `movie.en.srt`.
+pub fn subtitle_path(video_path: &Path, language: &str, extension: &str) -> PathBuf {
+    let language: String = language
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    let language = if language.is_empty() { "und".to_string() } else { language };
+    video_path.with_extension(format!("{}.{}", language, extension))
+}
+
+/// Downloads every segment of a subtitle track and returns the stitched cues.
+pub async fn download_cues(client: &Client, track: &StreamTrack, headers: &reqwest::header::HeaderMap) -> Result<Vec<Cue>, DownloadError> {
+    let mut segments = futures_util::stream::iter(track.segments.clone())
+        .map(|segment| {
+            let client = client.clone();
+            let headers = ByteRange::apply(segment.byte_range.as_ref(), headers);
+            async move { fetch_manifest(&segment.url, &client, &headers).await }
+        })
+        .buffered(8);
+
+    let mut stitcher = WebVttStitcher::new();
+    let mut index = 0;
+    while let Some(segment) = segments.next().await {
+        match segment {
+            Ok(text) => stitcher.add_segment(&text),
+            Err(e) => warn!("Skipping subtitle segment {}: {}", index, e),
+        }
+        index += 1;
+    }
+
+    let cues = stitcher.finish();
+    debug!(language = ?track.language, "Stitched {} subtitle cues from {} segments", cues.len(), index);
+    Ok(cues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_selection() {
+        let requested = vec!["en".to_string(), "pt-BR".to_string()];
+        assert!(language_selected(&requested, Some("en-US")));
+        assert!(language_selected(&requested, Some("pt-br")));
+        assert!(!language_selected(&requested, Some("pt-PT")));
+        assert!(!language_selected(&requested, None));
+        assert!(language_selected(&["*".to_string()], Some("de")));
+    }
+
+    #[test]
+    fn test_stitch_segments_with_timestamp_map() {
+        let mut stitcher = WebVttStitcher::new();
+        stitcher.add_segment(
+            "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\n\n\
+             1\n00:00:01.000 --> 00:00:02.500 line:90%\nHello\n\n\
+             00:00:05.000 --> 00:00:07.000\n<v Bob>Spans segments</v>\n",
+        );
+        // Segment-relative cue times: this segment starts 6 s later on the MPEG-TS clock.
+        stitcher.add_segment(
+            "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:1440000,LOCAL:00:00:00.000\n\n\
+             00:00:-1.000 --> 00:00:01.000\nbroken\n\n\
+             00:00:00.500 --> 00:00:01.000\n<i>Second</i> &amp; last\n",
+        );
+        let cues = stitcher.finish();
+
+        assert_eq!(cues.len(), 3);
+        assert_eq!((cues[0].start, cues[0].end), (1000, 2500));
+        assert_eq!(cues[0].settings, "line:90%");
+        assert_eq!((cues[2].start, cues[2].end), (6500, 7000));
+
+        let srt = to_srt(&cues);
+        assert!(srt.starts_with("1\n00:00:01,000 --> 00:00:02,500\nHello\n\n"));
+        assert!(srt.contains("\nSpans segments\n"));
+        assert!(srt.contains("<i>Second</i> & last"));
+
+        let vtt = to_webvtt(&cues);
+        assert!(vtt.starts_with("WEBVTT\n\n00:00:01.000 --> 00:00:02.500 line:90%\nHello\n"));
+    }
+
+    #[test]
+    fn test_duplicate_cues_are_dropped() {
+        let mut stitcher = WebVttStitcher::new();
+        let segment = "WEBVTT\n\n00:00:05.000 --> 00:00:07.000\nSame\n";
+        stitcher.add_segment(segment);
+        stitcher.add_segment(segment);
+        assert_eq!(stitcher.finish().len(), 1);
+    }
+
+    #[test]
+    fn test_shift_cues() {
+        let cue = |start, end| Cue { start, end, settings: String::new(), text: "x".to_string() };
+        let shifted = shift_cues(vec![cue(1_000, 2_000), cue(11_000, 13_000), cue(15_000, 16_000)], 12_000);
+        assert_eq!(shifted, vec![cue(0, 1_000), cue(3_000, 4_000)]);
+    }
+
+    #[test]
+    fn test_subtitle_path() {
+        let path = subtitle_path(Path::new("/downloads/movie.ts"), "en-US", "srt");
+        assert_eq!(path, PathBuf::from("/downloads/movie.en-US.srt"));
+    }
+}
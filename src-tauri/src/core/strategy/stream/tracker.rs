@@ -0,0 +1,191 @@
+//! Byte-level progress, speed and ETA for segmented downloads.
+//!
+//! Streams rarely announce their size up front, so the final size is extrapolated from the
+//! average bytes per second of media (or per segment when durations are unknown), and the ETA
+//! compares the playback duration still to fetch with how fast media time is being downloaded.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Weight of the newest sample in the speed moving average.
+const SPEED_SMOOTHING: f64 = 0.3;
+
+/// Payload of the `download-progress-detail` event.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressSnapshot {
+    pub id: String,
+    /// Percentage, 0-100.
+    pub progress: f64,
+    /// Bytes per second.
+    pub speed: f64,
+    /// Seconds remaining.
+    pub eta: u64,
+    pub downloaded: u64,
+    /// Estimated final size in bytes.
+    pub total: u64,
+    pub segments: usize,
+    pub total_segments: usize,
+}
+
+#[derive(Debug)]
+struct TrackerState {
+    total_segments: usize,
+    /// Summed segment durations of every track; only meaningful while `durations_known`.
+    total_duration: f64,
+    durations_known: bool,
+    segments_done: usize,
+    duration_done: f64,
+    bytes_done: u64,
+    /// Media seconds fetched by this run (excludes what a resumed download already had).
+    session_duration: f64,
+    session_started: Instant,
+    last_sample: Option<(Instant, u64)>,
+    speed: f64,
+}
+
+/// Shared by every track of a download; the writer records segments, a reporter takes snapshots.
+#[derive(Debug)]
+pub struct ProgressTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(TrackerState {
+                total_segments: 0,
+                total_duration: 0.0,
+                durations_known: true,
+                segments_done: 0,
+                duration_done: 0.0,
+                bytes_done: 0,
+                session_duration: 0.0,
+                session_started: Instant::now(),
+                last_sample: None,
+                speed: 0.0,
+            }),
+        }
+    }
+
+    /// Registers a track about to be downloaded. `durations` may be empty or all zero if unknown.
+    pub fn add_track(&self, segments: usize, durations: &[f64]) {
+        let mut state = self.state.lock().unwrap();
+        state.total_segments += segments;
+        state.total_duration += durations.iter().sum::<f64>();
+        state.durations_known &= durations.len() == segments && durations.iter().any(|d| *d > 0.0);
+    }
+
+    /// Counts segments already on disk from an earlier run of a resumed download.
+    pub fn add_existing(&self, segments: usize, duration: f64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.segments_done += segments;
+        state.duration_done += duration;
+        state.bytes_done += bytes;
+    }
+
+    pub fn record_segment(&self, bytes: u64, duration: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.segments_done += 1;
+        state.duration_done += duration;
+        state.session_duration += duration;
+        state.bytes_done += bytes;
+    }
+
+    pub fn bytes_done(&self) -> u64 {
+        self.state.lock().unwrap().bytes_done
+    }
+
+    pub fn snapshot(&self, id: &str) -> ProgressSnapshot {
+        self.snapshot_at(id, Instant::now())
+    }
+
+    fn snapshot_at(&self, id: &str, now: Instant) -> ProgressSnapshot {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some((at, bytes)) = state.last_sample {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            if elapsed > 0.0 {
+                let sample = state.bytes_done.saturating_sub(bytes) as f64 / elapsed;
+                state.speed = SPEED_SMOOTHING * sample + (1.0 - SPEED_SMOOTHING) * state.speed;
+            }
+        }
+        state.last_sample = Some((now, state.bytes_done));
+
+        let fraction = if state.durations_known && state.total_duration > 0.0 {
+            state.duration_done / state.total_duration
+        } else if state.total_segments > 0 {
+            state.segments_done as f64 / state.total_segments as f64
+        } else {
+            0.0
+        }
+        .min(1.0);
+
+        let total = if fraction > 0.0 {
+            ((state.bytes_done as f64 / fraction) as u64).max(state.bytes_done)
+        } else {
+            0
+        };
+
+        // Media seconds downloaded per wall-clock second; fall back to bytes when durations are unknown.
+        let elapsed = now.duration_since(state.session_started).as_secs_f64();
+        let eta = if state.durations_known && state.session_duration > 0.0 && elapsed > 0.0 {
+            let media_rate = state.session_duration / elapsed;
+            (state.total_duration - state.duration_done).max(0.0) / media_rate
+        } else if state.speed > 0.0 {
+            total.saturating_sub(state.bytes_done) as f64 / state.speed
+        } else {
+            0.0
+        };
+
+        ProgressSnapshot {
+            id: id.to_string(),
+            progress: fraction * 100.0,
+            speed: state.speed,
+            eta: eta.round() as u64,
+            downloaded: state.bytes_done,
+            total,
+            segments: state.segments_done,
+            total_segments: state.total_segments,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_estimates_from_segment_durations() {
+        let tracker = ProgressTracker::new();
+        tracker.add_track(4, &[0.0, 6.0, 6.0, 8.0]);
+        tracker.record_segment(1_000, 0.0);
+        tracker.record_segment(600_000, 6.0);
+
+        let started = tracker.state.lock().unwrap().session_started;
+        let snapshot = tracker.snapshot_at("dl", started + Duration::from_secs(3));
+
+        assert_eq!(snapshot.segments, 2);
+        assert_eq!(snapshot.total_segments, 4);
+        assert!((snapshot.progress - 30.0).abs() < 1e-9);
+        // 601 kB for 6 of 20 seconds extrapolates to ~2 MB.
+        assert_eq!(snapshot.total, 2_003_333);
+        // 6 media seconds took 3 s, so the remaining 14 take 7 s.
+        assert_eq!(snapshot.eta, 7);
+    }
+
+    #[test]
+    fn test_estimates_from_segment_count_when_durations_missing() {
+        let tracker = ProgressTracker::new();
+        tracker.add_track(10, &[]);
+        tracker.add_existing(4, 0.0, 400);
+        tracker.record_segment(100, 0.0);
+
+        let snapshot = tracker.snapshot("dl");
+        assert!((snapshot.progress - 50.0).abs() < 1e-9);
+        assert_eq!(snapshot.total, 1_000);
+        assert_eq!(snapshot.downloaded, 500);
+    }
+}
@@ -0,0 +1,163 @@
+//! MPEG-TS integrity checks for downloaded segments.
+//!
+//! A segment passes when it consists of whole 188-byte packets, every PID's continuity counter
+//! advances without gaps, and it carries a PAT plus the PMT the PAT points to, so that it can be
+//! decoded on its own.
+
+use super::mux::ts::{psi_section, TS_PACKET_SIZE};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+const NULL_PID: u16 = 0x1FFF;
+
+/// One problem found in a TS segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TsIssue {
+    /// The segment does not end on a packet boundary.
+    TruncatedPacket { trailing_bytes: usize },
+    /// A packet does not start with the 0x47 sync byte; nothing after it was checked.
+    LostSync { packet: usize },
+    ContinuityGap { pid: u16, expected: u8, found: u8 },
+    MissingPat,
+    MissingPmt,
+    /// Audio-only extraction found no audio stream.
+    NoAudio,
+    /// Audio-only extraction failed on the audio stream.
+    AudioUnreadable(String),
+}
+
+impl TsIssue {
+    /// A missing PAT or PMT, which is expected when an init section carries the tables.
+    pub fn is_missing_table(&self) -> bool {
+        matches!(self, Self::MissingPat | Self::MissingPmt)
+    }
+}
+
+impl fmt::Display for TsIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TruncatedPacket { trailing_bytes } => write!(f, "truncated packet ({} trailing bytes)", trailing_bytes),
+            Self::LostSync { packet } => write!(f, "lost sync at packet {}", packet),
+            Self::ContinuityGap { pid, expected, found } => {
+                write!(f, "continuity gap on PID {:#06x} (expected {}, found {})", pid, expected, found)
+            }
+            Self::MissingPat => write!(f, "no PAT"),
+            Self::MissingPmt => write!(f, "no PMT"),
+            Self::NoAudio => write!(f, "no audio stream"),
+            Self::AudioUnreadable(error) => write!(f, "audio could not be extracted: {}", error),
+        }
+    }
+}
+
+/// Checks one TS segment. Continuity is tracked per segment only, since segments are fetched
+/// independently and a counter may legitimately restart at a segment boundary.
+pub fn validate_ts(data: &[u8]) -> Vec<TsIssue> {
+    let mut issues = Vec::new();
+    let trailing_bytes = data.len() % TS_PACKET_SIZE;
+    if trailing_bytes != 0 {
+        issues.push(TsIssue::TruncatedPacket { trailing_bytes });
+    }
+
+    let mut counters: HashMap<u16, u8> = HashMap::new();
+    let mut pmt_pids: HashSet<u16> = HashSet::new();
+    let mut has_pat = false;
+    let mut has_pmt = false;
+
+    for (i, packet) in data.chunks_exact(TS_PACKET_SIZE).enumerate() {
+        if packet[0] != 0x47 {
+            issues.push(TsIssue::LostSync { packet: i });
+            break;
+        }
+        let pusi = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        if pid == NULL_PID {
+            continue;
+        }
+        let adaptation = (packet[3] >> 4) & 0x3;
+        let cc = packet[3] & 0x0F;
+        // The counter only advances on packets that carry payload.
+        if adaptation & 0x1 == 0 {
+            continue;
+        }
+
+        let has_adaptation = adaptation & 0x2 != 0;
+        let discontinuity = has_adaptation && packet[4] > 0 && packet[5] & 0x80 != 0;
+        if let Some(&previous) = counters.get(&pid) {
+            let expected = (previous + 1) & 0x0F;
+            // A single repeated counter marks a permitted duplicate packet.
+            if !discontinuity && cc != expected && cc != previous {
+                issues.push(TsIssue::ContinuityGap { pid, expected, found: cc });
+            }
+        }
+        counters.insert(pid, cc);
+
+        let offset = if has_adaptation { 5 + packet[4] as usize } else { 4 };
+        let Some(payload) = packet.get(offset..) else { continue };
+        if pid == 0 && pusi {
+            if let Some(section) = psi_section(payload) {
+                has_pat = true;
+                pmt_pids.extend(program_map_pids(section));
+            }
+        } else if pmt_pids.contains(&pid) {
+            has_pmt = true;
+        }
+    }
+
+    if !has_pat {
+        issues.push(TsIssue::MissingPat);
+    } else if !has_pmt {
+        issues.push(TsIssue::MissingPmt);
+    }
+    issues
+}
+
+/// PMT PIDs listed in a PAT section (program 0 is the network PID and is skipped).
+fn program_map_pids(section: &[u8]) -> Vec<u16> {
+    let end = section.len().saturating_sub(4);
+    section
+        .get(8..end)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .filter(|entry| u16::from_be_bytes([entry[0], entry[1]]) != 0)
+        .map(|entry| (((entry[2] & 0x1F) as u16) << 8) | entry[3] as u16)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::strategy::stream::mux::ts::tests::{packetize, pes_packet, psi_packets};
+
+    fn segment() -> Vec<u8> {
+        let mut data = psi_packets(&[(0x0F, 0x101)]);
+        let mut cc = 0;
+        data.extend(packetize(0x101, &pes_packet(0xC0, 0, None, &[0xAA; 600]), &mut cc));
+        data
+    }
+
+    #[test]
+    fn test_valid_segment_passes() {
+        assert_eq!(validate_ts(&segment()), vec![]);
+    }
+
+    #[test]
+    fn test_detects_truncation_gaps_and_missing_tables() {
+        let mut data = segment();
+        // Drop the second audio packet: the counter jumps from 0 to 2.
+        data.drain(3 * TS_PACKET_SIZE..4 * TS_PACKET_SIZE);
+        data.truncate(data.len() - 10);
+        let issues = validate_ts(&data);
+        assert_eq!(
+            issues,
+            vec![
+                TsIssue::TruncatedPacket { trailing_bytes: TS_PACKET_SIZE - 10 },
+                TsIssue::ContinuityGap { pid: 0x101, expected: 1, found: 2 },
+            ]
+        );
+
+        let without_pmt: Vec<u8> = segment().drain(..TS_PACKET_SIZE).chain(segment().drain(2 * TS_PACKET_SIZE..)).collect();
+        assert_eq!(validate_ts(&without_pmt), vec![TsIssue::MissingPmt]);
+        assert_eq!(validate_ts(&segment()[2 * TS_PACKET_SIZE..]), vec![TsIssue::MissingPat]);
+        assert!(TsIssue::MissingPat.is_missing_table() && !issues[1].is_missing_table());
+    }
+}
@@ -0,0 +1,172 @@
+//! Vimeo resolution.
+//!
+//! `player.vimeo.com/video/<id>/config` describes every way the player can stream a video:
+//! HLS and DASH manifests on a few CDNs, and for some videos progressive MP4 files. HLS is
+//! preferred (its H.264-only `avc_url` variant first, since the muxer reads H.264), then DASH,
+//! then the tallest fitting progressive file. Unlisted videos need the hash from their link.
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::platform::{best_progressive, PlatformSource};
+use super::resolver::{fetch_manifest, ResolvedStream, ResolverProbe, StreamResolver};
+use reqwest::Client;
+use serde_json::Value;
+use tracing::info;
+use url::Url;
+
+pub struct VimeoResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for VimeoResolver {
+    fn name(&self) -> &'static str {
+        "vimeo"
+    }
+
+    fn matches(&self, url: &str, _probe: &ResolverProbe) -> bool {
+        video_ref(url).is_some()
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn is_platform(&self) -> bool {
+        true
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        let (id, hash) = video_ref(url).ok_or_else(|| DownloadError::Config(format!("Not a Vimeo video link: {}", url)))?;
+        let mut config_url = format!("https://player.vimeo.com/video/{}/config", id);
+        if let Some(hash) = hash {
+            config_url = format!("{}?h={}", config_url, hash);
+        }
+        info!("Vimeo Resolver: Fetching player config for video {}", id);
+
+        // Embed-only videos check the Referer; fall back to the link itself if none was given.
+        let mut headers = headers.clone();
+        if !headers.contains_key(reqwest::header::REFERER) {
+            if let Ok(value) = reqwest::header::HeaderValue::from_str(url) {
+                headers.insert(reqwest::header::REFERER, value);
+            }
+        }
+
+        let config: Value = serde_json::from_str(&fetch_manifest(&config_url, client, &headers).await?)
+            .map_err(|e| DownloadError::Parse(format!("Invalid Vimeo player config: {}", e)))?;
+        let (source, duration) = parse_config(&config, options.max_height)?;
+        info!("Vimeo Resolver: Using {:?}", source);
+        source.resolve(client, &headers, options, duration).await
+    }
+}
+
+/// Video id and unlisted hash of a `vimeo.com` or `player.vimeo.com` link.
+pub fn video_ref(url: &str) -> Option<(String, Option<String>)> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.");
+    if host != "vimeo.com" && host != "player.vimeo.com" {
+        return None;
+    }
+    let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let position = segments.iter().position(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))?;
+    let id = segments[position].to_string();
+    let hash = url
+        .query_pairs()
+        .find(|(k, _)| k == "h")
+        .map(|(_, v)| v.into_owned())
+        .or_else(|| segments.get(position + 1).filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit())).map(|s| s.to_string()));
+    Some((id, hash))
+}
+
+/// Picks a source from a player config; also returns the video duration in seconds.
+pub fn parse_config(config: &Value, max_height: Option<u32>) -> Result<(PlatformSource, Option<f64>), DownloadError> {
+    if let Some(message) = config.get("message").and_then(Value::as_str) {
+        return Err(DownloadError::Config(format!("Vimeo refused the video: {}", message)));
+    }
+    let files = config
+        .pointer("/request/files")
+        .ok_or_else(|| DownloadError::Parse("Vimeo player config lists no files".to_string()))?;
+    let duration = config.pointer("/video/duration").and_then(Value::as_f64);
+
+    if let Some(url) = manifest_url(files, "hls") {
+        return Ok((PlatformSource::Hls(url), duration));
+    }
+    if let Some(url) = manifest_url(files, "dash") {
+        // The config links Vimeo's JSON playlist; the same path serves a regular MPD.
+        let url = url.replacen("/master.json", "/master.mpd", 1).replacen("/playlist.json", "/playlist.mpd", 1);
+        return Ok((PlatformSource::Dash(url), duration));
+    }
+
+    let progressive = files
+        .get("progressive")
+        .and_then(Value::as_array)
+        .map(|files| {
+            files
+                .iter()
+                .filter_map(|f| Some((f.get("url")?.as_str()?.to_string(), f.get("height").and_then(Value::as_u64).map(|h| h as u32))))
+                .collect()
+        })
+        .unwrap_or_default();
+    best_progressive(progressive, max_height)
+        .map(|source| (source, duration))
+        .ok_or_else(|| DownloadError::Config("Vimeo offers no downloadable stream for this video".to_string()))
+}
+
+/// Manifest URL of `kind` (`hls`/`dash`) on the default CDN, or any CDN.
+fn manifest_url(files: &Value, kind: &str) -> Option<String> {
+    let section = files.get(kind)?;
+    let cdns = section.get("cdns")?.as_object()?;
+    let default = section.get("default_cdn").and_then(Value::as_str).and_then(|name| cdns.get(name));
+    default
+        .into_iter()
+        .chain(cdns.values())
+        .find_map(|cdn| cdn.get("avc_url").or_else(|| cdn.get("url")).and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Value {
+        serde_json::from_str(include_str!("fixtures/vimeo_config.json")).unwrap()
+    }
+
+    #[test]
+    fn test_video_ref() {
+        assert_eq!(video_ref("https://vimeo.com/76979871"), Some(("76979871".to_string(), None)));
+        assert_eq!(video_ref("https://vimeo.com/channels/staffpicks/76979871"), Some(("76979871".to_string(), None)));
+        assert_eq!(video_ref("https://vimeo.com/76979871/8c953e4f1a"), Some(("76979871".to_string(), Some("8c953e4f1a".to_string()))));
+        assert_eq!(
+            video_ref("https://player.vimeo.com/video/76979871?h=8c953e4f1a&autoplay=1"),
+            Some(("76979871".to_string(), Some("8c953e4f1a".to_string())))
+        );
+        assert_eq!(video_ref("https://vimeo.com/about"), None);
+        assert_eq!(video_ref("https://example.com/76979871"), None);
+    }
+
+    #[test]
+    fn test_parse_config_prefers_hls_then_dash_then_progressive() {
+        let mut config = config();
+        let (source, duration) = parse_config(&config, None).unwrap();
+        assert_eq!(duration, Some(62.0));
+        match source {
+            PlatformSource::Hls(url) => assert!(url.starts_with("https://skyfire.vimeocdn.com/") && url.contains("omit=av1-hevc-opus")),
+            other => panic!("expected HLS, got {:?}", other),
+        }
+
+        config["request"]["files"].as_object_mut().unwrap().remove("hls");
+        match parse_config(&config, None).unwrap().0 {
+            PlatformSource::Dash(url) => assert!(url.contains("/primary/playlist.mpd?")),
+            other => panic!("expected DASH, got {:?}", other),
+        }
+
+        config["request"]["files"].as_object_mut().unwrap().remove("dash");
+        assert!(matches!(parse_config(&config, Some(720)).unwrap().0, PlatformSource::Progressive { height: Some(720), .. }));
+        assert!(matches!(parse_config(&config, None).unwrap().0, PlatformSource::Progressive { height: Some(1080), .. }));
+    }
+}
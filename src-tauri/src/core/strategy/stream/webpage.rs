@@ -0,0 +1,252 @@
+//! Media discovery on ordinary web pages.
+//!
+//! Users often paste the page a video is on rather than the media URL itself. The page is
+//! scanned for `<video>`/`<audio>`/`<source>` tags, Open Graph video tags, JSON-LD
+//! `contentUrl`/`embedUrl`, manifest URLs inside inline scripts and `<iframe>` embeds. The
+//! candidates are offered to the user, and the chosen one goes through the normal strategies.
+
+use crate::core::error::DownloadError;
+use super::resolver::fetch_manifest;
+use regex::Regex;
+use reqwest::Client;
+use serde::Serialize;
+use std::sync::OnceLock;
+use url::Url;
+
+/// What a candidate URL points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateKind {
+    Video,
+    Audio,
+    Hls,
+    Dash,
+    /// Another page (usually a player on a video platform) embedded in this one.
+    Embed,
+}
+
+/// Where on the page a candidate was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CandidateSource {
+    MediaTag,
+    OpenGraph,
+    JsonLd,
+    Script,
+    Iframe,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaCandidate {
+    pub url: String,
+    pub kind: CandidateKind,
+    pub source: CandidateSource,
+    pub mime_type: Option<String>,
+    /// Title or quality label, when the page gives one.
+    pub label: Option<String>,
+}
+
+/// Fetches `url` and lists the media it references.
+pub async fn fetch_candidates(url: &str, client: &Client, headers: &reqwest::header::HeaderMap) -> Result<Vec<MediaCandidate>, DownloadError> {
+    let page_url = Url::parse(url).map_err(|e| DownloadError::Config(format!("Invalid page URL: {}", e)))?;
+    let html = fetch_manifest(url, client, headers).await?;
+    Ok(extract_candidates(&html, &page_url))
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid pattern"))
+}
+
+/// Lists the media referenced by `html`, in page order and without duplicates.
+pub fn extract_candidates(html: &str, page_url: &Url) -> Vec<MediaCandidate> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static JSON_LD: OnceLock<Regex> = OnceLock::new();
+    static SCRIPT: OnceLock<Regex> = OnceLock::new();
+    static MANIFEST_URL: OnceLock<Regex> = OnceLock::new();
+
+    let mut found = Candidates { page_url, list: Vec::new() };
+    let title = meta_content(html, &["og:title", "twitter:title"]);
+
+    // `<source>` tags belong to the `<video>` or `<audio>` element opened last.
+    let mut parent = CandidateKind::Video;
+    for tag in regex(&TAG, r"(?is)<(video|audio|source|iframe|meta)\b([^>]*)>").captures_iter(html) {
+        let attrs = attributes(&tag[2]);
+        let attr = |name: &str| attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+        match tag[1].to_ascii_lowercase().as_str() {
+            "video" | "audio" => {
+                parent = if tag[1].eq_ignore_ascii_case("audio") { CandidateKind::Audio } else { CandidateKind::Video };
+                if let Some(src) = attr("src") {
+                    found.add(&src, parent, CandidateSource::MediaTag, None, attr("title").or_else(|| title.clone()));
+                }
+            }
+            "source" => {
+                if let Some(src) = attr("src") {
+                    let label = attr("label").or_else(|| attr("size").map(|s| format!("{}p", s))).or_else(|| title.clone());
+                    found.add(&src, parent, CandidateSource::MediaTag, attr("type"), label);
+                }
+            }
+            "iframe" => {
+                if let Some(src) = attr("src").or_else(|| attr("data-src")) {
+                    found.add(&src, CandidateKind::Embed, CandidateSource::Iframe, None, attr("title"));
+                }
+            }
+            _ => {
+                let property = attr("property").or_else(|| attr("name")).unwrap_or_default().to_ascii_lowercase();
+                if matches!(property.as_str(), "og:video" | "og:video:url" | "og:video:secure_url" | "twitter:player:stream") {
+                    if let Some(content) = attr("content") {
+                        found.add(&content, CandidateKind::Video, CandidateSource::OpenGraph, None, title.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let json_ld = regex(&JSON_LD, r#"(?is)<script[^>]*type\s*=\s*["']application/ld\+json["'][^>]*>(.*?)</script>"#);
+    for block in json_ld.captures_iter(html) {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(block[1].trim()) {
+            collect_json_ld(&value, &mut found);
+        }
+    }
+
+    let manifest_url = regex(&MANIFEST_URL, r#"https?:(?:\\?/){2}[^\s"'<>]*?\.(?:m3u8|mpd)(?:[?#][^\s"'<>]*)?"#);
+    for script in regex(&SCRIPT, r"(?is)<script\b[^>]*>(.*?)</script>").captures_iter(html) {
+        for url in manifest_url.find_iter(&script[1]) {
+            let url = url.as_str().replace("\\/", "/").replace("\\u0026", "&");
+            found.add(url.trim_end_matches('\\'), CandidateKind::Video, CandidateSource::Script, None, title.clone());
+        }
+    }
+
+    found.list
+}
+
+struct Candidates<'a> {
+    page_url: &'a Url,
+    list: Vec<MediaCandidate>,
+}
+
+impl Candidates<'_> {
+    fn add(&mut self, raw: &str, kind: CandidateKind, source: CandidateSource, mime_type: Option<String>, label: Option<String>) {
+        let raw = decode_entities(raw.trim());
+        let Ok(url) = self.page_url.join(&raw) else { return };
+        if !matches!(url.scheme(), "http" | "https") || self.list.iter().any(|c| c.url == url.as_str()) {
+            return;
+        }
+        let kind = refine_kind(kind, url.as_str(), mime_type.as_deref());
+        self.list.push(MediaCandidate { url: url.to_string(), kind, source, mime_type, label });
+    }
+}
+
+/// Manifest URLs are reported as HLS/DASH whatever element they were found in.
+fn refine_kind(kind: CandidateKind, url: &str, mime_type: Option<&str>) -> CandidateKind {
+    let path = url.split(['?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+    let mime = mime_type.unwrap_or_default().to_ascii_lowercase();
+    if kind == CandidateKind::Embed {
+        kind
+    } else if path.ends_with(".m3u8") || mime.contains("mpegurl") {
+        CandidateKind::Hls
+    } else if path.ends_with(".mpd") || mime.contains("dash+xml") {
+        CandidateKind::Dash
+    } else if mime.starts_with("audio/") {
+        CandidateKind::Audio
+    } else {
+        kind
+    }
+}
+
+/// Walks a JSON-LD document for `VideoObject`/`AudioObject` URLs.
+fn collect_json_ld(value: &serde_json::Value, found: &mut Candidates) {
+    match value {
+        serde_json::Value::Array(items) => items.iter().for_each(|item| collect_json_ld(item, found)),
+        serde_json::Value::Object(object) => {
+            let label = object.get("name").and_then(|n| n.as_str()).map(str::to_string);
+            let is_audio = object.get("@type").and_then(|t| t.as_str()) == Some("AudioObject");
+            let mime_type = object.get("encodingFormat").and_then(|f| f.as_str()).map(str::to_string);
+            if let Some(url) = object.get("contentUrl").and_then(|u| u.as_str()) {
+                let kind = if is_audio { CandidateKind::Audio } else { CandidateKind::Video };
+                found.add(url, kind, CandidateSource::JsonLd, mime_type, label.clone());
+            }
+            if let Some(url) = object.get("embedUrl").and_then(|u| u.as_str()) {
+                found.add(url, CandidateKind::Embed, CandidateSource::JsonLd, None, label);
+            }
+            object.values().filter(|v| v.is_object() || v.is_array()).for_each(|v| collect_json_ld(v, found));
+        }
+        _ => {}
+    }
+}
+
+/// `content` of the first `<meta>` whose `property` or `name` is one of `names`.
+fn meta_content(html: &str, names: &[&str]) -> Option<String> {
+    static META: OnceLock<Regex> = OnceLock::new();
+    regex(&META, r"(?is)<meta\b([^>]*)>").captures_iter(html).find_map(|tag| {
+        let attrs = attributes(&tag[1]);
+        let attr = |name: &str| attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+        let key = attr("property").or_else(|| attr("name"))?.to_ascii_lowercase();
+        names.contains(&key.as_str()).then(|| attr("content")).flatten().map(|c| decode_entities(&c))
+    })
+}
+
+/// `(lowercase name, raw value)` of each attribute in the inside of a tag.
+fn attributes(tag: &str) -> Vec<(String, String)> {
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    regex(&ATTRIBUTE, r#"(?s)([a-zA-Z_:][-a-zA-Z0-9_:.]*)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+))"#)
+        .captures_iter(tag)
+        .map(|c| {
+            let value = c.get(2).or_else(|| c.get(3)).or_else(|| c.get(4)).map_or("", |m| m.as_str());
+            (c[1].to_ascii_lowercase(), value.to_string())
+        })
+        .collect()
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!doctype html><html><head>
+<meta property="og:title" content="Launch &amp; Landing">
+<meta property="og:video:secure_url" content="https://cdn.example.com/og/launch.mp4">
+<script type="application/ld+json">{"@context":"https://schema.org","@type":"VideoObject","name":"Launch","contentUrl":"/media/launch-1080.mp4","embedUrl":"https://player.example.com/embed/42"}</script>
+</head><body>
+<video controls poster="/poster.jpg">
+  <source src="/media/launch-720.mp4?sig=a&amp;exp=1" type="video/mp4" size="720">
+  <source src='https://cdn.example.com/hls/launch/master.m3u8' type='application/x-mpegURL'>
+</video>
+<audio src="podcast.mp3"></audio>
+<iframe title="Interview" src="https://www.youtube.com/embed/dQw4w9WgXcQ"></iframe>
+<script>var player = {"dash":"https:\/\/cdn.example.com\/dash\/launch.mpd?token=x&t=1","hls":"https://cdn.example.com/hls/launch/master.m3u8"};</script>
+</body></html>"#;
+
+    #[test]
+    fn test_extract_candidates() {
+        let page_url = Url::parse("https://news.example.com/videos/launch").unwrap();
+        let candidates = extract_candidates(PAGE, &page_url);
+        let summary: Vec<(&str, CandidateKind, CandidateSource)> =
+            candidates.iter().map(|c| (c.url.as_str(), c.kind, c.source)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("https://cdn.example.com/og/launch.mp4", CandidateKind::Video, CandidateSource::OpenGraph),
+                ("https://news.example.com/media/launch-720.mp4?sig=a&exp=1", CandidateKind::Video, CandidateSource::MediaTag),
+                ("https://cdn.example.com/hls/launch/master.m3u8", CandidateKind::Hls, CandidateSource::MediaTag),
+                ("https://news.example.com/videos/podcast.mp3", CandidateKind::Audio, CandidateSource::MediaTag),
+                ("https://www.youtube.com/embed/dQw4w9WgXcQ", CandidateKind::Embed, CandidateSource::Iframe),
+                ("https://news.example.com/media/launch-1080.mp4", CandidateKind::Video, CandidateSource::JsonLd),
+                ("https://player.example.com/embed/42", CandidateKind::Embed, CandidateSource::JsonLd),
+                ("https://cdn.example.com/dash/launch.mpd?token=x&t=1", CandidateKind::Dash, CandidateSource::Script),
+            ]
+        );
+        assert_eq!(candidates[0].label.as_deref(), Some("Launch & Landing"));
+        assert_eq!(candidates[1].label.as_deref(), Some("720p"));
+        assert_eq!(candidates[4].label.as_deref(), Some("Interview"));
+    }
+}
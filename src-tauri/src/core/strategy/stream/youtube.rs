@@ -1,26 +0,0 @@
-use crate::core::error::DownloadError;
-use super::resolver::StreamResolver;
-use reqwest::Client;
-use tracing::info;
-
-pub struct YoutubeResolver;
-
-#[async_trait::async_trait]
-impl StreamResolver for YoutubeResolver {
-    async fn resolve(
-        &self,
-        url: &str,
-        _client: &Client,
-        _headers: &reqwest::header::HeaderMap,
-    ) -> Result<Vec<String>, DownloadError> {
-        info!("YouTube Resolver: Extracting streams from {}", url);
-        
-        // This is a placeholder for real YouTube extraction logic.
-        // YouTube requires complex signature deciphering and DASH manifest parsing.
-        // For now, we return a clear error indicating this module is a stub.
-        
-        Err(DownloadError::Config(
-            "YouTube resolution is not yet implemented. Please provide a direct .m3u8 link.".to_string()
-        ))
-    }
-}
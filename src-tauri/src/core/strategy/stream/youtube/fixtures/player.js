@@ -0,0 +1,14 @@
+var _yt_player={};(function(g){var window=this;'use strict';
+var Xq="split;reverse;join;length;splice;unshift;push".split(";");
+var Kv=function(a){return a&&typeof a==="object"?"{}":"[]"};
+var Vz={
+Rv:function(a){a.reverse()},
+Sp:function(a,b){a.splice(0,b)},
+Sw:function(a,b){var c=a[0];a[0]=a[b%a.length];a[b%a.length]=c}};
+Kx=function(a){a=a.split("");Vz.Sw(a,3);Vz.Rv(a,51);Vz.Sp(a,2);Vz.Sw(a,17);return a.join("")};
+var Qn=function(a){var b=a[Xq[0]](""),c=[function(d,e){e=(e%d[Xq[3]]+d[Xq[3]])%d[Xq[3]];d[Xq[4]](-e).reverse().forEach(function(f){d[Xq[5]](f)})},-1234,function(d){d[Xq[1]]()},"}{\"/,/'",function(d,e){e=(e%d.length+d.length)%d.length;var f=d[0];d[0]=d[e];d[e]=f},/[}{]+/g,function(d,e){for(e=(e%d.length+d.length)%d.length;e--;)d[Xq[6]](d.shift())}];if(typeof Xq==="undefined")return a;c[0](b,c[1]);c[2](b);c[4](b,7);c[6](b,-3);c[4](b,c[3].length);return b[Xq[2]]("")};
+var Pn=[Qn];
+g.Rl=function(a){var b;return(b=a.get("n"))&&(b=Pn[0](b),a.set("n",b),Pn.length||Qn(""))};
+g.Ul=function(a,b,c){c&&(c=Kx(decodeURIComponent(c)),a.set(b,encodeURIComponent(c)))};
+g.pc={signatureTimestamp:20012,experiments:Kv(null)};
+})(_yt_player);
@@ -0,0 +1,238 @@
+//! `streamingData` of a player response and the choice of formats to download.
+
+use crate::core::error::DownloadError;
+use crate::core::strategy::stream::segment::ByteRange;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawRange {
+    start: String,
+    end: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawAudioTrack {
+    #[serde(default)]
+    audio_is_default: bool,
+}
+
+/// A format entry as it appears in the JSON, where 64-bit numbers are strings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RawFormat {
+    itag: u32,
+    url: Option<String>,
+    signature_cipher: Option<String>,
+    mime_type: String,
+    bitrate: u64,
+    height: Option<u32>,
+    fps: Option<u32>,
+    content_length: Option<String>,
+    approx_duration_ms: Option<String>,
+    init_range: Option<RawRange>,
+    index_range: Option<RawRange>,
+    audio_track: Option<RawAudioTrack>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RawStreamingData {
+    formats: Vec<RawFormat>,
+    adaptive_formats: Vec<RawFormat>,
+    hls_manifest_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct RawPlayability {
+    status: String,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct RawPlayerResponse {
+    playability_status: RawPlayability,
+    streaming_data: Option<RawStreamingData>,
+}
+
+/// How a format's URL is obtained.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatUrl {
+    Plain(String),
+    /// `url` only works once the scrambled signature `s` is deciphered and added as `sp`.
+    Ciphered { url: String, s: String, sp: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Format {
+    pub itag: u32,
+    pub url: FormatUrl,
+    pub mime_type: String,
+    pub bitrate: u64,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub content_length: Option<u64>,
+    pub duration: Option<f64>,
+    /// Byte range of the `ftyp` + `moov` header of an adaptive format.
+    pub init_range: Option<ByteRange>,
+    /// Byte range of the `sidx` index of an adaptive format.
+    pub index_range: Option<ByteRange>,
+    /// `false` for dubbed or described alternatives of the original audio.
+    pub default_audio: bool,
+}
+
+impl Format {
+    fn from_raw(raw: RawFormat) -> Option<Self> {
+        let url = match (raw.url, raw.signature_cipher) {
+            (Some(url), _) => FormatUrl::Plain(url),
+            (None, Some(cipher)) => {
+                let field = |key: &str| {
+                    url::form_urlencoded::parse(cipher.as_bytes()).find(|(k, _)| k == key).map(|(_, v)| v.into_owned())
+                };
+                FormatUrl::Ciphered {
+                    url: field("url")?,
+                    s: field("s")?,
+                    sp: field("sp").unwrap_or_else(|| "signature".to_string()),
+                }
+            }
+            (None, None) => return None,
+        };
+        let range = |range: Option<RawRange>| {
+            let range = range?;
+            let (start, end): (u64, u64) = (range.start.parse().ok()?, range.end.parse().ok()?);
+            (end >= start).then(|| ByteRange { offset: start, length: end - start + 1 })
+        };
+        Some(Self {
+            itag: raw.itag,
+            url,
+            mime_type: raw.mime_type,
+            bitrate: raw.bitrate,
+            height: raw.height,
+            fps: raw.fps,
+            content_length: raw.content_length.and_then(|l| l.parse().ok()),
+            duration: raw.approx_duration_ms.and_then(|d| d.parse::<f64>().ok()).map(|ms| ms / 1000.0),
+            init_range: range(raw.init_range),
+            index_range: range(raw.index_range),
+            default_audio: raw.audio_track.is_none_or(|t| t.audio_is_default),
+        })
+    }
+
+    pub fn is_video(&self) -> bool {
+        self.mime_type.starts_with("video/")
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.mime_type.starts_with("audio/")
+    }
+
+    /// Whether the native muxer can read this format (MP4 with H.264/H.265/AAC).
+    pub fn is_muxable(&self) -> bool {
+        let codecs = self.mime_type.split("codecs=").nth(1).unwrap_or_default().trim_matches('"');
+        self.mime_type.contains("/mp4")
+            && !codecs.is_empty()
+            && codecs
+                .split(',')
+                .map(str::trim)
+                .all(|c| ["avc1", "avc3", "hev1", "hvc1", "mp4a"].iter().any(|p| c.starts_with(p)))
+    }
+}
+
+/// What the player response offers for a video.
+#[derive(Debug, Clone, Default)]
+pub struct StreamingData {
+    /// Progressive formats with audio and video in one file.
+    pub formats: Vec<Format>,
+    /// Video-only and audio-only formats.
+    pub adaptive_formats: Vec<Format>,
+    /// Set for live streams and premieres, which are served as HLS.
+    pub hls_manifest_url: Option<String>,
+}
+
+impl StreamingData {
+    /// Streaming data of a player response, or why the video cannot be played.
+    pub fn from_player_response(response: &serde_json::Value) -> Result<Self, DownloadError> {
+        let raw = RawPlayerResponse::deserialize(response)
+            .map_err(|e| DownloadError::Parse(format!("Unexpected player response: {}", e)))?;
+        if raw.playability_status.status != "OK" {
+            return Err(DownloadError::Config(format!(
+                "YouTube video is not playable ({}): {}",
+                raw.playability_status.status,
+                raw.playability_status.reason.unwrap_or_else(|| "no reason given".to_string())
+            )));
+        }
+        let data = raw
+            .streaming_data
+            .ok_or_else(|| DownloadError::Parse("Player response has no streaming data".to_string()))?;
+        Ok(Self {
+            formats: data.formats.into_iter().filter_map(Format::from_raw).collect(),
+            adaptive_formats: data.adaptive_formats.into_iter().filter_map(Format::from_raw).collect(),
+            hls_manifest_url: data.hls_manifest_url,
+        })
+    }
+
+    /// Best muxable video no taller than `max_height` (or the smallest one if none is).
+    pub fn best_video(&self, max_height: Option<u32>) -> Option<&Format> {
+        let candidates: Vec<&Format> = self.adaptive_formats.iter().filter(|f| f.is_video() && f.is_muxable()).collect();
+        let key = |f: &Format| (f.height.unwrap_or(0), f.fps.unwrap_or(0), f.bitrate);
+        let fitting = candidates.iter().filter(|f| max_height.is_none_or(|max| f.height.unwrap_or(0) <= max));
+        fitting.max_by_key(|f| key(f)).or_else(|| candidates.iter().min_by_key(|f| key(f))).copied()
+    }
+
+    /// Highest-bitrate muxable audio, preferring the original track over dubs.
+    pub fn best_audio(&self) -> Option<&Format> {
+        self.adaptive_formats
+            .iter()
+            .filter(|f| f.is_audio() && f.is_muxable())
+            .max_by_key(|f| (f.default_audio, f.bitrate))
+    }
+
+    /// Best progressive format, used when no adaptive pair can be muxed.
+    pub fn best_progressive(&self, max_height: Option<u32>) -> Option<&Format> {
+        let key = |f: &&Format| (f.height.unwrap_or(0), f.bitrate);
+        let fitting = self.formats.iter().filter(|f| max_height.is_none_or(|max| f.height.unwrap_or(0) <= max));
+        fitting.max_by_key(key).or_else(|| self.formats.iter().min_by_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::strategy::stream::youtube::page;
+
+    #[test]
+    fn test_select_formats_from_watch_page() {
+        let response = page::initial_player_response(include_str!("fixtures/watch.html")).unwrap().unwrap();
+        let data = StreamingData::from_player_response(&response).unwrap();
+        assert_eq!(data.formats.len(), 1);
+        assert_eq!(data.adaptive_formats.len(), 7);
+
+        // VP9 and AV1 are skipped because the muxer can't read them.
+        let video = data.best_video(None).unwrap();
+        assert_eq!(video.itag, 137);
+        assert_eq!(video.init_range, Some(ByteRange { offset: 0, length: 742 }));
+        assert_eq!(video.index_range, Some(ByteRange { offset: 742, length: 499 }));
+        match &video.url {
+            FormatUrl::Ciphered { url, s, sp } => {
+                assert!(url.contains("itag=137"));
+                assert_eq!(s.len(), 79);
+                assert_eq!(sp, "sig");
+            }
+            other => panic!("expected a ciphered URL, got {:?}", other),
+        }
+        assert_eq!(data.best_video(Some(720)).unwrap().itag, 136);
+        assert_eq!(data.best_video(Some(240)).unwrap().itag, 136);
+        assert_eq!(data.best_audio().unwrap().itag, 140);
+        assert_eq!(data.best_progressive(None).unwrap().itag, 18);
+        assert_eq!(video.duration, Some(212.061));
+    }
+
+    #[test]
+    fn test_unplayable_video_reports_reason() {
+        let response: serde_json::Value = serde_json::from_str(include_str!("fixtures/age_restricted.json")).unwrap();
+        let error = StreamingData::from_player_response(&response).unwrap_err();
+        assert!(error.to_string().contains("Sign in to confirm your age"));
+    }
+}
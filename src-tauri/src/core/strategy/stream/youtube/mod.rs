@@ -0,0 +1,267 @@
+//! YouTube resolution.
+//!
+//! The watch page embeds the player response (`ytInitialPlayerResponse`) with the video's
+//! formats and links the player script needed to unlock their URLs; when the page carries no
+//! streaming data the player API is asked instead. The best muxable video and audio formats
+//! become separate tracks, split into fragments along each file's `sidx` index so that they
+//! download in parallel and can be clipped and resumed like DASH. Live streams are handed to
+//! the HLS resolver.
+
+pub mod formats;
+pub mod page;
+pub mod player;
+
+use crate::core::error::DownloadError;
+use crate::core::state::StreamOptions;
+use super::mux::fmp4::{find_box, parse_sidx};
+use super::resolver::{fetch_manifest, fetch_manifest_bytes, HlsResolver, ResolvedStream, ResolverProbe, StreamResolver, StreamTrack, TrackKind};
+use super::segment::{ByteRange, InitSection, Segment};
+use formats::{Format, FormatUrl, StreamingData};
+use player::PlayerScript;
+use reqwest::Client;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use url::Url;
+
+const PLAYER_API_URL: &str = "https://www.youtube.com/youtubei/v1/player?prettyPrint=false";
+const DEFAULT_CLIENT_VERSION: &str = "2.20241017.01.00";
+/// Files without a usable index are fetched in ranges of this size; larger requests get throttled.
+const CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+
+pub struct YoutubeResolver;
+
+#[async_trait::async_trait]
+impl StreamResolver for YoutubeResolver {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn matches(&self, url: &str, _probe: &ResolverProbe) -> bool {
+        url.contains("youtube.com") || url.contains("youtu.be")
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn is_platform(&self) -> bool {
+        true
+    }
+
+    async fn resolve(
+        &self,
+        url: &str,
+        client: &Client,
+        headers: &reqwest::header::HeaderMap,
+        options: &StreamOptions,
+    ) -> Result<ResolvedStream, DownloadError> {
+        let id = page::video_id(url).ok_or_else(|| DownloadError::Config(format!("Not a YouTube video link: {}", url)))?;
+        info!("YouTube Resolver: Extracting streams for video {}", id);
+
+        let watch_url = format!("https://www.youtube.com/watch?v={}&hl=en&has_verified=1", id);
+        let page = fetch_manifest(&watch_url, client, headers).await?;
+        let player = match page::player_url(&page) {
+            Some(player_url) => {
+                debug!("YouTube Resolver: Fetching player script {}", player_url);
+                PlayerScript::parse(&fetch_manifest(&player_url, client, headers).await?)
+            }
+            None => {
+                warn!("YouTube Resolver: Watch page links no player script; protected formats will fail");
+                PlayerScript::default()
+            }
+        };
+
+        let response = match page::initial_player_response(&page)? {
+            Some(response) if response.get("streamingData").is_some() => response,
+            _ => {
+                debug!("YouTube Resolver: Watch page has no streaming data, asking the player API");
+                request_player_api(&id, &page, &player, client, headers).await?
+            }
+        };
+        let data = StreamingData::from_player_response(&response)?;
+
+        if let Some(hls_url) = &data.hls_manifest_url {
+            info!("YouTube Resolver: Live stream, following its HLS manifest");
+            return HlsResolver.resolve(hls_url, client, headers, options).await;
+        }
+
+        let tracks = match (data.best_video(options.max_height), data.best_audio()) {
+            (Some(video), Some(audio)) => {
+                info!("YouTube Resolver: Video itag {} ({:?}p), audio itag {}", video.itag, video.height, audio.itag);
+                vec![
+                    adaptive_track(TrackKind::Video, video, &player, client, headers).await?,
+                    adaptive_track(TrackKind::Audio, audio, &player, client, headers).await?,
+                ]
+            }
+            _ => {
+                let format = data
+                    .best_progressive(options.max_height)
+                    .ok_or_else(|| DownloadError::Config("YouTube offers no format that can be downloaded".to_string()))?;
+                info!("YouTube Resolver: No muxable adaptive pair, using progressive itag {}", format.itag);
+                let url = format_url(format, &player)?;
+                vec![StreamTrack::new(TrackKind::Muxed, chunked_segments(format, &url), None)]
+            }
+        };
+        Ok(ResolvedStream { tracks, live_playlist: None })
+    }
+}
+
+async fn request_player_api(
+    id: &str,
+    page: &str,
+    player: &PlayerScript,
+    client: &Client,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<serde_json::Value, DownloadError> {
+    let body = serde_json::json!({
+        "context": {
+            "client": {
+                "clientName": "WEB",
+                "clientVersion": page::client_version(page).unwrap_or_else(|| DEFAULT_CLIENT_VERSION.to_string()),
+                "hl": "en",
+            }
+        },
+        "videoId": id,
+        "playbackContext": { "contentPlaybackContext": { "signatureTimestamp": player.signature_timestamp() } },
+        "contentCheckOk": true,
+        "racyCheckOk": true,
+    });
+    let response = client
+        .post(PLAYER_API_URL)
+        .headers(headers.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| DownloadError::Network(format!("Player API request failed: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(DownloadError::Network(format!("Player API returned error: {}", response.status())));
+    }
+    let bytes = response.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| DownloadError::Parse(format!("Invalid player API response: {}", e)))
+}
+
+/// The playable URL of `format`: signature deciphered and `n` transformed.
+fn format_url(format: &Format, player: &PlayerScript) -> Result<String, DownloadError> {
+    let parse = |url: &str| Url::parse(url).map_err(|e| DownloadError::Parse(format!("Invalid format URL: {}", e)));
+    let mut url = match &format.url {
+        FormatUrl::Plain(url) => parse(url)?,
+        FormatUrl::Ciphered { url, s, sp } => {
+            let mut url = parse(url)?;
+            let signature = player.decipher(s)?;
+            url.query_pairs_mut().append_pair(sp, &signature);
+            url
+        }
+    };
+
+    let n = url.query_pairs().find(|(k, _)| k == "n").map(|(_, v)| v.into_owned());
+    if let Some(n) = n {
+        match player.transform_n(&n) {
+            Ok(transformed) => {
+                let pairs: Vec<(String, String)> = url
+                    .query_pairs()
+                    .map(|(k, v)| {
+                        let v = if k == "n" { transformed.clone() } else { v.into_owned() };
+                        (k.into_owned(), v)
+                    })
+                    .collect();
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+            Err(e) => warn!("YouTube Resolver: {}; itag {} will download slowly", e, format.itag),
+        }
+    }
+    Ok(url.to_string())
+}
+
+/// One track of an adaptive format, with a segment per `sidx` reference when the file has one.
+async fn adaptive_track(
+    kind: TrackKind,
+    format: &Format,
+    player: &PlayerScript,
+    client: &Client,
+    headers: &reqwest::header::HeaderMap,
+) -> Result<StreamTrack, DownloadError> {
+    let url = format_url(format, player)?;
+    let segments = match (format.init_range, format.index_range) {
+        (Some(init), Some(index)) => {
+            let head_range = ByteRange { offset: 0, length: index.end() };
+            let head = fetch_manifest_bytes(&url, client, &ByteRange::apply(Some(&head_range), headers)).await?;
+            indexed_segments(&url, &head, init, index).unwrap_or_else(|| {
+                warn!("YouTube Resolver: itag {} has no usable sidx, fetching it in chunks", format.itag);
+                chunked_segments(format, &url)
+            })
+        }
+        _ => chunked_segments(format, &url),
+    };
+    debug!("YouTube Resolver: itag {} split into {} segments", format.itag, segments.len());
+    Ok(StreamTrack::new(kind, segments, None))
+}
+
+/// Segments along the `sidx` found at `index` in `head`, the start of the file.
+fn indexed_segments(url: &str, head: &[u8], init: ByteRange, index: ByteRange) -> Option<Vec<Segment>> {
+    let init_data = head.get(init.offset as usize..init.end() as usize)?;
+    let sidx = parse_sidx(find_box(head.get(index.offset as usize..index.end() as usize)?, b"sidx")?)?;
+    let map = InitSection { url: url.to_string(), byte_range: Some(init), data: Some(Arc::from(init_data)) };
+
+    let mut offset = index.end() + sidx.first_offset;
+    let segments = sidx
+        .references
+        .iter()
+        .map(|&(size, duration)| {
+            let mut segment = Segment::new(url.to_string(), duration as f64 / sidx.timescale as f64).with_map(Some(map.clone()));
+            segment.byte_range = Some(ByteRange { offset, length: size as u64 });
+            offset += size as u64;
+            segment
+        })
+        .collect::<Vec<_>>();
+    (!segments.is_empty()).then_some(segments)
+}
+
+/// Fixed-size byte ranges over the whole file, with the duration spread evenly across them.
+fn chunked_segments(format: &Format, url: &str) -> Vec<Segment> {
+    let duration = format.duration.unwrap_or(0.0);
+    let Some(length) = format.content_length.filter(|&l| l > 0) else {
+        return vec![Segment::new(url.to_string(), duration)];
+    };
+    (0..length.div_ceil(CHUNK_SIZE))
+        .map(|i| {
+            let offset = i * CHUNK_SIZE;
+            let range = ByteRange { offset, length: CHUNK_SIZE.min(length - offset) };
+            let mut segment = Segment::new(url.to_string(), duration * range.length as f64 / length as f64);
+            segment.byte_range = Some(range);
+            segment
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch_page_data() -> StreamingData {
+        let response = page::initial_player_response(include_str!("fixtures/watch.html")).unwrap().unwrap();
+        StreamingData::from_player_response(&response).unwrap()
+    }
+
+    #[test]
+    fn test_format_url_applies_player_transforms() {
+        let data = watch_page_data();
+        let player = PlayerScript::parse(include_str!("fixtures/player.js"));
+        assert_eq!(
+            page::player_url(include_str!("fixtures/watch.html")).as_deref(),
+            Some("https://www.youtube.com/s/player/3f2a9c1e/player_ias.vflset/en_US/base.js")
+        );
+
+        let video = Url::parse(&format_url(data.best_video(None).unwrap(), &player).unwrap()).unwrap();
+        let query: Vec<(String, String)> = video.query_pairs().into_owned().collect();
+        let value = |key: &str| query.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        assert_eq!(value("itag"), Some("137"));
+        assert_eq!(value("n"), Some("w8zpkv2TX0BQmL3n"));
+        assert_eq!(value("sig"), Some("7IHGFEDCBA+.=_-98J6543210zyxwvutsrqponmlkjihgfedcbaZYXWVUTSRQPONMLKJIHGFEACBD"));
+
+        let progressive = data.best_progressive(None).unwrap();
+        let segments = chunked_segments(progressive, "https://example.com/v");
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].byte_range, Some(ByteRange { offset: CHUNK_SIZE, length: 13_500_000 - CHUNK_SIZE }));
+    }
+}
@@ -0,0 +1,74 @@
+//! Watch-page scraping: video ids, the embedded player response and the player script URL.
+
+use super::player::block_end;
+use crate::core::error::DownloadError;
+use url::Url;
+
+const ID_LEN: usize = 11;
+
+/// The 11-character video id of a watch, short, embed, live or `youtu.be` link.
+pub fn video_id(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.").trim_start_matches("m.").trim_start_matches("music.");
+    let mut path = url.path_segments()?.filter(|s| !s.is_empty());
+    let candidate = match host {
+        "youtu.be" => path.next()?.to_string(),
+        "youtube.com" | "youtube-nocookie.com" => match path.next()? {
+            "watch" => url.query_pairs().find(|(k, _)| k == "v")?.1.into_owned(),
+            "shorts" | "embed" | "live" | "v" => path.next()?.to_string(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let valid = candidate.len() == ID_LEN
+        && candidate.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then_some(candidate)
+}
+
+/// The `ytInitialPlayerResponse` object embedded in a watch page, if the page has one.
+pub fn initial_player_response(page: &str) -> Result<Option<serde_json::Value>, DownloadError> {
+    let Some(marker) = page.find("ytInitialPlayerResponse") else {
+        return Ok(None);
+    };
+    let Some(open) = page[marker..].find('{').map(|i| marker + i) else {
+        return Ok(None);
+    };
+    let end = block_end(page, open)
+        .ok_or_else(|| DownloadError::Parse("Unterminated player response in watch page".to_string()))?;
+    serde_json::from_str(&page[open..end])
+        .map(Some)
+        .map_err(|e| DownloadError::Parse(format!("Invalid player response in watch page: {}", e)))
+}
+
+/// Absolute URL of the player script (`base.js`) referenced by a watch page.
+pub fn player_url(page: &str) -> Option<String> {
+    let path = ["\"jsUrl\":\"", "\"PLAYER_JS_URL\":\""].iter().find_map(|key| {
+        let start = page.find(key)? + key.len();
+        let end = page[start..].find('"')? + start;
+        Some(page[start..end].replace("\\/", "/"))
+    })?;
+    Url::parse("https://www.youtube.com").ok()?.join(&path).ok().map(|u| u.to_string())
+}
+
+/// Client version the page's own requests use; the player API wants a plausible one.
+pub fn client_version(page: &str) -> Option<String> {
+    let key = "\"INNERTUBE_CLIENT_VERSION\":\"";
+    let start = page.find(key)? + key.len();
+    let end = page[start..].find('"')? + start;
+    Some(page[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_id() {
+        assert_eq!(video_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(video_id("https://youtu.be/dQw4w9WgXcQ?si=abc").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(video_id("https://m.youtube.com/shorts/dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(video_id("https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ").as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(video_id("https://www.youtube.com/channel/UC123"), None);
+        assert_eq!(video_id("https://www.youtube.com/watch?v=short"), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! The player script (`base.js`) and the two URL transforms hidden in it.
+//!
+//! Formats of protected videos carry a scrambled `s` signature that the player's signature
+//! function turns into the real one, and every stream URL has an `n` parameter that must be
+//! rewritten by a second function or the server throttles the download to a crawl. Both are
+//! obfuscated and renamed with every player release, so they are located with a few patterns,
+//! cut out together with the helper object or string table they use, and run in an embedded
+//! QuickJS interpreter.
+
+use crate::core::error::DownloadError;
+use regex::Regex;
+use rquickjs::{CatchResultExt, Context, Function, Runtime};
+use std::time::{Duration, Instant};
+
+/// Patterns whose first group names the signature function.
+const SIGNATURE_PATTERNS: &[&str] = &[
+    r#"\b[a-zA-Z0-9_$]+&&\([a-zA-Z0-9_$]+=([a-zA-Z0-9_$]{2,})\(decodeURIComponent\([a-zA-Z0-9_$]+\)\)"#,
+    r#"(?:^|[^a-zA-Z0-9_$.])([a-zA-Z0-9_$]{2,})\s*=\s*function\(\s*a\s*\)\s*\{\s*a\s*=\s*a\.split\(\s*""\s*\)"#,
+    r#"\.sig\|\|([a-zA-Z0-9_$]+)\("#,
+];
+
+/// Patterns whose first group names the `n` function, or an array holding it at the index in
+/// the second group.
+const N_PATTERNS: &[&str] = &[
+    r#"\.get\("n"\)\)&&\([a-zA-Z0-9_$]=([a-zA-Z0-9_$]+)(?:\[(\d+)\])?\([a-zA-Z0-9_$]\)"#,
+    r#"[=(,&|]([a-zA-Z0-9_$]+)(?:\[(\d+)\])?\([a-zA-Z0-9_$]\),[a-zA-Z0-9_$]\.set\("n","#,
+];
+
+/// Results of a failed `n` transform start with this instead of raising.
+const N_FAILURE_PREFIX: &str = "enhanced_except_";
+const EVAL_TIMEOUT: Duration = Duration::from_secs(5);
+const EVAL_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Self-contained source of one transform: running `code` defines a global `name`.
+#[derive(Debug, Clone)]
+struct Transform {
+    name: String,
+    code: String,
+}
+
+impl Transform {
+    fn run(&self, input: &str) -> Result<String, DownloadError> {
+        let runtime = Runtime::new().map_err(|e| js_error(&self.name, e))?;
+        runtime.set_memory_limit(EVAL_MEMORY_LIMIT);
+        let deadline = Instant::now() + EVAL_TIMEOUT;
+        runtime.set_interrupt_handler(Some(Box::new(move || Instant::now() > deadline)));
+        let context = Context::full(&runtime).map_err(|e| js_error(&self.name, e))?;
+        context
+            .with(|ctx| {
+                let result = ctx.eval::<(), _>(self.code.as_str()).and_then(|_| {
+                    let function: Function = ctx.globals().get(self.name.as_str())?;
+                    function.call::<_, String>((input,))
+                });
+                result.catch(&ctx).map_err(|e| e.to_string())
+            })
+            .map_err(|e| js_error(&self.name, e))
+    }
+}
+
+fn js_error(name: &str, error: impl std::fmt::Display) -> DownloadError {
+    DownloadError::Parse(format!("Player function {} failed: {}", name, error))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlayerScript {
+    signature: Option<Transform>,
+    n: Option<Transform>,
+    signature_timestamp: Option<u64>,
+}
+
+impl PlayerScript {
+    /// Locates both transforms. Missing ones only become an error once a format needs them.
+    pub fn parse(source: &str) -> Self {
+        let signature = signature_function_name(source).and_then(|name| {
+            let function = function_code(source, &name)?;
+            let helper = helper_object(source, &function);
+            Some(Transform { code: with_string_table(source, &format!("{}{}", helper.unwrap_or_default(), function)), name })
+        });
+        let n = n_function_name(source).and_then(|name| {
+            let function = strip_undefined_guard(&function_code(source, &name)?);
+            Some(Transform { code: with_string_table(source, &function), name })
+        });
+        let signature_timestamp = Regex::new(r"(?:signatureTimestamp|sts)\s*:\s*(\d{5})")
+            .ok()
+            .and_then(|re| re.captures(source))
+            .and_then(|c| c[1].parse().ok());
+        Self { signature, n, signature_timestamp }
+    }
+
+    /// Player build date the player API wants, so that it hands out matching ciphers.
+    pub fn signature_timestamp(&self) -> Option<u64> {
+        self.signature_timestamp
+    }
+
+    pub fn decipher(&self, scrambled: &str) -> Result<String, DownloadError> {
+        self.signature
+            .as_ref()
+            .ok_or_else(|| DownloadError::Parse("Signature function not found in the player script".to_string()))?
+            .run(scrambled)
+    }
+
+    pub fn transform_n(&self, n: &str) -> Result<String, DownloadError> {
+        let transform = self
+            .n
+            .as_ref()
+            .ok_or_else(|| DownloadError::Parse("n-parameter function not found in the player script".to_string()))?;
+        let result = transform.run(n)?;
+        if result.starts_with(N_FAILURE_PREFIX) || result.is_empty() {
+            return Err(DownloadError::Parse(format!("n-parameter function rejected its input: {}", result)));
+        }
+        Ok(result)
+    }
+}
+
+fn signature_function_name(source: &str) -> Option<String> {
+    SIGNATURE_PATTERNS
+        .iter()
+        .filter_map(|p| Regex::new(p).ok())
+        .find_map(|re| re.captures(source).map(|c| c[1].to_string()))
+}
+
+fn n_function_name(source: &str) -> Option<String> {
+    let (name, index) = N_PATTERNS.iter().filter_map(|p| Regex::new(p).ok()).find_map(|re| {
+        let captures = re.captures(source)?;
+        Some((captures[1].to_string(), captures.get(2).and_then(|m| m.as_str().parse::<usize>().ok())))
+    })?;
+    let Some(index) = index else {
+        return Some(name);
+    };
+    // `b=Pn[0](b)`: the function is an element of a small global array.
+    let array = Regex::new(&format!(r"(?:^|[^a-zA-Z0-9_$.])var\s+{}\s*=\s*\[([^\]]*)\]", regex::escape(&name))).ok()?;
+    let elements = array.captures(source)?[1].to_string();
+    elements.split(',').nth(index).map(|e| e.trim().to_string())
+}
+
+/// `var <name>=function(...){...};` for a function defined in `source` either way.
+fn function_code(source: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(
+        r"(?:^|[^a-zA-Z0-9_$.])(?:function\s+{0}|{0}\s*=\s*function)\s*\(([^)]*)\)\s*\{{",
+        regex::escape(name)
+    ))
+    .ok()?;
+    let captures = re.captures(source)?;
+    let open = captures.get(0)?.end() - 1;
+    let end = block_end(source, open)?;
+    Some(format!("var {}=function({}){};", name, &captures[1], &source[open..end]))
+}
+
+/// The object whose methods the signature function calls (`Vz.Sw(a,3)`).
+fn helper_object(source: &str, function: &str) -> Option<String> {
+    let call = Regex::new(r"[;{]\s*([a-zA-Z0-9_$]{2,})\.[a-zA-Z0-9_$]+\(a,").ok()?;
+    let name = call.captures(function)?[1].to_string();
+    let re = Regex::new(&format!(r"(?:^|[^a-zA-Z0-9_$.])(?:var\s+)?{}\s*=\s*\{{", regex::escape(&name))).ok()?;
+    let open = re.find(source)?.end() - 1;
+    let end = block_end(source, open)?;
+    Some(format!("var {}={};", name, &source[open..end]))
+}
+
+/// Prepends the player's global string table (`var Xq="split;reverse".split(";")`) when the
+/// function indexes into it.
+fn with_string_table(source: &str, code: &str) -> String {
+    let table = Regex::new(r#"(?:^|[^a-zA-Z0-9_$.])var\s+([a-zA-Z0-9_$]+)\s*=\s*(?:"[^"]*"|'[^']*')\.split\(\s*(?:"[^"]*"|'[^']*')\s*\)"#)
+        .ok()
+        .and_then(|re| re.captures(source).map(|c| (c[1].to_string(), c.get(0).map_or("", |m| m.as_str()).to_string())));
+    match table {
+        Some((name, declaration)) if code.contains(&format!("{}[", name)) => {
+            let declaration = declaration.trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+            format!("{};{}", declaration, code)
+        }
+        _ => code.to_string(),
+    }
+}
+
+/// Removes the `if(typeof X==="undefined")return a;` early exit the `n` function uses to
+/// detect that it was lifted out of the player.
+fn strip_undefined_guard(function: &str) -> String {
+    match Regex::new(r#";\s*if\s*\(\s*typeof\s+[a-zA-Z0-9_$]+\s*===?\s*(?:"undefined"|'undefined')\s*\)\s*return\s+[a-zA-Z0-9_$]+;"#) {
+        Ok(re) => re.replace_all(function, ";").into_owned(),
+        Err(_) => function.to_string(),
+    }
+}
+
+/// Index just past the `}` matching the `{` at `open`, skipping braces inside strings,
+/// template literals, comments and regular expression literals. Also works on JSON.
+pub fn block_end(source: &str, open: usize) -> Option<usize> {
+    let bytes = source.as_bytes();
+    if bytes.get(open) != Some(&b'{') {
+        return None;
+    }
+    let mut depth = 0usize;
+    let mut previous = b'{';
+    let mut i = open;
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            b'"' | b'\'' | b'`' => i = skip_quoted(bytes, i, c)?,
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = bytes[i..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |p| i + p);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = source[i + 2..].find("*/").map(|p| i + 2 + p + 1)?;
+            }
+            b'/' if b"(,=:[!&|?{};+-*%<>~^".contains(&previous) => i = skip_regex(bytes, i)?,
+            _ => {}
+        }
+        if !c.is_ascii_whitespace() {
+            previous = bytes[i];
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Index of the closing quote of the string starting at `start`.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> Option<usize> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b if b == quote => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Index of the `/` closing the regular expression literal starting at `start`.
+fn skip_regex(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut in_class = false;
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => return Some(i),
+            b'\n' => return None,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: &str = include_str!("fixtures/player.js");
+
+    #[test]
+    fn test_player_transforms() {
+        let player = PlayerScript::parse(PLAYER);
+        assert_eq!(player.signature_timestamp(), Some(20012));
+        assert_eq!(
+            player.decipher("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_=.+ABCDEFGHIJKL").unwrap(),
+            "7IHGFEDCBA+.=_-98J6543210zyxwvutsrqponmlkjihgfedcbaZYXWVUTSRQPONMLKJIHGFEACBD"
+        );
+        assert_eq!(player.transform_n("kBz8Tn3LmQp0Xw2v").unwrap(), "w8zpkv2TX0BQmL3n");
+    }
+
+    #[test]
+    fn test_block_end_skips_literals() {
+        let source = r#"x={a:"}",b:'{',c:/[}]+/g,d:`}`,e:function(){return 1/2}}// }"#;
+        assert_eq!(block_end(source, 2), Some(source.len() - 4));
+    }
+}
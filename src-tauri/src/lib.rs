@@ -14,11 +14,9 @@ use tracing::debug;
 
 // Module imports
 use core::error::DownloadError;
-use core::strategy::http::HttpStrategy;
-use core::strategy::stream::UniversalStreamingStrategy;
-use core::strategy::DownloadStrategy;
+use core::strategy;
 use core::{state, types};
-use network::{client, headers};
+use network::{client, headers, share_links};
 use utils::{filesystem, logger, format};
 // Import the struct from engine, do NOT redefine it
 use crate::commands::DownloadCommandResult;
@@ -26,6 +24,7 @@ use crate::commands::DownloadCommandResult;
 // REMOVED DUPLICATE STRUCT DEFINITION
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn download_file(
     app: tauri::AppHandle,
     url: String,
@@ -33,6 +32,7 @@ async fn download_file(
     threads: u64,
     headers: std::collections::HashMap<String, String>,
     referrer: Option<String>,
+    stream_options: Option<state::StreamOptions>,
     manager: tauri::State<'_, commands::DownloadManager>,
 ) -> Result<DownloadCommandResult, DownloadError> {
     let path = PathBuf::from(&filepath);
@@ -51,12 +51,14 @@ async fn download_file(
         (*manager).clone(),
         headers,
         referrer,
+        stream_options.unwrap_or_default(),
     )
     .await
 }
 
 /// Shared entry point for starting a download (used by Command and IPC)
 /// Shared entry point for starting a download (used by Command and IPC)
+#[allow(clippy::too_many_arguments)]
 pub async fn start_download(
     app: tauri::AppHandle,
     url: String,
@@ -66,33 +68,59 @@ pub async fn start_download(
     manager: commands::DownloadManager,
     headers: std::collections::HashMap<String, String>,
     referrer: Option<String>,
+    stream_options: state::StreamOptions,
 ) -> Result<DownloadCommandResult, DownloadError> {
     // Generate unique download ID
     let download_id = uuid::Uuid::new_v4().to_string();
     let download_control = Arc::new(commands::DownloadControl::new());
     let client = client::create_client()?;
 
-    // 1. Get Response & Size
-    let response = client.get(&url).send().await?;
-    let total_size = response.content_length().unwrap_or(0);
+    // 1. Share links (Google Drive, Dropbox, OneDrive) point at a landing page; swap in the
+    // direct URL and any cookies it needs before looking at the file.
+    let (url, headers) = match share_links::resolve(&client, &url, &headers::request_headers(&headers, referrer.as_deref())).await? {
+        Some(resolved) => {
+            let mut merged = headers;
+            merged.extend(resolved.headers);
+            (resolved.url, merged)
+        }
+        None => (url, headers),
+    };
 
-    // Resolve Filename
-    let final_filename = if let Some(hint) = filename_hint {
-        sanitize_filename::sanitize(hint)
+    // 2. Get Response & Size. Streaming protocols (rtmp://, rtsp://) can't be probed with a GET.
+    let response = if format::is_http(&url) {
+        Some(
+            client
+                .get(&url)
+                .headers(headers::request_headers(&headers, referrer.as_deref()))
+                .send()
+                .await?,
+        )
     } else {
-        headers::extract_filename(&response, &url)
+        None
+    };
+    let total_size = response.as_ref().and_then(|r| r.content_length()).unwrap_or(0);
+
+    // Resolve Filename
+    let final_filename = match (filename_hint, &response) {
+        (Some(hint), _) => sanitize_filename::sanitize(hint),
+        (None, Some(response)) => headers::extract_filename(response, &url),
+        (None, None) => headers::extract_filename_from_url(&url),
     };
 
     let filepath = target_dir.join(&final_filename);
     let mut filepath_str = filepath.to_string_lossy().to_string();
 
     // Strategy Selection
-    let is_streaming = format::is_streaming_protocol(&url);
+    let is_streaming = format::uses_streaming_engine(&url);
     let mut final_total_size = total_size;
     
     // If streaming, ensure we have a good extension and set size to 0 (unknown)
     if is_streaming {
-        let ext = format::get_output_container(&url);
+        let ext = if format::is_rtsp(&url) {
+            stream_options.recording_container.extension()
+        } else {
+            format::get_output_container(&url)
+        };
         let path = std::path::Path::new(&filepath_str);
         let new_path = path.with_extension(ext);
         filepath_str = new_path.to_string_lossy().to_string();
@@ -146,6 +174,9 @@ pub async fn start_download(
         stopped_at: None,
         completed_at: None,
         error_message: None,
+        stream_options,
+        damaged_segments: vec![],
+        substituted_segments: vec![],
     };
 
     manager
@@ -157,11 +188,7 @@ pub async fn start_download(
         .await;
 
     // 4. Run Loop (Delegated to Engine)
-    let strategy: Box<dyn DownloadStrategy> = if is_streaming || url.contains("youtube.com") || url.contains("youtu.be") {
-        Box::new(UniversalStreamingStrategy::new(None))
-    } else {
-        Box::new(HttpStrategy)
-    };
+    let strategy = strategy::for_url(&url);
 
     core::engine::DownloadEngine::start(
         app,
@@ -188,35 +215,28 @@ pub async fn fetch_file_details_with_headers(
 ) -> Result<(String, u64), DownloadError> {
     // 0. If it's a streaming manifest, don't even try to fetch size/details via HTTP HEAD/GET
     // This avoids the '2 bytes' bug and unnecessary network calls.
-    if format::is_streaming_protocol(url) {
+    if format::uses_streaming_engine(url) {
         let filename = headers::extract_filename_from_url(url);
         return Ok((filename, 0));
     }
 
-    let client_builder = reqwest::Client::builder();
-    
-    // Build headers
-    let mut header_map = reqwest::header::HeaderMap::new();
-    for (k, v) in headers {
-        if let (Ok(name), Ok(val)) = (reqwest::header::HeaderName::from_bytes(k.as_bytes()), reqwest::header::HeaderValue::from_str(v)) {
-            header_map.insert(name, val);
-        }
-    }
-    
-    if let Some(r) = referrer {
-        if let Ok(val) = reqwest::header::HeaderValue::from_str(r) {
-            header_map.insert(reqwest::header::REFERER, val);
-        }
-    }
-
-    let client = client_builder.default_headers(header_map).build()
+    let client = reqwest::Client::builder()
+        .default_headers(headers::request_headers(headers, referrer))
+        .build()
         .map_err(|e| DownloadError::Network(e.to_string()))?;
 
+    // Report the file behind a share link, not its landing page
+    let resolved = share_links::resolve(&client, url, &reqwest::header::HeaderMap::new()).await?;
+    let (url, link_headers) = match &resolved {
+        Some(link) => (link.url.as_str(), headers::request_headers(&link.headers, None)),
+        None => (url, reqwest::header::HeaderMap::new()),
+    };
+
     // Use the same logic as fetch_file_details but with our custom client
-    let mut response = client.head(url).send().await;
+    let mut response = client.head(url).headers(link_headers.clone()).send().await;
 
     if response.is_err() || !response.as_ref().map_or(false, |r| r.status().is_success()) {
-        response = client.get(url).header(RANGE, "bytes=0-0").send().await;
+        response = client.get(url).headers(link_headers).header(RANGE, "bytes=0-0").send().await;
     }
 
     let response = response.map_err(|e| DownloadError::Network(e.to_string()))?;
@@ -244,8 +264,15 @@ pub async fn fetch_file_details_with_headers(
 pub async fn fetch_file_details(url: &str) -> Result<(String, u64), DownloadError> {
     let client = client::create_client()?;
 
+    // Report the file behind a share link, not its landing page
+    let resolved = share_links::resolve(&client, url, &reqwest::header::HeaderMap::new()).await?;
+    let (url, link_headers) = match &resolved {
+        Some(link) => (link.url.as_str(), headers::request_headers(&link.headers, None)),
+        None => (url, reqwest::header::HeaderMap::new()),
+    };
+
     // 1. Try HEAD request first
-    let mut response = client.head(url).send().await;
+    let mut response = client.head(url).headers(link_headers.clone()).send().await;
 
     // 2. Fallback to GET if HEAD was rejected or failed
     if response.is_err()
@@ -255,7 +282,7 @@ pub async fn fetch_file_details(url: &str) -> Result<(String, u64), DownloadErro
             .map_or(false, |r| r.status().is_success())
     {
         debug!("HEAD request failed, falling back to GET with range header");
-        response = client.get(url).header(RANGE, "bytes=0-0").send().await;
+        response = client.get(url).headers(link_headers).header(RANGE, "bytes=0-0").send().await;
     }
 
     let response = response.map_err(|e| DownloadError::Network(e.to_string()))?;
@@ -326,6 +353,8 @@ pub fn run() {
             commands::download_control::resume_download,
             commands::download_control::stop_download,
             commands::download_control::cancel_download,
+            commands::streaming::get_stream_resolver,
+            commands::streaming::find_page_media,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
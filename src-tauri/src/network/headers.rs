@@ -19,15 +19,8 @@ pub fn extract_filename(response: &reqwest::Response, url: &str) -> String {
 
     // Try Content-Disposition header first
     if let Some(disp) = response.headers().get(CONTENT_DISPOSITION) {
-        if let Ok(disp_str) = disp.to_str() {
-            if let Some(name_part) = disp_str.split("filename=").nth(1) {
-                filename = name_part
-                    .trim()
-                    .trim_matches('"')
-                    .trim_matches('\'')
-                    .to_string();
-                return filename;
-            }
+        if let Some(name) = disp.to_str().ok().and_then(filename_from_disposition) {
+            return sanitize_filename::sanitize(name);
         }
     }
 
@@ -45,6 +38,71 @@ pub fn extract_filename(response: &reqwest::Response, url: &str) -> String {
     sanitize_filename::sanitize(filename)
 }
 
+/// Builds the request headers a download carries: the stored extra headers plus its referrer
+pub fn request_headers(headers: &std::collections::HashMap<String, String>, referrer: Option<&str>) -> reqwest::header::HeaderMap {
+    let mut header_map = reqwest::header::HeaderMap::new();
+    for (k, v) in headers {
+        if let (Ok(name), Ok(val)) = (
+            reqwest::header::HeaderName::from_bytes(k.as_bytes()),
+            reqwest::header::HeaderValue::from_str(v),
+        ) {
+            header_map.insert(name, val);
+        }
+    }
+    if let Some(r) = referrer {
+        if let Ok(val) = reqwest::header::HeaderValue::from_str(r) {
+            header_map.insert(reqwest::header::REFERER, val);
+        }
+    }
+    header_map
+}
+
+/// Filename from a Content-Disposition value
+///
+/// Prefers the RFC 5987 `filename*=UTF-8''...` form, which cloud storage services use for
+/// non-ASCII names, over the plain `filename=` parameter.
+pub fn filename_from_disposition(disposition: &str) -> Option<String> {
+    let params: Vec<(String, &str)> = disposition
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim()))
+        .collect();
+
+    let extended = params.iter().find(|(key, _)| key == "filename*").and_then(|(_, value)| {
+        let (_, encoded) = value.trim_matches('"').rsplit_once("''")?;
+        Some(percent_decode(encoded))
+    });
+    extended
+        .or_else(|| {
+            params
+                .iter()
+                .find(|(key, _)| key == "filename")
+                .map(|(_, value)| value.trim_matches('"').trim_matches('\'').to_string())
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| value.get(i + 1..i + 3)).flatten();
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 /// Extracts filename from URL path segments
 pub fn extract_filename_from_url(url: &str) -> String {
     let mut filename = "download.dat".to_string();
@@ -61,3 +119,19 @@ pub fn extract_filename_from_url(url: &str) -> String {
 
     sanitize_filename::sanitize(filename)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename_from_disposition() {
+        assert_eq!(filename_from_disposition("attachment; filename=\"report.pdf\"").as_deref(), Some("report.pdf"));
+        assert_eq!(filename_from_disposition("attachment; filename=report.pdf; size=1024").as_deref(), Some("report.pdf"));
+        assert_eq!(
+            filename_from_disposition("attachment; filename=\"r?sum?.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf").as_deref(),
+            Some("résumé.pdf")
+        );
+        assert_eq!(filename_from_disposition("inline"), None);
+    }
+}
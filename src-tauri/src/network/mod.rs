@@ -1,3 +1,4 @@
 /// Network utilities for HTTP operations
 pub mod client;
 pub mod headers;
+pub mod share_links;
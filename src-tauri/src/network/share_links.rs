@@ -0,0 +1,243 @@
+/// Cloud storage share links
+///
+/// Share links from Google Drive, Dropbox and OneDrive open a landing page rather than the
+/// file. This module turns them into direct download URLs:
+/// - Google Drive: `uc?export=download`, plus the confirmation step large files get because
+///   they can't be virus-scanned (a form or `confirm` token, and the cookies that go with it)
+/// - Dropbox: the same link with `dl=1`
+/// - OneDrive: the share API, whose redirect leads to the file
+use crate::core::error::DownloadError;
+use base64::Engine;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, COOKIE, SET_COOKIE};
+use std::collections::HashMap;
+use tracing::{debug, info};
+use url::Url;
+
+/// A share link rewritten to the file itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedLink {
+    pub url: String,
+    /// Headers later requests for the file must carry (cookies from the confirmation step)
+    pub headers: HashMap<String, String>,
+}
+
+/// Cloud service a share link belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareLink {
+    GoogleDrive { file_id: String },
+    Dropbox,
+    OneDrive,
+}
+
+/// Recognises a share link, or returns `None` for any other URL
+pub fn classify(url: &str) -> Option<ShareLink> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?.to_lowercase();
+    match host.as_str() {
+        "drive.google.com" | "docs.google.com" | "drive.usercontent.google.com" => {
+            google_drive_id(&parsed).map(|file_id| ShareLink::GoogleDrive { file_id })
+        }
+        "dropbox.com" | "www.dropbox.com" => is_dropbox_share(&parsed).then_some(ShareLink::Dropbox),
+        "1drv.ms" | "onedrive.live.com" => Some(ShareLink::OneDrive),
+        _ => None,
+    }
+}
+
+/// Dropbox share paths: `/s/<id>/...`, `/sh/<id>/...` and `/scl/fi/<id>/...`
+fn is_dropbox_share(url: &Url) -> bool {
+    let segments: Vec<&str> = url.path_segments().map(|s| s.filter(|s| !s.is_empty()).collect()).unwrap_or_default();
+    matches!(segments.as_slice(), ["s" | "sh", _, ..] | ["scl", "fi", _, ..])
+}
+
+/// File id from `/file/d/<id>/...` or an `id=` query parameter
+fn google_drive_id(url: &Url) -> Option<String> {
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let from_path = segments.iter().position(|s| *s == "d").and_then(|i| segments.get(i + 1)).map(|s| s.to_string());
+    from_path
+        .or_else(|| url.query_pairs().find(|(k, _)| k == "id").map(|(_, v)| v.into_owned()))
+        .filter(|id| !id.is_empty())
+}
+
+/// Direct URL for `url` if it is a share link, `None` otherwise
+pub async fn resolve(client: &reqwest::Client, url: &str, headers: &HeaderMap) -> Result<Option<ResolvedLink>, DownloadError> {
+    let Some(link) = classify(url) else {
+        return Ok(None);
+    };
+    info!(url = %url, service = ?link, "Resolving cloud share link");
+    let resolved = match link {
+        ShareLink::GoogleDrive { file_id } => resolve_google_drive(client, &file_id, headers).await?,
+        ShareLink::Dropbox => ResolvedLink { url: dropbox_direct_url(url)?, headers: HashMap::new() },
+        ShareLink::OneDrive => resolve_onedrive(client, url, headers).await?,
+    };
+    debug!(url = %resolved.url, "Share link resolved");
+    Ok(Some(resolved))
+}
+
+/// The same Dropbox link with `dl=1`, keeping `rlkey` and the other parameters
+pub fn dropbox_direct_url(url: &str) -> Result<String, DownloadError> {
+    let mut parsed = Url::parse(url).map_err(|e| DownloadError::Parse(format!("Invalid Dropbox link: {}", e)))?;
+    let mut pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(k, _)| k != "dl" && k != "raw")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    pairs.push(("dl".to_string(), "1".to_string()));
+    parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    Ok(parsed.to_string())
+}
+
+/// Share API address for a OneDrive link: `u!` + unpadded base64url of the link
+pub fn onedrive_share_api_url(url: &str) -> String {
+    let encoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(url);
+    format!("https://api.onedrive.com/v1.0/shares/u!{}/root/content", encoded)
+}
+
+async fn resolve_onedrive(client: &reqwest::Client, url: &str, headers: &HeaderMap) -> Result<ResolvedLink, DownloadError> {
+    // The API answers with a redirect to a pre-authenticated download URL; follow it once
+    // so the download itself doesn't go through the redirect for every chunk.
+    let response = client
+        .get(onedrive_share_api_url(url))
+        .headers(headers.clone())
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(DownloadError::Network(format!("OneDrive refused the share link: {}", response.status())));
+    }
+    Ok(ResolvedLink { url: response.url().to_string(), headers: HashMap::new() })
+}
+
+async fn resolve_google_drive(client: &reqwest::Client, file_id: &str, headers: &HeaderMap) -> Result<ResolvedLink, DownloadError> {
+    let url = format!("https://drive.google.com/uc?export=download&id={}", file_id);
+    let response = client.get(&url).headers(headers.clone()).send().await?;
+    if !response.status().is_success() {
+        return Err(DownloadError::Network(format!("Google Drive returned error: {}", response.status())));
+    }
+
+    let cookies = response_cookies(response.headers());
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|t| t.contains("text/html"));
+    if !is_html {
+        // Small files come straight back; dropping the response aborts the transfer.
+        return Ok(ResolvedLink { url: response.url().to_string(), headers: cookie_header(&cookies) });
+    }
+
+    let final_url = response.url().clone();
+    let page = response.text().await?;
+    let confirmed = google_drive_confirm_url(&page, &final_url, file_id, &cookies).ok_or_else(|| {
+        let title = Regex::new(r"(?is)<title>(.*?)</title>")
+            .ok()
+            .and_then(|re| re.captures(&page).map(|c| c[1].trim().to_string()))
+            .unwrap_or_default();
+        DownloadError::Config(format!("Google Drive did not offer the file for download ({})", title))
+    })?;
+    Ok(ResolvedLink { url: confirmed, headers: cookie_header(&cookies) })
+}
+
+/// Download URL behind Google Drive's "can't scan this file for viruses" page
+///
+/// Newer pages submit a `download-form` with hidden inputs; older ones link `confirm=<token>`
+/// or set the token in a `download_warning*` cookie.
+pub fn google_drive_confirm_url(page: &str, page_url: &Url, file_id: &str, cookies: &[(String, String)]) -> Option<String> {
+    let form = Regex::new(r#"(?is)<form[^>]*id="download-form"[^>]*action="([^"]+)"[^>]*>(.*?)</form>"#).ok()?;
+    if let Some(captures) = form.captures(page) {
+        let mut action = page_url.join(&captures[1].replace("&amp;", "&")).ok()?;
+        let input = Regex::new(r#"(?is)<input[^>]*type="hidden"[^>]*name="([^"]+)"[^>]*value="([^"]*)""#).ok()?;
+        let fields: Vec<(String, String)> = input.captures_iter(&captures[2]).map(|c| (c[1].to_string(), c[2].replace("&amp;", "&"))).collect();
+        action.query_pairs_mut().extend_pairs(fields);
+        return Some(action.to_string());
+    }
+
+    let token = Regex::new(r"confirm=([0-9A-Za-z_-]+)")
+        .ok()
+        .and_then(|re| re.captures(page).map(|c| c[1].to_string()))
+        .or_else(|| cookies.iter().find(|(name, _)| name.starts_with("download_warning")).map(|(_, value)| value.clone()))?;
+    Some(format!("https://drive.google.com/uc?export=download&confirm={}&id={}", token, file_id))
+}
+
+/// `(name, value)` of every `Set-Cookie` in a response
+fn response_cookies(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|cookie| {
+            let (name, value) = cookie.split(';').next()?.split_once('=')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+fn cookie_header(cookies: &[(String, String)]) -> HashMap<String, String> {
+    let value = cookies.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("; ");
+    if value.is_empty() || HeaderValue::from_str(&value).is_err() {
+        return HashMap::new();
+    }
+    HashMap::from([(COOKIE.as_str().to_string(), value)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_and_rewrite() {
+        assert_eq!(
+            classify("https://drive.google.com/file/d/1AbC-dEf_GhI/view?usp=sharing"),
+            Some(ShareLink::GoogleDrive { file_id: "1AbC-dEf_GhI".to_string() })
+        );
+        assert_eq!(
+            classify("https://drive.google.com/open?id=1AbC-dEf_GhI"),
+            Some(ShareLink::GoogleDrive { file_id: "1AbC-dEf_GhI".to_string() })
+        );
+        assert_eq!(classify("https://drive.google.com/drive/folders"), None);
+        assert_eq!(classify("https://example.com/file.zip"), None);
+        assert_eq!(classify("https://www.dropbox.com/s/x1y2z3/notes.txt?dl=0"), Some(ShareLink::Dropbox));
+        assert_eq!(classify("https://www.dropbox.com/scl/fi/abc123/report.pdf?rlkey=k9"), Some(ShareLink::Dropbox));
+        assert_eq!(classify("https://www.dropbox.com/home"), None);
+        assert_eq!(classify("https://dropbox.com/account"), None);
+
+        assert_eq!(
+            dropbox_direct_url("https://www.dropbox.com/scl/fi/abc123/report.pdf?rlkey=k9&dl=0").unwrap(),
+            "https://www.dropbox.com/scl/fi/abc123/report.pdf?rlkey=k9&dl=1"
+        );
+        // Example from the OneDrive API documentation.
+        assert_eq!(
+            onedrive_share_api_url("https://onedrive.live.com/redir?resid=1231244193912!12&authKey=1201919!12921!1"),
+            "https://api.onedrive.com/v1.0/shares/u!aHR0cHM6Ly9vbmVkcml2ZS5saXZlLmNvbS9yZWRpcj9yZXNpZD0xMjMxMjQ0MTkzOTEyITEyJmF1dGhLZXk9MTIwMTkxOSExMjkyMSEx/root/content"
+        );
+    }
+
+    #[test]
+    fn test_google_drive_confirmation() {
+        let page_url = Url::parse("https://drive.usercontent.google.com/download?id=1AbC&export=download").unwrap();
+        let form_page = r#"<html><head><title>Google Drive - Virus scan warning</title></head><body>
+<form id="download-form" action="https://drive.usercontent.google.com/download" method="get">
+<input type="submit" id="uc-download-link" value="Download anyway">
+<input type="hidden" name="id" value="1AbC"><input type="hidden" name="export" value="download">
+<input type="hidden" name="confirm" value="t"><input type="hidden" name="uuid" value="5f0c-77aa">
+</form></body></html>"#;
+        assert_eq!(
+            google_drive_confirm_url(form_page, &page_url, "1AbC", &[]).as_deref(),
+            Some("https://drive.usercontent.google.com/download?id=1AbC&export=download&confirm=t&uuid=5f0c-77aa")
+        );
+
+        let link_page = r#"<a id="uc-download-link" href="/uc?export=download&amp;confirm=Xy_9&amp;id=1AbC">Download anyway</a>"#;
+        assert_eq!(
+            google_drive_confirm_url(link_page, &page_url, "1AbC", &[]).as_deref(),
+            Some("https://drive.google.com/uc?export=download&confirm=Xy_9&id=1AbC")
+        );
+
+        let cookies = vec![("NID".to_string(), "1".to_string()), ("download_warning_13058876669334088843_1AbC".to_string(), "Q2w3".to_string())];
+        assert_eq!(
+            google_drive_confirm_url("<html>Quota</html>", &page_url, "1AbC", &cookies).as_deref(),
+            Some("https://drive.google.com/uc?export=download&confirm=Q2w3&id=1AbC")
+        );
+        assert_eq!(cookie_header(&cookies).get("cookie").map(String::as_str), Some("NID=1; download_warning_13058876669334088843_1AbC=Q2w3"));
+        assert_eq!(google_drive_confirm_url("<html>Access denied</html>", &page_url, "1AbC", &[]), None);
+    }
+}
@@ -25,12 +25,43 @@ pub fn is_streaming_protocol(url: &str) -> bool {
     false
 }
 
+/// Whether a URL is recorded over RTMP rather than fetched with HTTP requests.
+pub fn is_rtmp(url: &str) -> bool {
+    let url_lc = url.to_lowercase();
+    url_lc.starts_with("rtmp://") || url_lc.starts_with("rtmps://")
+}
+
+/// Whether a URL is recorded from an RTSP server such as an IP camera.
+pub fn is_rtsp(url: &str) -> bool {
+    let url_lc = url.to_lowercase();
+    url_lc.starts_with("rtsp://") || url_lc.starts_with("rtsps://")
+}
+
+/// Whether a URL is fetched over HTTP(S), so it can be probed with a request before starting.
+pub fn is_http(url: &str) -> bool {
+    let url_lc = url.to_lowercase();
+    url_lc.starts_with("http://") || url_lc.starts_with("https://")
+}
+
+/// Whether a download is handled by the Universal Streaming Engine rather than plain HTTP.
+pub fn uses_streaming_engine(url: &str) -> bool {
+    is_streaming_protocol(url) || is_platform_url(url)
+}
+
+/// Video sites handled by the engine's platform resolvers.
+pub fn is_platform_url(url: &str) -> bool {
+    let url_lc = url.to_lowercase();
+    ["youtube.com", "youtu.be", "vimeo.com", "dailymotion.com", "dai.ly"]
+        .iter()
+        .any(|host| url_lc.contains(host))
+}
+
 pub fn get_output_container(url: &str) -> &str {
     let url_lc = url.to_lowercase();
     
     if url_lc.contains(".m3u8") || url_lc.starts_with("srt://") {
         "ts"
-    } else if url_lc.contains(".mpd") || url_lc.contains(".ism") {
+    } else if url_lc.contains(".mpd") || url_lc.contains(".ism") || is_platform_url(url) {
         "mp4"
     } else if url_lc.starts_with("rtmp") || url_lc.contains(".f4m") {
         "flv"
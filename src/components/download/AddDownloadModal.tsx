@@ -4,18 +4,23 @@ import { open } from '@tauri-apps/plugin-dialog';
 import { downloadDir, join } from '@tauri-apps/api/path';
 import { useDownloadStore } from '../../stores/downloadStore';
 import { useUIStore } from '../../stores/uiStore';
+import type { MediaCandidate, StreamOptions, DiscontinuityMode, AudioContainer, RecordingContainer } from '../../types';
+import { DEFAULT_STREAM_OPTIONS, isRtspUrl, isStreamingUrl, parseLanguages, parseSeconds } from '../../utils/streaming';
 import './AddDownloadModal.css';
 
 export const AddDownloadModal = () => {
     const { showAddModal, setShowAddModal } = useUIStore();
-    const { pendingRequest, setPendingRequest, addDownload, setUrl, setSavePath, setThreads, threads } = useDownloadStore();
+    const { pendingRequest, setPendingRequest, addDownload, setUrl, setSavePath, setThreads, setStreamOptions, threads } = useDownloadStore();
 
     const [url, setLocalUrl] = useState('');
     const [savePath, setLocalSavePath] = useState('');
     const [filename, setLocalFilename] = useState('');
     const [localThreads, setLocalThreads] = useState(16);
-    const [loading, setLoading] = useState<'detect' | 'queue' | 'download' | null>(null);
+    const [loading, setLoading] = useState<'detect' | 'scan' | 'queue' | 'download' | null>(null);
     const [detectedSize, setDetectedSize] = useState<number | null>(null);
+    const [candidates, setCandidates] = useState<MediaCandidate[] | null>(null);
+    const [streamOptions, setLocalStreamOptions] = useState<StreamOptions>(DEFAULT_STREAM_OPTIONS);
+    const [subtitleText, setSubtitleText] = useState('');
     const urlInputRef = useRef<HTMLInputElement>(null);
 
     const isOpen = showAddModal || !!pendingRequest;
@@ -26,6 +31,8 @@ export const AddDownloadModal = () => {
             setLocalUrl(pendingRequest.url);
             setLocalFilename(pendingRequest.filename);
             setDetectedSize(pendingRequest.size ?? null);
+            setCandidates(null);
+            resetStreamOptions();
             initSavePath(pendingRequest.filename);
         }
     }, [pendingRequest]);
@@ -36,7 +43,9 @@ export const AddDownloadModal = () => {
             setLocalUrl('');
             setLocalFilename('');
             setDetectedSize(null);
+            setCandidates(null);
             setLocalThreads(threads);
+            resetStreamOptions();
             navigator.clipboard.readText().then(text => {
                 if (text.startsWith('http://') || text.startsWith('https://')) {
                     setLocalUrl(text);
@@ -54,16 +63,24 @@ export const AddDownloadModal = () => {
         } catch { }
     };
 
+    const resetStreamOptions = () => {
+        setLocalStreamOptions(DEFAULT_STREAM_OPTIONS);
+        setSubtitleText('');
+    };
+
+    const updateStreamOptions = (patch: Partial<StreamOptions>) =>
+        setLocalStreamOptions(prev => ({ ...prev, ...patch }));
+
     const handleClose = () => {
         setShowAddModal(false);
         setPendingRequest(null);
     };
 
-    const handleDetect = async () => {
-        if (!url.trim()) return;
+    const handleDetect = async (target = url) => {
+        if (!target.trim()) return;
         setLoading('detect');
         try {
-            const [name, size] = await invoke<[string, number]>('get_file_details', { url });
+            const [name, size] = await invoke<[string, number]>('get_file_details', { url: target });
             if (name) setLocalFilename(name);
             if (size) setDetectedSize(size);
         } catch (e) {
@@ -73,6 +90,34 @@ export const AddDownloadModal = () => {
         }
     };
 
+    // Look for media on a page URL (video tags, embeds, manifests in scripts)
+    const handleScan = async () => {
+        if (!url.trim()) return;
+        setLoading('scan');
+        try {
+            const found = await invoke<MediaCandidate[]>('find_page_media', {
+                url,
+                headers: pendingRequest?.headers ?? null,
+                referrer: pendingRequest?.referrer ?? null,
+            });
+            setCandidates(found);
+        } catch (e) {
+            console.warn('Could not scan page:', e);
+            setCandidates([]);
+        } finally {
+            setLoading(null);
+        }
+    };
+
+    const handlePickCandidate = async (candidate: MediaCandidate) => {
+        setLocalUrl(candidate.url);
+        setCandidates(null);
+        setDetectedSize(null);
+        const name = decodeURIComponent(new URL(candidate.url).pathname.split('/').pop() || '');
+        setLocalFilename(name);
+        await handleDetect(candidate.url);
+    };
+
     const handleBrowse = async () => {
         const selected = await open({
             directory: true,
@@ -96,6 +141,9 @@ export const AddDownloadModal = () => {
         const dlId = crypto.randomUUID();
         const headers = pendingRequest?.headers ?? {};
         const referrer = pendingRequest?.referrer ?? null;
+        const options = isStreamingUrl(url)
+            ? { ...streamOptions, subtitleLanguages: parseLanguages(subtitleText) }
+            : null;
 
         // Add to store immediately for optimistic UI
         addDownload({
@@ -111,6 +159,7 @@ export const AddDownloadModal = () => {
             status: queue ? 'queued' : 'active',
             headers,
             referrer,
+            streamOptions: options,
             addedAt: Date.now(),
         });
 
@@ -118,6 +167,7 @@ export const AddDownloadModal = () => {
         setUrl(url);
         setSavePath(fullPath);
         setThreads(localThreads);
+        if (options) setStreamOptions(options);
 
         handleClose();
 
@@ -129,6 +179,7 @@ export const AddDownloadModal = () => {
                     threads: localThreads,
                     headers,
                     referrer,
+                    streamOptions: options,
                 });
             } catch (e) {
                 console.error('Download failed:', e);
@@ -144,6 +195,9 @@ export const AddDownloadModal = () => {
         return `${v.toFixed(1)} ${units[i]}`;
     };
 
+    const streaming = isStreamingUrl(url);
+    const rtsp = isRtspUrl(url);
+
     if (!isOpen) return null;
 
     return (
@@ -168,12 +222,35 @@ export const AddDownloadModal = () => {
                                 onChange={e => setLocalUrl(e.target.value)}
                                 placeholder="https://..."
                                 spellCheck={false}
-                                onBlur={handleDetect}
+                                onBlur={() => handleDetect()}
                             />
+                            <button
+                                className="modal-browse-btn"
+                                onClick={handleScan}
+                                disabled={!url.trim() || loading !== null}
+                                title="Find media on this page"
+                            >
+                                {loading === 'scan' ? '⏳' : '🔍'}
+                            </button>
                         </div>
                         {detectedSize && (
                             <span className="modal-hint">Detected size: {formatBytes(detectedSize)}</span>
                         )}
+                        {candidates && candidates.length === 0 && (
+                            <span className="modal-hint modal-hint-muted">No media found on this page</span>
+                        )}
+                        {candidates && candidates.length > 0 && (
+                            <ul className="modal-candidates">
+                                {candidates.map(c => (
+                                    <li key={c.url}>
+                                        <button className="modal-candidate" onClick={() => handlePickCandidate(c)} title={c.url}>
+                                            <span className="modal-candidate-kind">{c.kind}</span>
+                                            <span className="modal-candidate-label">{c.label || c.url}</span>
+                                        </button>
+                                    </li>
+                                ))}
+                            </ul>
+                        )}
                     </div>
 
                     {/* Options section */}
@@ -221,6 +298,155 @@ export const AddDownloadModal = () => {
                             className="modal-slider"
                         />
                     </div>
+                    {/* Stream options (HLS, DASH, RTMP, RTSP, ...) */}
+                    {streaming && (
+                        <>
+                            <div className="modal-section-label">Stream</div>
+
+                            {rtsp ? (
+                                <div className="modal-row">
+                                    <div className="modal-field">
+                                        <label className="modal-label">Container:</label>
+                                        <select
+                                            className="modal-input modal-select"
+                                            value={streamOptions.recordingContainer}
+                                            onChange={e => updateStreamOptions({ recordingContainer: e.target.value as RecordingContainer })}
+                                        >
+                                            <option value="matroska">Matroska (.mkv)</option>
+                                            <option value="ts">MPEG-TS (.ts)</option>
+                                        </select>
+                                    </div>
+                                    <div className="modal-field">
+                                        <label className="modal-label">Max duration (s):</label>
+                                        <input
+                                            className="modal-input"
+                                            type="number"
+                                            min={0}
+                                            value={streamOptions.maxDuration ?? ''}
+                                            onChange={e => updateStreamOptions({ maxDuration: parseSeconds(e.target.value) })}
+                                            placeholder="Until stopped"
+                                        />
+                                    </div>
+                                </div>
+                            ) : (
+                                <>
+                                    <div className="modal-row">
+                                        <div className="modal-field">
+                                            <label className="modal-label">Quality:</label>
+                                            <select
+                                                className="modal-input modal-select"
+                                                value={streamOptions.maxHeight ?? ''}
+                                                onChange={e => updateStreamOptions({ maxHeight: e.target.value ? Number(e.target.value) : null })}
+                                            >
+                                                <option value="">Best</option>
+                                                <option value="2160">Up to 2160p</option>
+                                                <option value="1440">Up to 1440p</option>
+                                                <option value="1080">Up to 1080p</option>
+                                                <option value="720">Up to 720p</option>
+                                                <option value="480">Up to 480p</option>
+                                                <option value="360">Up to 360p</option>
+                                            </select>
+                                        </div>
+                                        <div className="modal-field">
+                                            <label className="modal-label">Discontinuities:</label>
+                                            <select
+                                                className="modal-input modal-select"
+                                                value={streamOptions.discontinuityMode}
+                                                onChange={e => updateStreamOptions({ discontinuityMode: e.target.value as DiscontinuityMode })}
+                                            >
+                                                <option value="keep">Keep</option>
+                                                <option value="dropAds">Drop ads</option>
+                                                <option value="split">Split into parts</option>
+                                            </select>
+                                        </div>
+                                    </div>
+
+                                    <div className="modal-row">
+                                        <div className="modal-field">
+                                            <label className="modal-label">Clip start (s):</label>
+                                            <input
+                                                className="modal-input"
+                                                type="number"
+                                                min={0}
+                                                value={streamOptions.clipStart ?? ''}
+                                                onChange={e => updateStreamOptions({ clipStart: parseSeconds(e.target.value) })}
+                                                placeholder="Beginning"
+                                            />
+                                        </div>
+                                        <div className="modal-field">
+                                            <label className="modal-label">Clip end (s):</label>
+                                            <input
+                                                className="modal-input"
+                                                type="number"
+                                                min={0}
+                                                value={streamOptions.clipEnd ?? ''}
+                                                onChange={e => updateStreamOptions({ clipEnd: parseSeconds(e.target.value) })}
+                                                placeholder="End"
+                                            />
+                                        </div>
+                                    </div>
+
+                                    <div className="modal-field">
+                                        <label className="modal-label">Subtitles:</label>
+                                        <input
+                                            className="modal-input"
+                                            value={subtitleText}
+                                            onChange={e => setSubtitleText(e.target.value)}
+                                            placeholder="en, fr (* for all)"
+                                            spellCheck={false}
+                                        />
+                                        <label className="modal-check">
+                                            <input
+                                                type="checkbox"
+                                                checked={streamOptions.subtitlesAsSrt}
+                                                onChange={e => updateStreamOptions({ subtitlesAsSrt: e.target.checked })}
+                                            />
+                                            Save subtitles as SRT
+                                        </label>
+                                    </div>
+
+                                    <label className="modal-check">
+                                        <input
+                                            type="checkbox"
+                                            checked={streamOptions.audioOnly}
+                                            onChange={e => updateStreamOptions({ audioOnly: e.target.checked })}
+                                        />
+                                        Audio only
+                                    </label>
+                                    {streamOptions.audioOnly && (
+                                        <div className="modal-field">
+                                            <label className="modal-label">Audio format:</label>
+                                            <select
+                                                className="modal-input modal-select"
+                                                value={streamOptions.audioContainer}
+                                                onChange={e => updateStreamOptions({ audioContainer: e.target.value as AudioContainer })}
+                                            >
+                                                <option value="m4a">M4A</option>
+                                                <option value="raw">Original stream</option>
+                                            </select>
+                                        </div>
+                                    )}
+
+                                    <label className="modal-check">
+                                        <input
+                                            type="checkbox"
+                                            checked={streamOptions.remuxToMp4}
+                                            onChange={e => updateStreamOptions({ remuxToMp4: e.target.checked })}
+                                        />
+                                        Remux to MP4
+                                    </label>
+                                    <label className="modal-check">
+                                        <input
+                                            type="checkbox"
+                                            checked={streamOptions.mirror}
+                                            onChange={e => updateStreamOptions({ mirror: e.target.checked })}
+                                        />
+                                        Mirror playlist for offline playback
+                                    </label>
+                                </>
+                            )}
+                        </>
+                    )}
                 </div>
 
                 {/* Footer Buttons */}
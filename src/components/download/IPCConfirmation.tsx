@@ -4,9 +4,10 @@ import { ConfirmDialog } from '../common/ConfirmDialog';
 import { invoke } from '@tauri-apps/api/core';
 import { downloadDir, join } from '@tauri-apps/api/path';
 import { useHistoryStore } from '../../stores/historyStore';
+import { isStreamingUrl } from '../../utils/streaming';
 
 export const IPCConfirmation = () => {
-    const { pendingRequest, setPendingRequest, setUrl, setSavePath, setStatus } = useDownloadStore();
+    const { pendingRequest, setPendingRequest, setUrl, setSavePath, setStatus, streamOptions } = useDownloadStore();
     const { addItem } = useHistoryStore();
 
     const handleConfirm = async () => {
@@ -28,7 +29,8 @@ export const IPCConfirmation = () => {
                 filepath: fullPath,
                 threads: 16, // Default
                 headers: pendingRequest.headers || {},
-                referrer: pendingRequest.referrer || null
+                referrer: pendingRequest.referrer || null,
+                streamOptions: isStreamingUrl(pendingRequest.url) ? streamOptions : null
             }).then(() => {
                 setStatus('Finished');
                 addItem(pendingRequest.url, fullPath, 0, 'Success');
@@ -64,8 +66,7 @@ export const IPCConfirmation = () => {
         return `${size.toFixed(2)} ${units[unitIndex]}`;
     };
 
-    const isStreaming = pendingRequest?.url.toLowerCase().includes('.m3u8') || 
-                      pendingRequest?.url.toLowerCase().includes('.mpd');
+    const isStreaming = isStreamingUrl(pendingRequest.url);
 
     const displaySize = isStreaming ? 'Stream (Unknown)' : formatSize(pendingRequest.size);
 
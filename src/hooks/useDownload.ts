@@ -3,9 +3,10 @@ import { invoke } from '@tauri-apps/api/core';
 import { save } from '@tauri-apps/plugin-dialog';
 import { useDownloadStore } from '../stores/downloadStore';
 import { useHistoryStore } from '../stores/historyStore';
+import { isStreamingUrl } from '../utils/streaming';
 
 export const useDownload = () => {
-    const { url, savePath, threads, totalSize, setSavePath, setTotalSize, setStatus } = useDownloadStore();
+    const { url, savePath, threads, totalSize, streamOptions, setSavePath, setTotalSize, setStatus } = useDownloadStore();
     const { addItem } = useHistoryStore();
 
     /**
@@ -64,7 +65,8 @@ export const useDownload = () => {
                 filepath: savePath,
                 threads: Number(threads),
                 headers: {},
-                referrer: null
+                referrer: null,
+                streamOptions: isStreamingUrl(url) ? streamOptions : null
             });
 
             if (result.status === 'completed') {
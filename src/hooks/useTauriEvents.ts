@@ -66,6 +66,9 @@ export const useTauriEvents = () => {
             eta: number;
             downloaded: number;
             total: number;
+            // Streaming downloads only
+            segments?: number;
+            totalSegments?: number;
         }>('download-progress-detail', (event) => {
             const { id, progress, speed, eta, downloaded, total } = event.payload;
             updateDownload(id, { progress, speed, eta, downloaded, totalSize: total });
@@ -1,6 +1,7 @@
 // Download state management with Zustand
 import { create } from 'zustand';
-import type { DownloadState, DownloadEntry, DownloadStatus } from '../types';
+import type { DownloadState, DownloadEntry, DownloadStatus, StreamOptions } from '../types';
+import { DEFAULT_STREAM_OPTIONS } from '../utils/streaming';
 
 type LegacyDownloadStateType = 'idle' | 'active' | 'paused' | 'stopped' | 'completed' | 'failed' | 'cancelled';
 
@@ -8,6 +9,7 @@ interface DownloadStore extends DownloadState {
     // Legacy single-download state (kept for backward compat with useDownload hook)
     downloadId: string | null;
     downloadState: LegacyDownloadStateType;
+    streamOptions: StreamOptions;
 
     // IPC Request State
     pendingRequest: {
@@ -30,6 +32,7 @@ interface DownloadStore extends DownloadState {
     setThreads: (threads: number) => void;
     setDownloadId: (id: string | null) => void;
     setDownloadState: (state: LegacyDownloadStateType) => void;
+    setStreamOptions: (options: StreamOptions) => void;
     setPendingRequest: (req: {
         url: string;
         filename: string;
@@ -50,6 +53,7 @@ interface DownloadStore extends DownloadState {
 const initialState: DownloadState & {
     downloadId: string | null;
     downloadState: LegacyDownloadStateType;
+    streamOptions: StreamOptions;
     pendingRequest: {
         url: string;
         filename: string;
@@ -67,6 +71,7 @@ const initialState: DownloadState & {
     threads: 16,
     downloadId: null,
     downloadState: 'idle',
+    streamOptions: DEFAULT_STREAM_OPTIONS,
     pendingRequest: null,
     downloads: [],
 };
@@ -83,6 +88,7 @@ export const useDownloadStore = create<DownloadStore>((set, get) => ({
     setThreads: (threads) => set({ threads }),
     setDownloadId: (id) => set({ downloadId: id }),
     setDownloadState: (state) => set({ downloadState: state }),
+    setStreamOptions: (options) => set({ streamOptions: options }),
     setPendingRequest: (req) => set({ pendingRequest: req }),
     reset: () => set(initialState),
 
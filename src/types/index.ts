@@ -30,6 +30,31 @@ export interface DownloadState {
     threads: number;
 }
 
+/** What to do at discontinuities (ad breaks, spliced content) in a stream */
+export type DiscontinuityMode = 'keep' | 'dropAds' | 'split';
+
+/** File format of audio-only stream downloads */
+export type AudioContainer = 'm4a' | 'raw';
+
+/** File format of RTSP recordings */
+export type RecordingContainer = 'matroska' | 'ts';
+
+/** Per-download options for streaming downloads (`StreamOptions` in the backend) */
+export interface StreamOptions {
+    remuxToMp4: boolean;
+    subtitleLanguages: string[];    // BCP 47 tags, '*' for all
+    subtitlesAsSrt: boolean;
+    clipStart: number | null;       // seconds
+    clipEnd: number | null;         // seconds
+    discontinuityMode: DiscontinuityMode;
+    maxHeight: number | null;       // null for the best quality
+    mirror: boolean;
+    audioOnly: boolean;
+    audioContainer: AudioContainer;
+    recordingContainer: RecordingContainer;
+    maxDuration: number | null;     // seconds
+}
+
 export type DownloadStatus = 'active' | 'paused' | 'queued' | 'completed' | 'failed' | 'cancelled' | 'waiting_for_link';
 
 export interface DownloadEntry {
@@ -45,5 +70,15 @@ export interface DownloadEntry {
     status: DownloadStatus;
     headers?: Record<string, string>;
     referrer?: string | null;
+    streamOptions?: StreamOptions | null;
     addedAt: number;        // timestamp ms
 }
+
+/** Media found on a web page by `find_page_media` */
+export interface MediaCandidate {
+    url: string;
+    kind: 'video' | 'audio' | 'hls' | 'dash' | 'embed';
+    source: 'mediaTag' | 'openGraph' | 'jsonLd' | 'script' | 'iframe';
+    mimeType: string | null;
+    label: string | null;
+}
@@ -0,0 +1,56 @@
+// Streaming URL detection and defaults for stream options
+import type { StreamOptions } from '../types';
+
+/** Same defaults as `StreamOptions::default()` in the backend */
+export const DEFAULT_STREAM_OPTIONS: StreamOptions = {
+    remuxToMp4: false,
+    subtitleLanguages: [],
+    subtitlesAsSrt: false,
+    clipStart: null,
+    clipEnd: null,
+    discontinuityMode: 'keep',
+    maxHeight: null,
+    mirror: false,
+    audioOnly: false,
+    audioContainer: 'm4a',
+    recordingContainer: 'matroska',
+    maxDuration: null,
+};
+
+/**
+ * Whether a URL is an RTSP camera stream
+ * @param url - Download URL
+ */
+export const isRtspUrl = (url: string): boolean => {
+    const lc = url.trim().toLowerCase();
+    return lc.startsWith('rtsp://') || lc.startsWith('rtsps://');
+};
+
+/**
+ * Whether the backend handles a URL with its streaming engine or a recorder
+ * (mirrors `format::uses_streaming_engine` and the RTMP/RTSP checks)
+ * @param url - Download URL
+ */
+export const isStreamingUrl = (url: string): boolean => {
+    const lc = url.trim().toLowerCase();
+    return ['.m3u8', '.mpd', '.ism', '.f4m'].some(ext => lc.includes(ext))
+        || ['rtmp://', 'rtmps://', 'rtsp://', 'rtsps://', 'mms://', 'mmsh://', 'srt://'].some(scheme => lc.includes(scheme))
+        || ['youtube.com', 'youtu.be', 'vimeo.com', 'dailymotion.com', 'dai.ly'].some(host => lc.includes(host));
+};
+
+/**
+ * Parses a comma-separated list of subtitle languages ("en, fr" or "*")
+ * @param value - Text from the languages field
+ */
+export const parseLanguages = (value: string): string[] =>
+    value.split(',').map(l => l.trim()).filter(l => l.length > 0);
+
+/**
+ * Parses an optional number of seconds; empty or invalid input gives null
+ * @param value - Text from a seconds field
+ */
+export const parseSeconds = (value: string): number | null => {
+    if (!value.trim()) return null;
+    const n = Number(value);
+    return Number.isFinite(n) && n >= 0 ? n : null;
+};